@@ -1,24 +1,276 @@
 use crate::operation::Node;
 use crate::{Grounding, Model};
 use anyhow::Result;
+#[cfg(feature = "json")]
+use anyhow::bail;
 use bumpalo_herd::Member;
 use hifitime::Duration;
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
 
 /// An activity, which decomposes into a statically-known set of operations. Implemented
 /// with the [impl_activity] macro.
+///
+/// The trait is dyn-compatible, so activities of different types can be kept together as
+/// `Box<dyn Activity<M>>` and inserted with [Plan::insert_boxed][crate::Plan::insert_boxed].
+/// A `Box<dyn Activity<M>>` of a [registered][crate::registry] type can be serialized with its
+/// type label, and deserialized again.
 pub trait Activity<'o, M: Model<'o>>: Send + Sync {
     fn decompose(
         &'o self,
         start: Grounding<'o, M>,
-        bump: Member<'o>,
+        bump: &Member<'o>,
     ) -> Result<(Duration, Vec<&'o dyn Node<'o, M>>)>;
+
+    /// The activity's type label, for error messages and reports.
+    fn label(&self) -> &'static str;
+
+    /// Checks the activity's arguments before it is inserted into a plan.
+    ///
+    /// Implemented with a `validate { ... }` block at the start of the [impl_activity][crate::impl_activity]
+    /// body; activities without one accept any arguments.
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The arguments of the command the activity is exported as, by name. See [commands][crate::commands].
+    ///
+    /// Implemented with an `arguments { name: value, ... }` block at the start of the
+    /// [impl_activity][crate::impl_activity] body, where each value is [Display];
+    /// activities without one have no arguments.
+    fn arguments(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+
+    /// The activity as [Any], so that the [registry][crate::registry] can find its type.
+    #[doc(hidden)]
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// An activity whose arguments can be saved, and loaded again after its fields change.
+///
+/// Saved arguments are tagged with the [VERSION][VersionedActivity::VERSION] of the type that
+/// saved them. When an activity gains, loses, or changes a field, bump the version and
+/// implement [upgrade][VersionedActivity::upgrade] to convert arguments from the previous
+/// version; loading applies the upgrades from the saved version one at a time.
+///
+/// ```
+/// # use peregrine::*;
+/// # use peregrine::activity::{SavedArguments, VersionedActivity};
+/// # resource!(saved_heat: f64);
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// pub struct Heat {
+///     power: f64,
+///     // Added in version 1.
+///     duty_cycle: f64,
+/// }
+/// impl_activity! { for Heat
+///     @(start) {
+///         ref mut: saved_heat += self.power * self.duty_cycle;
+///     }
+///     Duration::ZERO
+/// }
+///
+/// impl VersionedActivity for Heat {
+///     const VERSION: u32 = 1;
+///
+///     fn upgrade(from: u32, mut args: serde_json::Value) -> Result<serde_json::Value> {
+///         assert_eq!(0, from);
+///         args["duty_cycle"] = 1.0.into();
+///         Ok(args)
+///     }
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let saved: SavedArguments = serde_json::from_str(r#"{ "version": 0, "args": { "power": 5.0 } }"#)?;
+/// let heat = Heat::load(saved)?;
+/// assert_eq!(1.0, heat.duty_cycle);
+/// assert_eq!(1, heat.save()?.version);
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "json")]
+pub trait VersionedActivity: Serialize + DeserializeOwned {
+    /// The version of the activity's fields. Arguments saved without a version are version 0.
+    const VERSION: u32 = 0;
+
+    /// Converts arguments saved by version `from` to the fields of version `from + 1`.
+    ///
+    /// Only called for versions older than [VERSION][VersionedActivity::VERSION]. The default
+    /// refuses to, which is correct for activities that have never changed.
+    fn upgrade(from: u32, args: serde_json::Value) -> Result<serde_json::Value> {
+        let _ = args;
+        bail!(
+            "{} has no upgrade from version {from}",
+            std::any::type_name::<Self>()
+        )
+    }
+
+    /// The activity's arguments, tagged with the current version.
+    fn save(&self) -> Result<SavedArguments> {
+        Ok(SavedArguments {
+            version: Self::VERSION,
+            args: serde_json::to_value(self)?,
+        })
+    }
+
+    /// Upgrades saved arguments to the current version, and deserializes them.
+    fn load(saved: SavedArguments) -> Result<Self> {
+        if saved.version > Self::VERSION {
+            bail!(
+                "arguments were saved by version {} of {}, but this program has version {}",
+                saved.version,
+                std::any::type_name::<Self>(),
+                Self::VERSION
+            );
+        }
+        let mut args = saved.args;
+        for from in saved.version..Self::VERSION {
+            args = Self::upgrade(from, args)?;
+        }
+        Ok(serde_json::from_value(args)?)
+    }
+}
+
+/// The arguments of a [VersionedActivity], as saved in a plan file.
+#[cfg(feature = "json")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedArguments {
+    #[serde(default)]
+    pub version: u32,
+    pub args: serde_json::Value,
+}
+
+/// Hashes the arguments of an activity that implements [Serialize], so that its operations are
+/// only reused from history with the same arguments. Used by the [impl_activity][crate::impl_activity]
+/// macro as `(&ArgumentsHash(activity)).arguments_hash()`, which picks [HashSerialized] if the
+/// activity can be serialized and [HashNothing] otherwise.
+#[doc(hidden)]
+pub struct ArgumentsHash<'a, T>(pub &'a T);
+
+#[doc(hidden)]
+pub trait HashSerialized {
+    /// The hash of the serialized arguments, or 0 if there are none.
+    fn arguments_hash(&self) -> u64;
+}
+
+impl<T: Serialize> HashSerialized for ArgumentsHash<'_, T> {
+    fn arguments_hash(&self) -> u64 {
+        let mut writer = HashWriter {
+            state: crate::history::PeregrineDefaultHashBuilder::default().build_hasher(),
+            written: false,
+        };
+        match bincode::serde::encode_into_writer(self.0, &mut writer, bincode::config::standard()) {
+            Ok(()) if writer.written => writer.state.finish(),
+            _ => 0,
+        }
+    }
+}
+
+#[doc(hidden)]
+pub trait HashNothing {
+    fn arguments_hash(&self) -> u64 {
+        0
+    }
+}
+
+impl<T> HashNothing for &ArgumentsHash<'_, T> {}
+
+/// Encodes the arguments of an activity that implements [Serialize], so that its operations
+/// can be [offloaded][crate::offload]. Used like [ArgumentsHash], with [EncodeSerialized] and
+/// [EncodeNothing].
+#[doc(hidden)]
+pub struct ArgumentsBytes<'a, T>(pub &'a T);
+
+#[doc(hidden)]
+pub trait EncodeSerialized {
+    fn encoded_arguments(&self) -> Option<Vec<u8>>;
+}
+
+impl<T: Serialize> EncodeSerialized for ArgumentsBytes<'_, T> {
+    fn encoded_arguments(&self) -> Option<Vec<u8>> {
+        bincode::serde::encode_to_vec(self.0, bincode::config::standard()).ok()
+    }
+}
+
+#[doc(hidden)]
+pub trait EncodeNothing {
+    fn encoded_arguments(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl<T> EncodeNothing for &ArgumentsBytes<'_, T> {}
+
+struct HashWriter<H> {
+    state: H,
+    written: bool,
+}
+
+impl<H: Hasher> bincode::enc::write::Writer for HashWriter<H> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), bincode::error::EncodeError> {
+        self.written |= !bytes.is_empty();
+        self.state.write(bytes);
+        Ok(())
+    }
 }
 
 pub trait ActivityLabel {
     const LABEL: &'static str;
 }
 
+/// An activity owned by a plan.
+///
+/// The operations of an activity borrow it for `'o`, which is longer than the plan can prove
+/// it lives, so it is kept in an [Arc], whose contents don't move when the handle does, and is
+/// freed when the handle is dropped. A `Box` can't hold it, because moving a `Box` asserts that
+/// nothing else borrows its contents, while an [Arc] only ever hands out shared references.
+pub(crate) struct ActivityBox<'o, M: Model<'o>>(Arc<dyn Activity<'o, M> + 'o>);
+
+impl<'o, M: Model<'o>> ActivityBox<'o, M> {
+    pub(crate) fn new(activity: Box<dyn Activity<'o, M> + 'o>) -> Self {
+        ActivityBox(Arc::from(activity))
+    }
+
+    /// Borrows the activity for as long as the operations decomposed from it need it.
+    ///
+    /// # Safety
+    ///
+    /// The operations decomposed from the reference must be gone from the plan's timelines
+    /// before the handle is dropped. If they can't be removed, the handle must be kept or
+    /// [leaked][ActivityBox::leak] instead. Anything else that keeps the operations, like a
+    /// [Recording][crate::exec::Recording], must hold a [shared][ActivityBox::share] handle.
+    pub(crate) unsafe fn borrow_for_operations(&self) -> &'o (dyn Activity<'o, M> + 'o) {
+        unsafe { &*Arc::as_ptr(&self.0) }
+    }
+
+    /// Another handle to the activity, for something other than the plan that keeps operations
+    /// borrowing it, so that it isn't freed while they are still used.
+    pub(crate) fn share(&self) -> Arc<dyn Activity<'o, M> + 'o> {
+        self.0.clone()
+    }
+
+    /// Gives up the activity without freeing it, for when operations that borrow it are left
+    /// in the timelines by a failed edit.
+    pub(crate) fn leak(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl<'o, M: Model<'o>> Deref for ActivityBox<'o, M> {
+    type Target = dyn Activity<'o, M> + 'o;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
 /// A unique activity ID.
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Debug)]
 pub struct ActivityId(u32);
@@ -27,3 +279,24 @@ impl ActivityId {
         ActivityId(id)
     }
 }
+
+impl Display for ActivityId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Descriptive information about an activity instance in a plan. Not used by the engine.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityMetadata {
+    pub name: Option<String>,
+    pub subsystem: Option<String>,
+    pub tags: Vec<String>,
+    pub notes: Option<String>,
+}
+
+impl ActivityMetadata {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
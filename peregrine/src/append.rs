@@ -0,0 +1,90 @@
+//! Building plans in chronological order.
+//!
+//! [Plan::insert] inserts each grounded operation into its timeline's
+//! [BTreeMap][std::collections::BTreeMap] with a search from the root, even when it goes after
+//! everything already there, which is the usual case when a plan is built from a schedule. An
+//! [Append] inserts activities like [Plan::insert], but stages their grounded operations next
+//! to each timeline instead, and merges them into it all at once when it is dropped. Merging
+//! is linear in the size of the timeline, so building a plan in one [Append] costs `O(1)`
+//! amortized per operation.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(appended_count: u32);
+//! # model! { Appended(appended_count) }
+//! # pub struct Increment;
+//! # impl_activity! { for Increment
+//! #     @(start) {
+//! #         ref mut: appended_count += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Appended>(start, initial_conditions! { appended_count: 0 })?;
+//! let mut append = plan.append();
+//! for i in 1..=1000 {
+//!     append.insert(start + Duration::from_seconds(i as f64), Increment)?;
+//! }
+//! drop(append);
+//!
+//! assert_eq!(1000, plan.sample::<appended_count>(start + Duration::from_hours(1.0))?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Activities don't have to be in order, but staged operations are sorted before they are
+//! merged, which is only linear if they mostly are. Once a plan has been viewed, inserting has
+//! to search the timelines to notify the operations it displaces anyway, so an [Append] on a
+//! viewed plan inserts like [Plan::insert].
+
+use crate::activity::{Activity, ActivityId};
+use crate::{EngineError, Model, Plan, Time};
+use std::sync::atomic::Ordering;
+
+/// Inserts activities into a plan in bulk, from [Plan::append]. See [append][crate::append].
+pub struct Append<'p, 'o, M: Model<'o> + 'o> {
+    plan: &'p mut Plan<'o, M>,
+}
+
+impl<'o, M: Model<'o> + 'o> Append<'_, 'o, M> {
+    /// Like [Plan::insert].
+    pub fn insert(
+        &mut self,
+        time: Time,
+        activity: impl Activity<'o, M> + 'static,
+    ) -> Result<ActivityId, EngineError> {
+        self.insert_boxed(time, Box::new(activity))
+    }
+
+    /// Like [Plan::insert_boxed].
+    pub fn insert_boxed(
+        &mut self,
+        time: Time,
+        activity: Box<dyn Activity<'o, M>>,
+    ) -> Result<ActivityId, EngineError> {
+        let decomposition = self.plan.decompose(time, activity)?;
+        // The plan is borrowed mutably.
+        unsafe { self.plan.insert_decomposed(time, decomposition, None) }
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> Drop for Append<'_, 'o, M> {
+    fn drop(&mut self) {
+        self.plan.timelines.finish_append();
+        self.plan.notify_subscriptions();
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Starts inserting activities in bulk, which is faster than [Plan::insert] for activities
+    /// in chronological order. The plan can be used again once the [Append] is dropped. See
+    /// [append][crate::append].
+    pub fn append(&mut self) -> Append<'_, 'o, M> {
+        if !self.has_been_simulated.load(Ordering::Relaxed) {
+            self.timelines.start_append();
+        }
+        Append { plan: self }
+    }
+}
@@ -0,0 +1,77 @@
+#![doc(hidden)]
+
+//! The arenas that plans allocate their operations in.
+
+use bumpalo_herd::{Herd, Member};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A [Herd] that counts the memory it holds, and the nodes of the timelines that point into
+/// it. Each plan has its own, so that [Plan::compact][crate::Plan::compact] can free it.
+#[derive(Debug, Default)]
+pub struct Arena {
+    herd: Herd,
+    bytes: AtomicUsize,
+    nodes: AtomicUsize,
+}
+
+impl Arena {
+    /// Borrows an allocator from the arena, like [Herd::get].
+    pub fn get(&self) -> ArenaMember<'_> {
+        let member = self.herd.get();
+        let start = member.as_bump().allocated_bytes();
+        ArenaMember {
+            member,
+            arena: self,
+            start,
+        }
+    }
+
+    /// The bytes of the chunks that the arena's allocators hold, counted when the members
+    /// that allocated them are dropped.
+    pub fn allocated_bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// The number of operations in the timelines of the plan that uses the arena.
+    pub fn timeline_nodes(&self) -> usize {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_nodes(&self, count: usize) {
+        self.nodes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn remove_nodes(&self, count: usize) {
+        self.nodes.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    /// Forgets the nodes of timelines that were dropped, while a recording may still keep the
+    /// arena alive.
+    pub(crate) fn clear_nodes(&self) {
+        self.nodes.store(0, Ordering::Relaxed);
+    }
+}
+
+/// An allocator borrowed from an [Arena], which adds the chunks it allocated to the arena's
+/// count when dropped.
+pub struct ArenaMember<'a> {
+    member: Member<'a>,
+    arena: &'a Arena,
+    start: usize,
+}
+
+impl<'a> Deref for ArenaMember<'a> {
+    type Target = Member<'a>;
+
+    fn deref(&self) -> &Member<'a> {
+        &self.member
+    }
+}
+
+impl Drop for ArenaMember<'_> {
+    fn drop(&mut self) {
+        let grown = self.member.as_bump().allocated_bytes() - self.start;
+        self.arena.bytes.fetch_add(grown, Ordering::Relaxed);
+    }
+}
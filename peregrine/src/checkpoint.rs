@@ -0,0 +1,184 @@
+//! Views over huge ranges that can be resumed after the process restarts.
+//!
+//! A view of months of a high-fidelity model can run for hours, and if the process dies
+//! halfway, a plain [Plan::view] has nothing to show for it. [Plan::view_checkpointed] views
+//! the window a chunk at a time and hands each chunk to a callback, like one that appends it
+//! to an output file. After each chunk it flushes the history and writes a [Checkpoint] with
+//! the frontier: the time that everything before has been handed over. When the same view is
+//! run again after a restart, it picks up at the frontier instead of the start of the window.
+//!
+//! For the resumed view to skip the work before the frontier, the history needs a
+//! persistent backend, like a [DiskHistory][crate::history::disk::DiskHistory]. The
+//! operations before the frontier are then found in history instead of being simulated
+//! again. The checkpoint file is removed once the last chunk has been handed over.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::history::disk::DiskHistory;
+//! # resource!(checkpointed_count: u32);
+//! # model! { Checkpointed(checkpointed_count) }
+//! # pub struct Tick;
+//! # impl_activity! { for Tick
+//! #     @(start) {
+//! #         ref mut: checkpointed_count += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let directory = std::env::temp_dir().join(format!("peregrine-checkpoint-doc-{}", std::process::id()));
+//! # std::fs::create_dir_all(&directory)?;
+//! # let start = Time::from_tai_seconds(0.0);
+//! let session = Session::from(History::new().with_backend(DiskHistory::open(directory.join("history"))?));
+//! let mut plan = session.new_plan::<Checkpointed>(start, initial_conditions! { checkpointed_count: 0 })?;
+//! for i in 1..=10 {
+//!     plan.insert(start + Duration::from_hours(i as f64), Tick)?;
+//! }
+//!
+//! let mut rows = vec![];
+//! plan.view_checkpointed::<checkpointed_count>(
+//!     start..,
+//!     Duration::from_hours(4.0),
+//!     directory.join("count.checkpoint"),
+//!     |chunk| {
+//!         rows.extend(chunk);
+//!         Ok(())
+//!     },
+//! )?;
+//! assert_eq!(11, rows.len());
+//! assert!(!directory.join("count.checkpoint").exists());
+//! # drop(plan);
+//! # drop(session);
+//! # std::fs::remove_dir_all(directory)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::resource::Resource;
+use crate::{Duration, Model, Plan, Time};
+use anyhow::{Context, Result, bail, ensure};
+use serde::{Deserialize, Serialize};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+/// How far a [Plan::view_checkpointed] has gotten. See [checkpoint][crate::checkpoint].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The label of the viewed resource.
+    pub resource: String,
+    pub window: (Bound<Time>, Bound<Time>),
+    /// The values before this time have been handed over, and their operations are in
+    /// history.
+    pub frontier: Time,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint at `path`, or returns `None` if there isn't one.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("could not read checkpoint {}", path.display()));
+            }
+        };
+        let (checkpoint, _) =
+            bincode::serde::decode_from_slice(&bytes, bincode::config::standard())
+                .with_context(|| format!("invalid checkpoint {}", path.display()))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Writes the checkpoint to `path`. The old checkpoint is replaced all at once, so a crash
+    /// while saving leaves one or the other.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let bytes = bincode::serde::encode_to_vec(self, bincode::config::standard())?;
+        std::fs::write(&temporary, bytes)
+            .and_then(|()| std::fs::rename(&temporary, path))
+            .with_context(|| format!("could not write checkpoint {}", path.display()))
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Views `bounds` of `R` in chunks of `every`, handing each chunk to `consume` and then
+    /// checkpointing to `path`, as described in [checkpoint][crate::checkpoint]. If `path`
+    /// already holds a checkpoint of the same view, the chunks before its frontier are skipped.
+    ///
+    /// Each chunk holds the values that take effect within it, and the first chunk of the
+    /// window also holds the value in effect at its start, like [Plan::view]. An error from a
+    /// chunk's view or from `consume` stops the view without checkpointing that chunk. An
+    /// unbounded window is chunked until the [end][Plan::end] of the plan.
+    pub fn view_checkpointed<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+        every: Duration,
+        path: impl AsRef<Path>,
+        mut consume: impl FnMut(Vec<(Time, R::Read)>) -> Result<()>,
+    ) -> Result<()>
+    where
+        Self: 'o,
+    {
+        if every <= Duration::ZERO {
+            bail!("checkpoints must be a positive duration apart, not {every}");
+        }
+        let path = path.as_ref();
+        let window = (bounds.start_bound().cloned(), bounds.end_bound().cloned());
+
+        let mut from = match Checkpoint::load(path)? {
+            Some(checkpoint) => {
+                ensure!(
+                    checkpoint.resource == R::LABEL && checkpoint.window == window,
+                    "checkpoint {} is of a different view, of {} over {:?}",
+                    path.display(),
+                    checkpoint.resource,
+                    checkpoint.window
+                );
+                Bound::Included(checkpoint.frontier)
+            }
+            None => window.0,
+        };
+        let last = match window.1 {
+            Bound::Included(time) | Bound::Excluded(time) => time,
+            Bound::Unbounded => self.end(),
+        };
+
+        loop {
+            let chunk_start = match from {
+                Bound::Included(time) | Bound::Excluded(time) => time,
+                Bound::Unbounded => self.start,
+            };
+            let frontier = chunk_start + every;
+            let to = if frontier >= last {
+                window.1
+            } else {
+                Bound::Excluded(frontier)
+            };
+
+            let mut values = self.view::<R>((from, to))?;
+            if from != window.0 {
+                // The value in effect at the start was in the previous chunk.
+                values.retain(|(time, _)| *time >= chunk_start);
+            }
+            consume(values)?;
+
+            if to == window.1 {
+                return match std::fs::remove_file(path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)
+                        .with_context(|| format!("could not remove checkpoint {}", path.display())),
+                    _ => Ok(()),
+                };
+            }
+            self.session.history().flush();
+            Checkpoint {
+                resource: R::LABEL.to_string(),
+                window,
+                frontier,
+            }
+            .save(path)?;
+            from = Bound::Included(frontier);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! A command line runner for batch simulations, so that a model crate doesn't have to write
+//! its own `main`.
+//!
+//! [cli_main!][crate::cli_main!] generates a `main` that loads the initial conditions and a
+//! [plan document][crate::plan_file] of [registered][crate::registry] activities, views the
+//! requested resources, and writes them as CSV or JSON:
+//!
+//! ```no_run
+//! # use peregrine::*;
+//! resource!(cli_battery: f64);
+//! model! { Rover(cli_battery) }
+//!
+//! peregrine::cli_main!(Rover);
+//! ```
+//!
+//! ```text
+//! rover --start "2030-01-01T00:00:00 UTC" --initial-conditions ic.toml --plan plan.json \
+//!     --resource cli_battery --to "2030-01-02T00:00:00 UTC" --output battery.csv
+//! ```
+//!
+//! Every resource is written if none are requested. The CSV has a `resource,time,value` row
+//! for each value, with values as JSON; the JSON output is a list of resources, each with its
+//! label, unit, and points. Times are written in UTC. `--history` loads a
+//! [portable][crate::history::portable] history file before simulating, if it exists, and
+//! writes the history back to it afterwards, so that reruns only simulate what changed.
+//!
+//! With the `repl` feature, `--interactive` opens a [prompt][crate::repl] on the loaded plan
+//! instead of writing the resources.
+
+use crate::commands::iso_utc;
+use crate::history::History;
+use crate::labeled::LabeledView;
+use crate::{InitialConditions, Model, Session, Time};
+use anyhow::{Context, Result, anyhow, bail};
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+/// Generates a `main` function that runs the [command line runner][crate::cli] for a model.
+#[macro_export]
+macro_rules! cli_main {
+    ($model:ty) => {
+        fn main() -> std::process::ExitCode {
+            $crate::cli::main::<$model>()
+        }
+    };
+}
+
+const USAGE: &str = "\
+usage: [options] --start <time>
+
+options:
+    --start <time>                   the start of the plan, as a hifitime epoch
+    --initial-conditions <file>      initial conditions, as .json or .toml
+    --plan <file>                    a plan document, as .json or .toml
+    --resource <label>               a resource to write, can be repeated (default: all)
+    --from <time>                    the start of the window to write (default: --start)
+    --to <time>                      the end of the window to write (default: unbounded)
+    --output <file>                  where to write the results (default: stdout)
+    --format <csv|json>              the output format (default: from --output, or csv)
+    --threads <count>                the number of simulation threads (default: all cores)
+    --history <file>                 a portable history file to reuse and update
+    --interactive                    edit and sample the plan from a prompt
+    --help                           print this message";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// The command line arguments of the runner.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub start: Time,
+    pub initial_conditions: Option<PathBuf>,
+    pub plan: Option<PathBuf>,
+    pub resources: Vec<String>,
+    pub from: Option<Time>,
+    pub to: Option<Time>,
+    pub output: Option<PathBuf>,
+    pub format: Format,
+    pub threads: Option<usize>,
+    pub history: Option<PathBuf>,
+    /// Opens a [prompt][crate::repl] instead of writing the resources. Needs the `repl`
+    /// feature.
+    pub interactive: bool,
+}
+
+impl Options {
+    /// Parses arguments, not including the program name. Returns `None` if `--help` was
+    /// given.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>> {
+        let mut start = None;
+        let mut initial_conditions = None;
+        let mut plan = None;
+        let mut resources = vec![];
+        let mut from = None;
+        let mut to = None;
+        let mut output: Option<PathBuf> = None;
+        let mut format = None;
+        let mut threads = None;
+        let mut history = None;
+        let mut interactive = false;
+
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Ok(None);
+            }
+            if flag == "--interactive" {
+                if !cfg!(feature = "repl") {
+                    bail!("--interactive requires the `repl` feature");
+                }
+                interactive = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{flag} expects a value"))?;
+            match flag.as_str() {
+                "--start" => start = Some(parse_time(&value)?),
+                "--initial-conditions" => initial_conditions = Some(value.into()),
+                "--plan" => plan = Some(value.into()),
+                "--resource" => resources.push(value),
+                "--from" => from = Some(parse_time(&value)?),
+                "--to" => to = Some(parse_time(&value)?),
+                "--output" => output = Some(value.into()),
+                "--format" => {
+                    format = Some(match value.as_str() {
+                        "csv" => Format::Csv,
+                        "json" => Format::Json,
+                        _ => bail!("unknown format {value:?}, expected csv or json"),
+                    })
+                }
+                "--threads" => {
+                    threads = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("invalid thread count {value:?}"))?,
+                    )
+                }
+                "--history" => history = Some(value.into()),
+                _ => bail!("unknown option {flag}"),
+            }
+        }
+
+        let format = format.unwrap_or_else(|| match &output {
+            Some(path) if has_extension(path, "json") => Format::Json,
+            _ => Format::Csv,
+        });
+        Ok(Some(Options {
+            start: start.ok_or_else(|| anyhow!("--start is required"))?,
+            initial_conditions,
+            plan,
+            resources,
+            from,
+            to,
+            output,
+            format,
+            threads,
+            history,
+            interactive,
+        }))
+    }
+}
+
+/// Parses the process arguments and runs the simulation, printing errors to stderr. Used by
+/// [cli_main!][crate::cli_main!].
+pub fn main<M: for<'o> Model<'o> + 'static>() -> ExitCode {
+    let result = Options::parse(std::env::args().skip(1)).and_then(|options| match options {
+        Some(options) => run::<M>(&options),
+        None => {
+            println!("{USAGE}");
+            Ok(())
+        }
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs a simulation as described by `options`.
+pub fn run<M: for<'o> Model<'o> + 'static>(options: &Options) -> Result<()> {
+    match options.threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()?
+            .install(|| simulate::<M>(options)),
+        None => simulate::<M>(options),
+    }
+}
+
+fn simulate<M: for<'o> Model<'o> + 'static>(options: &Options) -> Result<()> {
+    let session = match &options.history {
+        Some(path) if path.exists() => {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("could not read history {}", path.display()))?;
+            Session::from(
+                History::import_portable(&bytes)
+                    .with_context(|| format!("in history {}", path.display()))?,
+            )
+        }
+        _ => Session::new(),
+    };
+
+    let initial_conditions = match &options.initial_conditions {
+        Some(path) => read_initial_conditions(path)?,
+        None => InitialConditions::new(),
+    };
+    let mut plan = session.new_plan::<M>(options.start, initial_conditions)?;
+    if let Some(path) = &options.plan {
+        plan.load(path)?;
+    }
+
+    #[cfg(feature = "repl")]
+    if options.interactive {
+        let stdin = std::io::stdin();
+        crate::repl::run(&mut plan, stdin.lock(), std::io::stdout())?;
+        drop(plan);
+        return write_history(&session, options);
+    }
+
+    let labels: Vec<String> = if options.resources.is_empty() {
+        M::schema()
+            .resources
+            .iter()
+            .map(|resource| resource.label.to_string())
+            .collect()
+    } else {
+        options.resources.clone()
+    };
+    let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+    let from = Bound::Included(options.from.unwrap_or(options.start));
+    let to = options.to.map_or(Bound::Unbounded, Bound::Included);
+    let views = plan.view_labeled(&labels, (from, to))?;
+
+    let mut text = vec![];
+    match options.format {
+        Format::Csv => write_csv(&views, &mut text)?,
+        Format::Json => write_json(&views, &mut text)?,
+    }
+    match &options.output {
+        Some(path) => std::fs::write(path, text)
+            .with_context(|| format!("could not write {}", path.display()))?,
+        None => std::io::stdout().write_all(&text)?,
+    }
+
+    drop(plan);
+    write_history(&session, options)
+}
+
+fn write_history(session: &Session, options: &Options) -> Result<()> {
+    if let Some(path) = &options.history {
+        std::fs::write(path, session.history().export_portable()?)
+            .with_context(|| format!("could not write history {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn read_initial_conditions(path: &Path) -> Result<InitialConditions> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read initial conditions {}", path.display()))?;
+    let initial_conditions = if has_extension(path, "toml") {
+        #[cfg(feature = "toml")]
+        {
+            InitialConditions::from_toml(&text)
+        }
+        #[cfg(not(feature = "toml"))]
+        {
+            Err(anyhow!(
+                "reading TOML initial conditions requires the `toml` feature"
+            ))
+        }
+    } else {
+        InitialConditions::from_json(&text)
+    };
+    initial_conditions.with_context(|| format!("in initial conditions {}", path.display()))
+}
+
+/// Writes a `resource,time,value` row for every value, with the values as JSON.
+pub fn write_csv(views: &[LabeledView], out: &mut impl Write) -> Result<()> {
+    writeln!(out, "resource,time,value")?;
+    for view in views {
+        for (time, value) in view.times().into_iter().zip(view.json_values()?) {
+            writeln!(
+                out,
+                "{},{},{}",
+                csv_field(view.label),
+                iso_utc(time),
+                csv_field(&value.to_string())
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a JSON list of resources, each with its label, unit, and points.
+pub fn write_json(views: &[LabeledView], out: &mut impl Write) -> Result<()> {
+    let mut resources = vec![];
+    for view in views {
+        let points: Vec<_> = view
+            .times()
+            .into_iter()
+            .zip(view.json_values()?)
+            .map(|(time, value)| serde_json::json!({ "time": iso_utc(time), "value": value }))
+            .collect();
+        resources.push(serde_json::json!({
+            "label": view.label,
+            "unit": view.unit,
+            "points": points,
+        }));
+    }
+    serde_json::to_writer_pretty(&mut *out, &resources)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|e| e == extension)
+}
+
+fn parse_time(value: &str) -> Result<Time> {
+    Time::from_str(value).map_err(|e| anyhow!("invalid time {value:?}: {e}"))
+}
@@ -0,0 +1,210 @@
+//! Command sequences exported from plans, for uplink tooling.
+//!
+//! Each activity in a plan is exported as one [Command] at its start time, named by the
+//! activity's label, with the arguments from the `arguments` block of its
+//! [impl_activity][crate::impl_activity] body. [Plan::commands][crate::Plan::commands] lists them in time order, and a
+//! [CommandFormatter] writes them out in whatever format the ground system reads. Two are
+//! provided: [CcsdsCommands], a time-tagged text sequence, and `JsonCommands` with the `json`
+//! feature.
+//!
+//! Activities that only exist for modeling can be filtered out of the list before it is
+//! formatted, for example by [tag][ActivityMetadata::tags].
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::commands::{CcsdsCommands, CommandFormatter};
+//! # resource!(heater_setpoint: f64);
+//! # model! { HeaterControl(heater_setpoint) }
+//! pub struct SetHeater {
+//!     zone: u8,
+//!     setpoint: f64,
+//! }
+//!
+//! impl_activity! { for SetHeater
+//!     arguments {
+//!         zone: self.zone,
+//!         setpoint: self.setpoint,
+//!     }
+//!     @(start) {
+//!         ref mut: heater_setpoint = self.setpoint;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! let start = Time::from_gregorian_utc_at_midnight(2030, 2, 1);
+//! let mut plan = session.new_plan::<HeaterControl>(start, initial_conditions! { heater_setpoint: 0.0 })?;
+//! plan.insert(start + Duration::from_seconds(90.0), SetHeater { zone: 2, setpoint: 21.5 })?;
+//!
+//! let sequence = CcsdsCommands::new().format(&plan.commands(start..));
+//! assert_eq!("2030-032T00:01:30.000Z SET_HEATER ZONE=2, SETPOINT=21.5\n", sequence);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::activity::{ActivityId, ActivityMetadata};
+use crate::{Duration, Time};
+use hifitime::TimeScale;
+use hifitime::efmt::{Format, Formatter};
+use std::io;
+use std::str::FromStr;
+
+/// An activity, exported as a command. Returned by [Plan::commands][crate::Plan::commands].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    pub time: Time,
+    /// The label of the activity.
+    pub mnemonic: &'static str,
+    /// The arguments from the activity's `arguments` block, in the order they were declared.
+    pub arguments: Vec<(&'static str, String)>,
+    pub activity: ActivityId,
+    pub key: Option<String>,
+    pub duration: Duration,
+    pub metadata: ActivityMetadata,
+}
+
+/// Writes a command sequence in some format.
+pub trait CommandFormatter {
+    /// Writes the commands, which are ordered by time.
+    fn write(&self, commands: &[Command], out: &mut dyn io::Write) -> io::Result<()>;
+
+    /// Formats the commands as a string.
+    fn format(&self, commands: &[Command]) -> String {
+        let mut out = vec![];
+        self.write(commands, &mut out)
+            .expect("writing to a Vec shouldn't fail");
+        String::from_utf8(out).expect("command sequences should be UTF-8")
+    }
+}
+
+/// A time-tagged text sequence, with one command on each line.
+///
+/// Times are UTC in the CCSDS ASCII day-of-year format (`YYYY-DDDThh:mm:ss.sssZ`), followed by the
+/// mnemonic and the comma-separated arguments. Mnemonics and argument names are converted to
+/// upper snake case, so `SetHeater` becomes `SET_HEATER`. Argument values that contain anything
+/// other than letters, digits, `.`, `+`, `-`, and `_` are quoted.
+#[derive(Clone, Debug, Default)]
+pub struct CcsdsCommands {
+    header: Vec<String>,
+}
+
+impl CcsdsCommands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a comment line to the start of the sequence, like the sequence name or the plan
+    /// it came from.
+    pub fn with_header(mut self, line: impl Into<String>) -> Self {
+        self.header.push(line.into());
+        self
+    }
+}
+
+impl CommandFormatter for CcsdsCommands {
+    fn write(&self, commands: &[Command], out: &mut dyn io::Write) -> io::Result<()> {
+        for line in &self.header {
+            writeln!(out, "; {line}")?;
+        }
+        for command in commands {
+            write!(
+                out,
+                "{} {}",
+                day_of_year(command.time),
+                upper_snake_case(command.mnemonic)
+            )?;
+            for (i, (name, value)) in command.arguments.iter().enumerate() {
+                let separator = if i == 0 { " " } else { ", " };
+                write!(out, "{separator}{}=", upper_snake_case(name))?;
+                if value.is_empty()
+                    || !value
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || ".+-_".contains(c))
+                {
+                    write!(out, "{value:?}")?;
+                } else {
+                    write!(out, "{value}")?;
+                }
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// A JSON object with a `commands` array, where each command has a UTC `time`, `mnemonic`,
+/// `arguments` object, `activity` ID, and `key`, `duration` in seconds, and `metadata`.
+#[cfg(feature = "json")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct JsonCommands {
+    pub pretty: bool,
+}
+
+#[cfg(feature = "json")]
+impl CommandFormatter for JsonCommands {
+    fn write(&self, commands: &[Command], out: &mut dyn io::Write) -> io::Result<()> {
+        let commands: Vec<_> = commands
+            .iter()
+            .map(|command| {
+                let arguments: serde_json::Map<_, _> = command
+                    .arguments
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone().into()))
+                    .collect();
+                serde_json::json!({
+                    "time": iso_utc(command.time),
+                    "mnemonic": command.mnemonic,
+                    "arguments": arguments,
+                    "activity": command.activity,
+                    "key": command.key,
+                    "duration": command.duration.to_seconds(),
+                    "metadata": command.metadata,
+                })
+            })
+            .collect();
+        let sequence = serde_json::json!({ "commands": commands });
+        if self.pretty {
+            serde_json::to_writer_pretty(&mut *out, &sequence)?;
+        } else {
+            serde_json::to_writer(&mut *out, &sequence)?;
+        }
+        writeln!(out)
+    }
+}
+
+fn format_utc(time: Time, format: &str) -> String {
+    let format = Format::from_str(format).expect("the format should be valid");
+    format!("{}", Formatter::to_time_scale(time, format, TimeScale::UTC))
+}
+
+/// Formats a time like `2030-032T00:01:30.000Z`.
+fn day_of_year(time: Time) -> String {
+    let mut formatted = format_utc(time, "%Y-%jT%H:%M:%S.%f");
+    // Milliseconds are enough for command timing.
+    formatted.truncate(formatted.len() - 6);
+    formatted + "Z"
+}
+
+/// Formats a time like `2030-02-01T00:01:30.000000000Z`.
+#[cfg(feature = "json")]
+pub(crate) fn iso_utc(time: Time) -> String {
+    format_utc(time, "%Y-%m-%dT%H:%M:%S.%f") + "Z"
+}
+
+/// Converts an activity path like `power::SetHeater` or a name like `setpoint` to `SET_HEATER` or
+/// `SETPOINT`.
+fn upper_snake_case(name: &str) -> String {
+    let name = name.rsplit("::").next().unwrap_or(name);
+    let name = name.split('<').next().unwrap_or(name);
+    let mut result = String::with_capacity(name.len() + 4);
+    let mut previous_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && previous_lower {
+            result.push('_');
+        }
+        previous_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        result.push(c.to_ascii_uppercase());
+    }
+    result
+}
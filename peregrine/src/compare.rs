@@ -0,0 +1,272 @@
+//! Where and by how much two profiles of a resource differ.
+//!
+//! [Plan::compare] views a resource in two plans, and returns the windows where the values
+//! differ. To see what an edit did to a plan, capture a view in a [snapshot][crate::PlanSnapshot]
+//! before the edit, and [diff] it against a view after. A [ComparisonReport] does the
+//! same for a list of resources, and prints a summary for reviewers.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::compare::ComparisonReport;
+//! # resource!(compared_battery: f64);
+//! # resource!(compared_data: u32);
+//! # model! { Compared(compared_battery, compared_data) }
+//! pub struct Heat;
+//! impl_activity! { for Heat
+//!     @(start) {
+//!         ref mut: compared_battery -= 10.0;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let hours = |h: f64| start + Duration::from_hours(h);
+//! let initial_conditions = || initial_conditions! { compared_battery: 100.0, compared_data: 0 };
+//! let mut before = session.new_plan::<Compared>(start, initial_conditions())?;
+//! let mut after = session.new_plan::<Compared>(start, initial_conditions())?;
+//! before.insert(hours(2.0), Heat)?;
+//! after.insert(hours(1.0), Heat)?;
+//!
+//! let differences = after.compare::<compared_battery>(&before, start..hours(3.0))?;
+//! assert_eq!(1, differences.len());
+//! assert_eq!(-10.0, differences[0].amount());
+//!
+//! let report = ComparisonReport::new()
+//!     .compare::<compared_battery>(&before, &after, start..hours(3.0))?
+//!     .compare::<compared_data>(&before, &after, start..hours(3.0))?;
+//! assert!(!report.is_empty());
+//! assert_eq!(10.0, report.resources()[0].max_amount());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::resource::Resource;
+use crate::subscription::TimeRange;
+use crate::{EngineError, Model, Plan, PlanAccess, Time};
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+/// A window where two profiles of a resource have different values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference<T> {
+    pub range: TimeRange,
+    pub before: T,
+    pub after: T,
+}
+
+impl<T: Copy + Into<f64>> Difference<T> {
+    /// How much the value went up from `before` to `after`.
+    pub fn amount(&self) -> f64 {
+        self.after.into() - self.before.into()
+    }
+}
+
+/// The windows within `window` where two views of a resource differ, ordered by time.
+///
+/// The views are ordered or not, like those returned by [Plan::view]. Neighboring windows with
+/// the same values on both sides are merged.
+pub fn diff<T: Copy + PartialEq>(
+    before: &[(Time, T)],
+    after: &[(Time, T)],
+    window: impl RangeBounds<Time>,
+) -> Vec<Difference<T>> {
+    let window: TimeRange = (window.start_bound().cloned(), window.end_bound().cloned());
+    let mut before = before.to_vec();
+    let mut after = after.to_vec();
+    before.sort_by_key(|(time, _)| *time);
+    after.sort_by_key(|(time, _)| *time);
+
+    let mut edges: Vec<Time> = before
+        .iter()
+        .chain(&after)
+        .map(|(time, _)| *time)
+        .filter(|time| window.contains(time))
+        .collect();
+    // Values from before the window start it.
+    match window.0 {
+        Bound::Included(start) | Bound::Excluded(start) => edges.push(start),
+        Bound::Unbounded => {}
+    }
+    edges.sort();
+    edges.dedup();
+
+    let value_at = |view: &[(Time, T)], time: Time| {
+        let index = view.partition_point(|(t, _)| *t <= time);
+        index.checked_sub(1).map(|i| view[i].1)
+    };
+
+    let mut differences: Vec<Difference<T>> = vec![];
+    for (i, &edge) in edges.iter().enumerate() {
+        let (Some(before), Some(after)) = (value_at(&before, edge), value_at(&after, edge)) else {
+            continue;
+        };
+        let start = match window.0 {
+            Bound::Included(_) | Bound::Excluded(_) if i == 0 => window.0,
+            _ => Bound::Included(edge),
+        };
+        let end = edges
+            .get(i + 1)
+            .map_or(window.1, |next| Bound::Excluded(*next));
+        if before == after {
+            continue;
+        }
+        match differences.last_mut() {
+            Some(last)
+                if last.before == before
+                    && last.after == after
+                    && matches!(last.range.1, Bound::Excluded(t) if t == edge) =>
+            {
+                last.range.1 = end;
+            }
+            _ => differences.push(Difference {
+                range: (start, end),
+                before,
+                after,
+            }),
+        }
+    }
+    differences
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// The windows within `bounds` where a resource in `other` differs from this plan, where
+    /// `other` is `before`. See [compare][crate::compare].
+    pub fn compare<R: Resource<'o> + 'o>(
+        &self,
+        other: &impl PlanAccess<'o>,
+        bounds: impl RangeBounds<Time> + Clone,
+    ) -> Result<Vec<Difference<R::Read>>, EngineError>
+    where
+        R::Read: PartialEq,
+    {
+        let before = other.view_resource::<R>(bounds.clone())?;
+        let after = self.view::<R>(bounds.clone())?;
+        Ok(diff(&before, &after, bounds))
+    }
+}
+
+/// The differences of one resource in a [ComparisonReport].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourceDifferences {
+    pub resource: &'static str,
+    pub differences: Vec<Difference<f64>>,
+}
+
+impl ResourceDifferences {
+    /// The largest absolute [amount][Difference::amount] of any difference, or 0 if there
+    /// are none.
+    pub fn max_amount(&self) -> f64 {
+        self.differences
+            .iter()
+            .map(|difference| difference.amount().abs())
+            .fold(0.0, f64::max)
+    }
+}
+
+/// The differences of several resources between two plans. See [compare][crate::compare].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ComparisonReport {
+    resources: Vec<ResourceDifferences>,
+}
+
+impl ComparisonReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the differences of a resource between two plans.
+    pub fn compare<'o, R: Resource<'o> + 'o>(
+        self,
+        before: &impl PlanAccess<'o>,
+        after: &impl PlanAccess<'o>,
+        bounds: impl RangeBounds<Time> + Clone,
+    ) -> Result<Self, EngineError>
+    where
+        R::Read: Into<f64>,
+    {
+        let before = before.view_resource::<R>(bounds.clone())?;
+        let after = after.view_resource::<R>(bounds.clone())?;
+        Ok(self.compare_views::<R>(&before, &after, bounds))
+    }
+
+    /// Adds the differences of a resource between two views, like one captured before an edit
+    /// and one after.
+    pub fn compare_views<'o, R: Resource<'o>>(
+        mut self,
+        before: &[(Time, R::Read)],
+        after: &[(Time, R::Read)],
+        window: impl RangeBounds<Time>,
+    ) -> Self
+    where
+        R::Read: Into<f64>,
+    {
+        let to_f64 = |view: &[(Time, R::Read)]| -> Vec<(Time, f64)> {
+            view.iter()
+                .map(|(time, value)| (*time, (*value).into()))
+                .collect()
+        };
+        self.resources.push(ResourceDifferences {
+            resource: R::LABEL,
+            differences: diff(&to_f64(before), &to_f64(after), window),
+        });
+        self
+    }
+
+    /// The compared resources, in the order they were added.
+    pub fn resources(&self) -> &[ResourceDifferences] {
+        &self.resources
+    }
+
+    /// Whether the plans are the same in every compared resource.
+    pub fn is_empty(&self) -> bool {
+        self.resources.iter().all(|r| r.differences.is_empty())
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for resource in &self.resources {
+            if resource.differences.is_empty() {
+                writeln!(f, "{}: no differences", resource.resource)?;
+                continue;
+            }
+            writeln!(
+                f,
+                "{}: {} windows differ, by up to {}",
+                resource.resource,
+                resource.differences.len(),
+                resource.max_amount()
+            )?;
+            for difference in &resource.differences {
+                writeln!(
+                    f,
+                    "  {}: {} -> {} ({:+})",
+                    RangeDisplay(difference.range),
+                    difference.before,
+                    difference.after,
+                    difference.amount()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct RangeDisplay(pub(crate) TimeRange);
+
+impl fmt::Display for RangeDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.0 {
+            Bound::Included(t) => write!(f, "[{t}")?,
+            Bound::Excluded(t) => write!(f, "({t}")?,
+            Bound::Unbounded => write!(f, "(..")?,
+        }
+        match self.0.1 {
+            Bound::Included(t) => write!(f, ", {t}]"),
+            Bound::Excluded(t) => write!(f, ", {t})"),
+            Bound::Unbounded => write!(f, ", ..)"),
+        }
+    }
+}
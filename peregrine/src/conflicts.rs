@@ -0,0 +1,141 @@
+//! Activities that might overwrite each other's writes.
+//!
+//! Operations at the same time are ordered arbitrarily, and so are operations whose times
+//! depend on the simulation and might overlap. That is fine when they all read the resource
+//! they write (like `ref mut: battery -= 10.0`), because each one builds on the other, but when
+//! one of them only writes it (like `mut: mode = Mode::Off`), whatever the other wrote is lost,
+//! or not, depending on the order. [Plan::write_conflicts] finds these pairs without
+//! simulating, so that they can be reviewed.
+//!
+//! The keys of [keyed resources][crate::resource::KeyedResource] aren't distinguished, so writes
+//! to different keys at the same time are also reported.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(conflicted_mode: u32);
+//! # resource!(conflicted_count: u32);
+//! # model! { Conflicted(conflicted_mode, conflicted_count) }
+//! pub struct SetMode(u32);
+//! impl_activity! { for SetMode
+//!     @(start) {
+//!         mut: conflicted_mode = self.0;
+//!         ref mut: conflicted_count += 1;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Conflicted>(start, initial_conditions! { conflicted_mode: 0, conflicted_count: 0 })?;
+//! let first = plan.insert(start + Duration::from_seconds(1.0), SetMode(1))?;
+//! let second = plan.insert(start + Duration::from_seconds(1.0), SetMode(2))?;
+//! plan.insert(start + Duration::from_seconds(2.0), SetMode(3))?;
+//!
+//! // Only the mode, since both activities read the count.
+//! let conflicts = plan.write_conflicts();
+//! assert_eq!(1, conflicts.len());
+//! assert_eq!("conflicted_mode", conflicts[0].resource);
+//! assert_eq!([first, second], [conflicts[0].first.activity_id, conflicts[0].second.activity_id]);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::activity::ActivityId;
+use crate::{Model, Plan, Time};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// An operation in a [WriteConflict].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConflictingOp {
+    pub activity: &'static str,
+    pub activity_id: ActivityId,
+    /// The index of the operation among the operations of its activity.
+    pub op_index: usize,
+    /// The earliest time the operation can happen.
+    pub min_time: Time,
+    pub max_time: Time,
+    /// Whether the operation reads the resource before writing it.
+    pub reads: bool,
+}
+
+/// Two operations from different activities that write a resource at times that might be the
+/// same, where at least one of them doesn't read it first. See [conflicts][crate::conflicts].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WriteConflict {
+    pub resource: &'static str,
+    /// The operation that can happen first, or the one from the earlier activity if they start
+    /// at the same time.
+    pub first: ConflictingOp,
+    pub second: ConflictingOp,
+}
+
+impl Display for WriteConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is written by activity {} ({:?}, operation {}) at {} and activity {} ({:?}, operation {}) at {}",
+            self.resource,
+            self.first.activity,
+            self.first.activity_id,
+            self.first.op_index,
+            self.first.min_time,
+            self.second.activity,
+            self.second.activity_id,
+            self.second.op_index,
+            self.second.min_time,
+        )
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Every pair of operations that might overwrite each other's writes, ordered by resource
+    /// and then by time. See [conflicts][crate::conflicts].
+    pub fn write_conflicts(&self) -> Vec<WriteConflict> {
+        let mut writers: HashMap<&'static str, Vec<ConflictingOp>> = HashMap::new();
+        for (id, decomposed) in self.activities.iter() {
+            for (index, op) in decomposed.operations.iter().enumerate() {
+                let info = op.info();
+                for resource in info.writes {
+                    writers.entry(resource).or_default().push(ConflictingOp {
+                        activity: info.activity,
+                        activity_id: *id,
+                        op_index: index,
+                        min_time: info.min_time,
+                        max_time: info.max_time,
+                        reads: info.reads.contains(resource),
+                    });
+                }
+            }
+        }
+
+        let mut conflicts = vec![];
+        for (resource, mut ops) in writers {
+            ops.sort_by_key(|op| (op.min_time, op.activity_id, op.op_index));
+            for (i, first) in ops.iter().enumerate() {
+                for second in ops[i + 1..]
+                    .iter()
+                    .take_while(|second| second.min_time <= first.max_time)
+                {
+                    if first.activity_id != second.activity_id && !(first.reads && second.reads) {
+                        conflicts.push(WriteConflict {
+                            resource,
+                            first: *first,
+                            second: *second,
+                        });
+                    }
+                }
+            }
+        }
+        conflicts.sort_by_key(|conflict| {
+            (
+                conflict.resource,
+                conflict.first.min_time,
+                conflict.first.activity_id,
+                conflict.second.activity_id,
+            )
+        });
+        conflicts
+    }
+}
@@ -0,0 +1,256 @@
+//! Conditions that a resource must satisfy, rechecked incrementally after edits.
+//!
+//! [Plan::add_constraint] adds a predicate that every value of a resource in a window must
+//! satisfy, and [Plan::violations] returns the windows where it doesn't. Each constraint
+//! caches the verdict of the predicate for every value it checked. After an edit, only the
+//! values in the ranges the edit made stale (see [Plan::dirty_ranges]) are checked again; the
+//! rest came from operations that are still cached, so their verdicts still hold. A constraint
+//! that no edit has touched since it was last checked returns its cached violations without
+//! viewing anything.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use std::ops::Bound;
+//! # resource!(constrained_battery: f64);
+//! # model! { Constrained(constrained_battery) }
+//! pub struct Drain;
+//! impl_activity! { for Drain
+//!     @(start) {
+//!         ref mut: constrained_battery -= 40.0;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let hours = |h: f64| start + Duration::from_hours(h);
+//! let mut plan = session.new_plan::<Constrained>(start, initial_conditions! { constrained_battery: 100.0 })?;
+//! plan.add_constraint::<constrained_battery>("battery never empty", start.., |charge| charge >= 0.0);
+//! plan.insert(hours(1.0), Drain)?;
+//! plan.insert(hours(2.0), Drain)?;
+//! assert!(plan.violations()?.is_empty());
+//!
+//! let third = plan.insert(hours(3.0), Drain)?;
+//! let violations = plan.violations()?;
+//! assert_eq!(1, violations.len());
+//! assert_eq!("battery never empty", violations[0].name);
+//! assert_eq!((Bound::Included(hours(3.0)), Bound::Unbounded), violations[0].range);
+//!
+//! plan.remove(third)?;
+//! assert!(plan.violations()?.is_empty());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::resource::Resource;
+use crate::subscription::{TimeRange, end_key, intersect, start_key};
+use crate::{EngineError, Model, OpInfo, Plan, Time};
+use std::ops::{Bound, RangeBounds};
+
+/// Identifies a constraint, to remove it with [Plan::remove_constraint].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConstraintId(pub(crate) u32);
+
+/// A window where a constraint's predicate is false. Returned by [Plan::violations].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub constraint: ConstraintId,
+    pub name: String,
+    /// The resource the constraint is on.
+    pub resource: &'static str,
+    pub range: TimeRange,
+}
+
+pub(crate) trait ErasedConstraint<'o, M: Model<'o>>: Send {
+    fn id(&self) -> ConstraintId;
+    /// Adds the ranges that an edit made stale to the ranges that need to be checked again.
+    fn invalidate(&mut self, plan: &Plan<'o, M>);
+    /// Notes that an operation is being removed from the plan.
+    fn removed(&mut self, op: &OpInfo);
+    /// Checks the values in the ranges that need it, and returns all violations.
+    fn check(&mut self, plan: &Plan<'o, M>) -> Result<Vec<Violation>, EngineError>;
+}
+
+pub(crate) struct Constraint<'o, R: Resource<'o>> {
+    pub(crate) id: ConstraintId,
+    pub(crate) name: String,
+    pub(crate) window: TimeRange,
+    /// The ranges that edits made stale since the last check, ordered and disjoint.
+    pub(crate) unchecked: Vec<TimeRange>,
+    /// Whether an operation that writes the resource was removed since the last check. Its
+    /// value is just gone, so it doesn't leave a stale range behind if nothing after it
+    /// changed.
+    pub(crate) removed: bool,
+    /// The time of every value in the window at the last check, including the one from before
+    /// its start, and whether the predicate held for it. Ordered by time.
+    pub(crate) verdicts: Vec<(Time, bool)>,
+    pub(crate) violations: Vec<TimeRange>,
+    pub(crate) predicate: Box<dyn Fn(R::Read) -> bool + Send>,
+}
+
+impl<'o, R: Resource<'o> + 'o, M: Model<'o> + 'o> ErasedConstraint<'o, M> for Constraint<'o, R> {
+    fn id(&self) -> ConstraintId {
+        self.id
+    }
+
+    fn invalidate(&mut self, plan: &Plan<'o, M>) {
+        let stale = plan.stale_ranges::<R>(R::ID, self.window);
+        if !stale.is_empty() {
+            self.unchecked.extend(stale);
+            self.unchecked = merge(std::mem::take(&mut self.unchecked));
+        }
+    }
+
+    fn removed(&mut self, op: &OpInfo) {
+        self.removed |= op.writes.contains(&R::LABEL);
+    }
+
+    fn check(&mut self, plan: &Plan<'o, M>) -> Result<Vec<Violation>, EngineError> {
+        self.invalidate(plan);
+        if self.removed || !self.unchecked.is_empty() {
+            let mut values = plan.view::<R>(self.window)?;
+            values.sort_by_key(|(time, _)| *time);
+
+            // A value from before the window changed if the stale range it starts is clipped
+            // to the window's start.
+            let before_window_stale = self
+                .unchecked
+                .first()
+                .is_some_and(|range| range.0 == self.window.0);
+            let stale = |time: &Time| {
+                if self.window.contains(time) {
+                    self.unchecked.iter().any(|range| range.contains(time))
+                } else {
+                    before_window_stale
+                }
+            };
+
+            let mut previous = self.verdicts.iter().peekable();
+            let verdicts: Vec<(Time, bool)> = values
+                .iter()
+                .map(|(time, value)| {
+                    while previous.next_if(|(t, _)| t < time).is_some() {}
+                    let cached = previous.next_if(|(t, _)| t == time);
+                    let holds = match cached {
+                        Some((_, holds)) if !stale(time) => *holds,
+                        _ => (self.predicate)(*value),
+                    };
+                    (*time, holds)
+                })
+                .collect();
+
+            self.violations = violation_windows(&verdicts, self.window);
+            self.verdicts = verdicts;
+            self.unchecked.clear();
+            self.removed = false;
+        }
+        Ok(self
+            .violations
+            .iter()
+            .map(|range| Violation {
+                constraint: self.id,
+                name: self.name.clone(),
+                resource: R::LABEL,
+                range: *range,
+            })
+            .collect())
+    }
+}
+
+/// The windows where the predicate is false, given every value's verdict in order.
+fn violation_windows(verdicts: &[(Time, bool)], window: TimeRange) -> Vec<TimeRange> {
+    let violations = verdicts
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, holds))| !holds)
+        .filter_map(|(i, (time, _))| {
+            let start = if window.contains(time) {
+                Bound::Included(*time)
+            } else {
+                window.0
+            };
+            let end = verdicts
+                .get(i + 1)
+                .map_or(window.1, |(next, _)| Bound::Excluded(*next));
+            intersect((start, end), window)
+        })
+        .collect();
+    merge(violations)
+}
+
+/// Merges overlapping and touching ranges, and orders them by time.
+fn merge(mut ranges: Vec<TimeRange>) -> Vec<TimeRange> {
+    ranges.sort_by_key(|range| start_key(range.0));
+    let mut merged: Vec<TimeRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if intersect(*last, range).is_some() || touch(last.1, range.0) => {
+                if end_key(range.1) > end_key(last.1) {
+                    last.1 = range.1;
+                }
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Whether a range that ends at `end` is immediately followed by one that starts at `start`.
+fn touch(end: Bound<Time>, start: Bound<Time>) -> bool {
+    matches!(
+        (end, start),
+        (Bound::Excluded(e), Bound::Included(s)) | (Bound::Included(e), Bound::Excluded(s)) if e == s
+    )
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Adds a constraint that `predicate` holds for every value of `R` in `window`, as
+    /// described in [constraints][crate::constraints]. Nothing is checked until
+    /// [Plan::violations] is called.
+    pub fn add_constraint<R: Resource<'o> + 'o>(
+        &mut self,
+        name: impl Into<String>,
+        window: impl RangeBounds<Time>,
+        predicate: impl Fn(R::Read) -> bool + Send + 'static,
+    ) -> ConstraintId {
+        let window = (window.start_bound().cloned(), window.end_bound().cloned());
+        let id = ConstraintId(self.constraint_counter);
+        self.constraint_counter += 1;
+        self.constraints.get_mut().push(Box::new(Constraint::<R> {
+            id,
+            name: name.into(),
+            window,
+            unchecked: vec![window],
+            removed: false,
+            verdicts: vec![],
+            violations: vec![],
+            predicate: Box::new(predicate),
+        }));
+        id
+    }
+
+    /// Removes a constraint, returning whether it existed.
+    pub fn remove_constraint(&mut self, id: ConstraintId) -> bool {
+        let constraints = self.constraints.get_mut();
+        let before = constraints.len();
+        constraints.retain(|constraint| constraint.id() != id);
+        constraints.len() < before
+    }
+
+    /// Checks every constraint, simulating whatever edits made stale in their windows, and
+    /// returns their violations, ordered by constraint and then by time.
+    pub fn violations(&self) -> Result<Vec<Violation>, EngineError> {
+        let mut violations = vec![];
+        for constraint in self.constraints.lock().iter_mut() {
+            violations.extend(constraint.check(self)?);
+        }
+        Ok(violations)
+    }
+
+    pub(crate) fn invalidate_constraints(&self) {
+        for constraint in self.constraints.lock().iter_mut() {
+            constraint.invalidate(self);
+        }
+    }
+}
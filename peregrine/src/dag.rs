@@ -0,0 +1,178 @@
+//! The operation graph of a plan, in the DOT language of [Graphviz](https://graphviz.org).
+//!
+//! [Plan::export_dag] draws each operation in a time range as a node, labeled with its
+//! activity, index, and time, and each resource that flows from one operation to another as an
+//! edge. Edges from operations whose order depends on the simulation, like ones at the same
+//! time, are dashed, because either might come first. Values that come from before the range,
+//! including the initial conditions, come from one node per resource.
+//!
+//! After the plan has been viewed, operations whose outputs are cached are filled green and the
+//! rest red, which shows what the next view of the range will evaluate.
+//!
+//! The graph is built from what each operation declares it reads and writes, like
+//! [Plan::write_conflicts], so it shows what the activities say, not what the engine did, which
+//! is usually what matters when looking for a dependency mistake. Render it with
+//! `dot -Tsvg plan.dot -o plan.svg`.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(graphed_heat: f64);
+//! # resource!(graphed_power: f64);
+//! # model! { Graphed(graphed_heat, graphed_power) }
+//! pub struct Heat;
+//! impl_activity! { for Heat
+//!     @(start) {
+//!         ref mut: graphed_power -= 5.0;
+//!         mut: graphed_heat = ref: graphed_power * 0.1;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Graphed>(start, initial_conditions! { graphed_heat: 0.0, graphed_power: 100.0 })?;
+//! plan.insert(start + Duration::from_seconds(1.0), Heat)?;
+//! plan.insert(start + Duration::from_seconds(2.0), Heat)?;
+//!
+//! let dot = plan.export_dag(start.., &["graphed_power"]);
+//! assert!(dot.starts_with("digraph plan {"));
+//! assert!(dot.contains("op0 -> op1 [label=\"graphed_power\"];"));
+//! assert!(!dot.contains("label=\"graphed_heat\""));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::activity::ActivityId;
+use crate::{Model, Plan, Time};
+use std::fmt::Write;
+use std::ops::RangeBounds;
+use std::sync::atomic::Ordering;
+
+/// An operation in the graph.
+struct GraphNode {
+    activity: &'static str,
+    activity_id: ActivityId,
+    op_index: usize,
+    min_time: Time,
+    max_time: Time,
+    reads: &'static [&'static str],
+    writes: &'static [&'static str],
+    evaluated: bool,
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// The operations that can happen within `bounds`, and the resources in `resources` that
+    /// flow between them, as a Graphviz graph. All resources are included if `resources` is
+    /// empty. See [dag][crate::dag].
+    pub fn export_dag(&self, bounds: impl RangeBounds<Time>, resources: &[&str]) -> String {
+        let included = |resource: &str| resources.is_empty() || resources.contains(&resource);
+        let mut nodes: Vec<GraphNode> = vec![];
+        for (id, decomposed) in self.activities.iter() {
+            for (index, op) in decomposed.operations.iter().enumerate() {
+                let info = op.info();
+                let in_bounds = bounds.contains(&info.min_time) || bounds.contains(&info.max_time);
+                let touches = info.reads.iter().chain(info.writes).any(|r| included(r));
+                if in_bounds && touches {
+                    nodes.push(GraphNode {
+                        activity: info.activity,
+                        activity_id: *id,
+                        op_index: index,
+                        min_time: info.min_time,
+                        max_time: info.max_time,
+                        reads: info.reads,
+                        writes: info.writes,
+                        evaluated: op.is_evaluated(),
+                    });
+                }
+            }
+        }
+        nodes.sort_by_key(|node| (node.min_time, node.activity_id, node.op_index));
+
+        let colored = self.has_been_simulated.load(Ordering::Relaxed);
+        let mut dot = String::from("digraph plan {\n    rankdir=LR;\n    node [shape=box];\n");
+        for (i, node) in nodes.iter().enumerate() {
+            let time = if node.min_time == node.max_time {
+                node.min_time.to_string()
+            } else {
+                format!("{} to {}", node.min_time, node.max_time)
+            };
+            let style = match (colored, node.evaluated) {
+                (false, _) => "",
+                (true, true) => ", style=filled, fillcolor=palegreen",
+                (true, false) => ", style=filled, fillcolor=lightpink",
+            };
+            writeln!(
+                dot,
+                "    op{i} [label=\"{} {:?}\\nop {}\\n{}\"{style}];",
+                escape(node.activity),
+                node.activity_id,
+                node.op_index,
+                time
+            )
+            .unwrap();
+        }
+
+        let mut sources: Vec<&'static str> = vec![];
+        let mut edges = String::new();
+        for (i, reader) in nodes.iter().enumerate() {
+            for &resource in reader.reads.iter().filter(|r| included(r)) {
+                let writers = || {
+                    nodes
+                        .iter()
+                        .enumerate()
+                        .filter(move |(j, writer)| *j != i && writer.writes.contains(&resource))
+                };
+                // The last writer that is certainly earlier, and any that might be either side.
+                let earlier = writers()
+                    .filter(|(_, writer)| writer.max_time < reader.min_time)
+                    .max_by_key(|(_, writer)| writer.max_time);
+                match earlier {
+                    Some((j, _)) => writeln!(
+                        edges,
+                        "    op{j} -> op{i} [label=\"{}\"];",
+                        escape(resource)
+                    )
+                    .unwrap(),
+                    None => {
+                        if !sources.contains(&resource) {
+                            sources.push(resource);
+                        }
+                        writeln!(
+                            edges,
+                            "    \"{0}\" -> op{i} [label=\"{0}\"];",
+                            escape(resource)
+                        )
+                        .unwrap();
+                    }
+                }
+                for (j, _) in writers().filter(|(_, writer)| {
+                    writer.max_time >= reader.min_time && writer.min_time <= reader.max_time
+                }) {
+                    writeln!(
+                        edges,
+                        "    op{j} -> op{i} [label=\"{}\", style=dashed];",
+                        escape(resource)
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        for source in sources {
+            writeln!(
+                dot,
+                "    \"{0}\" [shape=ellipse, label=\"{0}\"];",
+                escape(source)
+            )
+            .unwrap();
+        }
+        dot.push_str(&edges);
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Escapes a string for a quoted DOT identifier.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,128 @@
+//! Views that only return what changed since the previous one.
+//!
+//! [Plan::view_delta][crate::Plan::view_delta] splits the requested window at the edges of the ranges that an edit made
+//! stale (see [Plan::subscribe][crate::Plan::subscribe]), views the window, and compares each piece against the
+//! previous delta view of the same resource. Only the pieces that differ are returned, so a
+//! display only has to redraw what an edit actually changed.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(counter: u32);
+//! # model! { Counter(counter) }
+//! # pub struct Increment;
+//! # impl_activity! { for Increment
+//! #     @(start) {
+//! #         ref mut: counter += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! # let seconds = |s: f64| start + Duration::from_seconds(s);
+//! let mut plan = session.new_plan::<Counter>(start, initial_conditions! { counter: 0 })?;
+//! for s in 1..=10 {
+//!     plan.insert(seconds(s as f64), Increment)?;
+//! }
+//! // Everything is new the first time.
+//! assert_eq!(1, plan.view_delta::<counter>(start..)?.len());
+//!
+//! plan.insert(seconds(8.5), Increment)?;
+//! let delta = plan.view_delta::<counter>(start..)?;
+//! assert_eq!(1, delta.len());
+//! assert_eq!(seconds(8.5), delta[0].values[0].0);
+//! assert_eq!(3, delta[0].values.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::Time;
+use crate::resource::{ErasedResource, Resource};
+use crate::subscription::{TimeRange, contains, intersect};
+use std::ops::{Bound, RangeBounds};
+
+/// A piece of a window whose values differ from the previous [Plan::view_delta] of it.
+///
+/// [Plan::view_delta]: crate::Plan::view_delta
+#[derive(Clone, Debug, PartialEq)]
+pub struct ViewDelta<T> {
+    pub range: TimeRange,
+    /// The new values in `range`. The first piece of a window also includes the value from
+    /// before its start, as in [Plan::view][crate::Plan::view].
+    pub values: Vec<(Time, T)>,
+}
+
+/// The window and values of the last delta view of a resource.
+pub(crate) struct PreviousView<'o, R: Resource<'o>> {
+    pub(crate) window: TimeRange,
+    pub(crate) values: Vec<(Time, R::Read)>,
+}
+
+impl<'o, R: Resource<'o>> ErasedResource<'o> for PreviousView<'o, R> {
+    fn id(&self) -> u64 {
+        R::ID
+    }
+}
+
+/// Splits `window` into the `stale` ranges and the gaps between them. `stale` must be ordered,
+/// disjoint, and within the window.
+pub(crate) fn split(window: TimeRange, stale: &[TimeRange]) -> Vec<TimeRange> {
+    let mut pieces = Vec::with_capacity(2 * stale.len() + 1);
+    let mut from = Some(window.0);
+    for &range in stale {
+        let Some(start) = from else { break };
+        let gap_end = match range.0 {
+            Bound::Included(t) => Bound::Excluded(t),
+            Bound::Excluded(t) => Bound::Included(t),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        if !matches!(range.0, Bound::Unbounded) {
+            pieces.extend(intersect((start, gap_end), window));
+        }
+        pieces.push(range);
+        from = match range.1 {
+            Bound::Included(t) => Some(Bound::Excluded(t)),
+            Bound::Excluded(t) => Some(Bound::Included(t)),
+            Bound::Unbounded => None,
+        };
+    }
+    if let Some(start) = from {
+        pieces.extend(intersect((start, window.1), window));
+    }
+    pieces
+}
+
+/// The values that belong to a piece, where the first piece also takes the values from before
+/// the window.
+pub(crate) fn piece_values<T: Copy>(
+    values: &[(Time, T)],
+    piece: TimeRange,
+    first: bool,
+) -> Vec<(Time, T)> {
+    values
+        .iter()
+        .filter(|(time, _)| {
+            piece.contains(time)
+                || (first
+                    && match piece.0 {
+                        Bound::Included(start) => *time < start,
+                        Bound::Excluded(start) => *time <= start,
+                        Bound::Unbounded => false,
+                    })
+        })
+        .copied()
+        .collect()
+}
+
+/// Whether `piece` was part of the previous view and has the same values as before.
+pub(crate) fn unchanged<'o, R: Resource<'o>>(
+    previous: &PreviousView<'o, R>,
+    piece: TimeRange,
+    first: bool,
+    values: &[(Time, R::Read)],
+) -> bool
+where
+    R::Read: PartialEq,
+{
+    contains(previous.window, piece) && piece_values(&previous.values, piece, first) == values
+}
@@ -0,0 +1,121 @@
+//! Devices that only one activity can use at a time, and that may need to cool down after.
+//!
+//! A [Device] resource is claimed by an operation at the start of its use, and released by
+//! another at the end. Claiming a device that is already claimed, or still cooling down from
+//! its last use, fails the claiming operation, so a view reports the conflict with the usual
+//! [ErrorReport][crate::ErrorReport], pointing at the activity that tried to claim it.
+//!
+//! The owner can be any small value that identifies who is using the device, usually an enum.
+//! Releasing a device checks that it is released by its owner.
+//!
+//! ```
+//! # use peregrine::*;
+//! use peregrine::devices::Device;
+//!
+//! #[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+//! pub enum CameraUser { Imaging, Calibration }
+//!
+//! resource!(camera: Device<CameraUser> = Device::Free);
+//! model! { Imager(camera) }
+//!
+//! pub struct Image(Duration);
+//! impl_activity! { for Image
+//!     @(start) {
+//!         ref mut: camera.claim(CameraUser::Imaging, now)?;
+//!     }
+//!     @(start + self.0) {
+//!         ref mut: camera.release(CameraUser::Imaging, now, Duration::from_seconds(600.0))?;
+//!     }
+//!     self.0
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let hours = |h: f64| start + Duration::from_hours(h);
+//! let mut plan = session.new_plan::<Imager>(start, initial_conditions! {})?;
+//! plan.insert(hours(1.0), Image(Duration::from_hours(1.0)))?;
+//! assert_eq!(Device::CoolingDown(hours(2.0) + Duration::from_seconds(600.0)), plan.sample::<camera>(hours(2.0))?);
+//!
+//! // Overlaps the first image.
+//! let second = plan.insert(hours(1.5), Image(Duration::from_hours(1.0)))?;
+//! let Err(EngineError::ViewFailed(report)) = plan.sample::<camera>(hours(3.0)) else {
+//!     panic!("expected a conflict")
+//! };
+//! assert_eq!(vec![second], report.activity_ids());
+//!
+//! // Starts while the camera is still cooling down.
+//! plan.remove(second)?;
+//! plan.insert(hours(2.1), Image(Duration::from_hours(1.0)))?;
+//! assert!(plan.sample::<camera>(hours(4.0)).is_err());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::Time;
+use anyhow::{Result, bail};
+use hifitime::Duration;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+/// A device that can be claimed by one owner at a time. See the [module documentation][self].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Device<O> {
+    #[default]
+    Free,
+    Claimed(O),
+    /// Released, but unavailable until the time.
+    CoolingDown(Time),
+}
+
+impl<O: Copy + Debug + PartialEq> Device<O> {
+    /// Claims the device for `owner`, failing if it is claimed or cooling down at `now`.
+    pub fn claim(&mut self, owner: O, now: Time) -> Result<()> {
+        match *self {
+            Device::Claimed(current) => bail!("device is already claimed by {current:?}"),
+            Device::CoolingDown(until) if now < until => {
+                bail!("device is cooling down until {until}")
+            }
+            _ => {
+                *self = Device::Claimed(owner);
+                Ok(())
+            }
+        }
+    }
+
+    /// Releases the device, failing if it isn't claimed by `owner`. It can be claimed again
+    /// once `cooldown` has passed after `now`.
+    pub fn release(&mut self, owner: O, now: Time, cooldown: Duration) -> Result<()> {
+        match *self {
+            Device::Claimed(current) if current == owner => {
+                *self = if cooldown > Duration::ZERO {
+                    Device::CoolingDown(now + cooldown)
+                } else {
+                    Device::Free
+                };
+                Ok(())
+            }
+            Device::Claimed(current) => {
+                bail!("device is claimed by {current:?}, not {owner:?}")
+            }
+            _ => bail!("device is released by {owner:?}, but it isn't claimed"),
+        }
+    }
+
+    /// The owner of the device, if it is claimed.
+    pub fn owner(&self) -> Option<O> {
+        match self {
+            Device::Claimed(owner) => Some(*owner),
+            _ => None,
+        }
+    }
+
+    /// Whether the device can be claimed at `now`.
+    pub fn is_available(&self, now: Time) -> bool {
+        match self {
+            Device::Free => true,
+            Device::Claimed(_) => false,
+            Device::CoolingDown(until) => now >= *until,
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::Time;
+use crate::activity::ActivityId;
+use crate::exec::ErrorReport;
+use crate::operation::ObservedErrorOutput;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// The ways that engine operations on a [Plan][crate::Plan] can fail.
+///
+/// Errors produced by user code (activity bodies and operations) are kept as the `source`
+/// of the relevant variant.
+#[derive(Debug)]
+pub enum EngineError {
+    /// A resource in the model was not given an initial condition, and has no default.
+    MissingInitialCondition(&'static str),
+    /// No activity with the given ID is in the plan.
+    ActivityNotFound(ActivityId),
+    /// No activity with the given key is in the plan.
+    KeyNotFound(String),
+    /// An activity with the given key is already in the plan.
+    DuplicateKey(String),
+    /// The model has no resource with the given label.
+    UnknownResource(String),
+    /// The model contains more than one instantiation of the generic resource with the given
+    /// label. They share an ID, so a model can only contain one of them.
+    ConflictingInstantiations(&'static str),
+    /// No activity with the given label is registered for the plan's model, see
+    /// [registry][crate::registry].
+    UnknownActivity(String),
+    /// The session has no plan with the given name and model.
+    PlanNotFound(String),
+    /// The session already has a plan with the given name.
+    DuplicatePlan(String),
+    /// An activity's operations could not be removed from the plan. This should not happen,
+    /// and means the plan is in an inconsistent state.
+    RemovalFailed {
+        activity: ActivityId,
+        source: anyhow::Error,
+    },
+    /// An activity's arguments were rejected by its `validate` block.
+    InvalidArguments {
+        activity: &'static str,
+        source: anyhow::Error,
+    },
+    /// An activity failed while generating its operations.
+    DecompositionFailed {
+        activity: &'static str,
+        source: anyhow::Error,
+    },
+    /// An activity's operations could not be inserted into the plan.
+    InsertionFailed {
+        activity: &'static str,
+        source: anyhow::Error,
+    },
+    /// A single operation failed outside of a view, for example during a [replay][crate::Plan::replay].
+    OpFailed {
+        activity: &'static str,
+        time: Time,
+        source: anyhow::Error,
+    },
+    /// One or more operations failed during a view.
+    ViewFailed(ErrorReport),
+    /// The view depends on an operation that failed during an earlier view of the plan, and
+    /// whose error has already been reported.
+    PreviouslyFailed,
+    /// The boundary profile of a resource could not be loaded, for example because its
+    /// [time series][crate::series::TimeSeries] file is missing or malformed.
+    ProfileLoadFailed {
+        resource: &'static str,
+        source: anyhow::Error,
+    },
+    /// Problems with the initial conditions given to a new plan, found by
+    /// [InitialConditions::validate][crate::InitialConditions::validate].
+    InvalidInitialConditions(Vec<InitialConditionIssue>),
+    /// Problems found by [Plan::validate][crate::Plan::validate].
+    InvalidPlan(Vec<ValidationIssue>),
+    /// The activities of a [plan document][crate::plan_file] that could not be inserted.
+    InvalidPlanDocument(Vec<LoadIssue>),
+    /// A sample was requested at a time before any operations on the resource.
+    NothingToSample(Time),
+    /// Activities that don't fit in the horizon given to [Plan::trim][crate::Plan::trim].
+    OutsideHorizon(Vec<ActivityId>),
+    /// A time is outside of the range the engine can represent, which is roughly 292 years
+    /// either side of 1900 with the `ticks` feature.
+    TimeOutOfRange(Time),
+    /// The plan was edited after the [snapshot][crate::PlanSnapshot] was taken, so a view of
+    /// it would not match the snapshot's activities.
+    StaleSnapshot,
+}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::MissingInitialCondition(resource) => {
+                write!(
+                    f,
+                    "expected to find initial condition for resource {resource}, but found none"
+                )
+            }
+            EngineError::ActivityNotFound(id) => {
+                write!(f, "could not find activity with id {id:?}")
+            }
+            EngineError::KeyNotFound(key) => {
+                write!(f, "could not find activity with key {key:?}")
+            }
+            EngineError::DuplicateKey(key) => {
+                write!(f, "an activity with key {key:?} is already in the plan")
+            }
+            EngineError::UnknownResource(label) => {
+                write!(f, "the model has no resource named {label:?}")
+            }
+            EngineError::ConflictingInstantiations(label) => {
+                write!(
+                    f,
+                    "the model contains more than one instantiation of the generic resource {label}"
+                )
+            }
+            EngineError::UnknownActivity(label) => {
+                write!(f, "no activity named {label:?} is registered for the model")
+            }
+            EngineError::PlanNotFound(name) => {
+                write!(f, "could not find plan {name:?} with the requested model")
+            }
+            EngineError::DuplicatePlan(name) => {
+                write!(f, "a plan named {name:?} already exists")
+            }
+            EngineError::RemovalFailed { activity, .. } => {
+                write!(f, "could not remove activity with id {activity:?}")
+            }
+            EngineError::InvalidArguments { activity, .. } => {
+                write!(f, "invalid arguments for activity {activity}")
+            }
+            EngineError::DecompositionFailed { activity, .. } => {
+                write!(f, "could not decompose activity {activity}")
+            }
+            EngineError::InsertionFailed { activity, .. } => {
+                write!(f, "could not insert activity {activity}")
+            }
+            EngineError::OpFailed { activity, time, .. } => {
+                write!(f, "occurred in activity {activity} at {time}")
+            }
+            EngineError::ViewFailed(report) => report.fmt(f),
+            EngineError::PreviouslyFailed => ObservedErrorOutput.fmt(f),
+            EngineError::ProfileLoadFailed { resource, .. } => {
+                write!(f, "could not load the profile of resource {resource}")
+            }
+            EngineError::InvalidInitialConditions(issues) => {
+                write!(f, "initial conditions are invalid:")?;
+                for issue in issues {
+                    write!(f, "\n  - {issue}")?;
+                }
+                Ok(())
+            }
+            EngineError::InvalidPlan(issues) => {
+                write!(f, "plan is invalid:")?;
+                for issue in issues {
+                    write!(f, "\n  - {issue}")?;
+                }
+                Ok(())
+            }
+            EngineError::InvalidPlanDocument(issues) => {
+                write!(f, "plan document is invalid:")?;
+                for issue in issues {
+                    write!(f, "\n  - {issue}")?;
+                }
+                Ok(())
+            }
+            EngineError::NothingToSample(time) => {
+                write!(f, "No operations to sample found at or before {time}")
+            }
+            EngineError::OutsideHorizon(ids) => {
+                write!(
+                    f,
+                    "{} activities are outside of the horizon: {ids:?}",
+                    ids.len()
+                )
+            }
+            EngineError::TimeOutOfRange(time) => {
+                write!(
+                    f,
+                    "{time} is outside of the range of times the engine can represent"
+                )
+            }
+            EngineError::StaleSnapshot => {
+                write!(f, "the plan was edited after the snapshot was taken")
+            }
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EngineError::RemovalFailed { source, .. }
+            | EngineError::InvalidArguments { source, .. }
+            | EngineError::DecompositionFailed { source, .. }
+            | EngineError::InsertionFailed { source, .. }
+            | EngineError::ProfileLoadFailed { source, .. }
+            | EngineError::OpFailed { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ObservedErrorOutput> for EngineError {
+    fn from(_: ObservedErrorOutput) -> Self {
+        EngineError::PreviouslyFailed
+    }
+}
+
+impl From<ErrorReport> for EngineError {
+    fn from(report: ErrorReport) -> Self {
+        EngineError::ViewFailed(report)
+    }
+}
+
+/// A problem with a single operation, found without simulating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub activity: &'static str,
+    pub activity_id: ActivityId,
+    /// The index of the operation among the operations of its activity.
+    pub op_index: usize,
+    /// The earliest time the operation can happen.
+    pub time: Time,
+    pub kind: ValidationIssueKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationIssueKind {
+    /// The operation can happen at or before the start of the plan, where the initial
+    /// conditions are.
+    NotAfterPlanStart,
+    /// The operation reads a resource, but there is no write to it before the operation, not
+    /// even an initial condition.
+    NoUpstream(&'static str),
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "activity {} ({:?}, operation {}) at {}: ",
+            self.activity, self.activity_id, self.op_index, self.time
+        )?;
+        match self.kind {
+            ValidationIssueKind::NotAfterPlanStart => {
+                write!(f, "operation is not after the start of the plan")
+            }
+            ValidationIssueKind::NoUpstream(resource) => {
+                write!(f, "{resource} is read, but nothing writes it earlier")
+            }
+        }
+    }
+}
+
+/// An activity of a [plan document][crate::plan_file] that could not be inserted.
+#[derive(Debug)]
+pub struct LoadIssue {
+    /// The position of the activity in the document.
+    pub index: usize,
+    /// The activity's type, as written in the document.
+    pub activity: String,
+    pub key: Option<String>,
+    pub error: anyhow::Error,
+}
+
+impl Display for LoadIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "activity {} ({}", self.index, self.activity)?;
+        if let Some(key) = &self.key {
+            write!(f, " {key:?}")?;
+        }
+        write!(f, "): {:#}", self.error)
+    }
+}
+
+/// A problem with the initial condition of a single resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitialConditionIssue {
+    pub resource: &'static str,
+    pub kind: InitialConditionIssueKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitialConditionIssueKind {
+    /// The resource has no initial condition, and no default.
+    Missing,
+    /// The initial condition is for a resource with the same id, but a different type.
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl Display for InitialConditionIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            InitialConditionIssueKind::Missing => {
+                write!(f, "{} has no initial condition", self.resource)
+            }
+            InitialConditionIssueKind::WrongType { expected, found } => {
+                write!(
+                    f,
+                    "{} has an initial condition of type {found}, expected {expected}",
+                    self.resource
+                )
+            }
+        }
+    }
+}
@@ -1,17 +1,62 @@
-use crate::History;
+use crate::activity::ActivityId;
+use crate::arena::Arena;
+use crate::offload::Executor;
 use crate::operation::ObservedErrorOutput;
+use crate::{History, Time};
+use anyhow::Result;
 use crossbeam::queue::SegQueue;
 use derive_more::Deref;
-use std::cell::UnsafeCell;
+use parking_lot::{Condvar, Mutex};
+use rayon::{Scope, ThreadPool, ThreadPoolBuilder};
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::error::Error;
-use std::fmt::{Display, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::pin::pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll, Wake, Waker};
 
-pub const STACK_LIMIT: usize = 1000;
+/// The default [Tuning::stack_limit]. Deferring to the trampoline only costs an unwind, so
+/// this leaves plenty of room on rayon's stacks, even in debug builds.
+pub const STACK_LIMIT: usize = 250;
+
+/// Knobs for how simulations are scheduled, set for all plans of a session with
+/// [Session::with_tuning][crate::Session::with_tuning].
+///
+/// The defaults suit models with many cheap operations, where spawning a task can cost more
+/// than the operation itself. Models with a few expensive operations can do better spawning
+/// more of them, so that they run in parallel.
+///
+/// The inline capacities of the engine's small vectors are part of its node types, so they are
+/// fixed at compile time and can't be tuned here.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tuning {
+    /// How many continuations deep a thread's stack can get before the next one is deferred
+    /// until the stack unwinds, instead of run right away. Too high can overflow the stacks of
+    /// rayon's threads, and too low unwinds more often than needed.
+    pub stack_limit: usize,
+    /// How many downstreams of a finished operation the thread runs itself, instead of
+    /// spawning them. With 0, every continuation and upstream request is spawned.
+    pub inline_continuations: usize,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning {
+            stack_limit: STACK_LIMIT,
+            inline_continuations: 1,
+        }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub struct ExecEnvironment<'s, 'o: 's> {
     pub history: &'o History,
     pub errors: &'s ErrorAccumulator,
+    pub recorder: Option<&'s Recorder<'o>>,
+    pub audit: Option<&'s CacheAudit>,
+    pub determinism_check: DeterminismCheck,
+    pub executor: Option<&'s dyn Executor>,
+    pub tuning: Tuning,
     pub stack_counter: usize,
 }
 
@@ -29,6 +74,343 @@ impl<'s, 'o> ExecEnvironment<'s, 'o> {
             ..self
         }
     }
+
+    /// Whether the next continuation can run on this thread instead of being spawned.
+    pub fn can_inline(self) -> bool {
+        self.tuning.inline_continuations > 0
+    }
+
+    /// How many of `count` continuations to run on this thread. The rest are spawned.
+    pub fn inline_count(self, count: usize) -> usize {
+        self.tuning.inline_continuations.min(count)
+    }
+
+    /// Spawns a continuation as a new rayon task, with a fresh stack and its own trampoline.
+    pub fn spawn<F>(self, scope: &Scope<'s>, continuation: F)
+    where
+        F: FnOnce(&Scope<'s>, ExecEnvironment<'s, 'o>) + Send + 's,
+    {
+        scope.spawn(move |s| trampoline(|| continuation(s, self.reset())));
+    }
+
+    /// Runs a continuation on this thread, right away if the stack has room.
+    ///
+    /// Past the [stack limit][Tuning::stack_limit], the continuation is deferred to the
+    /// trampoline at the bottom of the stack, which runs it once the stack unwinds. Straight
+    /// chains of operations are then evaluated in a loop on one thread, instead of spawning a
+    /// task for every few links.
+    pub fn run_inline<F>(self, scope: &Scope<'s>, continuation: F)
+    where
+        F: FnOnce(&Scope<'s>, ExecEnvironment<'s, 'o>) + Send + 's,
+    {
+        if self.stack_counter < self.tuning.stack_limit {
+            return continuation(scope, self.increment());
+        }
+        if TRAMPOLINES.with_borrow(|trampolines| trampolines.is_empty()) {
+            // Not inside a task of the engine, so there is nothing to defer to.
+            return self.spawn(scope, continuation);
+        }
+        let deferred: Box<dyn FnOnce() + '_> = Box::new(move || continuation(scope, self.reset()));
+        // SAFETY: the trampoline that runs or drops the continuation is further down this
+        // thread's stack, inside the task that lent out `scope`, so everything it borrows
+        // outlives it.
+        let deferred: Deferred = unsafe { std::mem::transmute(deferred) };
+        TRAMPOLINES.with_borrow_mut(|trampolines| trampolines.last_mut().unwrap().push(deferred));
+    }
+}
+
+/// A continuation deferred to a [trampoline], with its lifetime erased.
+type Deferred = Box<dyn FnOnce() + 'static>;
+
+thread_local! {
+    /// The continuations deferred to each trampoline on this thread's stack, innermost last.
+    static TRAMPOLINES: RefCell<Vec<Vec<Deferred>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `task`, and then the continuations that were deferred while it ran, along with those
+/// they defer in turn, until there are none left. See [ExecEnvironment::run_inline].
+///
+/// Every task the engine spawns runs in a trampoline. They nest, in case a thread picks up
+/// another task while it waits, like in [block_on].
+pub fn trampoline(task: impl FnOnce()) {
+    TRAMPOLINES.with_borrow_mut(|trampolines| trampolines.push(Vec::new()));
+    let _trampoline = TrampolineGuard;
+    task();
+    while let Some(deferred) = TRAMPOLINES
+        .with_borrow_mut(|trampolines| trampolines.last_mut().and_then(|queue| queue.pop()))
+    {
+        deferred();
+    }
+}
+
+/// Removes the innermost trampoline when dropped, even if a continuation panicked.
+struct TrampolineGuard;
+
+impl Drop for TrampolineGuard {
+    fn drop(&mut self) {
+        let queue = TRAMPOLINES.with_borrow_mut(|trampolines| trampolines.pop());
+        // Dropped outside of the borrow, since the continuations can own anything.
+        drop(queue);
+    }
+}
+
+/// Which of two lanes a simulation is scheduled in, so that views someone is waiting on
+/// aren't stuck behind speculative work like [SharedPlan::speculate][crate::SharedPlan::speculate].
+///
+/// Each lane has its own rayon pool, so tasks of background simulations never queue ahead of
+/// foreground ones. A background simulation also waits to start until no foreground
+/// simulations are running, anywhere in the process. Once started it runs to the end, since
+/// foreground views may be waiting on operations it is evaluating.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Lane {
+    /// Views that a caller is waiting on, on rayon's global pool.
+    #[default]
+    Foreground,
+    /// Work that nobody is waiting on yet, on a pool of its own.
+    Background,
+}
+
+/// The number of foreground simulations running, which background ones wait for to reach 0.
+static FOREGROUND: Mutex<usize> = Mutex::new(0);
+static FOREGROUND_DONE: Condvar = Condvar::new();
+static BACKGROUND_POOL: OnceLock<Option<ThreadPool>> = OnceLock::new();
+
+impl Lane {
+    /// Runs a simulation in this lane, waiting for foreground simulations to finish first if
+    /// it is in the background.
+    pub fn run<T: Send>(self, simulation: impl FnOnce() -> T + Send) -> T {
+        match self {
+            Lane::Foreground => {
+                *FOREGROUND.lock() += 1;
+                let _running = ForegroundGuard;
+                simulation()
+            }
+            Lane::Background => {
+                Lane::wait_for_foreground();
+                let pool = BACKGROUND_POOL.get_or_init(|| {
+                    ThreadPoolBuilder::new()
+                        .thread_name(|i| format!("peregrine-background-{i}"))
+                        .build()
+                        .ok()
+                });
+                match pool {
+                    Some(pool) => pool.install(simulation),
+                    None => simulation(),
+                }
+            }
+        }
+    }
+}
+
+impl Lane {
+    /// Blocks until no foreground simulations are running. Background work that holds locks
+    /// while it simulates waits for this before taking them, so edits don't wait twice.
+    pub fn wait_for_foreground() {
+        let mut foreground = FOREGROUND.lock();
+        while *foreground > 0 {
+            FOREGROUND_DONE.wait(&mut foreground);
+        }
+    }
+}
+
+/// Counts a foreground simulation as finished when dropped, even if it panicked.
+struct ForegroundGuard;
+
+impl Drop for ForegroundGuard {
+    fn drop(&mut self) {
+        let mut foreground = FOREGROUND.lock();
+        *foreground -= 1;
+        if *foreground == 0 {
+            FOREGROUND_DONE.notify_all();
+        }
+    }
+}
+
+/// Provides the I/O and timers for futures awaited with [block_on], like a Tokio runtime.
+///
+/// Set with [set_async_runtime]. Without one, [block_on] only works with futures that don't
+/// need a runtime, like channels.
+pub trait AsyncRuntime: Send + Sync {
+    /// Calls `poll` inside the runtime's context, so that the futures it polls can register
+    /// with the runtime's drivers.
+    fn enter(&self, poll: &mut dyn FnMut());
+}
+
+/// Awaits futures on a Tokio runtime. It has to be a multi-threaded runtime, since its own
+/// threads drive the I/O and timers while simulation threads poll the futures.
+#[cfg(feature = "tokio")]
+pub struct TokioRuntime(pub tokio::runtime::Handle);
+
+#[cfg(feature = "tokio")]
+impl TokioRuntime {
+    /// The runtime that the current thread is in. Panics outside of a runtime, like
+    /// [Handle::current][tokio::runtime::Handle::current].
+    pub fn current() -> Self {
+        TokioRuntime(tokio::runtime::Handle::current())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRuntime for TokioRuntime {
+    fn enter(&self, poll: &mut dyn FnMut()) {
+        let _context = self.0.enter();
+        poll();
+    }
+}
+
+static ASYNC_RUNTIME: Mutex<Option<Arc<dyn AsyncRuntime>>> = Mutex::new(None);
+
+/// Sets the runtime for all futures awaited with [block_on] in this process, replacing the
+/// previous one.
+pub fn set_async_runtime(runtime: impl AsyncRuntime + 'static) {
+    *ASYNC_RUNTIME.lock() = Some(Arc::new(runtime));
+}
+
+/// How many [block_on]s are waiting further up the current thread's stack. Past
+/// [MAX_AWAITING], a thread stops running other tasks while it waits, so that it doesn't
+/// overflow its stack.
+const MAX_AWAITING: usize = 16;
+
+thread_local! {
+    static AWAITING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Waits for a future inside an operation body, like a request to a web service.
+///
+/// Operation bodies are synchronous, but waiting doesn't pin the simulation thread: until the
+/// future is ready, the thread runs other tasks of the simulation, and only sleeps when there
+/// are none. Futures are polled inside the runtime set with [set_async_runtime], so with the
+/// `tokio` feature and a [TokioRuntime] they can use Tokio's I/O and timers.
+///
+/// As with the rest of an operation body, the result has to be the same for the same inputs.
+///
+/// ```
+/// # use peregrine::*;
+/// # resource!(awaited_total: u32);
+/// # model! { Awaited(awaited_total) }
+/// pub struct Fetch;
+/// impl_activity! { for Fetch
+///     @(start) {
+///         ref mut: awaited_total += peregrine::exec::block_on(async { 5 });
+///     }
+///     Duration::ZERO
+/// }
+/// # fn main() -> Result<()> {
+/// # let session = Session::new();
+/// # let start = Time::from_tai_seconds(0.0);
+/// # let mut plan = session.new_plan::<Awaited>(start, initial_conditions! { awaited_total: 0 })?;
+/// plan.insert(start, Fetch)?;
+/// assert_eq!(5, plan.sample::<awaited_total>(start)?);
+/// # Ok(())
+/// # }
+/// ```
+pub fn block_on<F: IntoFuture>(future: F) -> F::Output {
+    let mut future = pin!(future.into_future());
+    let signal = Arc::new(Signal::default());
+    let waker = Waker::from(signal.clone());
+    let mut context = Context::from_waker(&waker);
+    let runtime = ASYNC_RUNTIME.lock().clone();
+
+    let awaiting = AWAITING.get();
+    AWAITING.set(awaiting + 1);
+    let _awaited = AwaitingGuard(awaiting);
+
+    loop {
+        let mut output = None;
+        let mut poll = || {
+            if let Poll::Ready(ready) = future.as_mut().poll(&mut context) {
+                output = Some(ready);
+            }
+        };
+        match &runtime {
+            Some(runtime) => runtime.enter(&mut poll),
+            None => poll(),
+        }
+        if let Some(output) = output {
+            return output;
+        }
+
+        while !signal.take() {
+            let executed =
+                awaiting < MAX_AWAITING && rayon::yield_now() == Some(rayon::Yield::Executed);
+            if !executed {
+                signal.wait();
+            }
+        }
+    }
+}
+
+/// Restores the number of [block_on]s waiting on the thread when dropped, even if the future
+/// panicked.
+struct AwaitingGuard(usize);
+
+impl Drop for AwaitingGuard {
+    fn drop(&mut self) {
+        AWAITING.set(self.0);
+    }
+}
+
+/// Wakes a [block_on] that is waiting for its future.
+#[derive(Default)]
+struct Signal {
+    woken: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Signal {
+    /// Whether the future was woken since the last call.
+    fn take(&self) -> bool {
+        std::mem::take(&mut *self.woken.lock())
+    }
+
+    fn wait(&self) {
+        let mut woken = self.woken.lock();
+        while !*woken {
+            self.condvar.wait(&mut woken);
+        }
+    }
+}
+
+impl Wake for Signal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        *self.woken.lock() = true;
+        self.condvar.notify_one();
+    }
+}
+
+/// How aggressively to check that operations are deterministic, at the cost of speed.
+///
+/// The engine assumes that an operation always produces the same output for the same inputs.
+/// An operation that breaks this will silently poison history with values that later
+/// simulations trust, so these checks are meant to be run while developing a model.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeterminismCheck {
+    #[default]
+    Off,
+    /// Evaluates every uncached operation twice, and errors if the outputs differ.
+    Uncached,
+    /// Like [DeterminismCheck::Uncached], but also re-evaluates roughly one in every `n`
+    /// cache hits and compares against the stored value.
+    ///
+    /// Cache hits are sampled by hash, so the same ones are checked on every run.
+    Sampled(u64),
+}
+
+impl DeterminismCheck {
+    pub fn check_uncached(self) -> bool {
+        self != DeterminismCheck::Off
+    }
+
+    pub fn check_cached(self, hash: u64) -> bool {
+        match self {
+            DeterminismCheck::Sampled(n) => hash.is_multiple_of(n.max(1)),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Deref, Default)]
@@ -40,30 +422,414 @@ impl<T> UnsafeSyncCell<T> {
     pub fn new(value: T) -> Self {
         Self(UnsafeCell::new(value))
     }
+
+    pub fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
+
+/// The span of an operation's evaluation, which is exited when dropped. Does nothing unless
+/// the `tracing` feature is enabled.
+#[must_use]
+pub struct OpSpan {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan,
+}
+
+/// Enters the span of an operation of `activity` evaluated at `time`.
+#[doc(hidden)]
+#[allow(unused_variables)]
+pub fn op_span(activity: &'static str, time: Time) -> OpSpan {
+    OpSpan {
+        #[cfg(feature = "tracing")]
+        _entered: tracing::trace_span!("operation", activity, %time).entered(),
+    }
+}
+
+/// Records whether the operation of the current [op_span] was found in the history.
+#[doc(hidden)]
+#[allow(unused_variables)]
+pub fn trace_lookup(hash: u64, cached: bool) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(hash, cached, "history lookup");
+}
+
+/// A failure of a single operation, and the root cause of any number of downstream failures.
+#[derive(Debug)]
+pub struct OpError {
+    pub activity: &'static str,
+    /// The ID of the activity instance in the plan. This is always present in errors
+    /// returned from a view.
+    pub activity_id: Option<ActivityId>,
+    /// The index of the failed operation among the operations of its activity, in the order
+    /// they were declared. This is always present in errors returned from a view.
+    pub op_index: Option<usize>,
+    pub time: Time,
+    /// The labels of the resources the operation writes. All values of these resources that
+    /// depend on the operation are poisoned by the failure.
+    pub poisoned: &'static [&'static str],
+    pub error: anyhow::Error,
+    node: usize,
+}
+
+impl OpError {
+    /// Creates an error for the operation at address `node`, which is used to look up the
+    /// activity ID and operation index after the view.
+    pub fn new(
+        activity: &'static str,
+        time: Time,
+        poisoned: &'static [&'static str],
+        node: *const (),
+        error: anyhow::Error,
+    ) -> Self {
+        OpError {
+            activity,
+            activity_id: None,
+            op_index: None,
+            time,
+            poisoned,
+            error,
+            node: node as usize,
+        }
+    }
+}
+
+impl Display for OpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "activity {}", self.activity)?;
+        if let (Some(id), Some(index)) = (self.activity_id, self.op_index) {
+            write!(f, " ({id:?}, operation {index})")?;
+        }
+        write!(
+            f,
+            " at {} (poisoning {}): {:#}",
+            self.time,
+            self.poisoned.join(", "),
+            self.error
+        )
+    }
 }
 
 #[derive(Default, Debug)]
-pub struct ErrorAccumulator(SegQueue<anyhow::Error>);
+pub struct ErrorAccumulator(SegQueue<OpError>);
 impl ErrorAccumulator {
-    pub fn push(&self, err: anyhow::Error) {
-        if !err.is::<ObservedErrorOutput>() {
+    pub fn push(&self, err: OpError) {
+        if !err.error.is::<ObservedErrorOutput>() {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(activity = err.activity, time = %err.time, error = %format!("{:#}", err.error), "operation failed");
             self.0.push(err);
         }
     }
 
-    pub fn into_vec(self) -> Vec<anyhow::Error> {
+    pub fn into_vec(self) -> Vec<OpError> {
         self.0.into_iter().collect()
     }
 
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    /// Collects the errors into a report, using `locate` to find the activity ID and operation
+    /// index of each failed operation from its address.
+    ///
+    /// Operations fail on whichever thread evaluated them, so the errors are sorted by activity
+    /// ID, time, and operation index, to report them in the same order every time.
+    pub fn into_report(
+        self,
+        resource: &'static str,
+        affected: Vec<Time>,
+        locate: impl Fn(usize) -> Option<(ActivityId, usize)>,
+    ) -> ErrorReport {
+        let mut errors = self.into_vec();
+        for error in &mut errors {
+            if let Some((id, index)) = locate(error.node) {
+                error.activity_id = Some(id);
+                error.op_index = Some(index);
+            }
+        }
+        errors.sort_by_key(|error| (error.activity_id, error.time, error.op_index));
+        ErrorReport {
+            resource,
+            affected,
+            errors,
+        }
+    }
+}
+
+/// The error returned by a view when one or more operations failed.
+///
+/// Contains one [OpError] for each root failure, ordered by activity ID and then time; the
+/// downstream operations that failed only because of them are not listed. Returned in [EngineError::ViewFailed][crate::EngineError::ViewFailed].
+#[derive(Debug)]
+pub struct ErrorReport {
+    /// The label of the resource that was viewed.
+    pub resource: &'static str,
+    /// The requested times whose values could not be computed because of the errors.
+    ///
+    /// Values of operations whose placement depends on a failed operation have no known
+    /// time, and are not included.
+    pub affected: Vec<Time>,
+    errors: Vec<OpError>,
+}
+
+impl ErrorReport {
+    /// The IDs of all activities that contain a failed operation, without duplicates.
+    pub fn activity_ids(&self) -> Vec<ActivityId> {
+        let mut ids: Vec<_> = self.errors.iter().filter_map(|e| e.activity_id).collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, OpError> {
+        self.errors.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
 }
 
-impl Display for ErrorAccumulator {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result {
-        todo!()
+impl IntoIterator for ErrorReport {
+    type Item = OpError;
+    type IntoIter = std::vec::IntoIter<OpError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.into_iter()
     }
 }
 
-impl Error for ErrorAccumulator {}
+impl<'a> IntoIterator for &'a ErrorReport {
+    type Item = &'a OpError;
+    type IntoIter = std::slice::Iter<'a, OpError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Display for ErrorReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "could not view {}: {} operation{} failed",
+            self.resource,
+            self.errors.len(),
+            if self.errors.len() == 1 { "" } else { "s" }
+        )?;
+        for error in &self.errors {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ErrorReport {}
+
+/// Re-evaluates an operation body from the hashes of its inputs, for debugging.
+///
+/// Implemented for all operations by the [impl_activity][crate::impl_activity] macro.
+pub trait Rerun<'o>: Sync {
+    /// Runs the body again with the inputs found in history, and returns an error if it
+    /// fails, or if the outputs differ from those stored in history under `hash`.
+    ///
+    /// Outputs are compared through their [Debug] representations, because resources are
+    /// not required to implement [PartialEq].
+    fn rerun(&self, history: &'o History, inputs: &[u64], hash: u64) -> Result<()>;
+}
+
+/// One operation evaluation observed during a recorded view.
+pub struct RecordedExecution<'o> {
+    pub activity: &'static str,
+    pub time: Time,
+    /// The hash of the operation's output.
+    pub hash: u64,
+    /// The hashes of the values the operation read, in declaration order.
+    pub inputs: Vec<u64>,
+    /// Whether the output was found in history instead of being computed.
+    pub cached: bool,
+    node: &'o dyn Rerun<'o>,
+}
+
+impl<'o> RecordedExecution<'o> {
+    pub fn new(
+        activity: &'static str,
+        time: Time,
+        hash: u64,
+        inputs: Vec<u64>,
+        cached: bool,
+        node: &'o dyn Rerun<'o>,
+    ) -> Self {
+        RecordedExecution {
+            activity,
+            time,
+            hash,
+            inputs,
+            cached,
+            node,
+        }
+    }
+
+    /// Runs this evaluation again on the current thread. See [Rerun::rerun].
+    pub fn rerun(&self, history: &'o History) -> Result<()> {
+        self.node.rerun(history, &self.inputs, self.hash)
+    }
+
+    /// The address of the evaluated operation.
+    pub(crate) fn node_address(&self) -> usize {
+        self.node as *const dyn Rerun<'o> as *const () as usize
+    }
+}
+
+impl Debug for RecordedExecution<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecordedExecution")
+            .field("activity", &self.activity)
+            .field("time", &self.time)
+            .field("hash", &self.hash)
+            .field("inputs", &self.inputs)
+            .field("cached", &self.cached)
+            .finish()
+    }
+}
+
+/// Collects operation evaluations in the order they happened, across all threads.
+#[derive(Default)]
+pub struct Recorder<'o>(SegQueue<RecordedExecution<'o>>);
+
+impl<'o> Recorder<'o> {
+    pub fn record(&self, execution: RecordedExecution<'o>) {
+        self.0.push(execution);
+    }
+
+    /// Finishes the recording. The operations were allocated in `arena`, which the recording
+    /// keeps alive.
+    pub fn into_recording(self, arena: Arc<Arena>) -> Recording<'o> {
+        Recording {
+            executions: self.0.into_iter().collect(),
+            _arena: Some(arena),
+            _activities: vec![],
+        }
+    }
+}
+
+/// The schedule of operation evaluations during a view, from
+/// [Plan::view_recorded][crate::Plan::view_recorded].
+#[derive(Default)]
+pub struct Recording<'o> {
+    pub executions: Vec<RecordedExecution<'o>>,
+    /// Keeps the recorded operations alive after [Plan::compact][crate::Plan::compact].
+    _arena: Option<Arc<Arena>>,
+    /// Keeps the activities that the recorded operations borrow alive after they are removed
+    /// from the plan.
+    _activities: Vec<Box<dyn Send + Sync + 'o>>,
+}
+
+impl<'o> Recording<'o> {
+    pub(crate) fn keep_alive(&mut self, activity: Box<dyn Send + Sync + 'o>) {
+        self._activities.push(activity);
+    }
+}
+
+impl Debug for Recording<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recording")
+            .field("executions", &self.executions)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A cached value that could not be reproduced by re-evaluating its operation.
+///
+/// This usually means that the operation depends on hidden state, which is not allowed.
+#[derive(Debug)]
+pub struct CacheDivergence {
+    pub activity: &'static str,
+    /// The ID of the activity instance in the plan.
+    pub activity_id: ActivityId,
+    /// The index of the operation among the operations of its activity, in the order they
+    /// were declared.
+    pub op_index: usize,
+    /// The labels of the resources the operation writes.
+    pub writes: &'static [&'static str],
+    pub time: Time,
+    pub error: anyhow::Error,
+}
+
+impl Display for CacheDivergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "activity {} ({:?}, operation {}), writing {}, at {}: {:#}",
+            self.activity,
+            self.activity_id,
+            self.op_index,
+            self.writes.join(", "),
+            self.time,
+            self.error
+        )
+    }
+}
+
+struct ReportedDivergence {
+    activity: &'static str,
+    writes: &'static [&'static str],
+    time: Time,
+    node: usize,
+    error: anyhow::Error,
+}
+
+/// Collects [CacheDivergence]s found during an audited view.
+#[derive(Default)]
+pub struct CacheAudit(SegQueue<ReportedDivergence>);
+
+impl CacheAudit {
+    /// Reports a divergence of the operation at address `node`, which is used to look up the
+    /// activity ID and operation index after the view, like [OpError::new].
+    pub fn report(
+        &self,
+        activity: &'static str,
+        writes: &'static [&'static str],
+        time: Time,
+        node: *const (),
+        error: anyhow::Error,
+    ) {
+        self.0.push(ReportedDivergence {
+            activity,
+            writes,
+            time,
+            node: node as usize,
+            error,
+        });
+    }
+
+    /// Collects the divergences, using `locate` to find the activity ID and operation index
+    /// of each operation from its address, sorted like [ErrorAccumulator::into_report].
+    pub fn into_vec(
+        self,
+        locate: impl Fn(usize) -> Option<(ActivityId, usize)>,
+    ) -> Vec<CacheDivergence> {
+        // Edits need `&mut Plan`, so every operation evaluated by the view is still in it.
+        let mut divergences: Vec<_> = self
+            .0
+            .into_iter()
+            .filter_map(|reported| {
+                let (activity_id, op_index) = locate(reported.node)?;
+                Some(CacheDivergence {
+                    activity: reported.activity,
+                    activity_id,
+                    op_index,
+                    writes: reported.writes,
+                    time: reported.time,
+                    error: reported.error,
+                })
+            })
+            .collect();
+        divergences.sort_by_key(|d| (d.activity_id, d.time, d.op_index));
+        divergences
+    }
+}
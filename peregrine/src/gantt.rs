@@ -0,0 +1,215 @@
+//! A JSON export of a plan's activities and resource profiles, for Gantt chart frontends.
+//!
+//! [Plan::export_timeline] collects the activities in a window, and
+//! [TimelineExport::resource] adds the profile of a resource as segments of constant value.
+//! The export serializes to the schema below, which only changes along with
+//! [SCHEMA_VERSION], so a frontend can check it before rendering:
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "start": "2030-01-01T00:00:00.000000000Z",
+//!   "end": null,
+//!   "activities": [
+//!     {
+//!       "id": 0, "label": "Drain", "key": null,
+//!       "name": null, "subsystem": "power", "tags": ["science"],
+//!       "start": "2030-01-01T01:00:00.000000000Z", "end": "2030-01-01T02:00:00.000000000Z",
+//!       "duration": 3600.0
+//!     }
+//!   ],
+//!   "resources": [
+//!     {
+//!       "label": "battery", "unit": "Wh",
+//!       "segments": [
+//!         { "start": "2030-01-01T00:00:00.000000000Z", "end": "2030-01-01T01:00:00.000000000Z", "value": 100.0 },
+//!         { "start": "2030-01-01T01:00:00.000000000Z", "end": null, "value": 90.0 }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Times are UTC, in ISO 8601 with nanoseconds. An `end` of `null` means unbounded, and
+//! `duration` is in seconds. Values are whatever the resource's type serializes to.
+//! Neighboring segments with the same value are merged.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(exported_battery: f64);
+//! # model! { Exported(exported_battery) }
+//! pub struct Drain;
+//! impl_activity! { for Drain
+//!     @(start) {
+//!         ref mut: exported_battery -= 10.0;
+//!     }
+//!     Duration::from_hours(1.0)
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Exported>(start, initial_conditions! { exported_battery: 100.0 })?;
+//! plan.insert(start + Duration::from_hours(1.0), Drain)?;
+//!
+//! let export = plan.export_timeline(start..).resource::<exported_battery>(&plan)?;
+//! assert_eq!(1, export.activities.len());
+//! assert_eq!(2, export.resources[0].segments.len());
+//!
+//! let json = serde_json::to_value(&export)?;
+//! assert_eq!(90.0, json["resources"][0]["segments"][1]["value"]);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::activity::ActivityId;
+use crate::commands::iso_utc;
+use crate::resource::Resource;
+use crate::subscription::TimeRange;
+use crate::{Model, Plan, PlanAccess, Time};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::ops::{Bound, RangeBounds};
+
+/// The version of the [TimelineExport] schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A plan's activities and resource profiles within a window. See [gantt][crate::gantt].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimelineExport {
+    pub schema_version: u32,
+    /// The start of the window, or `None` if it is unbounded.
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub activities: Vec<ExportedActivity>,
+    pub resources: Vec<ExportedResource>,
+    #[serde(skip)]
+    window: Option<TimeRange>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedActivity {
+    pub id: ActivityId,
+    pub label: String,
+    pub key: Option<String>,
+    pub name: Option<String>,
+    pub subsystem: Option<String>,
+    pub tags: Vec<String>,
+    pub start: String,
+    pub end: String,
+    /// In seconds.
+    pub duration: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedResource {
+    pub label: String,
+    pub unit: Option<String>,
+    pub segments: Vec<Segment>,
+}
+
+/// A span of time where a resource has one value.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: String,
+    /// The end of the segment, or `None` if it lasts past the end of the window.
+    pub end: Option<String>,
+    pub value: serde_json::Value,
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// The activities that overlap `bounds`, ordered by start time, ready to add resource
+    /// profiles to with [TimelineExport::resource]. See [gantt][crate::gantt].
+    pub fn export_timeline(&self, bounds: impl RangeBounds<Time>) -> TimelineExport {
+        let window: TimeRange = (bounds.start_bound().cloned(), bounds.end_bound().cloned());
+        let overlaps = |start: Time, end: Time| {
+            let after_start = match window.0 {
+                Bound::Included(t) | Bound::Excluded(t) => end >= t,
+                Bound::Unbounded => true,
+            };
+            let before_end = match window.1 {
+                Bound::Included(t) => start <= t,
+                Bound::Excluded(t) => start < t,
+                Bound::Unbounded => true,
+            };
+            after_start && before_end
+        };
+        let activities = self
+            .spans()
+            .into_iter()
+            .filter(|span| overlaps(span.start, span.end()))
+            .map(|span| {
+                let metadata = self.metadata(span.id).cloned().unwrap_or_default();
+                ExportedActivity {
+                    id: span.id,
+                    label: span.label.to_string(),
+                    key: self.get_key(span.id).map(str::to_string),
+                    name: metadata.name,
+                    subsystem: metadata.subsystem,
+                    tags: metadata.tags,
+                    start: iso_utc(span.start),
+                    end: iso_utc(span.end()),
+                    duration: span.duration.to_seconds(),
+                }
+            })
+            .collect();
+        TimelineExport {
+            schema_version: SCHEMA_VERSION,
+            start: bound_time(window.0).map(iso_utc),
+            end: bound_time(window.1).map(iso_utc),
+            activities,
+            resources: vec![],
+            window: Some(window),
+        }
+    }
+}
+
+impl TimelineExport {
+    /// Adds the profile of a resource in `plan` within the export's window. `plan` should be
+    /// the plan the export was made from.
+    pub fn resource<'o, R: Resource<'o> + 'o>(mut self, plan: &impl PlanAccess<'o>) -> Result<Self>
+    where
+        R::Read: Serialize,
+    {
+        let window = self.window.unwrap_or((Bound::Unbounded, Bound::Unbounded));
+        let mut view = plan.view_resource::<R>(window)?;
+        view.sort_by_key(|(time, _)| *time);
+
+        let mut segments: Vec<Segment> = Vec::with_capacity(view.len());
+        for (i, (time, value)) in view.iter().enumerate() {
+            let value = serde_json::to_value(value)?;
+            if segments.last().is_some_and(|last| last.value == value) {
+                continue;
+            }
+            if let Some(last) = segments.last_mut() {
+                last.end = Some(iso_utc(*time));
+            }
+            let start = match window.0 {
+                Bound::Included(t) | Bound::Excluded(t) if i == 0 && *time < t => t,
+                _ => *time,
+            };
+            segments.push(Segment {
+                start: iso_utc(start),
+                end: None,
+                value,
+            });
+        }
+        if let Some(last) = segments.last_mut() {
+            last.end = bound_time(window.1).map(iso_utc);
+        }
+
+        self.resources.push(ExportedResource {
+            label: R::LABEL.to_string(),
+            unit: R::UNIT.map(str::to_string),
+            segments,
+        });
+        Ok(self)
+    }
+}
+
+fn bound_time(bound: Bound<Time>) -> Option<Time> {
+    match bound {
+        Bound::Included(t) | Bound::Excluded(t) => Some(t),
+        Bound::Unbounded => None,
+    }
+}
@@ -0,0 +1,163 @@
+//! A history file format built on [rkyv], which can be loaded without decoding its entries.
+//!
+//! Serializing a [History] through serde has to decode every entry of every resource before
+//! the first lookup can happen, which can take a long time for gigabyte-scale histories. This
+//! format instead stores each entry as an opaque bincode blob inside an rkyv archive, so a file
+//! can be loaded with a single validation pass, and individual entries are only decoded when
+//! the engine actually asks for them.
+//!
+//! [ArchivedHistory::read] maps the file into memory instead of reading it, and lookups borrow
+//! the archived entry in place, so the only copy of an entry is the value bincode decodes
+//! from it. Decoded entries are kept in memory, so each is decoded at most once per history.
+//!
+//! ```
+//! # use peregrine::history::archive::ArchivedHistory;
+//! # use peregrine::{History, Result, resource};
+//! resource!(counter: u32);
+//!
+//! # fn main() -> Result<()> {
+//! let history = History::new();
+//! history.init::<counter>();
+//! history.insert::<counter>(0, 5);
+//!
+//! let bytes = history.to_archive()?;
+//!
+//! let loaded = History::new().with_backend(ArchivedHistory::from_bytes(&bytes)?);
+//! loaded.init::<counter>();
+//! assert_eq!(Some(5), loaded.get::<counter>(0));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::history::{History, HistoryBackend};
+use crate::resource::ResourceHistoryPlugin;
+use anyhow::{Result, anyhow};
+use memmap2::Mmap;
+use rkyv::rancor;
+use rkyv::util::AlignedVec;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::ops::Deref;
+use std::path::Path;
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct HistoryArchive {
+    /// Maps each resource type string to the encoded entries of its history.
+    histories: HashMap<String, HashMap<u64, Vec<u8>>>,
+}
+
+/// A validated, read-only history archive.
+///
+/// Attach it to a [History] with [History::with_backend] to use it as a fallback for
+/// entries that are not in memory yet.
+pub struct ArchivedHistory {
+    bytes: ArchiveBytes,
+}
+
+enum ArchiveBytes {
+    Aligned(AlignedVec),
+    Mapped(Mmap),
+}
+
+impl Deref for ArchiveBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ArchiveBytes::Aligned(bytes) => bytes,
+            ArchiveBytes::Mapped(map) => map,
+        }
+    }
+}
+
+impl ArchivedHistory {
+    /// Validates an archive produced by [History::to_archive].
+    ///
+    /// The bytes are copied once, since rkyv needs them aligned. Use [ArchivedHistory::read]
+    /// to load a file without copying it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut aligned = AlignedVec::with_capacity(bytes.len());
+        aligned.extend_from_slice(bytes);
+        Self::validate(ArchiveBytes::Aligned(aligned))
+    }
+
+    /// Maps an archive file into memory and validates it.
+    ///
+    /// The file must not be modified while the archive is alive; rewrite it to a new path
+    /// and rename it over the old one instead.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the map is only read, and the caller doesn't modify the file while it is
+        // mapped, as documented above. Maps are page-aligned, which is enough for rkyv.
+        let map = unsafe { Mmap::map(&file)? };
+        Self::validate(ArchiveBytes::Mapped(map))
+    }
+
+    fn validate(bytes: ArchiveBytes) -> Result<Self> {
+        rkyv::access::<ArchivedHistoryArchive, rancor::Error>(&bytes)
+            .map_err(|e| anyhow!("invalid history archive: {e}"))?;
+        Ok(ArchivedHistory { bytes })
+    }
+
+    fn archive(&self) -> &ArchivedHistoryArchive {
+        // Safety: the bytes were validated on construction and are never mutated.
+        unsafe { rkyv::access_unchecked::<ArchivedHistoryArchive>(&self.bytes) }
+    }
+
+    /// The total number of entries in the archive, across all resource types.
+    pub fn len(&self) -> usize {
+        self.archive()
+            .histories
+            .values()
+            .map(|entries| entries.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl HistoryBackend for ArchivedHistory {
+    /// Finds a single entry, without touching any other part of the archive, and lends it out
+    /// in place.
+    fn get(&self, type_string: &str, hash: u64) -> Option<Cow<'_, [u8]>> {
+        self.archive()
+            .histories
+            .get(type_string)?
+            .get(&rkyv::Archived::<u64>::from_native(hash))
+            .map(|bytes| Cow::Borrowed(bytes.as_slice()))
+    }
+
+    fn insert(&self, _type_string: &'static str, _hash: u64, _value: Vec<u8>) {}
+}
+
+impl History {
+    /// Writes all in-memory entries into the archive format.
+    ///
+    /// Unlike serde serialization, this does not take the entries out of the history.
+    pub fn to_archive(&self) -> Result<Vec<u8>> {
+        let maps = self.maps.read();
+        let mut histories = HashMap::new();
+
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            let type_string = plugin.write_type_string();
+            if !histories.contains_key(&type_string)
+                && let Some(entries) = plugin.encode_entries(&maps)
+            {
+                histories.insert(type_string, entries.into_iter().collect());
+            }
+        }
+
+        let bytes = rkyv::to_bytes::<rancor::Error>(&HistoryArchive { histories })
+            .map_err(|e| anyhow!("could not archive history: {e}"))?;
+        Ok(bytes.to_vec())
+    }
+
+    /// Writes all in-memory entries to an archive file.
+    pub fn write_archive(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_archive()?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! A persistent history store in a single append-only file.
+//!
+//! Each record is the type string, the hash, and the bincode-encoded value. The file is
+//! scanned once when it is opened to build an index of record offsets, and values are only
+//! read back when they are requested.
+
+use crate::history::HistoryBackend;
+use anyhow::Result;
+use parking_lot::{Mutex, RwLock};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The offset and length of each value in the file, by type string and hash.
+type Index = HashMap<&'static str, HashMap<u64, (u64, u32)>>;
+
+struct DiskFile {
+    file: File,
+    end: u64,
+}
+
+/// A [HistoryBackend] that persists every entry to a file.
+///
+/// Usually used as the cold tier behind an in-memory history; see [History::tiered].
+///
+/// [History::tiered]: crate::History::tiered
+pub struct DiskHistory {
+    /// Locked for every read and write, and held by writers until the index is updated, so
+    /// that an entry is never written twice.
+    file: Mutex<DiskFile>,
+    index: RwLock<Index>,
+}
+
+impl DiskHistory {
+    /// Opens the history file at `path`, creating it if necessary.
+    ///
+    /// A truncated record at the end of the file (for example from a crash mid-write) is
+    /// discarded and overwritten by the next insert.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let file_len = file.metadata()?.len();
+        let mut index = Index::new();
+        let mut reader = BufReader::new(&file);
+        let mut end = 0u64;
+        while let Ok(((type_string, hash), value_len, record_len)) =
+            Self::read_header(&mut reader, file_len - end)
+        {
+            if reader.seek_relative(value_len as i64).is_err() {
+                break;
+            }
+            // Type strings are looked up by the `&'static str`s that resources give their
+            // history, so the few distinct ones in the file live as long as those.
+            let type_string = match index.get_key_value(type_string.as_str()) {
+                Some((existing, _)) => *existing,
+                None => Box::leak(type_string.into_boxed_str()),
+            };
+            index
+                .entry(type_string)
+                .or_default()
+                .insert(hash, (end + record_len - value_len as u64, value_len));
+            end += record_len;
+        }
+        drop(reader);
+        file.set_len(end)?;
+
+        Ok(DiskHistory {
+            file: Mutex::new(DiskFile { file, end }),
+            index: RwLock::new(index),
+        })
+    }
+
+    /// Reads a record header, returning the key, the value length, and the total record length.
+    ///
+    /// Records longer than the `remaining` bytes of the file are truncated, and are refused
+    /// before anything is allocated for them, since a corrupted length could be anything.
+    fn read_header(
+        reader: &mut impl Read,
+        remaining: u64,
+    ) -> std::io::Result<((String, u64), u32, u64)> {
+        let truncated = || std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+        let mut u32_buf = [0; 4];
+        reader.read_exact(&mut u32_buf)?;
+        let type_string_len = u32::from_le_bytes(u32_buf) as u64;
+        if 16 + type_string_len > remaining {
+            return Err(truncated());
+        }
+        let mut type_string = vec![0; type_string_len as usize];
+        reader.read_exact(&mut type_string)?;
+        let mut hash = [0; 8];
+        reader.read_exact(&mut hash)?;
+        reader.read_exact(&mut u32_buf)?;
+        let value_len = u32::from_le_bytes(u32_buf);
+
+        let record_len = 16 + type_string_len + value_len as u64;
+        if record_len > remaining {
+            return Err(truncated());
+        }
+        let type_string = String::from_utf8(type_string)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok((
+            (type_string, u64::from_le_bytes(hash)),
+            value_len,
+            record_len,
+        ))
+    }
+
+    /// The number of entries stored in the file.
+    pub fn len(&self) -> usize {
+        self.index.read().values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl HistoryBackend for DiskHistory {
+    fn get(&self, type_string: &str, hash: u64) -> Option<Cow<'_, [u8]>> {
+        let (offset, len) = *self.index.read().get(type_string)?.get(&hash)?;
+        let mut buf = vec![0; len as usize];
+        let mut file = self.file.lock();
+        file.file.seek(SeekFrom::Start(offset)).ok()?;
+        file.file.read_exact(&mut buf).ok()?;
+        Some(Cow::Owned(buf))
+    }
+
+    fn insert(&self, type_string: &'static str, hash: u64, value: Vec<u8>) {
+        self.insert_batch(vec![(type_string, hash, value)]);
+    }
+
+    /// Appends all new entries with a single write.
+    fn insert_batch(&self, entries: Vec<(&'static str, u64, Vec<u8>)>) {
+        let mut file = self.file.lock();
+        let mut records = vec![];
+        let mut new_entries = vec![];
+        {
+            let index = self.index.read();
+            for (type_string, hash, value) in entries {
+                let written = index
+                    .get(type_string)
+                    .is_some_and(|hashes| hashes.contains_key(&hash));
+                if written
+                    || new_entries
+                        .iter()
+                        .any(|(t, h, _)| *t == type_string && *h == hash)
+                {
+                    continue;
+                }
+                records.extend_from_slice(&(type_string.len() as u32).to_le_bytes());
+                records.extend_from_slice(type_string.as_bytes());
+                records.extend_from_slice(&hash.to_le_bytes());
+                records.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                let value_offset = file.end + records.len() as u64;
+                records.extend_from_slice(&value);
+                new_entries.push((type_string, hash, (value_offset, value.len() as u32)));
+            }
+        }
+        if records.is_empty() {
+            return;
+        }
+
+        let end = file.end;
+        let written = file
+            .file
+            .seek(SeekFrom::Start(end))
+            .and_then(|_| file.file.write_all(&records));
+        if written.is_ok() {
+            file.end += records.len() as u64;
+            let mut index = self.index.write();
+            for (type_string, hash, location) in new_entries {
+                index.entry(type_string).or_default().insert(hash, location);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.file.lock().file.sync_data();
+    }
+}
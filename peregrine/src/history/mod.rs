@@ -0,0 +1,751 @@
+//! The record of every value operations have written, shared by all plans in a [Session][crate::Session].
+//!
+//! A [History] holds one container per resource type, which maps operation hashes to the values
+//! written under them. `resource!` picks [CopyHistory] or [DerefHistory] depending on whether
+//! the resource is `ref`, but any type that implements [HistoryAdapter] can be used instead, with
+//! a `#[history(Type)]` attribute. This is useful for domain-specific storage, like compressing
+//! trajectories that change slowly, or sharing a large value between entries.
+//!
+//! The smallest useful container keeps its entries in a map behind a lock:
+//!
+//! ```
+//! # use peregrine::*;
+//! use peregrine::history::{HistoryAdapter, HistoryContainer};
+//! use serde::{Deserialize, Serialize};
+//! use std::collections::BTreeMap;
+//! use std::sync::RwLock;
+//!
+//! #[derive(Debug, Default, Serialize, Deserialize)]
+//! pub struct LockedHistory(RwLock<BTreeMap<u64, u32>>);
+//!
+//! impl Clone for LockedHistory {
+//!     fn clone(&self) -> Self {
+//!         LockedHistory(RwLock::new(self.0.read().unwrap().clone()))
+//!     }
+//! }
+//!
+//! impl HistoryContainer for LockedHistory {
+//!     fn len(&self) -> usize {
+//!         self.0.read().unwrap().len()
+//!     }
+//!     fn evict(&mut self, count: usize) {
+//!         let entries = self.0.get_mut().unwrap();
+//!         for _ in 0..count {
+//!             entries.pop_first();
+//!         }
+//!     }
+//!     fn absorb(&self, other: Self) {
+//!         let mut entries = self.0.write().unwrap();
+//!         for (hash, value) in other.0.into_inner().unwrap() {
+//!             entries.entry(hash).or_insert(value);
+//!         }
+//!     }
+//!     fn encode_entries(&self) -> Vec<(u64, Vec<u8>)> {
+//!         let config = bincode::config::standard();
+//!         self.0.read().unwrap().iter()
+//!             .map(|(hash, value)| (*hash, bincode::serde::encode_to_vec(value, config).unwrap()))
+//!             .collect()
+//!     }
+//!     fn decode_entries(entries: Vec<(u64, Vec<u8>)>) -> Result<Self> {
+//!         let config = bincode::config::standard();
+//!         let mut decoded = BTreeMap::new();
+//!         for (hash, bytes) in entries {
+//!             decoded.insert(hash, bincode::serde::decode_from_slice(&bytes, config)?.0);
+//!         }
+//!         Ok(LockedHistory(RwLock::new(decoded)))
+//!     }
+//! }
+//!
+//! // Read by value, like the default container for resources that aren't `ref`.
+//! impl HistoryAdapter<u32, u32> for LockedHistory {
+//!     fn insert(&self, hash: u64, value: u32) -> u32 {
+//!         self.0.write().unwrap().insert(hash, value);
+//!         value
+//!     }
+//!     fn get(&self, hash: u64) -> Option<u32> {
+//!         self.0.read().unwrap().get(&hash).copied()
+//!     }
+//! }
+//!
+//! resource! {
+//!     #[history(LockedHistory)]
+//!     counted_sol: u32
+//! }
+//! # model! { Counted(counted_sol) }
+//! # pub struct Sol;
+//! # impl_activity! { for Sol
+//! #     @(start) {
+//! #         ref mut: counted_sol += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Counted>(start, initial_conditions! { counted_sol: 0 })?;
+//! plan.insert(start + Duration::from_seconds(1.0), Sol)?;
+//! assert_eq!(1, plan.sample::<counted_sol>(start + Duration::from_seconds(2.0))?);
+//!
+//! // A second plan finds the value in history instead of simulating it.
+//! let mut other = session.new_plan::<Counted>(start, initial_conditions! { counted_sol: 0 })?;
+//! other.insert(start + Duration::from_seconds(1.0), Sol)?;
+//! assert_eq!(1, other.sample::<counted_sol>(start + Duration::from_seconds(2.0))?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A history has one container of each type, so resources with the same container type share
+//! it. That is fine, because operation hashes are unique across resources.
+
+#[cfg(feature = "rkyv")]
+pub mod archive;
+pub mod disk;
+pub mod portable;
+pub mod remote;
+pub mod tiered;
+
+use crate::memory::HistoryMemory;
+use crate::resource::Resource;
+use crate::resource::ResourceHistoryPlugin;
+use bincode::config::standard;
+use dashmap::DashMap;
+use parking_lot::RwLock;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use stable_deref_trait::StableDeref;
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, Hasher};
+use std::mem::swap;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use type_map::concurrent::{Entry, TypeMap};
+use type_reg::untagged::TypeReg;
+
+#[doc(hidden)]
+pub type PeregrineDefaultHashBuilder = foldhash::fast::FixedState;
+
+/// A secondary store behind the in-memory history, such as a file or a cache shared over
+/// the network.
+///
+/// Entries are addressed by the resource's write type string (see
+/// [ResourceHistoryPlugin::write_type_string]) and the operation hash, and values are
+/// bincode-encoded. Since history is only a cache, backends are allowed to lose entries;
+/// failures should be reported as misses rather than panicking.
+pub trait HistoryBackend: Send + Sync {
+    /// Backends that keep their entries in memory can lend them out instead of copying.
+    fn get(&self, type_string: &str, hash: u64) -> Option<Cow<'_, [u8]>>;
+
+    /// Called for every entry inserted into the in-memory history. Read-only backends can
+    /// ignore it.
+    fn insert(&self, type_string: &'static str, hash: u64, value: Vec<u8>);
+
+    /// Inserts several entries at once. Backends that pay a cost per write, like a file or
+    /// a network round trip, can override this to write them together.
+    fn insert_batch(&self, entries: Vec<(&'static str, u64, Vec<u8>)>) {
+        for (type_string, hash, value) in entries {
+            self.insert(type_string, hash, value);
+        }
+    }
+
+    /// Blocks until all previous inserts are durable.
+    fn flush(&self) {}
+}
+
+#[derive(Default)]
+pub struct History {
+    maps: RwLock<TypeMap>,
+    backend: Option<Box<dyn HistoryBackend>>,
+    hot_capacity: Option<usize>,
+    /// Inserts since the hot tier was last checked against its capacity.
+    unchecked_inserts: AtomicUsize,
+}
+
+/// Finds the write type string of a resource from the registered history plugins.
+pub(crate) fn type_string<'h, R: Resource<'h>>() -> Option<&'static str> {
+    static TYPE_STRINGS: OnceLock<HashMap<TypeId, String>> = OnceLock::new();
+    TYPE_STRINGS
+        .get_or_init(|| {
+            inventory::iter::<&'static dyn ResourceHistoryPlugin>
+                .into_iter()
+                .map(|plugin| (plugin.history_type_id(), plugin.write_type_string()))
+                .collect()
+        })
+        .get(&TypeId::of::<R::History>())
+        .map(String::as_str)
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::from(TypeMap::new())
+    }
+
+    /// Places a backend behind the in-memory history. Misses are looked up in the backend,
+    /// and new entries are written through to it.
+    pub fn with_backend(mut self, backend: impl HistoryBackend + 'static) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    /// Blocks until every entry inserted so far is durable in the backend, if there is one.
+    pub fn flush(&self) {
+        if let Some(backend) = &self.backend {
+            backend.flush();
+        }
+    }
+
+    pub fn init<'h, R: Resource<'h>>(&self) {
+        match self.maps.write().entry::<R::History>() {
+            Entry::Occupied(_) => {}
+            Entry::Vacant(v) => {
+                v.insert(R::History::default());
+            }
+        }
+    }
+    pub fn insert<'h, R: Resource<'h>>(&'h self, hash: u64, value: R::Write) -> R::Read {
+        if let Some(backend) = &self.backend
+            && let Some(type_string) = type_string::<R>()
+            && let Ok(bytes) = bincode::serde::encode_to_vec(&value, standard())
+        {
+            backend.insert(type_string, hash, bytes);
+        }
+        let maps = self.maps.read();
+        let read = maps.get::<R::History>().unwrap().insert(hash, value);
+        if let Some(capacity) = self.hot_capacity {
+            self.trim_shared(&maps, capacity);
+        }
+        read
+    }
+    pub fn get<'h, R: Resource<'h>>(&'h self, hash: u64) -> Option<R::Read> {
+        let found = self.maps.read().get::<R::History>()?.get(hash);
+
+        if found.is_none()
+            && let Some(backend) = &self.backend
+        {
+            // The maps aren't locked during the lookup, which can be a disk read or a network
+            // round trip.
+            let type_string = type_string::<R>()?;
+            let bytes = backend.get(type_string, hash)?;
+            return match bincode::serde::decode_from_slice(&bytes, standard()) {
+                Ok((value, _)) => {
+                    let maps = self.maps.read();
+                    let read = maps.get::<R::History>()?.insert(hash, value);
+                    if let Some(capacity) = self.hot_capacity {
+                        self.trim_shared(&maps, capacity);
+                    }
+                    Some(read)
+                }
+                Err(_error) => {
+                    // Treated as a miss, like an entry the backend lost, so the operation is
+                    // simulated again instead.
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        type_string,
+                        hash,
+                        error = %_error,
+                        "could not decode a history entry from the backend"
+                    );
+                    None
+                }
+            };
+        }
+
+        found
+    }
+    /// Unions the entries of another history into this one.
+    ///
+    /// Since a hash always maps to the same value, entries that are present in both
+    /// histories are kept as they are in `self` without being compared.
+    pub fn merge(&self, other: History) {
+        let mut from = other.into_inner();
+        let mut into = self.maps.write();
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            plugin.merge(&mut into, &mut from);
+        }
+    }
+    /// Measures each container that has entries, largest first. See
+    /// [Session::memory_report][crate::Session::memory_report].
+    pub fn memory_report(&self) -> Vec<HistoryMemory> {
+        let maps = self.maps.read();
+        let mut containers = HashMap::<TypeId, HistoryMemory>::new();
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            containers
+                .entry(plugin.history_type_id())
+                .or_insert_with(|| HistoryMemory {
+                    write_type: plugin.write_type_string(),
+                    resources: vec![],
+                    entries: plugin.history_len(&maps),
+                    bytes: plugin.history_bytes(&maps),
+                })
+                .resources
+                .push(plugin.resource_label());
+        }
+        let mut report = containers
+            .into_values()
+            .filter(|container| container.entries > 0)
+            .map(|mut container| {
+                container.resources.sort();
+                container.resources.dedup();
+                container
+            })
+            .collect::<Vec<_>>();
+        report.sort_by(|a, b| {
+            b.bytes
+                .cmp(&a.bytes)
+                .then_with(|| a.write_type.cmp(&b.write_type))
+        });
+        report
+    }
+    pub fn take_inner(&self) -> TypeMap {
+        let mut replacement = TypeMap::new();
+        swap(&mut *self.maps.write(), &mut replacement);
+        replacement
+    }
+    pub fn into_inner(self) -> TypeMap {
+        self.maps.into_inner()
+    }
+}
+
+impl From<TypeMap> for History {
+    fn from(value: TypeMap) -> Self {
+        History {
+            maps: RwLock::new(value),
+            backend: None,
+            hot_capacity: None,
+            unchecked_inserts: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// The storage of one resource type's history: a map from operation hashes to values.
+///
+/// These are the parts of a history container that don't depend on the resource's read and
+/// write types, so that the engine can serialize, merge, and evict histories without knowing
+/// what is in them. The container itself is serialized when a [History] is, and its entries
+/// are encoded one by one for the formats that store them separately, like
+/// [History::export_portable] and [DiskHistory][disk::DiskHistory].
+pub trait HistoryContainer:
+    Default + Clone + Debug + Serialize + DeserializeOwned + Send + Sync + 'static
+{
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes up to `count` entries, preferably the least recently used.
+    fn evict(&mut self, count: usize);
+
+    /// Like [HistoryContainer::evict], but while other threads may be reading and inserting,
+    /// and returns the number of entries removed. Containers whose readers borrow from them,
+    /// like [DerefHistory], can't remove anything while they are shared, and keep the default,
+    /// which removes nothing.
+    fn evict_shared(&self, _count: usize) -> usize {
+        0
+    }
+
+    /// Adds the entries of `other`. Existing entries must not be replaced, because
+    /// references into them may be alive.
+    fn absorb(&self, other: Self);
+
+    /// Encodes every entry with bincode, for formats that store values as opaque bytes.
+    fn encode_entries(&self) -> Vec<(u64, Vec<u8>)>;
+
+    /// Decodes entries produced by [HistoryContainer::encode_entries] into a new container.
+    fn decode_entries(entries: Vec<(u64, Vec<u8>)>) -> anyhow::Result<Self>;
+
+    /// An estimate of the bytes of memory the container holds, for
+    /// [Session::memory_report][crate::Session::memory_report]. The default encodes every
+    /// entry to measure it, so containers that can count their memory directly should.
+    fn memory_bytes(&self) -> usize {
+        self.encode_entries()
+            .iter()
+            .map(|(hash, bytes)| size_of_val(hash) + bytes.len())
+            .sum()
+    }
+}
+
+/// How a resource stores the values written to it, and hands them back to readers.
+///
+/// A value is inserted once for each operation hash, as the write type `W`, and read any
+/// number of times as the read type `R`. Reads happen concurrently with inserts, from many
+/// threads, and a read of a hash must always return the value that was inserted under it.
+/// When `R` borrows from the container, like the `&str` of a `ref` resource, the borrowed data
+/// must not move or be dropped while the container is alive, except through
+/// [HistoryContainer::evict], which the engine only calls when nothing is borrowing it.
+///
+/// [CopyHistory] and [DerefHistory] are the containers `resource!` picks by default.
+/// See the [module documentation][self] for an example of a custom one.
+pub trait HistoryAdapter<W, R>: HistoryContainer {
+    /// Stores `value` under `hash`, and returns it as it will be read. If there is already a
+    /// value under `hash`, it is equal to `value`, and either can be kept.
+    fn insert(&self, hash: u64, value: W) -> R;
+
+    fn get(&self, hash: u64) -> Option<R>;
+}
+
+const DASHMAP_STARTING_CAPACITY: usize = 1000;
+
+/// Advances whenever a value is inserted into any history, to order entries by when they were
+/// last used.
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// A history value, with the last time it was inserted or read on the [CLOCK], so that
+/// containers can evict the least recently used entries first. Serialized as just the value.
+struct Stamped<T> {
+    value: T,
+    used: AtomicU64,
+}
+
+impl<T> Stamped<T> {
+    fn new(value: T) -> Self {
+        Stamped {
+            value,
+            used: AtomicU64::new(CLOCK.fetch_add(1, Ordering::Relaxed)),
+        }
+    }
+
+    fn touch(&self) -> &T {
+        self.used
+            .store(CLOCK.load(Ordering::Relaxed), Ordering::Relaxed);
+        &self.value
+    }
+}
+
+impl<T: Clone> Clone for Stamped<T> {
+    fn clone(&self) -> Self {
+        Stamped {
+            value: self.value.clone(),
+            used: AtomicU64::new(self.used.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<T: Debug> Debug for Stamped<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<T: Serialize> Serialize for Stamped<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Stamped<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Stamped::new)
+    }
+}
+
+/// The history of resources that are read by value. See [Resource].
+///
+/// Readers copy values out, so entries can be evicted at any time to keep a
+/// [tiered][History::tiered] history within its capacity.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CopyHistory<T: Copy + Clone>(DashMap<u64, Stamped<T>, PassThroughHashBuilder>);
+
+impl<T: Copy + Clone> Default for CopyHistory<T> {
+    fn default() -> Self {
+        CopyHistory(DashMap::with_capacity_and_hasher(
+            DASHMAP_STARTING_CAPACITY,
+            PassThroughHashBuilder,
+        ))
+    }
+}
+
+impl<T> HistoryContainer for CopyHistory<T>
+where
+    T: Copy + Debug + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn evict(&mut self, count: usize) {
+        evict_least_recent(&self.0, count);
+    }
+
+    fn evict_shared(&self, count: usize) -> usize {
+        evict_least_recent(&self.0, count)
+    }
+
+    fn absorb(&self, other: Self) {
+        for (hash, value) in other.0 {
+            self.0.entry(hash).or_insert(value);
+        }
+    }
+
+    fn encode_entries(&self) -> Vec<(u64, Vec<u8>)> {
+        encode_entries(&self.0)
+    }
+
+    fn decode_entries(entries: Vec<(u64, Vec<u8>)>) -> anyhow::Result<Self> {
+        let history = Self::default();
+        decode_entries(&history.0, entries)?;
+        Ok(history)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.0.capacity() * size_of::<(u64, Stamped<T>)>()
+    }
+}
+
+impl<T> HistoryAdapter<T, T> for CopyHistory<T>
+where
+    T: Copy + Debug + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn insert(&self, hash: u64, value: T) -> T {
+        self.0.insert(hash, Stamped::new(value));
+        value
+    }
+
+    fn get(&self, hash: u64) -> Option<T> {
+        self.0.get(&hash).map(|r| *r.touch())
+    }
+}
+
+/// The history of `ref` resources, read through a reference to their [Deref][std::ops::Deref]
+/// target. See [Resource].
+///
+/// Readers borrow values from the container, so entries are only evicted when nothing is
+/// borrowing it, by [History::enforce_capacity].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DerefHistory<T: StableDeref + Clone>(DashMap<u64, Stamped<T>, PassThroughHashBuilder>);
+
+impl<T: StableDeref + Clone> Default for DerefHistory<T> {
+    fn default() -> Self {
+        DerefHistory(DashMap::with_capacity_and_hasher(
+            DASHMAP_STARTING_CAPACITY,
+            PassThroughHashBuilder,
+        ))
+    }
+}
+
+impl<T> HistoryContainer for DerefHistory<T>
+where
+    T: StableDeref + Clone + Debug + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn evict(&mut self, count: usize) {
+        evict_least_recent(&self.0, count);
+    }
+
+    fn absorb(&self, other: Self) {
+        for (hash, value) in other.0 {
+            self.0.entry(hash).or_insert(value);
+        }
+    }
+
+    fn encode_entries(&self) -> Vec<(u64, Vec<u8>)> {
+        encode_entries(&self.0)
+    }
+
+    fn decode_entries(entries: Vec<(u64, Vec<u8>)>) -> anyhow::Result<Self> {
+        let history = Self::default();
+        decode_entries(&history.0, entries)?;
+        Ok(history)
+    }
+
+    /// Counts the targets the entries point to, but not anything those targets point to in
+    /// turn.
+    fn memory_bytes(&self) -> usize {
+        self.0.capacity() * size_of::<(u64, Stamped<T>)>()
+            + self
+                .0
+                .iter()
+                .map(|entry| size_of_val::<T::Target>(&entry.value().value))
+                .sum::<usize>()
+    }
+}
+
+impl<'h, T> HistoryAdapter<T, &'h T::Target> for DerefHistory<T>
+where
+    T: StableDeref + Clone + Debug + Serialize + DeserializeOwned + Send + Sync + 'static,
+    T: From<&'h T::Target>,
+    Self: 'h,
+{
+    fn insert(&self, hash: u64, value: T) -> &'h T::Target {
+        let inserted: *const T = &self.0.entry(hash).or_insert(Stamped::new(value)).value;
+        unsafe { &*inserted }
+    }
+
+    fn get(&self, hash: u64) -> Option<&'h T::Target> {
+        self.0.get(&hash).map(|r| unsafe {
+            let value: *const T = r.touch();
+            &**value
+        })
+    }
+}
+
+/// Removes the `count` least recently used entries, and returns how many there were.
+fn evict_least_recent<T>(
+    map: &DashMap<u64, Stamped<T>, PassThroughHashBuilder>,
+    count: usize,
+) -> usize {
+    let mut stamps: Vec<(u64, u64)> = map
+        .iter()
+        .map(|entry| (entry.used.load(Ordering::Relaxed), *entry.key()))
+        .collect();
+    let count = count.min(stamps.len());
+    if count == 0 {
+        return 0;
+    }
+    stamps.select_nth_unstable(count - 1);
+    for (_, hash) in &stamps[..count] {
+        map.remove(hash);
+    }
+    count
+}
+
+fn encode_entries<T: Serialize>(
+    map: &DashMap<u64, Stamped<T>, PassThroughHashBuilder>,
+) -> Vec<(u64, Vec<u8>)> {
+    map.iter()
+        .map(|entry| {
+            (
+                *entry.key(),
+                bincode::serde::encode_to_vec(&entry.value().value, standard())
+                    .expect("could not encode history entry"),
+            )
+        })
+        .collect()
+}
+
+fn decode_entries<T: DeserializeOwned>(
+    map: &DashMap<u64, Stamped<T>, PassThroughHashBuilder>,
+    entries: Vec<(u64, Vec<u8>)>,
+) -> anyhow::Result<()> {
+    for (hash, bytes) in entries {
+        let (value, _) = bincode::serde::decode_from_slice(&bytes, standard())?;
+        map.insert(hash, Stamped::new(value));
+    }
+    Ok(())
+}
+
+/// The history of resources that are never stored, like the groundings of operations.
+impl HistoryContainer for () {
+    fn len(&self) -> usize {
+        0
+    }
+
+    fn evict(&mut self, _count: usize) {}
+
+    fn absorb(&self, _other: Self) {}
+
+    fn encode_entries(&self) -> Vec<(u64, Vec<u8>)> {
+        vec![]
+    }
+
+    fn decode_entries(_entries: Vec<(u64, Vec<u8>)>) -> anyhow::Result<Self> {
+        Ok(())
+    }
+
+    fn memory_bytes(&self) -> usize {
+        0
+    }
+}
+
+impl<W, R> HistoryAdapter<W, R> for () {
+    fn insert(&self, _hash: u64, _value: W) -> R {
+        unreachable!()
+    }
+
+    fn get(&self, _hash: u64) -> Option<R> {
+        None
+    }
+}
+
+// i suspect the compiler will be able to turn this into a no-op
+#[doc(hidden)]
+pub struct PassThroughHasher(u64);
+
+impl Hasher for PassThroughHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!()
+    }
+    fn write_u8(&mut self, _i: u8) {
+        unreachable!()
+    }
+    fn write_u16(&mut self, _i: u16) {
+        unreachable!()
+    }
+    fn write_u32(&mut self, _i: u32) {
+        unreachable!()
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+
+    fn write_usize(&mut self, _i: usize) {
+        unreachable!()
+    }
+}
+
+#[doc(hidden)]
+#[derive(Copy, Clone, Default)]
+pub struct PassThroughHashBuilder;
+
+impl BuildHasher for PassThroughHashBuilder {
+    type Hasher = PassThroughHasher;
+
+    fn build_hasher(&self) -> PassThroughHasher {
+        PassThroughHasher(0)
+    }
+}
+
+inventory::collect!(&'static dyn ResourceHistoryPlugin);
+
+impl Serialize for History {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut ser_type_map = type_reg::untagged::TypeMap::<String>::new();
+
+        let mut taken = self.take_inner();
+
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            if !ser_type_map.contains_key(&plugin.write_type_string()) {
+                plugin.ser(&mut taken, &mut ser_type_map)
+            }
+        }
+
+        ser_type_map.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for History {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut type_reg = TypeReg::<String>::new();
+
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            plugin.register(&mut type_reg);
+        }
+
+        let mut de_type_map = type_reg.deserialize_map(deserializer)?;
+
+        let mut result = TypeMap::new();
+
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            plugin.de(&mut result, &mut de_type_map);
+        }
+
+        Ok(result.into())
+    }
+}
@@ -0,0 +1,336 @@
+//! A versioned, self-describing history format for shipping histories between machines.
+//!
+//! Serde serialization of a [History] relies on both ends agreeing on the exact set of
+//! registered resources, and silently loads anything it is given. The portable format instead
+//! records, for every history container, the type string and the names of the resources that
+//! share it, and protects both the whole file and each container with a CRC32 checksum. An
+//! imported history is fully decoded and validated before it is handed to a session.
+//!
+//! Sections are matched to this program's resources by name, so the entries of a resource
+//! whose write type changed are checked against its new type, rather than skipped or loaded
+//! into another resource that now has the old type.
+//!
+//! The layout is an 8 byte magic string, a little-endian `u32` format version, a bincode-encoded
+//! list of sections, and finally a little-endian `u32` checksum of the encoded sections.
+//!
+//! ## Schemas
+//!
+//! Entries are encoded with bincode, which doesn't describe the values it encodes, so entries
+//! written before a resource's type changed may decode as garbage instead of failing. Each
+//! section therefore records the [Schema] of its type: the `#[schema_version = N]` declared on
+//! the resource (0 if there isn't one), and a fingerprint of the type's name, size, and
+//! version. A section whose schema doesn't match this program's is refused on import, unless
+//! a [Migrations] converts its entries:
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::history::portable::{Migrations, Schema};
+//! # use bincode::config::standard;
+//! #[derive(Copy, Clone, Debug, serde::Serialize, serde::Deserialize)]
+//! pub struct Reading { volts: f32, amps: f32 }
+//!
+//! resource! {
+//!     // Version 0 stored only the volts.
+//!     #[schema_version = 1]
+//!     migrated_reading: Reading
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # // What a program from before the bump, where `migrated_reading` was an `f32`, exported
+//! # // after inserting 3.3 volts under hash 7.
+//! # let entries = vec![(7u64, bincode::serde::encode_to_vec(3.3f32, standard())?)];
+//! # let mut checksum = crc32fast::Hasher::new();
+//! # checksum.update(&7u64.to_le_bytes());
+//! # checksum.update(&entries[0].1);
+//! # let section = ("f32", vec!["migrated_reading"], Some(Schema::new("f32", 4, 0)), entries, checksum.finalize());
+//! # let body = bincode::serde::encode_to_vec(vec![section], standard())?;
+//! # let mut old_bytes = b"PRGNHIST".to_vec();
+//! # old_bytes.extend_from_slice(&2u32.to_le_bytes());
+//! # old_bytes.extend_from_slice(&body);
+//! # old_bytes.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+//! assert!(History::import_portable(&old_bytes).is_err());
+//!
+//! let migrations = Migrations::new()
+//!     .add::<migrated_reading, f32>(0, |volts| Reading { volts, amps: 0.0 });
+//! let history = History::import_portable_with(&old_bytes, &migrations)?;
+//!
+//! history.init::<migrated_reading>();
+//! let reading = history.get::<migrated_reading>(7).unwrap();
+//! assert_eq!((3.3, 0.0), (reading.volts, reading.amps));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Since history is only a cache, [Migrations::discard_mismatched] can instead drop the
+//! sections that don't match. Files written in version 1 of the format have no schemas, so
+//! only a change of a resource's write type is found in them.
+
+use crate::history::History;
+use crate::resource::{Resource, ResourceHistoryPlugin};
+use anyhow::{Result, anyhow, bail, ensure};
+use bincode::config::standard;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::TypeId;
+use std::collections::HashMap;
+use type_map::concurrent::TypeMap;
+
+const MAGIC: &[u8; 8] = b"PRGNHIST";
+
+/// The portable format version written by this build.
+pub const PORTABLE_FORMAT_VERSION: u32 = 2;
+
+/// The version and fingerprint of a resource type's encoding. See [portable][self].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schema {
+    pub version: u32,
+    pub fingerprint: u32,
+}
+
+impl Schema {
+    pub fn new(type_string: &str, size: usize, version: u32) -> Self {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(type_string.as_bytes());
+        hasher.update(&(size as u64).to_le_bytes());
+        hasher.update(&version.to_le_bytes());
+        Schema {
+            version,
+            fingerprint: hasher.finalize(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Section {
+    type_string: String,
+    resources: Vec<String>,
+    schema: Option<Schema>,
+    entries: Vec<(u64, Vec<u8>)>,
+    checksum: u32,
+}
+
+/// A section of format version 1, which has no schema.
+#[derive(Deserialize)]
+struct SectionV1 {
+    type_string: String,
+    resources: Vec<String>,
+    entries: Vec<(u64, Vec<u8>)>,
+    checksum: u32,
+}
+
+type Migration = Box<dyn Fn(&[u8]) -> Result<Vec<u8>> + Send + Sync>;
+
+/// Conversions of entries written with older schemas, for [History::import_portable_with].
+#[derive(Default)]
+pub struct Migrations {
+    migrations: HashMap<(TypeId, u32), Migration>,
+    discard_mismatched: bool,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Converts entries of `R` written with schema version `from`, which were encoded as
+    /// `Old`, to the current write type.
+    ///
+    /// Entries are stored per write type, so this applies to every resource with the same
+    /// write type as `R`.
+    pub fn add<R, Old>(
+        mut self,
+        from: u32,
+        convert: impl Fn(Old) -> R::Write + Send + Sync + 'static,
+    ) -> Self
+    where
+        R: Resource<'static>,
+        Old: DeserializeOwned,
+    {
+        self.migrations.insert(
+            (TypeId::of::<R::History>(), from),
+            Box::new(move |bytes| {
+                let (old, _) = bincode::serde::decode_from_slice(bytes, standard())?;
+                Ok(bincode::serde::encode_to_vec(convert(old), standard())?)
+            }),
+        );
+        self
+    }
+
+    /// Skips sections whose schema doesn't match and that have no migration, instead of
+    /// refusing the whole file.
+    pub fn discard_mismatched(mut self) -> Self {
+        self.discard_mismatched = true;
+        self
+    }
+}
+
+fn checksum_entries(entries: &[(u64, Vec<u8>)]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for (hash, bytes) in entries {
+        hasher.update(&hash.to_le_bytes());
+        hasher.update(bytes);
+    }
+    hasher.finalize()
+}
+
+impl History {
+    /// Exports all in-memory entries in the portable format.
+    pub fn export_portable(&self) -> Result<Vec<u8>> {
+        let maps = self.maps.read();
+        let mut sections: Vec<Section> = vec![];
+        let mut indices: HashMap<String, usize> = HashMap::new();
+
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            let type_string = plugin.write_type_string();
+            let name = plugin.resource_name();
+            if let Some(&i) = indices.get(&type_string) {
+                sections[i].resources.push(name);
+            } else if let Some(mut entries) = plugin.encode_entries(&maps) {
+                entries.sort_unstable_by_key(|(hash, _)| *hash);
+                indices.insert(type_string.clone(), sections.len());
+                sections.push(Section {
+                    type_string,
+                    resources: vec![name],
+                    schema: Some(plugin.schema()),
+                    checksum: checksum_entries(&entries),
+                    entries,
+                });
+            }
+        }
+
+        let body = bincode::serde::encode_to_vec(&sections, standard())?;
+
+        let mut result = Vec::with_capacity(body.len() + 16);
+        result.extend_from_slice(MAGIC);
+        result.extend_from_slice(&PORTABLE_FORMAT_VERSION.to_le_bytes());
+        result.extend_from_slice(&body);
+        result.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+        Ok(result)
+    }
+
+    /// Validates and imports a history exported with [History::export_portable].
+    ///
+    /// Sections whose resources are not in this program are checked and then skipped. Any
+    /// corruption, undecodable entry, or [schema][crate::history::portable#schemas] mismatch
+    /// rejects the whole file.
+    pub fn import_portable(bytes: &[u8]) -> Result<History> {
+        History::import_portable_with(bytes, &Migrations::default())
+    }
+
+    /// Imports like [History::import_portable], converting the entries of sections with
+    /// old schemas with `migrations`.
+    pub fn import_portable_with(bytes: &[u8], migrations: &Migrations) -> Result<History> {
+        ensure!(
+            bytes.len() >= MAGIC.len() + 8 && bytes.starts_with(MAGIC),
+            "not a portable peregrine history"
+        );
+        let (header, rest) = bytes.split_at(MAGIC.len() + 4);
+        let version = u32::from_le_bytes(header[MAGIC.len()..].try_into()?);
+        if version > PORTABLE_FORMAT_VERSION {
+            bail!(
+                "portable history has format version {version}, but only versions up to {PORTABLE_FORMAT_VERSION} are supported"
+            );
+        }
+
+        let (body, checksum) = rest.split_at(rest.len() - 4);
+        ensure!(
+            crc32fast::hash(body) == u32::from_le_bytes(checksum.try_into()?),
+            "portable history checksum mismatch"
+        );
+        let sections: Vec<Section> = if version == 1 {
+            let (sections, _): (Vec<SectionV1>, _) =
+                bincode::serde::decode_from_slice(body, standard())?;
+            sections
+                .into_iter()
+                .map(|section| Section {
+                    type_string: section.type_string,
+                    resources: section.resources,
+                    schema: None,
+                    entries: section.entries,
+                    checksum: section.checksum,
+                })
+                .collect()
+        } else {
+            bincode::serde::decode_from_slice(body, standard())?.0
+        };
+
+        let plugins: HashMap<String, &'static dyn ResourceHistoryPlugin> =
+            inventory::iter::<&'static dyn ResourceHistoryPlugin>
+                .into_iter()
+                .map(|plugin| (plugin.resource_name(), *plugin))
+                .collect();
+
+        let mut result = TypeMap::new();
+        for mut section in sections {
+            ensure!(
+                checksum_entries(&section.entries) == section.checksum,
+                "checksum mismatch in history section for {} (used by {})",
+                section.type_string,
+                section.resources.join(", ")
+            );
+
+            // The resources that shared the section may not share a write type anymore, so
+            // the entries are checked against each of their current types.
+            let mut targets: Vec<(&str, &'static dyn ResourceHistoryPlugin)> = vec![];
+            for name in &section.resources {
+                if let Some(plugin) = plugins.get(name)
+                    && !targets
+                        .iter()
+                        .any(|(_, t)| t.history_type_id() == plugin.history_type_id())
+                {
+                    targets.push((name, *plugin));
+                }
+            }
+
+            for (i, (name, plugin)) in targets.iter().enumerate() {
+                let mut entries = if i + 1 == targets.len() {
+                    std::mem::take(&mut section.entries)
+                } else {
+                    section.entries.clone()
+                };
+                let current = plugin.schema();
+                let type_string = plugin.write_type_string();
+                let matches = section.type_string == type_string
+                    && section.schema.is_none_or(|schema| schema == current);
+                if !matches {
+                    let version = section.schema.map_or(0, |schema| schema.version);
+                    match migrations
+                        .migrations
+                        .get(&(plugin.history_type_id(), version))
+                    {
+                        Some(migrate) => {
+                            for (_, bytes) in &mut entries {
+                                *bytes = migrate(bytes).map_err(|e| {
+                                    anyhow!(
+                                        "could not migrate history of {name} from {} at schema version {version}: {e}",
+                                        section.type_string,
+                                    )
+                                })?;
+                            }
+                        }
+                        None if migrations.discard_mismatched => continue,
+                        None => {
+                            let fingerprint = section
+                                .schema
+                                .map_or(String::new(), |s| format!(" ({:08x})", s.fingerprint));
+                            bail!(
+                                "history of {name} was written as {} with schema version {version}{fingerprint}, but this program writes {type_string} with version {} ({:08x})",
+                                section.type_string,
+                                current.version,
+                                current.fingerprint
+                            )
+                        }
+                    }
+                }
+                plugin.decode_entries(&mut result, entries).map_err(|e| {
+                    anyhow!(
+                        "could not decode history of {name} as {}: {e}",
+                        section.type_string
+                    )
+                })?;
+            }
+        }
+
+        Ok(result.into())
+    }
+}
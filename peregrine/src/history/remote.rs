@@ -0,0 +1,270 @@
+//! A history cache shared between processes over TCP.
+//!
+//! [HistoryServer] keeps a single in-memory map of encoded entries, and any number of
+//! [RemoteHistory] clients can be attached to local histories with [History::with_backend][crate::History::with_backend].
+//! This lets a fleet of scheduler workers reuse each other's simulation results, even across
+//! hosts. The protocol is a minimal memcached-style get/insert keyed by type string and hash;
+//! bigger deployments can implement [HistoryBackend] for an existing store like Redis instead.
+//!
+//! The server has no authentication or encryption: any peer that can reach it can read and
+//! overwrite every entry, so bind it to loopback or a trusted private network.
+//!
+//! Each request from a client is a blocking round trip. Wrap clients in a
+//! [WriteBack][crate::history::tiered::WriteBack] to move inserts off the simulation thread,
+//! which also sends them to the server in batches of one round trip each.
+//!
+//! ```
+//! # use peregrine::history::remote::{HistoryServer, RemoteHistory};
+//! # use peregrine::{History, Result, resource};
+//! resource!(counter: u32);
+//!
+//! # fn main() -> Result<()> {
+//! let server = HistoryServer::bind("127.0.0.1:0")?;
+//! let address = server.local_addr()?;
+//! server.spawn();
+//!
+//! let worker_a = History::new().with_backend(RemoteHistory::connect(address)?);
+//! let worker_b = History::new().with_backend(RemoteHistory::connect(address)?);
+//! worker_a.init::<counter>();
+//! worker_b.init::<counter>();
+//!
+//! worker_a.insert::<counter>(0, 5);
+//! assert_eq!(Some(5), worker_b.get::<counter>(0));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [History]: crate::History
+
+use crate::history::HistoryBackend;
+use anyhow::Result;
+use parking_lot::{Mutex, RwLock};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+
+const GET: u8 = 0;
+const INSERT: u8 = 1;
+const INSERT_BATCH: u8 = 2;
+
+/// The largest type string or value either side reads, so that a peer can't make the other
+/// allocate arbitrary amounts of memory with a bogus length. Values larger than this aren't
+/// shared.
+pub const MAX_FRAME: u32 = 64 << 20;
+
+fn write_bytes(stream: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    stream.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    stream.write_all(bytes)
+}
+
+fn write_key(stream: &mut impl Write, type_string: &str, hash: u64) -> std::io::Result<()> {
+    write_bytes(stream, type_string.as_bytes())?;
+    stream.write_all(&hash.to_le_bytes())
+}
+
+fn read_u32(stream: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_bytes(stream: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let len = read_u32(stream)?;
+    if len > MAX_FRAME {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {len} bytes is larger than the limit of {MAX_FRAME}"),
+        ));
+    }
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_key(stream: &mut impl Read) -> std::io::Result<(String, u64)> {
+    let type_string = String::from_utf8_lossy(&read_bytes(stream)?).into_owned();
+    let mut hash = [0; 8];
+    stream.read_exact(&mut hash)?;
+    Ok((type_string, u64::from_le_bytes(hash)))
+}
+
+/// A client for a [HistoryServer].
+///
+/// Keeps a pool of connections, so that threads reading the history concurrently don't wait on
+/// each other's round trips. Network failures are treated as cache misses, and the failed
+/// connection is replaced on the next request.
+pub struct RemoteHistory {
+    address: SocketAddr,
+    idle: Mutex<Vec<BufWriter<TcpStream>>>,
+}
+
+impl RemoteHistory {
+    /// The most idle connections a client keeps open.
+    pub const MAX_IDLE: usize = 16;
+
+    pub fn connect(address: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(RemoteHistory {
+            address: stream.peer_addr()?,
+            idle: Mutex::new(vec![BufWriter::new(stream)]),
+        })
+    }
+
+    /// Runs one request on an idle connection, or a new one if there are none. The pool is
+    /// only locked to take and return the connection, not during the round trip.
+    fn exchange<T>(
+        &self,
+        f: impl FnOnce(&mut BufWriter<TcpStream>) -> std::io::Result<T>,
+    ) -> Option<T> {
+        let idle = self.idle.lock().pop();
+        let mut connection = match idle {
+            Some(connection) => connection,
+            None => {
+                let stream = TcpStream::connect(self.address).ok()?;
+                stream.set_nodelay(true).ok()?;
+                BufWriter::new(stream)
+            }
+        };
+        let result = f(&mut connection).ok()?;
+        let mut idle = self.idle.lock();
+        if idle.len() < Self::MAX_IDLE {
+            idle.push(connection);
+        }
+        Some(result)
+    }
+}
+
+impl HistoryBackend for RemoteHistory {
+    fn get(&self, type_string: &str, hash: u64) -> Option<Cow<'_, [u8]>> {
+        self.exchange(|connection| {
+            connection.write_all(&[GET])?;
+            write_key(connection, type_string, hash)?;
+            connection.flush()?;
+            let stream = connection.get_mut();
+            let mut found = [0];
+            stream.read_exact(&mut found)?;
+            if found[0] == 1 {
+                Ok(Some(Cow::Owned(read_bytes(stream)?)))
+            } else {
+                Ok(None)
+            }
+        })
+        .flatten()
+    }
+
+    fn insert(&self, type_string: &'static str, hash: u64, value: Vec<u8>) {
+        self.insert_batch(vec![(type_string, hash, value)]);
+    }
+
+    /// Sends the entries in one request.
+    fn insert_batch(&self, entries: Vec<(&'static str, u64, Vec<u8>)>) {
+        let entries: Vec<_> = entries
+            .into_iter()
+            .filter(|(_, _, value)| value.len() <= MAX_FRAME as usize)
+            .collect();
+        if entries.is_empty() {
+            return;
+        }
+        self.exchange(|connection| {
+            connection.write_all(&[INSERT_BATCH])?;
+            connection.write_all(&(entries.len() as u32).to_le_bytes())?;
+            for (type_string, hash, value) in &entries {
+                write_key(connection, type_string, *hash)?;
+                write_bytes(connection, value)?;
+            }
+            connection.flush()?;
+            connection.get_mut().read_exact(&mut [0])
+        });
+    }
+}
+
+type Entries = RwLock<HashMap<(String, u64), Arc<[u8]>>>;
+
+/// An in-memory history store served over TCP to [RemoteHistory] clients.
+///
+/// Unauthenticated; see [remote][crate::history::remote].
+pub struct HistoryServer {
+    listener: TcpListener,
+    entries: Arc<Entries>,
+}
+
+impl HistoryServer {
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self> {
+        Ok(HistoryServer {
+            listener: TcpListener::bind(address)?,
+            entries: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections forever, handling each one on its own thread.
+    pub fn serve(self) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let entries = self.entries.clone();
+            thread::spawn(move || Self::handle(stream, &entries));
+        }
+        Ok(())
+    }
+
+    /// Serves connections on a background thread.
+    pub fn spawn(self) -> thread::JoinHandle<Result<()>> {
+        thread::spawn(move || self.serve())
+    }
+
+    fn handle(stream: TcpStream, entries: &Entries) -> std::io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        loop {
+            let mut op = [0];
+            if reader.read_exact(&mut op).is_err() {
+                // The client hung up.
+                return Ok(());
+            }
+            match op[0] {
+                GET => {
+                    let key = read_key(&mut reader)?;
+                    // Cloned out so that the lock isn't held while writing to the client.
+                    let value = entries.read().get(&key).cloned();
+                    match value {
+                        Some(value) => {
+                            writer.write_all(&[1])?;
+                            write_bytes(&mut writer, &value)?;
+                        }
+                        None => writer.write_all(&[0])?,
+                    }
+                }
+                INSERT => {
+                    let key = read_key(&mut reader)?;
+                    let value = read_bytes(&mut reader)?;
+                    entries.write().entry(key).or_insert(value.into());
+                    writer.write_all(&[1])?;
+                }
+                INSERT_BATCH => {
+                    let count = read_u32(&mut reader)?;
+                    let mut batch = vec![];
+                    for _ in 0..count {
+                        let key = read_key(&mut reader)?;
+                        batch.push((key, read_bytes(&mut reader)?));
+                    }
+                    let mut entries = entries.write();
+                    for (key, value) in batch {
+                        entries.entry(key).or_insert(value.into());
+                    }
+                    drop(entries);
+                    writer.write_all(&[1])?;
+                }
+                _ => return Ok(()),
+            }
+            writer.flush()?;
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! A two-tier history: a bounded in-memory hot tier in front of a persistent cold store.
+//!
+//! Storing every simulation result in memory forever eventually runs out of memory, but
+//! dropping results throws away reuse. A tiered history writes every new entry back to a
+//! cold [HistoryBackend] (usually a [DiskHistory][crate::history::disk::DiskHistory])
+//! asynchronously, so the in-memory tier can be trimmed without losing anything. Entries
+//! that are requested again after being evicted are promoted back into memory, and the
+//! entries that were used least recently are the first to be evicted.
+//!
+//! The write-back runs on a background thread, which takes entries off a bounded queue in
+//! batches, so a backend that pays for each write (like a file sync or a network round trip)
+//! pays for it once per batch. If the backend falls so far behind that the queue fills up,
+//! operations block on inserting into it until there is room again, which bounds the memory
+//! held by the queue instead of letting it grow for as long as the simulation outpaces the
+//! disk. See [WriteBack::with_limits].
+//!
+//! The capacity is enforced during simulation, as entries are inserted, for the resources that
+//! are read by value. Plans hold references into the entries of `ref` resources while they are
+//! alive, so those can only be evicted with exclusive access to the history: call
+//! [History::enforce_capacity] (or
+//! [Session::enforce_history_capacity][crate::Session::enforce_history_capacity]) between
+//! planning sessions, when no plans are borrowing the history. Entries evicted before the
+//! write-back reaches them are lost, and simulated again if they are needed.
+
+use crate::history::{History, HistoryBackend};
+use crate::resource::ResourceHistoryPlugin;
+use crossbeam::channel::{Sender, bounded};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+use type_map::concurrent::TypeMap;
+
+enum Message {
+    Insert(&'static str, u64, Vec<u8>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Wraps a backend so that inserts are written on a background thread, instead of blocking
+/// the operation that produced them.
+pub struct WriteBack<B: HistoryBackend + 'static> {
+    backend: Arc<B>,
+    sender: Option<Sender<Message>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<B: HistoryBackend + 'static> WriteBack<B> {
+    /// The number of queued inserts [WriteBack::new] allows before blocking.
+    pub const DEFAULT_CAPACITY: usize = 1 << 16;
+    /// The largest batch [WriteBack::new] passes to the backend at once.
+    pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+    pub fn new(backend: B) -> Self {
+        Self::with_limits(backend, Self::DEFAULT_CAPACITY, Self::DEFAULT_BATCH_SIZE)
+    }
+
+    /// Queues at most `capacity` inserts, and passes them to [HistoryBackend::insert_batch]
+    /// in batches of at most `batch_size`. Batches are only as large as what is queued when the
+    /// worker gets to them, so a backend that keeps up still receives entries right away.
+    pub fn with_limits(backend: B, capacity: usize, batch_size: usize) -> Self {
+        let backend = Arc::new(backend);
+        let (sender, receiver) = bounded::<Message>(capacity);
+        let worker_backend = backend.clone();
+        let batch_size = batch_size.max(1);
+        let worker = std::thread::spawn(move || {
+            let mut batch = Vec::with_capacity(batch_size);
+            while let Ok(first) = receiver.recv() {
+                let mut next = Some(first);
+                while let Some(message) = next.take() {
+                    match message {
+                        Message::Insert(type_string, hash, value) => {
+                            batch.push((type_string, hash, value));
+                            if batch.len() == batch_size {
+                                worker_backend.insert_batch(std::mem::take(&mut batch));
+                            }
+                            next = receiver.try_recv().ok();
+                        }
+                        Message::Flush(done) => {
+                            if !batch.is_empty() {
+                                worker_backend.insert_batch(std::mem::take(&mut batch));
+                            }
+                            worker_backend.flush();
+                            let _ = done.send(());
+                        }
+                    }
+                }
+                if !batch.is_empty() {
+                    worker_backend.insert_batch(std::mem::take(&mut batch));
+                }
+            }
+        });
+        WriteBack {
+            backend,
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl<B: HistoryBackend + 'static> HistoryBackend for WriteBack<B> {
+    fn get(&self, type_string: &str, hash: u64) -> Option<Cow<'_, [u8]>> {
+        self.backend.get(type_string, hash)
+    }
+
+    /// Queues the insert, blocking while the queue is full.
+    fn insert(&self, type_string: &'static str, hash: u64, value: Vec<u8>) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Message::Insert(type_string, hash, value));
+        }
+    }
+
+    /// Blocks until all queued inserts have been written to the inner backend.
+    fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        if let Some(sender) = &self.sender
+            && sender.send(Message::Flush(done)).is_ok()
+        {
+            let _ = wait.recv();
+        }
+    }
+}
+
+impl<B: HistoryBackend + 'static> Drop for WriteBack<B> {
+    fn drop(&mut self) {
+        self.flush();
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl History {
+    /// Creates a tiered history that keeps about `hot_capacity` entries in memory, with all
+    /// entries written back asynchronously to `cold`. See [tiered][crate::history::tiered]
+    /// for when the capacity is enforced.
+    pub fn tiered(cold: impl HistoryBackend + 'static, hot_capacity: usize) -> Self {
+        let mut history = History::new().with_backend(WriteBack::new(cold));
+        history.hot_capacity = Some(hot_capacity);
+        history
+    }
+
+    /// The number of entries currently stored in memory.
+    pub fn len(&self) -> usize {
+        hot_len(&self.maps.read())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Evicts in-memory entries until there are no more than the hot capacity given
+    /// to [History::tiered]. Does nothing for histories without a capacity.
+    ///
+    /// The backend is flushed first, so evicted entries can always be promoted again later.
+    pub fn enforce_capacity(&mut self) {
+        let Some(capacity) = self.hot_capacity else {
+            return;
+        };
+        let total = self.len();
+        if total <= capacity {
+            return;
+        }
+        if let Some(backend) = &self.backend {
+            backend.flush();
+        }
+
+        let excess = total - capacity;
+        let maps = self.maps.get_mut();
+        for plugin in containers() {
+            let len = plugin.history_len(maps);
+            // Evict from each container in proportion to its size, rounding up.
+            plugin.evict(maps, (len * excess).div_ceil(total));
+        }
+    }
+
+    /// Evicts the least recently used entries of the containers that can be evicted from
+    /// while plans are using them, if the hot tier is over `capacity`. Called on every insert,
+    /// but only counts the entries once every eighth of the capacity.
+    pub(crate) fn trim_shared(&self, maps: &TypeMap, capacity: usize) {
+        let interval = (capacity / 8).max(1);
+        if self.unchecked_inserts.fetch_add(1, Ordering::Relaxed) + 1 < interval {
+            return;
+        }
+        self.unchecked_inserts.store(0, Ordering::Relaxed);
+        let total = hot_len(maps);
+        if total <= capacity {
+            return;
+        }
+
+        let excess = total - capacity;
+        let containers = containers();
+        let mut remaining = excess;
+        for plugin in &containers {
+            let share = (plugin.history_len(maps) * excess).div_ceil(total);
+            remaining -= plugin.evict_shared(maps, share.min(remaining));
+        }
+        // Containers that can't be evicted from leave their share to the others.
+        for plugin in &containers {
+            if remaining == 0 {
+                break;
+            }
+            remaining -= plugin.evict_shared(maps, remaining);
+        }
+    }
+}
+
+/// One history plugin for each container type.
+fn containers() -> Vec<&'static dyn ResourceHistoryPlugin> {
+    let mut seen = HashSet::new();
+    inventory::iter::<&'static dyn ResourceHistoryPlugin>
+        .into_iter()
+        .filter(|plugin| seen.insert(plugin.history_type_id()))
+        .copied()
+        .collect()
+}
+
+fn hot_len(maps: &TypeMap) -> usize {
+    containers()
+        .into_iter()
+        .map(|plugin| plugin.history_len(maps))
+        .sum()
+}
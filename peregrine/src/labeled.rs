@@ -0,0 +1,158 @@
+//! Views of resources chosen by label at runtime, for tools that don't know the model's types.
+//!
+//! [Plan::view_labeled] views every resource of the model whose label is requested, and
+//! returns the values as [LabeledView]s, which can be printed or, with the `json` feature,
+//! serialized without knowing their types.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(labeled_power: f64);
+//! # resource!(ref labeled_mode: String);
+//! # model! { Labeled(labeled_power, labeled_mode) }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let plan = session.new_plan::<Labeled>(
+//!     start,
+//!     initial_conditions! { labeled_power: 5.0, labeled_mode: "idle".to_string() },
+//! )?;
+//!
+//! let views = plan.view_labeled(&["labeled_mode"], start..)?;
+//! assert_eq!("labeled_mode", views[0].label);
+//! assert_eq!(vec![start], views[0].times());
+//! assert_eq!("\"idle\"", views[0].debug_value(0));
+//!
+//! assert!(plan.view_labeled(&["labeled_speed"], start..).is_err());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::resource::Resource;
+use crate::subscription::TimeRange;
+use crate::{EngineError, Model, Plan, Time};
+use serde::Serialize;
+use std::fmt::Debug;
+use std::ops::RangeBounds;
+
+/// The values of one resource within a window, from [Plan::view_labeled].
+pub struct LabeledView<'o> {
+    pub label: &'static str,
+    pub unit: Option<&'static str>,
+    values: Box<dyn ErasedValues + 'o>,
+}
+
+impl LabeledView<'_> {
+    pub fn len(&self) -> usize {
+        self.values.times().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn times(&self) -> Vec<Time> {
+        self.values.times()
+    }
+
+    /// The [Debug] representation of the value at `index`.
+    pub fn debug_value(&self, index: usize) -> String {
+        self.values.debug_value(index)
+    }
+
+    /// The values, serialized as JSON.
+    #[cfg(feature = "json")]
+    pub fn json_values(&self) -> serde_json::Result<Vec<serde_json::Value>> {
+        self.values.json_values()
+    }
+}
+
+trait ErasedValues: Send + Sync {
+    fn times(&self) -> Vec<Time>;
+    fn debug_value(&self, index: usize) -> String;
+    #[cfg(feature = "json")]
+    fn json_values(&self) -> serde_json::Result<Vec<serde_json::Value>>;
+}
+
+struct Values<W>(Vec<(Time, W)>);
+
+impl<W: Debug + Serialize + Send + Sync> ErasedValues for Values<W> {
+    fn times(&self) -> Vec<Time> {
+        self.0.iter().map(|(time, _)| *time).collect()
+    }
+
+    fn debug_value(&self, index: usize) -> String {
+        format!("{:?}", self.0[index].1)
+    }
+
+    #[cfg(feature = "json")]
+    fn json_values(&self) -> serde_json::Result<Vec<serde_json::Value>> {
+        self.0
+            .iter()
+            .map(|(_, value)| serde_json::to_value(value))
+            .collect()
+    }
+}
+
+/// The views requested from [Model::view_labeled], filled in by the code that [model!][crate::model] generates.
+pub struct LabeledViews<'o> {
+    requested: Vec<String>,
+    bounds: TimeRange,
+    views: Vec<LabeledView<'o>>,
+}
+
+impl<'o> LabeledViews<'o> {
+    /// Views `R` if it is requested and hasn't been viewed yet.
+    pub fn view<R: Resource<'o> + 'o, M: Model<'o> + 'o>(
+        &mut self,
+        plan: &Plan<'o, M>,
+    ) -> Result<(), EngineError>
+    where
+        R::Write: From<R::Read>,
+    {
+        if !self.requested.iter().any(|label| label == R::LABEL)
+            || self.views.iter().any(|view| view.label == R::LABEL)
+        {
+            return Ok(());
+        }
+        let values = plan
+            .view::<R>(self.bounds)?
+            .into_iter()
+            .map(|(time, value)| (time, R::Write::from(value)))
+            .collect();
+        self.views.push(LabeledView {
+            label: R::LABEL,
+            unit: R::UNIT,
+            values: Box::new(Values(values)),
+        });
+        Ok(())
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Views the resources with the given labels, in the order of `labels`. Fails with
+    /// [EngineError::UnknownResource] if the model has no resource with one of them. See
+    /// [labeled][crate::labeled].
+    pub fn view_labeled(
+        &self,
+        labels: &[&str],
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Vec<LabeledView<'o>>, EngineError> {
+        let mut views = LabeledViews {
+            requested: labels.iter().map(|label| label.to_string()).collect(),
+            bounds: (bounds.start_bound().cloned(), bounds.end_bound().cloned()),
+            views: vec![],
+        };
+        M::view_labeled(self, &mut views)?;
+
+        let mut result = Vec::with_capacity(labels.len());
+        for label in labels {
+            let index = views
+                .views
+                .iter()
+                .position(|view| view.label == *label)
+                .ok_or_else(|| EngineError::UnknownResource(label.to_string()))?;
+            result.push(views.views.swap_remove(index));
+        }
+        Ok(result)
+    }
+}
@@ -65,8 +65,9 @@
 //! This approach's main drawback is memory usage. By indiscriminately storing all sim results without
 //! knowing if they will ever be reused, it can build up gigabytes of store after simulating on the
 //! order of tens of millions of operations. Since the keys in the storage are meaningless hashes,
-//! there is currently no good way to prune the history to reduce memory usage. This poses some technical
-//! problems for long-running venues, though I don't believe they are insurmountable.
+//! there is no good way to decide which entries are worth keeping. Instead, a [tiered][History::tiered]
+//! history writes everything back to a persistent store, so that the in-memory portion can be trimmed
+//! between planning sessions without losing any results.
 //!
 //! ### Models
 //!
@@ -157,6 +158,26 @@
 //! its non-trivial comparison and ordering. I believe its worth it for compatibility with SPICE,
 //! and the penalty isn't present during simulation anyway.
 //!
+//! If plan construction throughput matters more, the `ticks` feature converts every placement to
+//! an integer count of TAI nanoseconds as soon as it enters the engine, and uses those for
+//! timeline keys and groundings. The API is still [Time] and [Duration] either way, but times more
+//! than about 292 years from 1900 can't be represented, and fail with [EngineError::TimeOutOfRange].
+//!
+//! ## Tracing
+//!
+//! The `tracing` feature instruments the engine with [tracing](https://docs.rs/tracing) spans,
+//! for use with any subscriber. Insertions, removals, and replacements are `debug` spans, and
+//! so are views, with the resource and the number of nodes requested. Each operation evaluation
+//! is a `trace` span, with events for its history lookup and the timeline searches that find its
+//! inputs. Failed operations are reported as `debug` events when they fail, before the view
+//! that requested them returns.
+//!
+//! ## Async Operations
+//!
+//! Operation bodies that wait on I/O can await futures with [exec::block_on], which runs other
+//! tasks of the simulation on the thread until the future is ready. With the `tokio` feature,
+//! [exec::set_async_runtime] and a `TokioRuntime` let those futures use Tokio's I/O and timers.
+//!
 //! ## Possible Features
 //!
 //! This project is currently a proof-of-concept, but I've set it up with future development in mind.
@@ -168,9 +189,6 @@
 //!   any other operation writes to a given resource.
 //! - **Maybe-reads and maybe-writes;** optimizations for operations that may or may not read or write a
 //!   resource.
-//! - **Global persistent history;** I made a lot of grand claims about sharing history between plans and
-//!   models, but I haven't actually implemented that yet. Storing history on the filesystem is possible
-//!   already though.
 //! - **Stable graph hashing;** currently there are no guarantees that operations will generate the
 //!   same hashes when the program is recompiled, but this could be fixed.
 //! - **Linked lists in history;** the above example of accumulating a `Vec<String>` buffer in a resource
@@ -182,8 +200,6 @@
 //! - **Look-back reads;** currently operations can only read the current value of resources when
 //!   they happen, but there's no reason why they shouldn't be able to look back to a pre-determined
 //!   time.
-//! - **Activity anchoring;** activities could be defined relative to other activities, as long as the
-//!   relationship is known ahead-of-time.
 //! - **Activity spawning;** the activity body could automatically spawn child activities when inserted
 //!   into the plan, as long as this spawning is only a function of the activity arguments.
 //! - **Probabilistic Caching;** if the overhead of reading/writing history is a problem, I could
@@ -194,19 +210,22 @@
 //!
 //! Peregrine has to impose some restrictions on your activities and operations, so some things are
 //! impossible:
-//! - **Operation placement at runtime;** the exact placement of all activities and operations must
-//!   be determined by only statically-known values like activity arguments and start time.
+//! - **Unbounded placement at runtime;** the placement of all activities must be determined by only
+//!   statically-known values like activity arguments and start time. Operations can be delayed by
+//!   a simulated amount, but only from a statically-known time.
 //! - **Hidden state;** all state in the simulation must be recorded by the history. Getting around
-//!   this restriction is UB.
+//!   this restriction is UB. [Plan::audit] can help track it down.
 //! - **Non-reentrant or non-deterministic activities;** the engine assumes that for the same input,
 //!   all operations will produce the same output, and if a cached value exists in history then it is valid.
 //!   It also assumes that it is OK to only resimulate a portion of an activity's operations.
 
 #![cfg_attr(feature = "nightly", feature(btree_cursors))]
 
-use std::cell::Cell;
-use std::collections::HashMap;
-use std::ops::{Add, RangeBounds};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::{Add, Bound, RangeBounds};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
 
 /// Creates a model and associated structs from a selection of resources.
 ///
@@ -230,8 +249,208 @@ use std::ops::{Add, RangeBounds};
 /// are used to create a new plan, and has one field for each resource where you can populate
 /// the resource's `Write` value. The histories are used to cache simulation results to be reused
 /// in later simulations.
+///
+/// Models can include all the resources of other models with `use`, so that subsystem models
+/// can be shared between crates and composed. Resources that appear in more than one submodel
+/// are only included once.
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, model};
+/// # resource!(battery: f32);
+/// # resource!(bus_voltage: f32);
+/// # resource!(heater_on: bool);
+/// # resource!(mode: u8);
+/// model! {
+///     pub Power(battery, bus_voltage)
+/// }
+/// model! {
+///     pub Thermal(heater_on, bus_voltage)
+/// }
+/// model! {
+///     pub Spacecraft(mode, use Power, use Thermal)
+/// }
+/// ```
+///
+/// The generated initial conditions struct only has fields for the model's own resources.
+///
+/// A `MyModelPlanExt` trait is generated too, with a shorthand for [Plan::view] and [Plan::sample]
+/// for each resource, so that `plan.view::<res_a>(..)` can be written as `plan.res_a(..)`
+/// and `plan.sample::<res_a>(time)` as `plan.sample_res_a(time)`. It is implemented for plans
+/// of the model, and for plans of any model that uses it directly as a submodel. If a resource is
+/// in more than one submodel, its shorthand has to be called through one of the traits, as in
+/// `PowerPlanExt::bus_voltage(&plan, ..)`.
+///
+/// Array resources are listed as `name[..]`, which includes all of their elements. Their
+/// shorthands take the index as a const parameter, as in `plan.sample_heaters::<3>(time)`.
+/// Struct resources are listed as `name::*`, which includes all of their fields, or each field
+/// can be listed on its own as `name::field`.
+///
+/// Listing the same resource or submodel twice directly is a compile error:
+///
+/// ```compile_fail
+/// # fn main() {}
+/// # use peregrine::{resource, model};
+/// # resource!(battery: f32);
+/// model! {
+///     pub Power(battery, battery)
+/// }
+/// ```
 pub use peregrine_macros::model;
 
+/// Declares a resource.
+///
+/// The simplest form is `resource!(name: Type)`, for [Copy] types that are read and written
+/// by value. Adding `ref` (`resource!(ref name: Type)`) stores a heap-allocated type, like
+/// [String] or [Vec], that is written by value but read through its [Deref][std::ops::Deref]
+/// target, without cloning. Either form can be given a visibility. Collections that many
+/// operations add to are cheaper to write as [persistent] collections, which aren't copied by
+/// each write.
+///
+/// A default initial condition can be given after the type, as in `resource!(sol_counter: u32 = 0)`.
+/// Plans use it when the initial conditions they are created with don't include the resource.
+///
+/// The resource's doc comment and an optional `#[unit = "..."]` attribute are kept as metadata,
+/// available at runtime through [ResourceInfo][resource::ResourceInfo]:
+///
+/// ```
+/// # use peregrine::resource;
+/// # use peregrine::resource::{Resource, ResourceInfo};
+/// resource! {
+///     /// State of charge
+///     #[unit = "%"]
+///     pub battery_soc: f32 = 100.0
+/// }
+///
+/// assert_eq!(Some("%"), battery_soc::UNIT);
+/// assert_eq!("battery_soc [%] - State of charge", ResourceInfo::of::<battery_soc>().to_string());
+/// ```
+///
+/// Values are stored in history in a container chosen from whether the resource is `ref`, or
+/// in a custom one given with `#[history(Type)]`, see [history]. When the type of a resource
+/// changes, bump its `#[schema_version = N]` so that exported histories from before the change
+/// aren't misread, see [portable][history::portable].
+///
+/// Writes can also be checked by the engine: `#[finite]` rejects NaN and infinite floats (see
+/// [Finite][resource::Finite]), and `#[state_machine]` rejects illegal changes of mode (see
+/// [StateMachine][resource::StateMachine]).
+///
+/// Resources can also be generic, with any number of type parameters and an optional where-clause.
+/// This lets a shared crate declare a resource once for whatever payload type a mission uses:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, register_resource, model};
+/// resource!(pub setpoint<T>: Option<T> where T: PartialOrd);
+/// resource!(pub ref latest<T>: Vec<T>);
+///
+/// register_resource!(setpoint<f32>);
+///
+/// model! {
+///     Thermal(setpoint<f32>, latest<f64>)
+/// }
+/// ```
+///
+/// The bounds needed to store the payload in the history are added to generic resources automatically.
+/// Different instantiations of a generic resource share an ID though, so a model may
+/// only contain one instantiation of each, and creating a plan of a model that contains more
+/// (through its submodels) fails with [EngineError::ConflictingInstantiations]. Generic
+/// resources must also be registered with [register_resource] for their histories to be
+/// included in serialization.
+///
+/// Fixed-size groups of values, like a bank of heaters, can be declared as an `array` resource.
+/// Each element is a separate resource, written `name<I>` outside of operations and
+/// `name[I]` inside them, so operations on different elements don't depend on each other and
+/// changing one element doesn't invalidate operations that only touch the others. Indices
+/// must be integer literals. Elements are [Copy], and share the resource's default, unit
+/// and description.
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, model, impl_activity, initial_conditions, Duration};
+/// resource!(pub array heaters: [f32; 12] = 0.0);
+///
+/// model! {
+///     Thermal(heaters[..])
+/// }
+///
+/// pub struct SetHeater3;
+/// impl_activity! { for SetHeater3
+///     @(start) {
+///         mut: heaters[3] = ref: heaters[2] + 5.0;
+///     }
+///     Duration::ZERO
+/// }
+///
+/// let initial_conditions = initial_conditions! { heaters[2]: 20.0 };
+/// ```
+///
+/// When the set of elements isn't fixed, a `map` resource keeps a separate timeline for each
+/// value of a key type instead. Operations pick a key with any expression, evaluated when the
+/// activity is decomposed, so it can use the activity's fields and local variables. Each key
+/// starts from the initial condition of the whole resource, unless it is given its own with
+/// [InitialConditions::insert_key]. Keys are viewed with [Plan::view_key] and [Plan::sample_key].
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, model, impl_activity, Duration};
+/// #[derive(Hash, Debug, Clone, Copy)]
+/// pub enum Instrument { Camera, Spectrometer }
+///
+/// resource!(pub map images_taken[Instrument]: u32 = 0);
+///
+/// model! {
+///     Payload(images_taken)
+/// }
+///
+/// pub struct TakeImage(Instrument);
+/// impl_activity! { for TakeImage
+///     @(start) {
+///         ref mut: images_taken[self.0] += 1;
+///     }
+///     Duration::ZERO
+/// }
+/// ```
+///
+/// A resource made of a few related values can be declared as a `struct` resource, whose
+/// fields are separate resources in a module of the same name. Operations refer to them as
+/// `name.field`, so reading one field doesn't depend on writes to the others. Each field is
+/// declared like a standalone resource, with its own doc comment, unit and default, and is
+/// labelled `name.field`.
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, model, impl_activity, Duration};
+/// resource!(pub struct spacecraft {
+///     #[unit = "W"]
+///     power: f32 = 0.0,
+///     /// Pointing, in degrees from the sun
+///     attitude: f32 = 0.0,
+/// });
+///
+/// model! {
+///     Spacecraft(spacecraft::*)
+/// }
+///
+/// pub struct PointAtSun;
+/// impl_activity! { for PointAtSun
+///     @(start) {
+///         ref mut: spacecraft.attitude = 0.0;
+///     }
+///     @(start + Duration::from_seconds(60.0)) {
+///         ref mut: spacecraft.power += 100.0 * ref: spacecraft.attitude.to_radians().cos();
+///     }
+///     Duration::ZERO
+/// }
+/// ```
+///
+/// An operation can only use one key of each keyed resource, and an integer literal in the
+/// brackets always means an array element, so literal keys need to be written another way,
+/// like `[{ 3 }]`. To index into a read of some other collection resource, or to access a
+/// field of a resource that isn't a struct resource, wrap the read in parentheses, like
+/// `(ref: readings)[0]` or `(ref: position).x`.
+pub use peregrine_macros::resource;
+
 /// Implements the [Activity] trait for a type.
 ///
 /// Expects a block of statements preceded by `for MyActivity`. The inside of the block is a function
@@ -261,7 +480,9 @@ pub use peregrine_macros::model;
 ///
 /// 1. First declare an empty struct `IncrementSol` to be our activity type. It has to
 ///    implement [Serialize], and [DeserializeOwned], and this is done through derive macros
-///    provided by serde.
+///    provided by serde. The serialized arguments are part of the hashes of the activity's
+///    operations, so that they are only reused from history with the same arguments. Without
+///    [Serialize][serde::Serialize], arguments that operations read are hidden state.
 /// 2. Call [impl_activity] with the preamble `for IncrementSol`. Everything else inside the
 ///    macro is your function body. In this context, `start` is the start time of the activity,
 ///    and `args` are the arguments (in this case there are none).
@@ -270,61 +491,466 @@ pub use peregrine_macros::model;
 ///      that evaluates to a [Duration].
 ///    - TODO explain ref mut
 ///    - The body of the operation can do whatever you want, as long as it is deterministic.
-///      The body is also an async context; you could make a non-blocking web request if you want,
-///      as long as it can be assumed to always return the same output for the same input.
+///      The body can also wait for futures with [exec::block_on]; you could make a web request if
+///      you want, as long as it can be assumed to always return the same output for the same input.
+///    - Inside the body, `now` is the [Time] the operation happens at, even if it was delayed.
+///      Operations that use it are only reused from history at the same time, so avoid it when
+///      the result doesn't really depend on time.
 /// 4. Finally, we end the activity body by returning `Duration::ZERO`, which means the activity took
 ///    zero duration.
 ///
+/// Operations can also be placed relative to `end`, the start time plus the duration returned by
+/// the body. These placements are evaluated after the rest of the body, so `end` can't be shadowed
+/// by a local variable, and anything else the placement uses is moved into it:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, impl_activity, Duration};
+/// # resource!(heater_on: bool);
+/// struct Heat {
+///     duration: Duration,
+/// }
+///
+/// impl_activity! { for Heat
+///     @(start) {
+///         ref mut: heater_on |= true;
+///     }
+///     @(end) {
+///         ref mut: heater_on &= false;
+///     }
+///     self.duration
+/// }
+/// ```
+///
+/// Operations that only add to a counter-like resource can be marked `commutative` if their
+/// effect doesn't depend on their order, like `@(start) commutative { ref mut: images_taken += 1; }`.
+/// A commutative operation must read and write exactly one resource, apart from other reads.
+/// Any ordering of the same commutative operations after the same upstream produces the same
+/// hash, so moving them around among each other doesn't invalidate history further downstream.
+/// The engine trusts this marker; if the operations don't really commute it is hidden state.
+///
+/// Operations that interactive views usually wait on, like the ones that write the resources
+/// a planner samples while editing, can be marked `priority`, like `@(start) priority { ... }`.
+/// When an upstream finishes, its `priority` downstreams are run first, on the same thread,
+/// and the rest are spawned to be picked up by other threads. This only changes the order of
+/// the work, never the results. Markers can be combined, as in `priority commutative { ... }`.
+///
 /// It is *technically* valid to generate operations before the start time or after the declared end time.
 /// It would just be very un-hygienic and potentially hard to debug.
+///
+/// Consecutive operations with the same placement and markers that read and write the same
+/// resources (and the same keys and elements of them) are fused into a single operation, which
+/// runs their bodies in order. This saves the per-operation overhead in the engine, and means the
+/// second body sees the writes of the first. The placement of a fused run is only evaluated once.
+///
+/// Operations at the same time happen in the order they were inserted into the plan. To make the
+/// order explicit, add `order = ...` to the placement. Operations with a lower order happen first,
+/// and the default is `0`:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, impl_activity, Duration};
+/// # resource!(battery: f64);
+/// # resource!(battery_at_start_of_pass: f64);
+/// struct RecordBattery;
+///
+/// impl_activity! { for RecordBattery
+///     // Sees the battery before any other operations at the start of the pass change it.
+///     @(start, order = -1) {
+///         mut: battery_at_start_of_pass = ref: battery;
+///     }
+///     Duration::ZERO
+/// }
+/// ```
+///
+/// Operations can be placed inside `for` loops, as long as the iteration only depends on the
+/// activity arguments and start time. Operation bodies can't see the loop variable though, only
+/// the placement expression can:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, impl_activity, Duration};
+/// # resource!(images_taken: u32);
+/// struct TakeImages {
+///     count: u32,
+///     period: Duration,
+/// }
+///
+/// impl_activity! { for TakeImages
+///     for i in 0..self.count {
+///         @(start + self.period * i as i64) {
+///             ref mut: images_taken += 1;
+///         }
+///     }
+///     self.period * self.count as i64
+/// }
+/// ```
+///
+/// Similarly, operations can be placed conditionally with `if`, `else if`, and `else`:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, impl_activity, Duration};
+/// # resource!(heater_cycles: u32);
+/// struct Warmup {
+///     use_heater: bool,
+/// }
+///
+/// impl_activity! { for Warmup
+///     if self.use_heater {
+///         @(start) {
+///             ref mut: heater_cycles += 1;
+///         }
+///     }
+///     Duration::ZERO
+/// }
+/// ```
+///
+/// An operation's time can also depend on the simulation, by adding a delay that is computed
+/// by reading resources at the start time. The delay is written like an operation body that
+/// evaluates to a [Duration], and can be followed by `..= (max)` to bound how long it can be.
+/// The bound isn't required, but operations whose time is unknown add overhead to every read of
+/// the resources they write within the bound, so it should be as tight as possible. Delays
+/// outside of `0 s ..= max` are reported as errors during views.
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, impl_activity, Duration};
+/// # resource!(temperature: f64);
+/// # resource!(instrument_on: bool);
+/// struct PowerOnWhenWarm;
+///
+/// impl_activity! { for PowerOnWhenWarm
+///     @(start) + (Duration::from_seconds((20.0 - ref: temperature).max(0.0) * 60.0)) ..= (Duration::from_hours(1.0)) {
+///         ref mut: instrument_on |= true;
+///     }
+///     Duration::ZERO
+/// }
+/// ```
+///
+/// The start of a delayed operation must not itself be delayed.
+///
+/// The body can optionally start with a `validate` block, which checks the activity arguments
+/// when the activity is inserted into a plan, before any operations are generated:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, impl_activity, ensure, Duration};
+/// # resource!(battery: f32);
+/// struct Charge {
+///     amount: f32,
+/// }
+///
+/// impl_activity! { for Charge
+///     validate {
+///         ensure!(self.amount >= 0.0, "cannot charge a negative amount");
+///     }
+///     @(start) {
+///         ref mut: battery += self.amount;
+///     }
+///     Duration::ZERO
+/// }
+/// ```
+///
+/// It can also have an `arguments` block, before or after `validate`, which lists the
+/// arguments of the command the activity is exported as (see [commands]). Each value is
+/// formatted with [Display][std::fmt::Display]:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, impl_activity, Duration};
+/// # resource!(heater_power: f32);
+/// struct HeaterOn {
+///     watts: f32,
+/// }
+///
+/// impl_activity! { for HeaterOn
+///     arguments {
+///         watts: self.watts,
+///     }
+///     @(start) {
+///         ref mut: heater_power += self.watts;
+///     }
+///     Duration::ZERO
+/// }
+/// ```
+///
+/// Generic activities declare their parameters after `for`, like an `impl` block would, and can
+/// have a where-clause after the type (without a trailing comma):
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, impl_activity, Duration};
+/// # resource!(delta_v: f32);
+/// trait Thruster: Send + Sync {
+///     const IMPULSE: f32;
+/// }
+///
+/// struct Maneuver<T> {
+///     burns: u32,
+///     thruster: T,
+/// }
+///
+/// impl_activity! { for<T: Thruster> Maneuver<T> where T: 'static
+///     @(start) {
+///         ref mut: delta_v += self.burns as f32 * T::IMPULSE;
+///     }
+///     Duration::ZERO
+/// }
+/// ```
 pub use peregrine_macros::impl_activity;
 
 pub mod activity;
+pub mod append;
+pub mod arena;
+pub mod checkpoint;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod commands;
+pub mod compare;
+pub mod conflicts;
+pub mod constraints;
+pub mod dag;
+pub mod delta;
+pub mod devices;
+pub mod error;
 pub mod exec;
+#[cfg(feature = "json")]
+pub mod gantt;
 pub mod history;
+pub mod labeled;
+pub mod lint;
+pub mod memory;
+pub mod monte_carlo;
+pub mod offload;
 pub mod operation;
+pub mod owned;
+pub mod persistent;
+#[cfg(feature = "json")]
+pub mod plan_file;
+pub mod profile;
+pub mod random;
 pub mod reexports;
+#[cfg(feature = "json")]
+pub mod registry;
+#[cfg(feature = "repl")]
+pub mod repl;
 pub mod resource;
+pub mod series;
+pub mod shared;
+pub mod snapshot;
+#[cfg(feature = "spice")]
+pub mod spice;
+#[cfg(feature = "json")]
+pub mod stream;
+pub mod subscription;
+pub mod testing;
 pub mod timeline;
 
-pub use crate::activity::{Activity, ActivityId};
-use crate::exec::{ErrorAccumulator, ExecEnvironment};
+use crate::activity::ActivityBox;
+pub use crate::activity::{Activity, ActivityId, ActivityMetadata};
+pub use crate::append::Append;
+use crate::arena::Arena;
+pub use crate::commands::Command;
+pub use crate::conflicts::WriteConflict;
+use crate::constraints::ErasedConstraint;
+pub use crate::constraints::{ConstraintId, Violation};
+pub use crate::delta::ViewDelta;
+use crate::delta::{PreviousView, piece_values, split, unchanged};
+pub use crate::error::{
+    EngineError, InitialConditionIssue, InitialConditionIssueKind, LoadIssue, ValidationIssue,
+    ValidationIssueKind,
+};
+use crate::exec::{
+    CacheAudit, CacheDivergence, ErrorAccumulator, ExecEnvironment, Lane, Recorder, Recording,
+};
+pub use crate::exec::{DeterminismCheck, ErrorReport, Tuning};
 pub use crate::history::History;
+use crate::offload::Executor;
 pub use crate::operation::initial_conditions::InitialConditions;
-use crate::operation::ungrounded::peregrine_grounding;
-use crate::operation::{InternalResult, Upstream};
-use crate::timeline::{MaybeGrounded, Timelines, duration_to_epoch, epoch_to_duration};
-pub use anyhow::{Context, Error, Result, anyhow, bail};
-use bumpalo_herd::Herd;
+use crate::operation::ungrounded::{peregrine_delay, peregrine_grounding};
+use crate::operation::{InternalResult, Removal, Upstream};
+pub use crate::owned::OwnedPlan;
+pub use crate::profile::Profile;
+use crate::shared::EditCell;
+pub use crate::shared::SharedPlan;
+use crate::snapshot::SnapshotIndex;
+pub use crate::snapshot::{PlanSnapshot, SnapshotActivity};
+use crate::subscription::{
+    ErasedSubscription, Notification, OnInvalidate, Subscription, SubscriptionId, TimeRange,
+    intersect,
+};
+use crate::timeline::{
+    Instant, MaybeGrounded, Timelines, advance, epoch_to_instant, instant_bounds, instant_to_epoch,
+};
+pub use anyhow::{Context, Error, Result, anyhow, bail, ensure};
 pub use hifitime::{Duration, Epoch as Time};
 use oneshot::Receiver;
+pub use operation::OpInfo;
 use operation::{Continuation, Node};
-use resource::Resource;
+use parking_lot::Mutex;
+use resource::{ErasedResource, KeyedResource, Resource, ResourceSchema, key_id};
 
 #[derive(Default)]
 pub struct Session {
-    herd: Herd,
+    /// Declared first so that the plans are dropped before the history they use.
+    plans: Mutex<BTreeMap<String, Arc<Mutex<NamedPlan>>>>,
+    /// The arenas of the session's plans, for [Session::memory_report].
+    arenas: Mutex<Vec<Weak<Arena>>>,
     history: History,
+    tuning: Tuning,
 }
 
+/// A plan owned by a [Session], with its lifetime erased to `'static`. It is only ever lent out
+/// with the lifetime of a borrow of the session.
+struct NamedPlan(Box<dyn Any + Send>);
+
 impl Session {
     pub fn new() -> Self {
         Self::default()
     }
 
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Sets how simulations of the session's plans are scheduled. See [Tuning].
+    pub fn with_tuning(mut self, tuning: Tuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+
+    pub fn tuning(&self) -> Tuning {
+        self.tuning
+    }
+
     pub fn into_history(self) -> History {
         self.history
     }
 
+    /// Evicts in-memory history down to the hot capacity of a [tiered][History::tiered]
+    /// history. Requires `&mut self` because no plans may be using the history at the time.
+    ///
+    /// Panics if the session holds any [named plans][Session::create_plan].
+    pub fn enforce_history_capacity(&mut self) {
+        assert!(
+            self.plans.get_mut().is_empty(),
+            "cannot evict history while the session holds named plans"
+        );
+        self.history.enforce_capacity();
+    }
+
+    /// Creates a plan that is owned by the session under `name`, instead of borrowing it.
+    ///
+    /// This is convenient for services that keep several branches of a plan around, because
+    /// they don't have to store `Plan<'o, M>` next to the session it borrows. Use
+    /// [Session::with_plan] to edit and view it.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// # resource!(counter: u32);
+    /// # model! { Counter(counter) }
+    /// # pub struct Increment;
+    /// # impl_activity! { for Increment
+    /// #     @(start) {
+    /// #         ref mut: counter += 1;
+    /// #     }
+    /// #     Duration::ZERO
+    /// # }
+    /// # fn main() -> Result<()> {
+    /// let session = Session::new();
+    /// let start = Time::from_tai_seconds(0.0);
+    /// for branch in ["branch-A", "branch-B"] {
+    ///     session.create_plan::<Counter>(branch, start, initial_conditions! { counter: 0 })?;
+    /// }
+    ///
+    /// session.with_plan::<Counter, _>("branch-B", |plan| {
+    ///     plan.insert(start + Duration::from_seconds(1.0), Increment)
+    /// })??;
+    ///
+    /// assert_eq!(vec!["branch-A", "branch-B"], session.plans());
+    /// let later = start + Duration::from_seconds(2.0);
+    /// assert_eq!(0, session.with_plan::<Counter, _>("branch-A", |plan| plan.sample::<counter>(later))??);
+    /// assert_eq!(1, session.with_plan::<Counter, _>("branch-B", |plan| plan.sample::<counter>(later))??);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create_plan<M: for<'o> Model<'o> + 'static>(
+        &self,
+        name: impl Into<String>,
+        time: Time,
+        initial_conditions: InitialConditions,
+    ) -> Result<(), EngineError> {
+        let name = name.into();
+        let mut plans = self.plans.lock();
+        if plans.contains_key(&name) {
+            return Err(EngineError::DuplicatePlan(name));
+        }
+        let plan = self.new_plan::<M>(time, initial_conditions)?;
+        // The plan borrows the session, and is dropped before the session's history.
+        let plan = unsafe { std::mem::transmute::<Plan<'_, M>, Plan<'static, M>>(plan) };
+        plans.insert(name, Arc::new(Mutex::new(NamedPlan(Box::new(plan)))));
+        Ok(())
+    }
+
+    /// Lends out the named plan created with [Session::create_plan].
+    ///
+    /// Other threads can use other plans at the same time, but calling this again for the same
+    /// plan from inside `f` deadlocks.
+    ///
+    /// `f` works for plans of any lifetime, so it can't swap in a plan from another session
+    /// that might be dropped first:
+    ///
+    /// ```compile_fail
+    /// # use peregrine::*;
+    /// # resource!(counter: u32);
+    /// # model! { Counter(counter) }
+    /// # fn main() -> Result<()> {
+    /// # let start = Time::from_tai_seconds(0.0);
+    /// let session = Session::new();
+    /// session.create_plan::<Counter>("plan", start, initial_conditions! { counter: 0 })?;
+    /// let other = Session::new();
+    /// let mut other_plan = other.new_plan::<Counter>(start, initial_conditions! { counter: 0 })?;
+    /// session.with_plan::<Counter, _>("plan", |plan| std::mem::swap(plan, &mut other_plan))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_plan<M: for<'m> Model<'m> + 'static, T>(
+        &self,
+        name: &str,
+        f: impl for<'o> FnOnce(&mut Plan<'o, M>) -> T,
+    ) -> Result<T, EngineError> {
+        let not_found = || EngineError::PlanNotFound(name.to_string());
+        let plan = self.plans.lock().get(name).cloned().ok_or_else(not_found)?;
+        let mut plan = plan.lock();
+        let plan = plan
+            .0
+            .downcast_mut::<Plan<'static, M>>()
+            .ok_or_else(not_found)?;
+        Ok(f(plan))
+    }
+
+    /// The names of the session's plans, in order.
+    pub fn plans(&self) -> Vec<String> {
+        self.plans.lock().keys().cloned().collect()
+    }
+
+    /// Drops a named plan, returning whether it existed. If another thread is using it, it is
+    /// dropped when that thread is done.
+    pub fn remove_plan(&self, name: &str) -> bool {
+        self.plans.lock().remove(name).is_some()
+    }
+
     pub fn new_plan<'o, M: Model<'o>>(
         &'o self,
         time: Time,
         initial_conditions: InitialConditions,
-    ) -> Plan<'o, M>
+    ) -> Result<Plan<'o, M>, EngineError>
     where
         Self: 'o,
     {
         M::init_history(&self.history);
+        self.history.init::<peregrine_delay>();
         Plan::new(self, time, initial_conditions)
     }
 }
@@ -339,83 +965,1105 @@ impl From<History> for Session {
 }
 
 /// A plan session for iterative editing and simulating.
+///
+/// Plans are `Send` and `Sync`, so they can be moved to another thread to be edited there, and
+/// [view][Plan::view], [sample][Plan::sample], and the other queries through `&self` can be
+/// called from several threads at once, like from a scoped thread per resource.
+/// Operations that more than one query needs are only simulated once, and the others wait for
+/// the result. Edits need `&mut self`, so they can't overlap with queries.
 pub struct Plan<'o, M: Model<'o>> {
-    activities: HashMap<ActivityId, DecomposedActivity<'o, M>>,
-    id_counter: u32,
+    /// Behind cells so that a [SharedPlan] can insert and remove activities through `&Plan`.
+    activities: EditCell<HashMap<ActivityId, DecomposedActivity<'o, M>>>,
+    keys: EditCell<HashMap<String, ActivityId>>,
+    id_counter: EditCell<u32>,
     timelines: Timelines<'o, M>,
+    start: Time,
 
     session: &'o Session,
 
-    has_been_simulated: Cell<bool>,
+    has_been_simulated: AtomicBool,
+    determinism_check: DeterminismCheck,
+    executor: Option<Box<dyn Executor>>,
+
+    /// The activities of the last snapshot, and the activities that changed since.
+    snapshot: Mutex<SnapshotIndex>,
+    changed_since_snapshot: Mutex<HashSet<ActivityId>>,
+    /// Counts edits, so that a snapshot can tell if the plan changed since it was taken.
+    generation: AtomicU64,
+
+    /// Only used through `&mut`, but locked so that the plan can be shared between threads.
+    subscriptions: Mutex<Vec<Box<dyn ErasedSubscription<'o, M> + 'o>>>,
+    subscription_counter: u32,
+
+    constraints: Mutex<Vec<Box<dyn ErasedConstraint<'o, M> + 'o>>>,
+    constraint_counter: u32,
+
+    /// The last [Plan::view_delta] of each resource.
+    previous_views: Mutex<HashMap<u64, Box<dyn ErasedResource<'o>>>>,
+
+    /// Where the operations are allocated. Declared last so that it outlives everything that
+    /// points into it, and shared with the recordings of the plan's views.
+    arena: Arc<Arena>,
+}
+
+/// The result of [Plan::view_partial].
+#[derive(Debug)]
+pub struct PartialView<T> {
+    /// All requested points that could be computed.
+    pub values: Vec<(Time, T)>,
+    /// The failures, if there were any.
+    pub errors: Option<ErrorReport>,
+}
+
+/// Generic resource access used by the `PlanExt` traits that [model] generates.
+#[doc(hidden)]
+pub trait PlanAccess<'o> {
+    fn view_resource<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Vec<(Time, R::Read)>, EngineError>;
+    fn sample_resource<R: Resource<'o> + 'o>(&self, time: Time) -> Result<R::Read, EngineError>;
+}
+
+impl<'o, M: Model<'o> + 'o> PlanAccess<'o> for Plan<'o, M> {
+    fn view_resource<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Vec<(Time, R::Read)>, EngineError> {
+        self.view::<R>(bounds)
+    }
+    fn sample_resource<R: Resource<'o> + 'o>(&self, time: Time) -> Result<R::Read, EngineError> {
+        self.sample::<R>(time)
+    }
+}
+
+/// Where an activity is in the plan, and what its operations do. Returned by [Plan::span].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActivitySpan {
+    pub id: ActivityId,
+    pub label: &'static str,
+    pub start: Time,
+    /// The duration returned by the activity body.
+    pub duration: Duration,
+    /// The activity's operations, in the order they were declared.
+    pub operations: Vec<OpInfo>,
+}
+
+impl ActivitySpan {
+    pub fn end(&self) -> Time {
+        self.start + self.duration
+    }
+}
+
+/// What to do with the activities anchored to an activity that is being removed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dependents {
+    /// Remove them too, along with everything anchored to them, recursively.
+    Cascade,
+    /// Keep them at their current times, and anchor them to whatever the removed activity
+    /// was anchored to, if anything.
+    Reanchor,
+}
+
+/// What [Plan::trim] does with activities outside of the new horizon.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutsideHorizon {
+    Remove,
+    Error,
 }
 
-struct DecomposedActivity<'o, M> {
-    activity: *mut dyn Activity<'o, M>,
+/// Every requested point from a view, with its time if known, and the error report if any
+/// operations failed.
+type ViewResults<'o, R> = (
+    Vec<(Option<Time>, InternalResult<<R as Resource<'o>>::Read>)>,
+    Option<ErrorReport>,
+);
+
+/// A validated activity, its duration, and its operations, before they are inserted.
+type Decomposition<'o, M> = (ActivityBox<'o, M>, Duration, Vec<&'o dyn Node<'o, M>>);
+
+struct DecomposedActivity<'o, M: Model<'o>> {
+    activity: ActivityBox<'o, M>,
     operations: Vec<&'o dyn Node<'o, M>>,
+    key: Option<String>,
+    start: Time,
+    duration: Duration,
+    metadata: ActivityMetadata,
+    anchor: Option<(ActivityId, Duration)>,
+    /// The last sequence number before the operations were inserted, so that
+    /// [Plan::compact] can insert them again in the same order.
+    inserted: u64,
 }
 
 impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
     /// Create a new empty plan from initial conditions and a session.
-    fn new(session: &'o Session, time: Time, initial_conditions: InitialConditions) -> Self {
-        Plan {
-            activities: HashMap::new(),
+    fn new(
+        session: &'o Session,
+        time: Time,
+        initial_conditions: InitialConditions,
+    ) -> Result<Self, EngineError> {
+        initial_conditions.validate::<M>()?;
+        let arena = Arc::new(Arena::default());
+        session.register_arena(&arena);
+        Ok(Plan {
+            activities: EditCell::new(HashMap::new()),
+            keys: EditCell::new(HashMap::new()),
             timelines: M::init_timelines(
-                epoch_to_duration(time),
+                epoch_to_instant(time)?,
                 initial_conditions,
-                &session.herd,
-            ),
-            id_counter: 0,
+                // The plan owns the arena, as below.
+                unsafe { &*Arc::as_ptr(&arena) },
+            )?,
+            start: time,
+            id_counter: EditCell::new(0),
 
             session,
 
-            has_been_simulated: Cell::new(false),
-        }
+            has_been_simulated: AtomicBool::new(false),
+            determinism_check: DeterminismCheck::Off,
+            executor: None,
+
+            snapshot: Mutex::default(),
+            changed_since_snapshot: Mutex::default(),
+            generation: AtomicU64::new(0),
+
+            subscriptions: Mutex::default(),
+            subscription_counter: 0,
+
+            constraints: Mutex::default(),
+            constraint_counter: 0,
+
+            previous_views: Mutex::default(),
+
+            arena,
+        })
     }
 
-    pub fn reserve_activity_capacity(&mut self, additional: usize) {
-        self.activities.reserve(additional);
+    /// The plan's arena, with the lifetime of the operations allocated in it.
+    fn arena(&self) -> &'o Arena {
+        // The arena is only freed when the plan and the recordings of its views are dropped, or
+        // when compaction has replaced everything that points into it.
+        unsafe { &*Arc::as_ptr(&self.arena) }
     }
 
-    /// Inserts a new activity into the plan, and returns its unique ID.
-    pub fn insert(
-        &mut self,
-        time: Time,
-        activity: impl Activity<'o, M> + 'static,
-    ) -> Result<ActivityId> {
-        let id = ActivityId::new(self.id_counter);
-        self.id_counter += 1;
-        let bump = self.session.herd.get();
-        let activity = bump.alloc(activity);
-        let activity_pointer = activity as *mut dyn Activity<'o, M>;
-        let (_duration, operations) =
-            activity.decompose(Grounding::Static(epoch_to_duration(time)), bump)?;
+    /// Enables checks for non-deterministic operations in all future views. See [DeterminismCheck].
+    pub fn set_determinism_check(&mut self, check: DeterminismCheck) {
+        self.determinism_check = check;
+    }
 
-        for op in &operations {
-            op.insert_self(&mut self.timelines, self.has_been_simulated.take())?;
+    /// Sends the operations of the activities that `executor` accepts to it, instead of
+    /// evaluating them on the plan's threads. See [offload].
+    pub fn set_executor(&mut self, executor: impl Executor + 'static) {
+        self.executor = Some(Box::new(executor));
+    }
+
+    /// Checks every operation in the plan for problems that would make simulation fail,
+    /// without simulating, and reports all of them at once.
+    ///
+    /// All operations must happen strictly after the plan start, and every resource they read
+    /// must have a write before them. The initial conditions count as one, so reads only lack
+    /// an upstream if they are before the initial conditions, or of a resource that isn't in
+    /// the model.
+    pub fn validate(&self) -> Result<(), EngineError> {
+        let mut issues = vec![];
+        for (id, decomposed) in self.activities.iter() {
+            for (index, op) in decomposed.operations.iter().enumerate() {
+                let info = op.info();
+                let issue = |kind| ValidationIssue {
+                    activity: info.activity,
+                    activity_id: *id,
+                    op_index: index,
+                    time: info.min_time,
+                    kind,
+                };
+                if info.min_time <= self.start {
+                    issues.push(issue(ValidationIssueKind::NotAfterPlanStart));
+                }
+                issues.extend(
+                    op.missing_upstreams(&self.timelines)
+                        .into_iter()
+                        .map(|read| issue(ValidationIssueKind::NoUpstream(read))),
+                );
+            }
         }
 
-        self.activities.insert(
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            issues.sort_by_key(|issue| (issue.activity_id, issue.op_index));
+            Err(EngineError::InvalidPlan(issues))
+        }
+    }
+
+    pub fn reserve_activity_capacity(&mut self, additional: usize) {
+        self.activities.reserve(additional);
+    }
+
+    /// Inserts a new activity into the plan, and returns its unique ID.
+    pub fn insert(
+        &mut self,
+        time: Time,
+        activity: impl Activity<'o, M> + 'static,
+    ) -> Result<ActivityId, EngineError> {
+        self.insert_inner(time, Box::new(activity), None)
+    }
+
+    /// Like [Plan::insert], for an activity whose type isn't known until runtime, like one
+    /// picked from a list or made by a factory.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// # resource!(boxed_count: u32);
+    /// # model! { Boxed(boxed_count) }
+    /// # pub struct Increment;
+    /// # impl_activity! { for Increment
+    /// #     @(start) {
+    /// #         ref mut: boxed_count += 1;
+    /// #     }
+    /// #     Duration::ZERO
+    /// # }
+    /// # pub struct Double;
+    /// # impl_activity! { for Double
+    /// #     @(start) {
+    /// #         ref mut: boxed_count *= 2;
+    /// #     }
+    /// #     Duration::ZERO
+    /// # }
+    /// # fn main() -> Result<()> {
+    /// # let session = Session::new();
+    /// # let start = Time::from_tai_seconds(0.0);
+    /// let mut plan = session.new_plan::<Boxed>(start, initial_conditions! { boxed_count: 0 })?;
+    /// let activities: Vec<Box<dyn Activity<Boxed>>> =
+    ///     vec![Box::new(Increment), Box::new(Double), Box::new(Increment)];
+    /// for (i, activity) in activities.into_iter().enumerate() {
+    ///     plan.insert_boxed(start + Duration::from_seconds(i as f64), activity)?;
+    /// }
+    /// assert_eq!(3, plan.sample::<boxed_count>(start + Duration::from_seconds(3.0))?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn insert_boxed(
+        &mut self,
+        time: Time,
+        activity: Box<dyn Activity<'o, M>>,
+    ) -> Result<ActivityId, EngineError> {
+        self.insert_inner(time, activity, None)
+    }
+
+    /// Inserts a new activity with a caller-supplied key, such as a UUID or a database
+    /// primary key, so that it can be found again with [Plan::get_id] or removed with
+    /// [Plan::remove_by_key].
+    ///
+    /// Fails without changing the plan if the key is already in use.
+    pub fn insert_with_key(
+        &mut self,
+        key: impl Into<String>,
+        time: Time,
+        activity: impl Activity<'o, M> + 'static,
+    ) -> Result<ActivityId, EngineError> {
+        let key = key.into();
+        if self.keys.contains_key(&key) {
+            return Err(EngineError::DuplicateKey(key));
+        }
+        self.insert_inner(time, Box::new(activity), Some(key))
+    }
+
+    /// Finds the ID of the activity inserted with `key`.
+    pub fn get_id(&self, key: &str) -> Option<ActivityId> {
+        self.keys.get(key).copied()
+    }
+
+    /// Finds the key of an activity, if it was inserted with one.
+    pub fn get_key(&self, id: ActivityId) -> Option<&str> {
+        self.activities.get(&id)?.key.as_deref()
+    }
+
+    /// Replaces the metadata of an activity.
+    pub fn set_metadata(
+        &mut self,
+        id: ActivityId,
+        metadata: ActivityMetadata,
+    ) -> Result<(), EngineError> {
+        self.activities
+            .get_mut(&id)
+            .ok_or(EngineError::ActivityNotFound(id))?
+            .metadata = metadata;
+        self.changed(id);
+        Ok(())
+    }
+
+    pub fn metadata(&self, id: ActivityId) -> Option<&ActivityMetadata> {
+        Some(&self.activities.get(&id)?.metadata)
+    }
+
+    /// The time an activity was inserted at.
+    pub fn start_of(&self, id: ActivityId) -> Option<Time> {
+        Some(self.activities.get(&id)?.start)
+    }
+
+    /// The placement and operations of an activity.
+    pub fn span(&self, id: ActivityId) -> Option<ActivitySpan> {
+        let decomposed = self.activities.get(&id)?;
+        Some(ActivitySpan {
+            id,
+            label: decomposed.activity.label(),
+            start: decomposed.start,
+            duration: decomposed.duration,
+            operations: decomposed.operations.iter().map(|op| op.info()).collect(),
+        })
+    }
+
+    /// The spans of all activities in the plan, ordered by start time.
+    pub fn spans(&self) -> Vec<ActivitySpan> {
+        let mut spans: Vec<_> = self
+            .activities
+            .keys()
+            .filter_map(|id| self.span(*id))
+            .collect();
+        spans.sort_by_key(|span| (span.start, span.id));
+        spans
+    }
+
+    /// An immutable copy of the plan's current activities, that can be kept and read while
+    /// the plan is edited. See [PlanSnapshot].
+    ///
+    /// Only the activities that changed since the last snapshot are copied.
+    pub fn snapshot(&self) -> PlanSnapshot<'o> {
+        let mut snapshot = self.snapshot.lock();
+        let mut changed = self.changed_since_snapshot.lock();
+        if !changed.is_empty() {
+            let index = Arc::make_mut(&mut snapshot);
+            for id in changed.drain() {
+                match (self.span(id), self.activities.get(&id)) {
+                    (Some(span), Some(decomposed)) => {
+                        index.insert(
+                            id,
+                            Arc::new(SnapshotActivity {
+                                span,
+                                key: decomposed.key.clone(),
+                                metadata: decomposed.metadata.clone(),
+                            }),
+                        );
+                    }
+                    _ => {
+                        index.remove(&id);
+                    }
+                }
+            }
+        }
+        PlanSnapshot::new(self.start, snapshot.clone(), self.generation())
+    }
+
+    /// Records an edit of an activity, for the next snapshot.
+    fn changed(&self, id: ActivityId) {
+        self.changed_since_snapshot.lock().insert(id);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// The number of edits made to the plan so far.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Finds all activities with `tag` that start within `bounds`, ordered by start time.
+    pub fn activities_tagged(&self, tag: &str, bounds: impl RangeBounds<Time>) -> Vec<ActivityId> {
+        let mut found: Vec<_> = self
+            .activities
+            .iter()
+            .filter(|(_, a)| bounds.contains(&a.start) && a.metadata.has_tag(tag))
+            .map(|(id, a)| (a.start, *id))
+            .collect();
+        found.sort();
+        found.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// The command sequence of the activities that start within `bounds`, ordered by time.
+    /// See [commands].
+    pub fn commands(&self, bounds: impl RangeBounds<Time>) -> Vec<Command> {
+        let mut commands: Vec<_> = self
+            .activities
+            .iter()
+            .filter(|(_, a)| bounds.contains(&a.start))
+            .map(|(id, a)| {
+                let activity = &a.activity;
+                Command {
+                    time: a.start,
+                    mnemonic: activity.label(),
+                    arguments: activity.arguments(),
+                    activity: *id,
+                    key: a.key.clone(),
+                    duration: a.duration,
+                    metadata: a.metadata.clone(),
+                }
+            })
+            .collect();
+        commands.sort_by_key(|command| (command.time, command.activity));
+        commands
+    }
+
+    /// Removes the activity inserted with `key`.
+    pub fn remove_by_key(&mut self, key: &str) -> Result<(), EngineError> {
+        let id = self
+            .get_id(key)
+            .ok_or_else(|| EngineError::KeyNotFound(key.to_string()))?;
+        self.remove(id)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", name = "insert", skip_all, fields(%time, ?key, id = tracing::field::Empty))
+    )]
+    fn insert_inner(
+        &mut self,
+        time: Time,
+        activity: Box<dyn Activity<'o, M>>,
+        key: Option<String>,
+    ) -> Result<ActivityId, EngineError> {
+        let decomposition = self.decompose(time, activity)?;
+        // The plan is borrowed mutably.
+        let id = unsafe { self.insert_decomposed(time, decomposition, key)? };
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("id", tracing::field::debug(id));
+        self.notify_subscriptions();
+        Ok(id)
+    }
+
+    /// Inserts a decomposed activity through `&self`, for [SharedPlan].
+    ///
+    /// # Safety
+    ///
+    /// Nothing else can read the activities, or use the timelines of the resources the
+    /// operations write, until this returns.
+    unsafe fn insert_decomposed(
+        &self,
+        time: Time,
+        (activity, duration, operations): Decomposition<'o, M>,
+        key: Option<String>,
+    ) -> Result<ActivityId, EngineError> {
+        let inserted = self.timelines.sequence();
+        let disruptive = self.has_been_simulated.load(Ordering::Relaxed);
+        unsafe { self.insert_operations(&operations, disruptive) }.map_err(|source| {
+            EngineError::InsertionFailed {
+                activity: activity.label(),
+                source,
+            }
+        })?;
+
+        let id_counter = unsafe { self.id_counter.edit() };
+        let id = ActivityId::new(*id_counter);
+        *id_counter += 1;
+        if let Some(key) = &key {
+            unsafe { self.keys.edit() }.insert(key.clone(), id);
+        }
+        unsafe { self.activities.edit() }.insert(
             id,
             DecomposedActivity {
-                activity: activity_pointer,
+                activity,
                 operations,
+                key,
+                start: time,
+                duration,
+                metadata: ActivityMetadata::default(),
+                anchor: None,
+                inserted,
             },
         );
+        self.changed(id);
 
         Ok(id)
     }
 
-    /// Removes an activity from the plan, by ID.
-    pub fn remove(&mut self, id: ActivityId) -> Result<()> {
+    /// Inserts all of `operations` into the timelines, or none of them: if one can't be
+    /// inserted, the ones before it are removed again, so that nothing is left borrowing the
+    /// activity they were decomposed from.
+    ///
+    /// # Safety
+    ///
+    /// As for [Node::insert_self].
+    unsafe fn insert_operations(
+        &self,
+        operations: &[&'o dyn Node<'o, M>],
+        disruptive: bool,
+    ) -> anyhow::Result<()> {
+        for (index, op) in operations.iter().enumerate() {
+            if let Err(e) = unsafe { op.insert_self(&self.timelines, disruptive) } {
+                let inserted = &operations[..index];
+                let removal = Removal::new(inserted.iter().copied());
+                for op in inserted {
+                    unsafe { op.remove_self(&self.timelines, &removal) }
+                        .expect("operations that were just inserted can be removed");
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates an activity and generates its operations, without inserting them.
+    fn decompose(
+        &self,
+        time: Time,
+        activity: Box<dyn Activity<'o, M>>,
+    ) -> Result<Decomposition<'o, M>, EngineError> {
+        activity
+            .validate()
+            .map_err(|source| EngineError::InvalidArguments {
+                activity: activity.label(),
+                source,
+            })?;
+
+        let bump = self.arena().get();
+        // Activities are on the heap rather than in the arena, so that compaction can
+        // decompose them again into a new arena.
+        let activity = ActivityBox::new(activity);
+        let label = activity.label();
+        // The operations are only inserted along with the activity, see
+        // [Plan::insert_decomposed].
+        let (duration, operations) = unsafe { activity.borrow_for_operations() }
+            .decompose(Grounding::Static(epoch_to_instant(time)?), &bump)
+            .map_err(|source| EngineError::DecompositionFailed {
+                activity: label,
+                source,
+            })?;
+        for op in &operations {
+            if let Some(resource) = op
+                .info()
+                .writes
+                .iter()
+                .find(|resource| self.timelines.is_profile(resource))
+            {
+                return Err(EngineError::InsertionFailed {
+                    activity: label,
+                    source: anyhow!("{resource} is a boundary profile, and can't be written"),
+                });
+            }
+        }
+        Ok((activity, duration, operations))
+    }
+
+    /// Swaps an activity for another one at the same start time, keeping its ID, key,
+    /// metadata, and anchor.
+    ///
+    /// This is an edit rather than a removal and an insertion: the new operations are inserted
+    /// before the old ones are removed, so operations downstream of both are only invalidated
+    /// once, and only those reading the resources around the old or new operations are
+    /// invalidated at all. If the new activity is invalid, the plan is unchanged.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(?id))
+    )]
+    pub fn replace(
+        &mut self,
+        id: ActivityId,
+        activity: impl Activity<'o, M> + 'static,
+    ) -> Result<(), EngineError> {
+        let start = self.start_of(id).ok_or(EngineError::ActivityNotFound(id))?;
+        let (activity, duration, operations) = self.decompose(start, Box::new(activity))?;
+
+        let disruptive = self.has_been_simulated.load(Ordering::Relaxed);
+        let inserted = self.timelines.sequence();
+        // The plan is borrowed mutably.
+        unsafe { self.insert_operations(&operations, disruptive) }.map_err(|source| {
+            EngineError::InsertionFailed {
+                activity: activity.label(),
+                source,
+            }
+        })?;
+
+        let decomposed = self.activities.get_mut(&id).unwrap();
+        let old_operations = std::mem::replace(&mut decomposed.operations, operations);
+        let old_activity = std::mem::replace(&mut decomposed.activity, activity);
+        decomposed.duration = duration;
+        decomposed.inserted = inserted;
+        self.changed(id);
+
+        let removal = Removal::new(old_operations.iter().copied());
+        for op in old_operations {
+            // The plan is borrowed mutably.
+            if let Err(source) = unsafe { op.remove_self(&self.timelines, &removal) } {
+                // The operations that weren't removed still borrow the old activity.
+                old_activity.leak();
+                return Err(EngineError::RemovalFailed {
+                    activity: id,
+                    source,
+                });
+            }
+        }
+        drop(old_activity);
+        self.notify_subscriptions();
+
+        Ok(())
+    }
+
+    /// Varies an activity across `values`, [replacing][Plan::replace] it with the activity made
+    /// from each value in turn and then calling `evaluate`, and returns each value with what
+    /// `evaluate` returned for it.
+    ///
+    /// Every variant uses the same history, so only the operations downstream of the activity
+    /// are simulated again for each value, and only if the value changes their inputs. The
+    /// activity needs to implement [Serialize][serde::Serialize] for its arguments to be part
+    /// of the hashes of its operations. It is left made from the last value, so replace it
+    /// again afterwards to keep a different one.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// # resource!(sweep_battery: f64);
+    /// # model! { SweptPower(sweep_battery) }
+    /// #[derive(serde::Serialize)]
+    /// pub struct Heat(f64);
+    /// impl_activity! { for Heat
+    ///     @(start) {
+    ///         ref mut: sweep_battery -= self.0;
+    ///     }
+    ///     Duration::ZERO
+    /// }
+    ///
+    /// # fn main() -> Result<()> {
+    /// # let session = Session::new();
+    /// # let start = Time::from_tai_seconds(0.0);
+    /// let mut plan = session.new_plan::<SweptPower>(start, initial_conditions! { sweep_battery: 100.0 })?;
+    /// let end = start + Duration::from_hours(2.0);
+    /// let heater = plan.insert(start + Duration::from_hours(1.0), Heat(10.0))?;
+    ///
+    /// let results = plan.sweep(heater, [5.0, 10.0, 20.0], |&watts| Heat(watts), |plan| {
+    ///     plan.sample::<sweep_battery>(end)
+    /// })?;
+    /// assert_eq!(vec![(5.0, 95.0), (10.0, 90.0), (20.0, 80.0)], results);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn sweep<P, A: Activity<'o, M> + 'static, T>(
+        &mut self,
+        id: ActivityId,
+        values: impl IntoIterator<Item = P>,
+        mut activity: impl FnMut(&P) -> A,
+        mut evaluate: impl FnMut(&Self) -> Result<T, EngineError>,
+    ) -> Result<Vec<(P, T)>, EngineError> {
+        let mut results = vec![];
+        for value in values {
+            self.replace(id, activity(&value))?;
+            let result = evaluate(self)?;
+            results.push((value, result));
+        }
+        Ok(results)
+    }
+
+    /// Moves an activity by `offset`, keeping its ID, key, metadata, and anchor. Activities
+    /// anchored to it are moved with it, so their offsets from it stay the same.
+    ///
+    /// If the activity can't be decomposed at its new start, the plan is unchanged.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(?id))
+    )]
+    pub fn shift(&mut self, id: ActivityId, offset: Duration) -> Result<(), EngineError> {
+        let mut moved = vec![id];
+        let mut i = 0;
+        while i < moved.len() {
+            moved.extend(self.anchored_to(moved[i]));
+            i += 1;
+        }
+        for (index, moving) in moved.iter().enumerate() {
+            if let Err(e) = self.move_activity(*moving, offset) {
+                for undo in &moved[..index] {
+                    self.move_activity(*undo, -offset)?;
+                }
+                return Err(e);
+            }
+        }
+        self.notify_subscriptions();
+        Ok(())
+    }
+
+    fn move_activity(&mut self, id: ActivityId, offset: Duration) -> Result<(), EngineError> {
         let decomposed = self
             .activities
-            .remove(&id)
-            .ok_or_else(|| anyhow!("could not find activity with id {id:?}"))?;
-        for op in decomposed.operations {
-            op.remove_self(&mut self.timelines)?;
+            .get(&id)
+            .ok_or(EngineError::ActivityNotFound(id))?;
+        let start = decomposed.start + offset;
+        // The old operations are replaced by the new ones, which are kept along with the
+        // activity.
+        let activity = unsafe { decomposed.activity.borrow_for_operations() };
+        let label = activity.label();
+        let bump = self.arena().get();
+        let (duration, operations) = activity
+            .decompose(Grounding::Static(epoch_to_instant(start)?), &bump)
+            .map_err(|source| EngineError::DecompositionFailed {
+                activity: label,
+                source,
+            })?;
+
+        let disruptive = self.has_been_simulated.load(Ordering::Relaxed);
+        let inserted = self.timelines.sequence();
+        // The plan is borrowed mutably.
+        unsafe { self.insert_operations(&operations, disruptive) }.map_err(|source| {
+            EngineError::InsertionFailed {
+                activity: label,
+                source,
+            }
+        })?;
+
+        let decomposed = self.activities.get_mut(&id).unwrap();
+        let old_operations = std::mem::replace(&mut decomposed.operations, operations);
+        decomposed.start = start;
+        decomposed.duration = duration;
+        decomposed.inserted = inserted;
+        self.changed(id);
+
+        let removal = Removal::new(old_operations.iter().copied());
+        for op in old_operations {
+            // The plan is borrowed mutably.
+            unsafe { op.remove_self(&self.timelines, &removal) }.map_err(|source| {
+                EngineError::RemovalFailed {
+                    activity: id,
+                    source,
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Inserts an activity that starts `offset` after the start of another activity.
+    ///
+    /// The relationship is used when the anchor is removed; see [Plan::remove_with].
+    pub fn insert_anchored(
+        &mut self,
+        anchor: ActivityId,
+        offset: Duration,
+        activity: impl Activity<'o, M> + 'static,
+    ) -> Result<ActivityId, EngineError> {
+        let start = self
+            .start_of(anchor)
+            .ok_or(EngineError::ActivityNotFound(anchor))?;
+        let id = self.insert(start + offset, activity)?;
+        self.activities.get_mut(&id).unwrap().anchor = Some((anchor, offset));
+        Ok(id)
+    }
+
+    /// The activity that `id` is anchored to, and the offset from its start.
+    pub fn anchor_of(&self, id: ActivityId) -> Option<(ActivityId, Duration)> {
+        self.activities.get(&id)?.anchor
+    }
+
+    /// All activities directly anchored to `id`.
+    pub fn anchored_to(&self, id: ActivityId) -> Vec<ActivityId> {
+        let mut dependents: Vec<_> = self
+            .activities
+            .iter()
+            .filter(|(_, a)| matches!(a.anchor, Some((anchor, _)) if anchor == id))
+            .map(|(dependent, _)| *dependent)
+            .collect();
+        dependents.sort();
+        dependents
+    }
+
+    /// Removes an activity from the plan, by ID.
+    ///
+    /// Activities anchored to it are kept in place, and re-anchored as described in
+    /// [Dependents::Reanchor].
+    pub fn remove(&mut self, id: ActivityId) -> Result<(), EngineError> {
+        self.remove_with(id, Dependents::Reanchor).map(|_| ())
+    }
+
+    /// Removes an activity, and handles the activities anchored to it according to
+    /// `dependents`. Returns the IDs of all removed activities, starting with `id`.
+    ///
+    /// Sub-activities spawned by an activity's body are part of that activity, and are always
+    /// removed with it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", name = "remove", skip(self), fields(removed = tracing::field::Empty))
+    )]
+    pub fn remove_with(
+        &mut self,
+        id: ActivityId,
+        dependents: Dependents,
+    ) -> Result<Vec<ActivityId>, EngineError> {
+        let anchor = self
+            .activities
+            .get(&id)
+            .ok_or(EngineError::ActivityNotFound(id))?
+            .anchor;
+
+        let removed = match dependents {
+            Dependents::Cascade => {
+                let mut removed = vec![id];
+                let mut i = 0;
+                while i < removed.len() {
+                    removed.extend(self.anchored_to(removed[i]));
+                    i += 1;
+                }
+                removed
+            }
+            Dependents::Reanchor => {
+                // The plan is borrowed mutably.
+                unsafe { self.reanchor_dependents(id, anchor) };
+                vec![id]
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("removed", removed.len());
+        // The plan is borrowed mutably.
+        unsafe { self.remove_batch(&removed)? };
+        self.notify_subscriptions();
+        Ok(removed)
+    }
+
+    /// Removes several activities at once. Activities anchored to them are re-anchored as in
+    /// [Plan::remove]. If any of the IDs are not in the plan, nothing is removed.
+    ///
+    /// This is faster than removing them one by one, because operations don't clear the caches
+    /// of downstream operations that are also being removed. Only the operations that stay in
+    /// the plan are notified, once.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(removed = tracing::field::Empty))
+    )]
+    pub fn remove_all(
+        &mut self,
+        ids: impl IntoIterator<Item = ActivityId>,
+    ) -> Result<(), EngineError> {
+        let mut ids: Vec<_> = ids.into_iter().collect();
+        ids.sort();
+        ids.dedup();
+        if let Some(missing) = ids.iter().find(|id| !self.activities.contains_key(id)) {
+            return Err(EngineError::ActivityNotFound(*missing));
         }
-        unsafe { std::ptr::drop_in_place(decomposed.activity) };
 
+        for id in &ids {
+            let anchor = self.activities[id].anchor;
+            // The plan is borrowed mutably.
+            unsafe { self.reanchor_dependents(*id, anchor) };
+        }
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("removed", ids.len());
+        unsafe { self.remove_batch(&ids)? };
+        self.notify_subscriptions();
+        Ok(())
+    }
+
+    /// Removes every activity that matches `predicate`, as in [Plan::remove_all]. Returns the
+    /// IDs of the removed activities, ordered by start time.
+    pub fn remove_where(
+        &mut self,
+        mut predicate: impl FnMut(&ActivitySpan, &ActivityMetadata) -> bool,
+    ) -> Result<Vec<ActivityId>, EngineError> {
+        let removed: Vec<_> = self
+            .spans()
+            .into_iter()
+            .filter(|span| predicate(span, &self.activities[&span.id].metadata))
+            .map(|span| span.id)
+            .collect();
+        self.remove_all(removed.iter().copied())?;
+        Ok(removed)
+    }
+
+    /// Removes every activity, keeping the initial conditions, so that the plan can be reused
+    /// for another planning cycle.
+    ///
+    /// IDs are not reused, and the memory of the removed operations is kept until
+    /// [Plan::compact] is called.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(removed = self.activities.len()))
+    )]
+    pub fn clear(&mut self) -> Result<(), EngineError> {
+        let ids: Vec<_> = self.activities.keys().copied().collect();
+        // The plan is borrowed mutably.
+        unsafe { self.remove_batch(&ids)? };
+        self.notify_subscriptions();
+        Ok(())
+    }
+
+    /// Restricts the plan to the activities that fit entirely within `horizon`, from their
+    /// start to their end and the latest time any of their operations can happen.
+    ///
+    /// With [OutsideHorizon::Remove] the other activities are removed as in [Plan::remove_all],
+    /// and their IDs are returned ordered by start time. With [OutsideHorizon::Error] nothing
+    /// is removed, and they are reported in an [EngineError::OutsideHorizon] instead.
+    pub fn trim(
+        &mut self,
+        horizon: impl RangeBounds<Time>,
+        outside: OutsideHorizon,
+    ) -> Result<Vec<ActivityId>, EngineError> {
+        let outside_ids: Vec<_> =
+            self.spans()
+                .into_iter()
+                .filter(|span| {
+                    !(horizon.contains(&span.start)
+                        && horizon.contains(&span.end())
+                        && span.operations.iter().all(|op| {
+                            horizon.contains(&op.min_time) && horizon.contains(&op.max_time)
+                        }))
+                })
+                .map(|span| span.id)
+                .collect();
+
+        match outside {
+            OutsideHorizon::Error if !outside_ids.is_empty() => {
+                Err(EngineError::OutsideHorizon(outside_ids))
+            }
+            OutsideHorizon::Error => Ok(outside_ids),
+            OutsideHorizon::Remove => {
+                self.remove_all(outside_ids.iter().copied())?;
+                Ok(outside_ids)
+            }
+        }
+    }
+
+    /// Calls `callback` after every edit that makes part of `window` of `R` stale, as described
+    /// in [subscription].
+    ///
+    /// Parts of the window that are already stale, for example because they have never been
+    /// viewed, are not reported until something else in the window goes stale.
+    pub fn subscribe<R: Resource<'o> + 'o>(
+        &mut self,
+        window: impl RangeBounds<Time>,
+        on_invalidate: OnInvalidate,
+        callback: impl FnMut(Notification<R::Read>) + Send + 'static,
+    ) -> SubscriptionId {
+        let window = (window.start_bound().cloned(), window.end_bound().cloned());
+        let reported = self.stale_ranges::<R>(R::ID, window);
+        self.add_subscription(|id| {
+            Box::new(Subscription::<R> {
+                id,
+                window,
+                on_invalidate,
+                reported,
+                callback: Box::new(callback),
+            })
+        })
+    }
+
+    pub(crate) fn add_subscription(
+        &mut self,
+        subscription: impl FnOnce(SubscriptionId) -> Box<dyn ErasedSubscription<'o, M> + 'o>,
+    ) -> SubscriptionId {
+        let id = SubscriptionId(self.subscription_counter);
+        self.subscription_counter += 1;
+        self.subscriptions.get_mut().push(subscription(id));
+        id
+    }
+
+    /// Removes a subscription, returning whether it existed.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let subscriptions = self.subscriptions.get_mut();
+        let before = subscriptions.len();
+        subscriptions.retain(|subscription| subscription.id() != id);
+        subscriptions.len() < before
+    }
+
+    pub(crate) fn notify_subscriptions(&mut self) {
+        let mut subscriptions = std::mem::take(self.subscriptions.get_mut());
+        for subscription in &mut subscriptions {
+            subscription.check(self);
+        }
+        *self.subscriptions.get_mut() = subscriptions;
+        self.invalidate_constraints();
+    }
+
+    /// The time ranges of `R` that have been invalidated by edits since they were last
+    /// simulated, or were never simulated, ordered by time. Nothing is simulated.
+    ///
+    /// Viewing exactly these ranges brings the whole resource up to date. Each range starts at
+    /// an operation that would be evaluated again, and ends at the next operation after it
+    /// that is still cached, which may not depend on it at all, so the ranges are an upper
+    /// bound on what changed.
+    pub fn dirty_ranges<R: Resource<'o> + 'o>(&self) -> Vec<TimeRange> {
+        self.stale_ranges::<R>(R::ID, (Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// Like [Plan::dirty_ranges], for one key of a [keyed resource][resource::KeyedResource].
+    pub fn dirty_ranges_key<R: KeyedResource<'o> + 'o>(&self, key: &R::Key) -> Vec<TimeRange> {
+        self.stale_ranges::<R>(key_id::<R>(key), (Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// The parts of `window` of the timeline `id` that a view would have to evaluate again.
+    pub(crate) fn stale_ranges<R: Resource<'o> + 'o>(
+        &self,
+        id: u64,
+        window: TimeRange,
+    ) -> Vec<TimeRange> {
+        self.timelines
+            .uncached::<R>(id)
+            .into_iter()
+            .filter_map(|(start, end)| {
+                let range = (
+                    Bound::Included(instant_to_epoch(start)),
+                    end.map_or(Bound::Unbounded, |end| {
+                        Bound::Excluded(instant_to_epoch(end))
+                    }),
+                );
+                intersect(range, window)
+            })
+            .collect()
+    }
+
+    /// Anchors the dependents of `id` to `anchor`, the anchor of `id` itself.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else can read the activities until this returns.
+    unsafe fn reanchor_dependents(&self, id: ActivityId, anchor: Option<(ActivityId, Duration)>) {
+        for dependent in self.anchored_to(id) {
+            let decomposed = unsafe { self.activities.edit() }
+                .get_mut(&dependent)
+                .unwrap();
+            let (_, offset) = decomposed.anchor.unwrap();
+            decomposed.anchor =
+                anchor.map(|(grandparent, parent_offset)| (grandparent, parent_offset + offset));
+        }
+    }
+
+    /// Removes the activities through `&self`, for [SharedPlan].
+    ///
+    /// # Safety
+    ///
+    /// Nothing else can read the activities, or use the timelines of the resources their
+    /// operations write, until this returns.
+    unsafe fn remove_batch(&self, ids: &[ActivityId]) -> Result<(), EngineError> {
+        let removal = Removal::new(
+            ids.iter()
+                .filter_map(|id| self.activities.get(id))
+                .flat_map(|decomposed| decomposed.operations.iter().copied()),
+        );
+        for id in ids {
+            unsafe { self.remove_single(*id, &removal)? };
+        }
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// As for [Plan::remove_batch].
+    unsafe fn remove_single(&self, id: ActivityId, removal: &Removal) -> Result<(), EngineError> {
+        let activities = unsafe { self.activities.edit() };
+        let decomposed = activities
+            .get(&id)
+            .ok_or(EngineError::ActivityNotFound(id))?;
+        // Marked before removing anything, since a failed removal still changes the timelines.
+        self.changed(id);
+        for op in &decomposed.operations {
+            if let Err(source) = unsafe { op.remove_self(&self.timelines, removal) } {
+                // The operations that weren't removed still borrow the activity, so the plan
+                // keeps it.
+                return Err(EngineError::RemovalFailed {
+                    activity: id,
+                    source,
+                });
+            }
+        }
+
+        let decomposed = activities.remove(&id).unwrap();
+        if let Some(key) = &decomposed.key {
+            unsafe { self.keys.edit() }.remove(key);
+        }
+        for constraint in self.constraints.lock().iter_mut() {
+            for op in &decomposed.operations {
+                constraint.removed(&op.info());
+            }
+        }
         Ok(())
     }
 
@@ -429,15 +2077,191 @@ impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
     pub fn view<R: Resource<'o> + 'o>(
         &self,
         bounds: impl RangeBounds<Time>,
-    ) -> Result<Vec<(Time, R::Read)>>
+    ) -> Result<Vec<(Time, R::Read)>, EngineError>
+    where
+        Self: 'o,
+    {
+        Self::collect_view::<R>(self.view_inner::<R>(R::ID, bounds, None, None)?)
+    }
+
+    /// Like [Plan::view], but only returns the pieces of the window whose values changed since
+    /// the last call for `R`, as described in [delta]. The first call returns the whole window.
+    pub fn view_delta<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Vec<ViewDelta<R::Read>>, EngineError>
+    where
+        Self: 'o,
+        R::Read: PartialEq,
+    {
+        let window = (bounds.start_bound().cloned(), bounds.end_bound().cloned());
+        let stale = self.stale_ranges::<R>(R::ID, window);
+        let values = self.view::<R>(window)?;
+
+        let mut previous_views = self.previous_views.lock();
+        let deltas = match previous_views.get(&R::ID) {
+            None => vec![ViewDelta {
+                range: window,
+                values: values.clone(),
+            }],
+            Some(previous) => {
+                let previous = unsafe { previous.downcast::<PreviousView<'o, R>>() };
+                split(window, &stale)
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, piece)| {
+                        let new_values = piece_values(&values, piece, i == 0);
+                        (!unchanged(previous, piece, i == 0, &new_values)).then_some(ViewDelta {
+                            range: piece,
+                            values: new_values,
+                        })
+                    })
+                    .collect()
+            }
+        };
+        previous_views.insert(R::ID, Box::new(PreviousView::<R> { window, values }));
+        Ok(deltas)
+    }
+
+    /// Like [Plan::view], but also records every operation evaluation in the order it happened,
+    /// along with the hashes of the values it consumed.
+    ///
+    /// This is a debugging tool for the rare bugs that only show up under a particular
+    /// interleaving of threads; the recording can be replayed on a single thread with
+    /// [Plan::replay]. Operations whose results were already computed by an earlier view of
+    /// this plan are not evaluated again, and so are not recorded.
+    #[allow(clippy::type_complexity)]
+    pub fn view_recorded<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<(Vec<(Time, R::Read)>, Recording<'o>), EngineError>
+    where
+        Self: 'o,
+    {
+        let recorder = Recorder::default();
+        let result =
+            Self::collect_view::<R>(self.view_inner::<R>(R::ID, bounds, Some(&recorder), None)?)?;
+        let mut recording = recorder.into_recording(self.arena.clone());
+        let locate = self.op_locator();
+        let recorded: HashSet<ActivityId> = recording
+            .executions
+            .iter()
+            .filter_map(|execution| locate(execution.node_address()))
+            .map(|(id, _)| id)
+            .collect();
+        for id in recorded {
+            recording.keep_alive(Box::new(self.activities[&id].activity.share()));
+        }
+        Ok((result, recording))
+    }
+
+    /// Re-evaluates every operation in a recording on the current thread, in the recorded order,
+    /// using the values stored in history as inputs.
+    ///
+    /// Returns an error at the first operation that fails, or whose output differs from what
+    /// was produced during the recorded view.
+    pub fn replay(&self, recording: &Recording<'o>) -> Result<(), EngineError> {
+        for (i, execution) in recording.executions.iter().enumerate() {
+            execution
+                .rerun(&self.session.history)
+                .map_err(|source| EngineError::OpFailed {
+                    activity: execution.activity,
+                    time: execution.time,
+                    source: source.context(format!("replay diverged at step {i}")),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Checks the cached values used by a view for hidden state, by re-evaluating every
+    /// operation that hit the cache and comparing against the stored value.
+    ///
+    /// Returns all divergences found, instead of failing at the first one. Only operations
+    /// that are evaluated by this view are checked; values computed (rather than loaded from
+    /// history) by this view are not re-evaluated, and neither are operations that were
+    /// already evaluated by an earlier view of this plan. To audit everything, call this on a
+    /// fresh plan built on a session whose history has been populated.
+    pub fn audit<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Vec<CacheDivergence>, EngineError>
+    where
+        Self: 'o,
+    {
+        let audit = CacheAudit::default();
+        Self::collect_view::<R>(self.view_inner::<R>(R::ID, bounds, None, Some(&audit))?)?;
+        Ok(audit.into_vec(self.op_locator()))
+    }
+
+    /// Like [Plan::view], but returns all values that could be computed even if some
+    /// operations failed, along with a report of the failures.
+    ///
+    /// Requested points that depend on a failed operation are left out of the values.
+    pub fn view_partial<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<PartialView<R::Read>, EngineError>
+    where
+        Self: 'o,
+    {
+        let (results, errors) = self.view_inner::<R>(R::ID, bounds, None, None)?;
+        Ok(PartialView {
+            values: results
+                .into_iter()
+                .filter_map(|(time, result)| Some((time?, result.ok()?)))
+                .collect(),
+            errors,
+        })
+    }
+
+    fn view_inner<R: Resource<'o> + 'o>(
+        &self,
+        id: u64,
+        bounds: impl RangeBounds<Time>,
+        recorder: Option<&Recorder<'o>>,
+        audit: Option<&CacheAudit>,
+    ) -> Result<ViewResults<'o, R>, EngineError>
+    where
+        Self: 'o,
+    {
+        let (results, errors) =
+            self.simulate::<R>(id, bounds, recorder, audit, Lane::Foreground)?;
+        Ok(self.report::<R>(results, errors))
+    }
+
+    /// Simulates everything needed for a view, without locating the failed operations in the
+    /// plan. See [Plan::report].
+    #[allow(clippy::type_complexity)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", name = "view", skip_all, fields(resource = R::LABEL, nodes = tracing::field::Empty, failed = tracing::field::Empty))
+    )]
+    pub(crate) fn simulate<R: Resource<'o> + 'o>(
+        &self,
+        id: u64,
+        bounds: impl RangeBounds<Time>,
+        recorder: Option<&Recorder<'o>>,
+        audit: Option<&CacheAudit>,
+        lane: Lane,
+    ) -> Result<
+        (
+            Vec<(Option<Time>, InternalResult<R::Read>)>,
+            ErrorAccumulator,
+        ),
+        EngineError,
+    >
     where
         Self: 'o,
     {
-        self.has_been_simulated.set(true);
-        let mut nodes: Vec<MaybeGrounded<'o, R, M>> = self.timelines.range((
-            bounds.start_bound().map(|t| epoch_to_duration(*t)),
-            bounds.end_bound().map(|t| epoch_to_duration(*t)),
-        ));
+        let instants = instant_bounds(&bounds)?;
+        self.has_been_simulated.store(true, Ordering::Relaxed);
+        let mut nodes: Vec<MaybeGrounded<'o, R, M>> = self.timelines.range(id, instants);
+
+        let has_ungrounded = nodes
+            .iter()
+            .any(|node| matches!(node, MaybeGrounded::Ungrounded(_)));
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("nodes", nodes.len());
 
         let session = &self.session;
 
@@ -445,90 +2269,401 @@ impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
         let errors = ErrorAccumulator::default();
 
         enum MaybeGroundedResult<'o, R: Resource<'o>> {
-            Grounded(Duration, Receiver<InternalResult<R::Read>>),
+            Grounded(Instant, Receiver<InternalResult<R::Read>>),
             Ungrounded(
-                Receiver<InternalResult<Duration>>,
+                Receiver<InternalResult<Instant>>,
                 Receiver<InternalResult<R::Read>>,
             ),
         }
 
         let timelines = &self.timelines;
+        let determinism_check = self.determinism_check;
+        let executor = self.executor.as_deref();
 
-        rayon::scope(|scope| {
-            let env = ExecEnvironment {
-                errors: &errors,
-                history: &session.history,
-                stack_counter: 0,
-            };
-            for node in nodes.drain(..) {
-                let (sender, receiver) = oneshot::channel();
-
-                match node {
-                    MaybeGrounded::Grounded(t, n) => {
-                        receivers.push(MaybeGroundedResult::Grounded(t, receiver));
-                        scope.spawn(move |s| {
-                            n.request(Continuation::Root(sender), s, timelines, env.reset())
-                        });
-                    }
-                    MaybeGrounded::Ungrounded(n) => {
-                        let (grounding_sender, grounding_receiver) = oneshot::channel();
-                        receivers.push(MaybeGroundedResult::Ungrounded(
-                            grounding_receiver,
-                            receiver,
-                        ));
-                        scope.spawn(move |s| {
-                            n.request(
-                                Continuation::<peregrine_grounding, M>::Root(grounding_sender),
-                                s,
-                                timelines,
-                                env.reset(),
-                            );
-                            n.request(
-                                Continuation::<R, M>::Root(sender),
-                                s,
-                                timelines,
-                                env.reset(),
-                            );
-                        });
+        lane.run(|| {
+            rayon::scope(|scope| {
+                let env = ExecEnvironment {
+                    errors: &errors,
+                    history: &session.history,
+                    recorder,
+                    audit,
+                    determinism_check,
+                    executor,
+                    tuning: session.tuning,
+                    stack_counter: 0,
+                };
+                for node in nodes.drain(..) {
+                    let (sender, receiver) = oneshot::channel();
+
+                    match node {
+                        MaybeGrounded::Grounded(t, n) => {
+                            receivers.push(MaybeGroundedResult::Grounded(t, receiver));
+                            env.spawn(scope, move |s, env| {
+                                n.request(Continuation::Root(sender), s, timelines, env)
+                            });
+                        }
+                        MaybeGrounded::Ungrounded(n) => {
+                            let (grounding_sender, grounding_receiver) = oneshot::channel();
+                            receivers.push(MaybeGroundedResult::Ungrounded(
+                                grounding_receiver,
+                                receiver,
+                            ));
+                            env.spawn(scope, move |s, env| {
+                                n.request(
+                                    Continuation::<peregrine_grounding, M>::Root(grounding_sender),
+                                    s,
+                                    timelines,
+                                    env,
+                                );
+                                n.request(Continuation::<R, M>::Root(sender), s, timelines, env);
+                            });
+                        }
                     }
                 }
-            }
+            });
         });
 
-        if !errors.is_empty() {
-            Err(errors.into())
+        let mut results: Vec<(Option<Time>, InternalResult<R::Read>)> = receivers
+            .into_iter()
+            .map(|r| match r {
+                MaybeGroundedResult::Grounded(t, recv) => {
+                    (Some(instant_to_epoch(t)), recv.recv().unwrap())
+                }
+                MaybeGroundedResult::Ungrounded(t_recv, recv) => {
+                    let time = t_recv.recv().unwrap().ok().map(instant_to_epoch);
+                    (time, recv.recv().unwrap())
+                }
+            })
+            .collect();
+
+        if has_ungrounded {
+            Self::trim_to_bounds::<R>(&mut results, &bounds);
+        }
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("failed", !errors.is_empty());
+        Ok((results, errors))
+    }
+
+    /// Finishes a view by collecting its errors into a report, if there were any.
+    pub(crate) fn report<R: Resource<'o> + 'o>(
+        &self,
+        results: Vec<(Option<Time>, InternalResult<R::Read>)>,
+        errors: ErrorAccumulator,
+    ) -> ViewResults<'o, R> {
+        let report = if errors.is_empty() {
+            None
         } else {
-            receivers
-                .into_iter()
-                .map(|r| match r {
-                    MaybeGroundedResult::Grounded(t, recv) => {
-                        Ok((duration_to_epoch(t), recv.recv().unwrap()?))
-                    }
-                    MaybeGroundedResult::Ungrounded(t_recv, recv) => Ok((
-                        duration_to_epoch(t_recv.recv().unwrap()?),
-                        recv.recv().unwrap()?,
-                    )),
+            let affected = results
+                .iter()
+                .filter(|(_, result)| result.is_err())
+                .filter_map(|(time, _)| *time)
+                .collect();
+            Some(errors.into_report(R::LABEL, affected, self.op_locator()))
+        };
+        (results, report)
+    }
+
+    /// Dynamically placed operations are only known to be inside the view after they are
+    /// grounded. Sorts the results by time and drops those outside of `bounds`, except for the
+    /// last one before the start, which gives the value at the start.
+    fn trim_to_bounds<R: Resource<'o> + 'o>(
+        results: &mut Vec<(Option<Time>, InternalResult<R::Read>)>,
+        bounds: &impl RangeBounds<Time>,
+    ) {
+        results.sort_by_key(|(time, _)| (time.is_none(), *time));
+        results.retain(|(time, _)| {
+            time.is_none_or(|t| match bounds.end_bound() {
+                Bound::Included(end) => t <= *end,
+                Bound::Excluded(end) => t < *end,
+                Bound::Unbounded => true,
+            })
+        });
+
+        let before_start = results
+            .iter()
+            .take_while(|(time, _)| {
+                time.is_some_and(|t| match bounds.start_bound() {
+                    Bound::Included(start) => t < *start,
+                    Bound::Excluded(start) => t <= *start,
+                    Bound::Unbounded => false,
                 })
-                .collect()
+            })
+            .count();
+        let at_start = matches!(
+            (bounds.start_bound(), results.get(before_start)),
+            (Bound::Included(start), Some((Some(t), _))) if t == start
+        );
+        let keep_from = if at_start {
+            before_start
+        } else {
+            before_start.saturating_sub(1)
+        };
+        results.drain(..keep_from);
+    }
+
+    fn collect_view<R: Resource<'o> + 'o>(
+        (results, report): ViewResults<'o, R>,
+    ) -> Result<Vec<(Time, R::Read)>, EngineError> {
+        if let Some(report) = report {
+            return Err(report.into());
         }
+        results
+            .into_iter()
+            .map(|(time, result)| Ok((time.ok_or(EngineError::PreviouslyFailed)?, result?)))
+            .collect()
+    }
+
+    /// Maps the addresses of operations in the plan to their activity ID and index.
+    fn op_locator(&self) -> impl Fn(usize) -> Option<(ActivityId, usize)> {
+        let locations: HashMap<usize, (ActivityId, usize)> = self
+            .activities
+            .iter()
+            .flat_map(|(id, decomposed)| {
+                decomposed
+                    .operations
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, op)| {
+                        (
+                            *op as *const dyn Node<'o, M> as *const () as usize,
+                            (*id, i),
+                        )
+                    })
+            })
+            .collect();
+        move |node| locations.get(&node).copied()
     }
 
-    pub fn sample<R: Resource<'o> + 'o>(&self, time: Time) -> Result<R::Read> {
+    /// The value of a resource at `time`, after every operation at exactly that time.
+    pub fn sample<R: Resource<'o> + 'o>(&self, time: Time) -> Result<R::Read, EngineError> {
         Ok(self
             .view::<R>(time..=time)?
-            .first()
-            .ok_or_else(|| anyhow!("No operations to sample found at or before {time}"))?
+            .last()
+            .ok_or(EngineError::NothingToSample(time))?
             .1)
     }
-}
 
-impl<'o, M: Model<'o>> Drop for Plan<'o, M> {
-    fn drop(&mut self) {
-        for decomposed in self.activities.values_mut() {
-            unsafe {
-                decomposed.activity.drop_in_place();
+    /// The latest time reached by any activity or operation in the plan, or the start of the
+    /// plan if it is empty.
+    pub fn end(&self) -> Time {
+        self.activities
+            .values()
+            .flat_map(|decomposed| {
+                std::iter::once(decomposed.start + decomposed.duration)
+                    .chain(decomposed.operations.iter().map(|op| op.info().max_time))
+            })
+            .fold(self.start, |end, time| end.max(time))
+    }
+
+    /// Samples every resource in the model at the [end][Plan::end] of the plan, as initial
+    /// conditions for a plan of the next period that picks up where this one leaves off.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// # resource!(counter: u32);
+    /// # model! { Counter(counter) }
+    /// # pub struct Increment;
+    /// # impl_activity! { for Increment
+    /// #     @(start) {
+    /// #         ref mut: counter += 1;
+    /// #     }
+    /// #     Duration::from_seconds(1.0)
+    /// # }
+    /// # fn main() -> Result<()> {
+    /// # let session = Session::new();
+    /// # let start = Time::from_tai_seconds(0.0);
+    /// let mut week_1 = session.new_plan::<Counter>(start, initial_conditions! { counter: 0 })?;
+    /// week_1.insert(start + Duration::from_seconds(1.0), Increment)?;
+    ///
+    /// let mut week_2 = session.new_plan::<Counter>(week_1.end(), week_1.final_conditions()?)?;
+    /// week_2.insert(week_1.end() + Duration::from_seconds(1.0), Increment)?;
+    /// assert_eq!(2, week_2.sample::<counter>(week_2.end())?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn final_conditions(&self) -> Result<InitialConditions, EngineError> {
+        self.conditions_at(self.end())
+    }
+
+    /// Samples every resource in the model at `time`, including the keys of keyed resources
+    /// that have their own timelines.
+    pub fn conditions_at(&self, time: Time) -> Result<InitialConditions, EngineError> {
+        let mut conditions = InitialConditions::new();
+        M::sample_conditions(self, time, &mut conditions)?;
+        Ok(conditions)
+    }
+
+    /// The downstreams that the operations of an activity keep to clear when their outputs
+    /// change. Used by tests, to check that removed operations are forgotten.
+    #[doc(hidden)]
+    pub fn recorded_downstreams(&self, id: ActivityId) -> usize {
+        self.activities.get(&id).map_or(0, |decomposed| {
+            decomposed
+                .operations
+                .iter()
+                .map(|op| op.recorded_downstreams())
+                .sum()
+        })
+    }
+
+    /// The bytes of memory that the plan's operations take up. Removing or replacing an
+    /// activity doesn't free the memory of its old operations; only [Plan::compact] does.
+    pub fn arena_bytes(&self) -> usize {
+        self.arena.allocated_bytes()
+    }
+
+    /// Frees the memory of the operations of removed and replaced activities, by decomposing
+    /// every activity again into a fresh arena. Long editing sessions can call this every so
+    /// often to keep [Plan::arena_bytes] from growing without bound.
+    ///
+    /// The timelines are created again from the plan's initial conditions, with boundary
+    /// profiles taken from a copy instead of being loaded again. Activity IDs, keys, metadata
+    /// and anchors are unchanged, and operations at the same time keep their order. The new
+    /// operations haven't been simulated, but their values are found in history, so the next
+    /// view costs little more than a cached one. On an error, the plan is left as it was.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// # resource!(compacted_count: u32);
+    /// # model! { Compacted(compacted_count) }
+    /// # pub struct Bump;
+    /// # impl_activity! { for Bump
+    /// #     @(start) {
+    /// #         ref mut: compacted_count += 1;
+    /// #     }
+    /// #     Duration::ZERO
+    /// # }
+    /// # fn main() -> Result<()> {
+    /// # let session = Session::new();
+    /// # let start = Time::from_tai_seconds(0.0);
+    /// let mut plan = session.new_plan::<Compacted>(start, initial_conditions! { compacted_count: 0 })?;
+    /// for i in 0..1000 {
+    ///     let id = plan.insert(start + Duration::from_seconds(i as f64), Bump)?;
+    ///     if i % 10 != 0 {
+    ///         plan.remove(id)?;
+    ///     }
+    /// }
+    /// let before = plan.arena_bytes();
+    /// plan.compact()?;
+    /// assert!(plan.arena_bytes() < before);
+    /// assert_eq!(100, plan.sample::<compacted_count>(start + Duration::from_hours(1.0))?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compact(&mut self) -> Result<(), EngineError> {
+        let arena = Arc::new(Arena::default());
+        self.session.register_arena(&arena);
+        // The arena is moved into the plan below, along with everything that points into it.
+        let arena_ref: &'o Arena = unsafe { &*Arc::as_ptr(&arena) };
+        // Creating the timelines again from the conditions they were created from can't fail.
+        let mut timelines = M::init_timelines(
+            epoch_to_instant(self.start)?,
+            std::mem::take(self.timelines.initial_conditions_mut()),
+            arena_ref,
+        )?;
+
+        let mut activities = self.activities.iter().collect::<Vec<_>>();
+        activities.sort_by_key(|(_, decomposed)| decomposed.inserted);
+        let bump = arena_ref.get();
+        let mut decompositions = Vec::with_capacity(activities.len());
+        let insert_all = || -> Result<(), EngineError> {
+            for (id, decomposed) in activities {
+                // The new operations replace the old ones only if they are all inserted, and
+                // are dropped along with the new timelines otherwise.
+                let activity = unsafe { decomposed.activity.borrow_for_operations() };
+                let (duration, operations) = activity
+                    .decompose(
+                        Grounding::Static(epoch_to_instant(decomposed.start)?),
+                        &bump,
+                    )
+                    .map_err(|source| EngineError::DecompositionFailed {
+                        activity: activity.label(),
+                        source,
+                    })?;
+                let inserted = timelines.sequence();
+                for op in &operations {
+                    // The new timelines aren't shared yet.
+                    unsafe { op.insert_self(&timelines, false) }.map_err(|source| {
+                        EngineError::InsertionFailed {
+                            activity: activity.label(),
+                            source,
+                        }
+                    })?;
+                }
+                decompositions.push((*id, duration, operations, inserted));
             }
+            Ok(())
+        };
+        if let Err(e) = insert_all() {
+            *self.timelines.initial_conditions_mut() =
+                std::mem::take(timelines.initial_conditions_mut());
+            return Err(e);
+        }
+        drop(bump);
+
+        for (id, duration, operations, inserted) in decompositions {
+            let decomposed = self.activities.get_mut(&id).unwrap();
+            decomposed.duration = duration;
+            decomposed.operations = operations;
+            decomposed.inserted = inserted;
+        }
+        self.timelines = timelines;
+        self.arena = arena;
+        self.has_been_simulated.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Samples a single resource into `conditions`, unless it is already there. Used by the
+    /// code that [model] generates.
+    #[doc(hidden)]
+    pub fn sample_condition<R: Resource<'o> + 'o>(
+        &self,
+        time: Time,
+        conditions: &mut InitialConditions,
+    ) -> Result<(), EngineError>
+    where
+        R::Write: From<R::Read>,
+    {
+        if conditions.contains_id(R::ID) {
+            return Ok(());
         }
+        for id in std::iter::once(R::ID).chain(self.timelines.key_ids::<R>()) {
+            let value =
+                Self::collect_view::<R>(self.view_inner::<R>(id, time..=time, None, None)?)?
+                    .last()
+                    .ok_or(EngineError::NothingToSample(time))?
+                    .1;
+            conditions.insert_id::<R>(id, value.into());
+        }
+        Ok(())
+    }
+
+    /// Like [Plan::view], for one key of a [keyed resource][resource::KeyedResource].
+    pub fn view_key<R: KeyedResource<'o> + 'o>(
+        &self,
+        key: &R::Key,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Vec<(Time, R::Read)>, EngineError>
+    where
+        Self: 'o,
+    {
+        Self::collect_view::<R>(self.view_inner::<R>(key_id::<R>(key), bounds, None, None)?)
+    }
+
+    /// Like [Plan::sample], for one key of a [keyed resource][resource::KeyedResource].
+    pub fn sample_key<R: KeyedResource<'o> + 'o>(
+        &self,
+        key: &R::Key,
+        time: Time,
+    ) -> Result<R::Read, EngineError>
+    where
+        Self: 'o,
+    {
+        Ok(self
+            .view_key::<R>(key, time..=time)?
+            .last()
+            .ok_or(EngineError::NothingToSample(time))?
+            .1)
     }
 }
 
@@ -536,19 +2671,102 @@ impl<'o, M: Model<'o>> Drop for Plan<'o, M> {
 ///
 /// Autogenerated by the [model] macro.
 pub trait Model<'o>: Sync {
+    /// The name of the model, as written in [model!].
+    const LABEL: &'static str;
+
     fn init_history(history: &History);
     fn init_timelines(
-        time: Duration,
+        time: Instant,
         initial_conditions: InitialConditions,
-        herd: &'o Herd,
-    ) -> Timelines<'o, Self>;
+        arena: &'o Arena,
+    ) -> Result<Timelines<'o, Self>, EngineError>;
+
+    /// Initializes the timelines of this model's resources inside the timelines of
+    /// another model `M`, skipping resources it already has. Used to compose submodels.
+    fn init_timelines_into<M: Model<'o>>(
+        time: Instant,
+        initial_conditions: &mut InitialConditions,
+        timelines: &mut Timelines<'o, M>,
+    ) -> Result<(), EngineError>;
+
+    /// Adds the problems with this model's initial conditions to `issues`. See
+    /// [InitialConditions::validate].
+    fn check_initial_conditions(
+        initial_conditions: &InitialConditions,
+        issues: &mut Vec<InitialConditionIssue>,
+    );
+
+    /// Samples this model's resources into `conditions`, skipping resources it already has.
+    /// See [Plan::conditions_at].
+    fn sample_conditions<M: Model<'o> + 'o>(
+        plan: &Plan<'o, M>,
+        time: Time,
+        conditions: &mut InitialConditions,
+    ) -> Result<(), EngineError>;
+
+    /// Adds the schemas of this model's resources to `resources`, skipping resources it
+    /// already has. See [Model::schema].
+    fn describe(resources: &mut Vec<ResourceSchema>);
+
+    /// Views the requested resources of this model into `views`. See [Plan::view_labeled].
+    fn view_labeled<M: Model<'o> + 'o>(
+        plan: &Plan<'o, M>,
+        views: &mut labeled::LabeledViews<'o>,
+    ) -> Result<(), EngineError>;
+
+    /// A description of the model's resources, in the order they are listed in [model!], with
+    /// the resources of submodels after them.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// resource! {
+    ///     #[unit = "W"]
+    ///     described_power: f64
+    /// }
+    /// resource!(ref described_mode: String);
+    /// model! { Described(described_power, described_mode) }
+    ///
+    /// let schema = Described::schema();
+    /// assert_eq!("Described", schema.name);
+    /// assert_eq!("described_power", schema.resources[0].label);
+    /// assert_eq!(Some("W"), schema.resources[0].unit);
+    /// assert_eq!("&str", schema.resources[1].read_type);
+    /// assert_eq!("alloc::string::String", schema.resources[1].write_type);
+    /// ```
+    fn schema() -> ModelSchema
+    where
+        Self: Sized,
+    {
+        let mut resources = vec![];
+        Self::describe(&mut resources);
+        ModelSchema {
+            name: Self::LABEL,
+            resources,
+        }
+    }
+}
+
+/// A runtime description of what a compiled model contains, for tools that don't know its
+/// types, like UIs and importers. Made with [Model::schema].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct ModelSchema {
+    pub name: &'static str,
+    pub resources: Vec<ResourceSchema>,
+}
+
+impl ModelSchema {
+    pub fn resource(&self, label: &str) -> Option<&ResourceSchema> {
+        self.resources
+            .iter()
+            .find(|resource| resource.label == label)
+    }
 }
 
 pub enum Grounding<'o, M: Model<'o>> {
-    Static(Duration),
+    Static(Instant),
     Dynamic {
-        min: Duration,
-        max: Duration,
+        min: Instant,
+        max: Instant,
         node: &'o dyn Upstream<'o, peregrine_grounding, M>,
     },
 }
@@ -569,7 +2787,7 @@ impl<'o, M: Model<'o>> Grounding<'o, M> {
         }
     }
 
-    pub fn min(&self) -> Duration {
+    pub fn min(&self) -> Instant {
         match self {
             Grounding::Static(start) => *start,
             Grounding::Dynamic { min, .. } => *min,
@@ -582,10 +2800,10 @@ impl<'o, M: Model<'o>> Add<Duration> for Grounding<'o, M> {
 
     fn add(self, rhs: Duration) -> Self::Output {
         match self {
-            Grounding::Static(start) => Grounding::Static(start + rhs),
+            Grounding::Static(start) => Grounding::Static(advance(start, rhs)),
             Grounding::Dynamic { min, max, node } => Grounding::Dynamic {
-                min: min + rhs,
-                max: max + rhs,
+                min: advance(min, rhs),
+                max: advance(max, rhs),
                 node,
             },
         }
@@ -0,0 +1,204 @@
+//! Likely mistakes in how activities use resources.
+//!
+//! Some mistakes are caught when an activity is declared: [impl_activity][crate::impl_activity]
+//! warns about resources tagged `ref mut:` that the operation never writes, which should be
+//! `ref:` so that they don't add a node to the resource's timeline. Others depend on where the
+//! activities are placed, and [Plan::lint] finds them in a plan without simulating:
+//!
+//! - a write that is always overwritten before anything reads it;
+//! - a read of a resource that nothing in the plan writes, so it is always the initial
+//!   condition.
+//!
+//! These aren't always wrong, so unlike [Plan::validate] they don't fail anything.
+//!
+//! The keys of [keyed resources][crate::resource::KeyedResource] aren't distinguished, so a
+//! write to one key can look like it is overwritten by a write to another.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::lint::LintKind;
+//! # resource!(linted_mode: u32);
+//! # resource!(linted_gain: u32);
+//! # model! { Linted(linted_mode, linted_gain) }
+//! pub struct SetMode(u32);
+//! impl_activity! { for SetMode
+//!     @(start) {
+//!         mut: linted_mode = self.0 * ref: linted_gain;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Linted>(start, initial_conditions! { linted_mode: 0, linted_gain: 2 })?;
+//! let overwritten = plan.insert(start + Duration::from_seconds(1.0), SetMode(1))?;
+//! let second = plan.insert(start + Duration::from_seconds(2.0), SetMode(2))?;
+//!
+//! // Both activities read the gain, and the first mode is overwritten.
+//! let issues = plan.lint();
+//! assert_eq!(3, issues.len());
+//! assert_eq!(LintKind::NeverWritten("linted_gain"), issues[0].kind);
+//! assert_eq!(overwritten, issues[1].activity_id);
+//! assert_eq!(
+//!     LintKind::Overwritten { resource: "linted_mode", by: second, at: start + Duration::from_seconds(2.0) },
+//!     issues[1].kind
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::activity::ActivityId;
+use crate::{Model, Plan, Time};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// A likely mistake found by [Plan::lint].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LintIssue {
+    pub activity: &'static str,
+    pub activity_id: ActivityId,
+    /// The index of the operation among the operations of its activity.
+    pub op_index: usize,
+    /// The earliest time the operation can happen.
+    pub time: Time,
+    pub kind: LintKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// The operation writes a resource, but the operation of activity `by` writes it again at
+    /// `at` without reading it, and nothing reads it in between.
+    Overwritten {
+        resource: &'static str,
+        by: ActivityId,
+        at: Time,
+    },
+    /// The operation reads a resource that nothing in the plan writes.
+    NeverWritten(&'static str),
+}
+
+impl LintKind {
+    /// The label of the resource the issue is about.
+    pub fn resource(&self) -> &'static str {
+        match self {
+            LintKind::Overwritten { resource, .. } | LintKind::NeverWritten(resource) => resource,
+        }
+    }
+}
+
+impl Display for LintIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "activity {} ({:?}, operation {}) at {}: ",
+            self.activity, self.activity_id, self.op_index, self.time
+        )?;
+        match self.kind {
+            LintKind::Overwritten { resource, by, at } => {
+                write!(
+                    f,
+                    "{resource} is written, but {by:?} overwrites it at {at} before anything reads it"
+                )
+            }
+            LintKind::NeverWritten(resource) => {
+                write!(
+                    f,
+                    "{resource} is read, but nothing writes it, so it is always the initial condition"
+                )
+            }
+        }
+    }
+}
+
+/// How an operation uses one resource.
+struct Access {
+    activity: &'static str,
+    activity_id: ActivityId,
+    op_index: usize,
+    min_time: Time,
+    max_time: Time,
+    reads: bool,
+    writes: bool,
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Finds likely mistakes in how the plan's activities use resources, ordered by activity,
+    /// operation, and resource. See [lint][crate::lint].
+    pub fn lint(&self) -> Vec<LintIssue> {
+        let mut accesses: HashMap<&'static str, Vec<Access>> = HashMap::new();
+        for (id, decomposed) in self.activities.iter() {
+            for (index, op) in decomposed.operations.iter().enumerate() {
+                let info = op.info();
+                let resources: HashSet<&'static str> =
+                    info.reads.iter().chain(info.writes).copied().collect();
+                for resource in resources {
+                    accesses.entry(resource).or_default().push(Access {
+                        activity: info.activity,
+                        activity_id: *id,
+                        op_index: index,
+                        min_time: info.min_time,
+                        max_time: info.max_time,
+                        reads: info.reads.contains(&resource),
+                        writes: info.writes.contains(&resource),
+                    });
+                }
+            }
+        }
+
+        let mut issues = vec![];
+        for (resource, mut accesses) in accesses {
+            accesses.sort_by_key(|access| (access.min_time, access.activity_id, access.op_index));
+            let issue = |access: &Access, kind| LintIssue {
+                activity: access.activity,
+                activity_id: access.activity_id,
+                op_index: access.op_index,
+                time: access.min_time,
+                kind,
+            };
+
+            if !accesses.iter().any(|access| access.writes) {
+                issues.extend(
+                    accesses
+                        .iter()
+                        .map(|access| issue(access, LintKind::NeverWritten(resource))),
+                );
+                continue;
+            }
+
+            // Only writes at known times can be overwritten for certain, by the next write at a
+            // known later time that doesn't read the resource.
+            for (i, write) in accesses.iter().enumerate() {
+                if !write.writes || write.min_time != write.max_time {
+                    continue;
+                }
+                let Some(overwrite) = accesses[i + 1..].iter().find(|other| {
+                    other.min_time > write.max_time
+                        && other.min_time == other.max_time
+                        && other.writes
+                        && !other.reads
+                }) else {
+                    continue;
+                };
+                let read_between = accesses.iter().enumerate().any(|(j, other)| {
+                    j != i
+                        && other.reads
+                        && other.max_time >= write.min_time
+                        && other.min_time <= overwrite.min_time
+                });
+                if !read_between {
+                    issues.push(issue(
+                        write,
+                        LintKind::Overwritten {
+                            resource,
+                            by: overwrite.activity_id,
+                            at: overwrite.min_time,
+                        },
+                    ));
+                }
+            }
+        }
+        issues.sort_by_key(|issue| (issue.activity_id, issue.op_index, issue.kind.resource()));
+        issues
+    }
+}
@@ -0,0 +1,167 @@
+//! Where the memory of a session went.
+//!
+//! A long-running session holds memory in two places: the arena of each plan, where the
+//! operations of its activities are allocated, and the session's [History][crate::History],
+//! where the values they produced are cached. [Session::memory_report] measures both, so that
+//! an operator can tell whether to [compact][crate::Plan::compact] plans,
+//! [evict][crate::History::enforce_capacity] history, or restart.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(reported_count: u32);
+//! # model! { Reported(reported_count) }
+//! # pub struct Count;
+//! # impl_activity! { for Count
+//! #     @(start) {
+//! #         ref mut: reported_count += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Reported>(start, initial_conditions! { reported_count: 0 })?;
+//! for i in 1..=10 {
+//!     plan.insert(start + Duration::from_seconds(i as f64), Count)?;
+//! }
+//! plan.view::<reported_count>(..)?;
+//!
+//! let report = session.memory_report();
+//! assert_eq!(11, report.arenas[0].timeline_nodes);
+//! assert!(report.history[0].resources.contains(&"reported_count"));
+//! println!("{report}");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::Session;
+use crate::arena::Arena;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Weak};
+
+/// The memory of a session, from [Session::memory_report].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryReport {
+    /// Every arena that is still alive, oldest first: one for each plan, and any more that are
+    /// only kept alive by [recordings][crate::exec::Recording] made before a plan was compacted.
+    pub arenas: Vec<ArenaMemory>,
+    /// Every history container with entries, largest first.
+    pub history: Vec<HistoryMemory>,
+}
+
+/// The memory of one plan's arena.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ArenaMemory {
+    /// See [Plan::arena_bytes][crate::Plan::arena_bytes].
+    pub bytes: usize,
+    /// The operations in the plan's timelines, including initial conditions. Operations of
+    /// removed and replaced activities aren't counted, though their memory is until the plan
+    /// is compacted.
+    pub timeline_nodes: usize,
+}
+
+/// The memory of one history container. Resources with the same container type share it, see
+/// [history][crate::history].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryMemory {
+    /// The write type string of the container, see
+    /// [ResourceHistoryPlugin::write_type_string][crate::resource::ResourceHistoryPlugin::write_type_string].
+    pub write_type: String,
+    /// The labels of the resources in the program that share the container, sorted, whether
+    /// or not the session's plans use them.
+    pub resources: Vec<&'static str>,
+    pub entries: usize,
+    /// The estimate from [HistoryContainer::memory_bytes][crate::history::HistoryContainer::memory_bytes].
+    pub bytes: usize,
+}
+
+impl MemoryReport {
+    pub fn arena_bytes(&self) -> usize {
+        self.arenas.iter().map(|arena| arena.bytes).sum()
+    }
+
+    pub fn history_bytes(&self) -> usize {
+        self.history.iter().map(|container| container.bytes).sum()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.arena_bytes() + self.history_bytes()
+    }
+}
+
+impl Display for MemoryReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} arenas: {}, {} timeline nodes",
+            self.arenas.len(),
+            Bytes(self.arena_bytes()),
+            self.arenas
+                .iter()
+                .map(|arena| arena.timeline_nodes)
+                .sum::<usize>()
+        )?;
+        write!(f, "history: {}", Bytes(self.history_bytes()))?;
+        for container in &self.history {
+            write!(
+                f,
+                "\n  {} [{}]: {}, {} entries",
+                container.write_type,
+                container.resources.join(", "),
+                Bytes(container.bytes),
+                container.entries
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a byte count with a binary unit.
+struct Bytes(usize);
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{value:.1} {}", UNITS[unit])
+    }
+}
+
+impl Session {
+    /// Measures the arenas of the session's plans and its history. See [memory][crate::memory].
+    ///
+    /// Arena bytes are exact, but history bytes are estimates, because values can own memory
+    /// that their containers don't know about.
+    pub fn memory_report(&self) -> MemoryReport {
+        let arenas = self
+            .arenas
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|arena| ArenaMemory {
+                bytes: arena.allocated_bytes(),
+                timeline_nodes: arena.timeline_nodes(),
+            })
+            .collect();
+        MemoryReport {
+            arenas,
+            history: self.history.memory_report(),
+        }
+    }
+
+    /// Adds the arena of a new or compacted plan to [Session::memory_report], and forgets the
+    /// ones that have been freed.
+    pub(crate) fn register_arena(&self, arena: &Arc<Arena>) {
+        let mut arenas = self.arenas.lock();
+        arenas.retain(|arena| arena.strong_count() > 0);
+        arenas.push(Arc::downgrade(arena));
+    }
+}
@@ -0,0 +1,235 @@
+//! Dispersion analysis, by simulating many variants of a plan.
+//!
+//! [Session::monte_carlo] builds each variant from a template and a sample of whatever is being
+//! dispersed, like activity arguments or initial conditions, and records resources at the
+//! requested times. The variants share the session's history, so the parts of the plan that
+//! a sample doesn't change (everything before the first perturbed operation, and anything
+//! that doesn't depend on it) are only simulated once across all of them.
+//!
+//! Arguments are only part of the hashes of operations if the activity implements
+//! [Serialize][serde::Serialize], so activities with dispersed arguments need to.
+//!
+//! Samplers are given the index of the variant, so they can seed a random number generator
+//! with it and make the whole analysis reproducible.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(battery: f64);
+//! # model! { Power(battery) }
+//! #[derive(serde::Serialize)]
+//! pub struct Drain(f64);
+//! impl_activity! { for Drain
+//!     @(start) {
+//!         ref mut: battery -= self.0;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! let session = Session::new();
+//! let start = Time::from_tai_seconds(0.0);
+//! let hours = |h: f64| start + Duration::from_hours(h);
+//!
+//! let results = session
+//!     .monte_carlo(
+//!         |session, drain: f64| {
+//!             let mut plan = session.new_plan::<Power>(start, initial_conditions! { battery: 100.0 })?;
+//!             plan.insert(hours(1.0), Drain(10.0))?;
+//!             plan.insert(hours(2.0), Drain(drain))?;
+//!             Ok(plan)
+//!         },
+//!         101,
+//!         |i| i as f64 / 10.0,
+//!     )
+//!     .record::<battery>([hours(1.5), hours(2.5)])
+//!     .run()?;
+//!
+//! let battery = results.get::<battery>().unwrap();
+//! assert_eq!(vec![(hours(1.5), 90.0), (hours(2.5), 85.0)], battery.percentile(50.0));
+//! assert_eq!(vec![(hours(1.5), 90.0), (hours(2.5), 80.0)], battery.min());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::resource::Resource;
+use crate::{EngineError, Model, Plan, Session, Time};
+use anyhow::{Context, Result, ensure};
+use std::collections::HashMap;
+
+type Recorder<'o, M> = fn(&Plan<'o, M>, &[Time]) -> Result<Vec<f64>, EngineError>;
+
+/// A batch of plan variants to simulate. Created with [Session::monte_carlo].
+pub struct MonteCarlo<'o, M: Model<'o>> {
+    session: &'o Session,
+    runs: usize,
+    #[allow(clippy::type_complexity)]
+    variant: Box<dyn FnMut(&'o Session, usize) -> Result<Plan<'o, M>> + 'o>,
+    recorders: Vec<(u64, &'static str, Vec<Time>, Recorder<'o, M>)>,
+}
+
+impl Session {
+    /// Simulates `n` variants of a plan, each built by `template` from a sample returned by
+    /// `sampler`. Record resources with [MonteCarlo::record], and simulate with
+    /// [MonteCarlo::run]. See [monte_carlo][crate::monte_carlo].
+    pub fn monte_carlo<'o, M: Model<'o> + 'o, S>(
+        &'o self,
+        template: impl Fn(&'o Session, S) -> Result<Plan<'o, M>> + 'o,
+        n: usize,
+        mut sampler: impl FnMut(usize) -> S + 'o,
+    ) -> MonteCarlo<'o, M> {
+        MonteCarlo {
+            session: self,
+            runs: n,
+            variant: Box::new(move |session, i| template(session, sampler(i))),
+            recorders: vec![],
+        }
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> MonteCarlo<'o, M> {
+    /// Records the value of a resource at each of `times` in every variant.
+    pub fn record<R: Resource<'o> + 'o>(mut self, times: impl IntoIterator<Item = Time>) -> Self
+    where
+        R::Read: Into<f64>,
+    {
+        let mut times: Vec<Time> = times.into_iter().collect();
+        times.sort();
+        times.dedup();
+        self.recorders
+            .push((R::ID, R::LABEL, times, record::<M, R>));
+        self
+    }
+
+    /// Builds and simulates every variant, one at a time.
+    pub fn run(mut self) -> Result<MonteCarloResults> {
+        ensure!(
+            self.runs > 0,
+            "a Monte Carlo analysis needs at least one run"
+        );
+        let mut samples: Vec<Vec<Vec<f64>>> = self
+            .recorders
+            .iter()
+            .map(|(_, _, times, _)| vec![Vec::with_capacity(self.runs); times.len()])
+            .collect();
+        for i in 0..self.runs {
+            let plan =
+                (self.variant)(self.session, i).with_context(|| format!("building run {i}"))?;
+            for ((_, label, times, recorder), samples) in self.recorders.iter().zip(&mut samples) {
+                let values = recorder(&plan, times)
+                    .with_context(|| format!("recording {label} in run {i}"))?;
+                for (samples, value) in samples.iter_mut().zip(values) {
+                    samples.push(value);
+                }
+            }
+        }
+        let statistics = self
+            .recorders
+            .into_iter()
+            .zip(samples)
+            .map(|((id, _, times, _), mut samples)| {
+                for samples in &mut samples {
+                    samples.sort_by(f64::total_cmp);
+                }
+                (id, ResourceStatistics { times, samples })
+            })
+            .collect();
+        Ok(MonteCarloResults { statistics })
+    }
+}
+
+fn record<'o, M: Model<'o> + 'o, R: Resource<'o> + 'o>(
+    plan: &Plan<'o, M>,
+    times: &[Time],
+) -> Result<Vec<f64>, EngineError>
+where
+    R::Read: Into<f64>,
+{
+    let (Some(first), Some(last)) = (times.first(), times.last()) else {
+        return Ok(vec![]);
+    };
+    let mut view = plan.view::<R>(*first..=*last)?;
+    view.sort_by_key(|(time, _)| *time);
+    times
+        .iter()
+        .map(|time| {
+            let index = view.partition_point(|(t, _)| t <= time);
+            let (_, value) = view
+                .get(index.wrapping_sub(1))
+                .ok_or(EngineError::NothingToSample(*time))?;
+            Ok((*value).into())
+        })
+        .collect()
+}
+
+/// The recorded resources from a [MonteCarlo] run.
+#[derive(Clone, Debug)]
+pub struct MonteCarloResults {
+    statistics: HashMap<u64, ResourceStatistics>,
+}
+
+impl MonteCarloResults {
+    /// The statistics of a resource, if it was recorded.
+    pub fn get<'o, R: Resource<'o>>(&self) -> Option<&ResourceStatistics> {
+        self.statistics.get(&R::ID)
+    }
+}
+
+/// The values of a resource across all variants, at each recorded time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourceStatistics {
+    times: Vec<Time>,
+    /// The values at each time, sorted.
+    samples: Vec<Vec<f64>>,
+}
+
+impl ResourceStatistics {
+    /// The recorded times, in order.
+    pub fn times(&self) -> &[Time] {
+        &self.times
+    }
+
+    /// The values of every variant at the `index`th time, sorted from lowest to highest.
+    pub fn samples(&self, index: usize) -> &[f64] {
+        &self.samples[index]
+    }
+
+    /// The `percentile` (from 0 to 100) at each time, interpolating linearly between variants.
+    pub fn percentile(&self, percentile: f64) -> Vec<(Time, f64)> {
+        let rank = percentile.clamp(0.0, 100.0) / 100.0;
+        self.map(|samples| {
+            let position = rank * (samples.len() - 1) as f64;
+            let (below, above) = (position.floor() as usize, position.ceil() as usize);
+            let fraction = position - below as f64;
+            samples[below] + (samples[above] - samples[below]) * fraction
+        })
+    }
+
+    /// The lower and upper percentiles at each time, like `(5.0, 95.0)` for a 90% envelope.
+    pub fn envelope(&self, lower: f64, upper: f64) -> Vec<(Time, f64, f64)> {
+        self.percentile(lower)
+            .into_iter()
+            .zip(self.percentile(upper))
+            .map(|((time, low), (_, high))| (time, low, high))
+            .collect()
+    }
+
+    pub fn min(&self) -> Vec<(Time, f64)> {
+        self.map(|samples| samples[0])
+    }
+
+    pub fn max(&self) -> Vec<(Time, f64)> {
+        self.map(|samples| samples[samples.len() - 1])
+    }
+
+    pub fn mean(&self) -> Vec<(Time, f64)> {
+        self.map(|samples| samples.iter().sum::<f64>() / samples.len() as f64)
+    }
+
+    fn map(&self, f: impl Fn(&[f64]) -> f64) -> Vec<(Time, f64)> {
+        self.times
+            .iter()
+            .zip(&self.samples)
+            .map(|(time, samples)| (*time, f(samples)))
+            .collect()
+    }
+}
@@ -0,0 +1,115 @@
+//! Evaluating expensive operations somewhere else, like on workers that run the same model.
+//!
+//! Some operations, like a high-fidelity orbit propagation, cost far more than everything else
+//! in the plan. [Plan::set_executor][crate::Plan::set_executor] sends those to an [Executor] instead of evaluating them on
+//! the plan's threads. When an operation of an activity that the executor
+//! [accepts][Executor::accepts] isn't found in history, the plan hands the executor a [Job]:
+//! the activity's label and encoded arguments, which operation to run and when, the history
+//! hashes of the operation's inputs, and the hash to store its outputs under. The executor has
+//! the job evaluated, usually by sending it to a worker that calls [evaluate], and the plan then
+//! reads the outputs from history. Finding upstreams, caching, and invalidation all stay in the
+//! plan; only operation bodies move.
+//!
+//! The worker reads the inputs from its history and writes the outputs to it, so the plan and
+//! the workers need histories with a shared backend, like a
+//! [HistoryServer][crate::history::remote::HistoryServer]. Workers find activity types in the
+//! [registry][crate::registry], so offloaded activities have to be registered and serializable.
+//! Operations of activities inserted as sub-activities are offloaded with the label of the
+//! sub-activity.
+//!
+//! See [evaluate] for an example.
+
+use crate::{History, Time};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Evaluates the operations of the activities it accepts, on behalf of a plan. See
+/// [offload][crate::offload].
+pub trait Executor: Send + Sync {
+    /// Whether the operations of the activity with this label should be sent to the executor.
+    fn accepts(&self, activity: &str) -> bool;
+
+    /// Evaluates a job, storing its outputs in the plan's history under [Job::hash]. Called
+    /// from the plan's simulation threads, which wait for it to return.
+    ///
+    /// An error fails the operation, like an error from its body would.
+    fn execute(&self, job: &Job) -> Result<()>;
+}
+
+/// An operation to evaluate, with everything a worker needs to evaluate it. See
+/// [offload][crate::offload].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    /// The label of the operation's activity.
+    pub activity: String,
+    /// Identifies the operation among the activity's operations in the worker's build of the
+    /// model, which has to be the same as the plan's.
+    pub operation: String,
+    /// The activity, encoded with bincode.
+    pub arguments: Vec<u8>,
+    pub time: Time,
+    /// The history hashes of the values the operation reads, in declaration order.
+    pub inputs: Vec<u64>,
+    /// The hash to store the operation's outputs under.
+    pub hash: u64,
+}
+
+/// Evaluates a [Job] with the inputs found in `history`, and stores the outputs in it. See
+/// [offload][crate::offload].
+///
+/// ```
+/// # use peregrine::*;
+/// # use peregrine::activity::VersionedActivity;
+/// # use peregrine::history::remote::{HistoryServer, RemoteHistory};
+/// # use peregrine::offload::{Executor, Job};
+/// # resource!(offloaded_position: f64);
+/// # model! { Offloaded(offloaded_position) }
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// pub struct Propagate { seconds: f64 }
+/// impl_activity! { for Propagate
+///     @(start) {
+///         ref mut: offloaded_position += 7.5 * self.seconds;
+///     }
+///     Duration::ZERO
+/// }
+/// impl VersionedActivity for Propagate {}
+/// register_activity!(Propagate => Offloaded);
+///
+/// /// Sends jobs through JSON to a worker with its own history, as if it were remote.
+/// struct Worker(History);
+/// impl Executor for Worker {
+///     fn accepts(&self, activity: &str) -> bool {
+///         activity == "Propagate"
+///     }
+///
+///     fn execute(&self, job: &Job) -> Result<()> {
+///         let job: Job = serde_json::from_str(&serde_json::to_string(job)?)?;
+///         peregrine::offload::evaluate(&job, &self.0)
+///     }
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let server = HistoryServer::bind("127.0.0.1:0")?;
+/// let address = server.local_addr()?;
+/// server.spawn();
+///
+/// let session = Session::from(History::new().with_backend(RemoteHistory::connect(address)?));
+/// # let start = Time::from_tai_seconds(0.0);
+/// let mut plan = session.new_plan::<Offloaded>(start, initial_conditions! { offloaded_position: 0.0 })?;
+/// plan.set_executor(Worker(History::new().with_backend(RemoteHistory::connect(address)?)));
+///
+/// plan.insert(start + Duration::from_seconds(1.0), Propagate { seconds: 2.0 })?;
+/// assert_eq!(15.0, plan.sample::<offloaded_position>(start + Duration::from_seconds(2.0))?);
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "json")]
+pub fn evaluate(job: &Job, history: &History) -> Result<()> {
+    crate::registry::evaluate_registered(job, history)
+}
+
+/// Evaluates the operations of an activity for a worker. Implemented for all activities by
+/// the [impl_activity][crate::impl_activity] macro.
+pub trait Evaluate {
+    fn evaluate(&self, job: &Job, history: &History) -> Result<()>;
+}
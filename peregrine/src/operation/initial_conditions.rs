@@ -1,26 +1,65 @@
-use crate::Model;
+use crate::error::{InitialConditionIssue, InitialConditionIssueKind};
 use crate::exec::ExecEnvironment;
 use crate::history::PeregrineDefaultHashBuilder;
-use crate::operation::{Continuation, Node, Upstream};
-use crate::resource::{ErasedResource, Resource};
-use crate::timeline::Timelines;
-use anyhow::anyhow;
-use hifitime::Duration;
+use crate::operation::{Continuation, Node, NodeKey, OpInfo, Removal, Upstream, address};
+use crate::resource::{ErasedResource, KeyedResource, Resource, ResourceHistoryPlugin, key_id};
+use crate::series::TimeSeries;
+use crate::timeline::{Instant, Timelines, instant_to_epoch};
+use crate::{EngineError, Model, Time};
+use anyhow::{Result, anyhow};
 use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 use rayon::Scope;
-use smallvec::SmallVec;
+use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 use std::hash::BuildHasher;
+use std::path::PathBuf;
+use std::str::FromStr;
+use type_reg::untagged::TypeReg;
 
 #[macro_export]
 macro_rules! initial_conditions {
-    ($($res:ident: $val:expr),*$(,)?) => {
+    ($($res:ident $([$index:expr])?: $val:expr),*$(,)?) => {
         $crate::operation::initial_conditions::InitialConditions::new()
-            $(.insert::<$res>($val))*
+            $(.insert::<$res $(<{ $index }>)?>($val))*
     };
 }
 
-pub struct InitialConditions(HashMap<u64, Box<dyn ErasedResource<'static>>>);
+pub struct InitialConditions {
+    values: HashMap<u64, Condition>,
+    /// The boundary profiles given with [InitialConditions::insert_profile].
+    profiles: HashMap<u64, Condition>,
+}
+
+struct Condition {
+    value: Box<dyn ErasedResource<'static>>,
+    /// The name of the write type of the resource the value was inserted for.
+    write_type: &'static str,
+}
+
+impl Condition {
+    fn new<'o, R: Resource<'o>, T: ErasedResource<'o>>(value: T) -> Self {
+        let value: Box<dyn ErasedResource<'o> + 'o> = Box::new(value);
+        Condition {
+            // Resource write types don't borrow from the history, so only the lifetime differs.
+            value: unsafe {
+                std::mem::transmute::<
+                    Box<dyn ErasedResource<'o> + 'o>,
+                    Box<dyn ErasedResource<'static>>,
+                >(value)
+            },
+            write_type: std::any::type_name::<R::Write>(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The condition must have been made from a `T`.
+    unsafe fn into_inner<'o, T: ErasedResource<'o>>(self) -> T {
+        let value = Box::into_raw(self.value);
+        // Only the lifetime differs, as above.
+        *unsafe { Box::from_raw(value as *mut T) }
+    }
+}
 
 impl Default for InitialConditions {
     fn default() -> Self {
@@ -30,19 +69,253 @@ impl Default for InitialConditions {
 
 impl InitialConditions {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self {
+            values: HashMap::new(),
+            profiles: HashMap::new(),
+        }
     }
     pub fn insert<R: Resource<'static> + 'static>(mut self, value: R::Write) -> Self {
-        let value: WriteValue<'static, R> = WriteValue(value);
-        self.0.insert(value.id(), Box::new(value));
+        self.values
+            .insert(R::ID, Condition::new::<R, _>(WriteValue::<R>(value)));
         self
     }
     pub fn take<R: Resource<'static> + 'static>(&mut self) -> Option<R::Write> {
-        unsafe {
-            self.0
-                .remove(&R::ID)
-                .map(|v| v.downcast_owned::<WriteValue<'static, R>>().0)
+        self.take_id::<R>(R::ID)
+    }
+
+    /// Gives a single key of a keyed resource its own initial condition, instead of the one
+    /// for the resource as a whole.
+    pub fn insert_key<R: KeyedResource<'static> + 'static>(
+        mut self,
+        key: &R::Key,
+        value: R::Write,
+    ) -> Self {
+        self.values.insert(
+            key_id::<R>(key),
+            Condition::new::<R, _>(WriteValue::<R>(value)),
+        );
+        self
+    }
+
+    /// Seeds a resource with a pre-computed profile of values, such as predicted eclipses or
+    /// ground station visibility, instead of a single initial value.
+    ///
+    /// The value at the start of the plan is the latest one at or before it, or the first
+    /// one if they are all later. Each later value takes effect at its time. Activities can
+    /// read the resource, but not write it.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// # resource!(in_eclipse: bool);
+    /// # model! { Orbit(in_eclipse) }
+    /// # fn main() -> Result<()> {
+    /// # let session = Session::new();
+    /// # let start = Time::from_tai_seconds(0.0);
+    /// # let seconds = |s: f64| start + Duration::from_seconds(s);
+    /// let initial_conditions = InitialConditions::new().insert_profile::<in_eclipse>(vec![
+    ///     (start, false),
+    ///     (seconds(10.0), true),
+    ///     (seconds(15.0), false),
+    /// ]);
+    /// let plan = session.new_plan::<Orbit>(start, initial_conditions)?;
+    /// assert!(plan.sample::<in_eclipse>(seconds(12.0))?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn insert_profile<R: Resource<'static> + 'static>(
+        mut self,
+        profile: Vec<(Time, R::Write)>,
+    ) -> Self {
+        if !profile.is_empty() {
+            self.profiles.insert(
+                R::ID,
+                Condition::new::<R, _>(Profile::<R>(Box::new(move || Ok(profile)))),
+            );
+        }
+        self
+    }
+
+    /// Seeds a resource with an external [TimeSeries], as in [InitialConditions::insert_profile].
+    pub fn insert_series<R: Resource<'static> + 'static>(
+        self,
+        series: TimeSeries<R::Write>,
+    ) -> Self {
+        self.insert_profile::<R>(series.into_points())
+    }
+
+    /// Seeds a resource with a [TimeSeries] read from a CSV file, as in
+    /// [InitialConditions::insert_profile]. The file isn't read until a plan is created.
+    pub fn insert_series_file<R: Resource<'static> + 'static>(
+        mut self,
+        path: impl Into<PathBuf>,
+    ) -> Self
+    where
+        R::Write: FromStr,
+        <R::Write as FromStr>::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let path = path.into();
+        self.profiles.insert(
+            R::ID,
+            Condition::new::<R, _>(Profile::<R>(Box::new(move || {
+                Ok(TimeSeries::from_csv(path)?.into_points())
+            }))),
+        );
+        self
+    }
+
+    /// Checks that there is an initial condition for every resource of the model `M` that
+    /// doesn't have a default, and that each has the type of its resource, reporting every
+    /// problem at once. [Session::new_plan][crate::Session::new_plan] does this before
+    /// creating the plan.
+    pub fn validate<'o, M: Model<'o>>(&self) -> Result<(), EngineError> {
+        let mut issues = vec![];
+        M::check_initial_conditions(self, &mut issues);
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(EngineError::InvalidInitialConditions(issues))
+        }
+    }
+
+    /// Checks the initial condition of a single resource. Used by the code that
+    /// [model][crate::model] generates.
+    #[doc(hidden)]
+    pub fn check<R: Resource<'static> + 'static>(&self, issues: &mut Vec<InitialConditionIssue>) {
+        let condition = self
+            .values
+            .get(&R::ID)
+            .or_else(|| self.profiles.get(&R::ID));
+        let kind = match condition {
+            None if R::default_initial_condition().is_none() => InitialConditionIssueKind::Missing,
+            Some(condition) if condition.write_type != std::any::type_name::<R::Write>() => {
+                InitialConditionIssueKind::WrongType {
+                    expected: std::any::type_name::<R::Write>(),
+                    found: condition.write_type,
+                }
+            }
+            _ => return,
+        };
+        // Resources shared by several submodels are checked once for each.
+        if !issues.iter().any(|issue| issue.resource == R::LABEL) {
+            issues.push(InitialConditionIssue {
+                resource: R::LABEL,
+                kind,
+            });
+        }
+    }
+
+    pub(crate) fn contains_id(&self, id: u64) -> bool {
+        self.values.contains_key(&id)
+    }
+
+    /// Inserts the initial condition of the timeline `id`, which is either [Resource::ID] or
+    /// the [key_id] of a key of `R`.
+    pub(crate) fn insert_id<'o, R: Resource<'o>>(&mut self, id: u64, value: R::Write) {
+        self.values
+            .insert(id, Condition::new::<R, _>(WriteValue::<R>(value)));
+    }
+
+    /// Takes the initial condition of the timeline `id`, as in [InitialConditions::insert_id].
+    pub(crate) fn take_id<'o, R: Resource<'o>>(&mut self, id: u64) -> Option<R::Write> {
+        self.values
+            .remove(&id)
+            // The value was inserted for `R`.
+            .map(|condition| unsafe { condition.into_inner::<WriteValue<R>>() }.0)
+    }
+
+    /// Inserts a profile of `R` that has already been loaded, as in
+    /// [InitialConditions::insert_profile].
+    pub(crate) fn insert_profile_id<'o, R: Resource<'o>>(
+        &mut self,
+        profile: Vec<(Time, R::Write)>,
+    ) {
+        self.profiles.insert(
+            R::ID,
+            Condition::new::<R, _>(Profile::<R>(Box::new(move || Ok(profile)))),
+        );
+    }
+
+    /// Takes the profile of `R`, ordered by time, loading it if it comes from a file.
+    pub(crate) fn take_profile<'o, R: Resource<'o>>(&mut self) -> Result<Vec<(Time, R::Write)>> {
+        let Some(condition) = self.profiles.remove(&R::ID) else {
+            return Ok(vec![]);
+        };
+        let mut profile = unsafe { condition.into_inner::<Profile<R>>() }.0()?;
+        profile.sort_by_key(|(time, _)| *time);
+        Ok(profile)
+    }
+
+    /// Takes the initial conditions of every key of `R`, along with the ids of their timelines.
+    pub(crate) fn take_keys<'o, R: Resource<'o>>(&mut self) -> Vec<(u64, R::Write)> {
+        let ids = self
+            .values
+            .iter()
+            .filter(|(id, condition)| **id != R::ID && condition.value.id() == R::ID)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        ids.into_iter()
+            .map(|id| (id, self.take_id::<R>(id).unwrap()))
+            .collect()
+    }
+}
+
+/// Reads a map from resource labels to values. The type of each value is looked up by its
+/// label among every resource in the program, so initial conditions can be loaded from
+/// config files in any self-describing format. Labels of resources that don't exist are an
+/// error, and labels shared by several resources are read as whichever was registered last.
+impl<'de> Deserialize<'de> for InitialConditions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut type_reg = TypeReg::<String>::new();
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            plugin.register_initial_condition(&mut type_reg);
+        }
+        let mut values = type_reg.deserialize_map(deserializer)?;
+        let mut conditions = InitialConditions::new();
+        for plugin in inventory::iter::<&'static dyn ResourceHistoryPlugin> {
+            plugin.take_initial_condition(&mut values, &mut conditions);
         }
+        Ok(conditions)
+    }
+}
+
+impl InitialConditions {
+    /// Reads initial conditions from a JSON object of resource labels and values.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// # resource!(heater_setpoint: f64);
+    /// # resource!(ref heater_mode: String);
+    /// # model! { Heater(heater_setpoint, heater_mode) }
+    /// # fn main() -> Result<()> {
+    /// # let session = Session::new();
+    /// let initial_conditions =
+    ///     InitialConditions::from_json(r#"{ "heater_setpoint": 20.0, "heater_mode": "auto" }"#)?;
+    /// let plan = session.new_plan::<Heater>(Time::from_tai_seconds(0.0), initial_conditions)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Reads initial conditions from a TOML table of resource labels and values.
+    ///
+    /// ```
+    /// # use peregrine::*;
+    /// # resource!(cooler_setpoint: f64);
+    /// # resource!(ref cooler_mode: String);
+    /// # model! { Cooler(cooler_setpoint, cooler_mode) }
+    /// # fn main() -> Result<()> {
+    /// # let session = Session::new();
+    /// let initial_conditions = InitialConditions::from_toml("cooler_setpoint = -10.0\ncooler_mode = \"auto\"")?;
+    /// let plan = session.new_plan::<Cooler>(Time::from_tai_seconds(0.0), initial_conditions)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(toml)?)
     }
 }
 
@@ -54,36 +327,88 @@ impl<'h, R: Resource<'h>> ErasedResource<'h> for WriteValue<'h, R> {
     }
 }
 
+/// Produces the points of a boundary profile when the plan is created.
+#[allow(clippy::type_complexity)]
+struct Profile<'h, R: Resource<'h>>(
+    Box<dyn FnOnce() -> Result<Vec<(Time, R::Write)>> + Send + Sync + 'h>,
+);
+
+impl<'h, R: Resource<'h>> ErasedResource<'h> for Profile<'h, R> {
+    fn id(&self) -> u64 {
+        R::ID
+    }
+}
+
 pub struct InitialConditionOp<'o, R: Resource<'o>, M: Model<'o>> {
     value: R::Write,
     result: RwLock<Option<(u64, R::Read)>>,
-    downstreams: Mutex<SmallVec<Continuation<'o, R, M>, 2>>,
-    _time: Duration,
+    downstreams: Mutex<HashMap<NodeKey, Continuation<'o, R, M>>>,
+    time: Instant,
 }
 
 impl<'o, R: Resource<'o>, M: Model<'o>> InitialConditionOp<'o, R, M> {
-    pub fn new(time: Duration, value: R::Write) -> Self {
+    pub(crate) fn time(&self) -> Instant {
+        self.time
+    }
+
+    pub(crate) fn value(&self) -> &R::Write {
+        &self.value
+    }
+
+    pub fn new(time: Instant, value: R::Write) -> Self {
         Self {
             value,
             result: RwLock::new(None),
             downstreams: Mutex::default(),
-            _time: time,
+            time,
         }
     }
 }
 
 impl<'o, R: Resource<'o>, M: Model<'o>> Node<'o, M> for InitialConditionOp<'o, R, M> {
-    fn insert_self(
+    unsafe fn insert_self(
         &'o self,
-        _timelines: &mut Timelines<'o, M>,
+        _timelines: &Timelines<'o, M>,
         _disruptive: bool,
     ) -> anyhow::Result<()> {
         unreachable!()
     }
 
-    fn remove_self(&self, _timelines: &mut Timelines<'o, M>) -> anyhow::Result<()> {
+    unsafe fn remove_self(
+        &self,
+        _timelines: &Timelines<'o, M>,
+        _removal: &Removal,
+    ) -> anyhow::Result<()> {
         Err(anyhow!("Cannot remove initial conditions."))
     }
+
+    fn detach(&self) {
+        unreachable!()
+    }
+
+    fn info(&self) -> OpInfo {
+        OpInfo {
+            activity: "initial conditions",
+            min_time: instant_to_epoch(self.time),
+            max_time: instant_to_epoch(self.time),
+            reads: &[],
+            writes: const { &[R::LABEL] },
+            dynamic: false,
+            priority: false,
+        }
+    }
+
+    fn missing_upstreams(&self, _timelines: &Timelines<'o, M>) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn is_evaluated(&self) -> bool {
+        true
+    }
+
+    fn recorded_downstreams(&self) -> usize {
+        self.downstreams.lock().len()
+    }
 }
 
 impl<'o, R: Resource<'o> + 'o, M: Model<'o>> Upstream<'o, R, M> for InitialConditionOp<'o, R, M> {
@@ -113,20 +438,27 @@ impl<'o, R: Resource<'o> + 'o, M: Model<'o>> Upstream<'o, R, M> for InitialCondi
             self.result.read()
         };
 
-        if let Some(c) = continuation.copy_node() {
-            self.downstreams.lock().push(c);
+        if let Some((key, copy)) = continuation.recorded() {
+            self.downstreams.lock().entry(key).or_insert(copy);
         }
 
         continuation.run(Ok(read.unwrap()), scope, timelines, env.increment());
     }
 
-    fn notify_downstreams(&self, time_of_change: Duration) {
+    fn notify_downstreams(&self, time_of_change: Instant) {
+        self.downstreams.lock().retain(|_, downstream| {
+            downstream.reads_from(self as *const Self as *const ())
+                && downstream.clear_upstream(Some(time_of_change))
+        });
+    }
+
+    fn is_cached(&self) -> bool {
+        true
+    }
+
+    fn forget_downstream(&self, downstream: *const ()) {
         self.downstreams
             .lock()
-            .retain(|downstream| match downstream {
-                Continuation::Node(n) => n.clear_upstream(Some(time_of_change)),
-                Continuation::MarkedNode(_, n) => n.clear_upstream(Some(time_of_change)),
-                _ => unreachable!(),
-            });
+            .retain(|(node, _), _| *node != address(downstream));
     }
 }
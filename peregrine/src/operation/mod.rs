@@ -3,23 +3,70 @@
 pub mod initial_conditions;
 pub mod ungrounded;
 
-use crate::Model;
 use crate::exec::ExecEnvironment;
 use crate::operation::ungrounded::{Marked, MarkedValue};
 use crate::resource::Resource;
-use crate::timeline::Timelines;
+use crate::timeline::{Instant, Timelines};
+use crate::{Model, Time};
 use anyhow::Result;
 use derive_more::with_trait::Error as DeriveError;
-use hifitime::Duration;
 use rayon::Scope;
 use smallvec::SmallVec;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
 
 pub type InternalResult<T> = Result<T, ObservedErrorOutput>;
 
 pub trait Node<'o, M: Model<'o> + 'o>: Sync {
-    fn insert_self(&'o self, timelines: &mut Timelines<'o, M>, disruptive: bool) -> Result<()>;
-    fn remove_self(&self, timelines: &mut Timelines<'o, M>) -> Result<()>;
+    /// # Safety
+    ///
+    /// Nothing else can use the timelines of the resources the operation writes, or the
+    /// operations that read them, until this returns.
+    unsafe fn insert_self(&'o self, timelines: &Timelines<'o, M>, disruptive: bool) -> Result<()>;
+    /// Removes the operation from the timelines, and clears the caches of its downstreams
+    /// unless they are being removed too.
+    ///
+    /// # Safety
+    ///
+    /// As for [insert_self][Node::insert_self].
+    unsafe fn remove_self(&self, timelines: &Timelines<'o, M>, removal: &Removal) -> Result<()>;
+    /// Marks the operation as removed, and has its upstreams forget it. Part of
+    /// [remove_self][Node::remove_self], and called on its own for operations that are only
+    /// used by another operation instead of being in the timelines.
+    fn detach(&self);
+
+    /// Static information about the operation, available without simulating.
+    fn info(&self) -> OpInfo;
+
+    /// The labels of the resources the operation reads that nothing writes before its earliest
+    /// time, not even the initial conditions.
+    fn missing_upstreams(&self, timelines: &Timelines<'o, M>) -> Vec<&'static str>;
+
+    /// Whether the operation's outputs are in memory, so that viewing them won't evaluate it.
+    fn is_evaluated(&self) -> bool;
+
+    /// The downstreams the operation keeps to clear when its outputs change.
+    fn recorded_downstreams(&self) -> usize;
+}
+
+/// What an operation reads and writes, and when it can happen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OpInfo {
+    pub activity: &'static str,
+    /// The earliest time the operation can happen. Equal to `max_time` unless the operation's
+    /// placement depends on another operation.
+    pub min_time: Time,
+    pub max_time: Time,
+    /// The labels of the resources the operation reads, including those it also writes.
+    pub reads: &'static [&'static str],
+    /// The labels of the resources the operation writes.
+    pub writes: &'static [&'static str],
+    /// Whether the operation is placed by other operations during simulation, so that it
+    /// also depends on whatever they read, which is not listed in `reads`.
+    pub dynamic: bool,
+    /// Whether the operation was marked `priority`, so that it is run before other
+    /// downstreams of the same upstreams.
+    pub priority: bool,
 }
 
 pub trait Downstream<'o, R: Resource<'o>, M: Model<'o> + 'o>: Node<'o, M> {
@@ -33,7 +80,11 @@ pub trait Downstream<'o, R: Resource<'o>, M: Model<'o> + 'o>: Node<'o, M> {
         'o: 's;
 
     fn clear_cache(&self);
-    fn clear_upstream(&self, time_of_change: Option<Duration>) -> bool;
+    fn clear_upstream(&self, time_of_change: Option<Instant>) -> bool;
+
+    /// Whether a change to `upstream` still has to clear the operation: it hasn't been removed,
+    /// and it reads from `upstream`, or hasn't found its upstream again since it was cleared.
+    fn reads_from(&self, upstream: *const ()) -> bool;
 }
 
 pub trait Upstream<'o, R: Resource<'o>, M: Model<'o> + 'o>: Node<'o, M> {
@@ -46,9 +97,23 @@ pub trait Upstream<'o, R: Resource<'o>, M: Model<'o> + 'o>: Node<'o, M> {
     ) where
         'o: 's;
 
-    fn notify_downstreams(&self, time_of_change: Duration);
+    fn notify_downstreams(&self, time_of_change: Instant);
+
+    /// Whether the operation's output is in memory, so that viewing it won't evaluate anything.
+    fn is_cached(&self) -> bool;
+
+    /// Whether requests to this operation can be answered by `upstream`.
+    fn forwards_to(&self, upstream: *const ()) -> bool {
+        std::ptr::addr_eq(self, upstream)
+    }
+
+    /// Stops clearing `downstream` when the output changes, because it was removed.
+    fn forget_downstream(&self, downstream: *const ());
 }
 
+/// The address of a node that has been sent a value, and the marker it was sent with, if any.
+pub type NodeKey = (usize, Option<usize>);
+
 pub enum Continuation<'o, R: Resource<'o>, M: Model<'o> + 'o> {
     Node(&'o dyn Downstream<'o, R, M>),
     MarkedNode(usize, &'o dyn Downstream<'o, Marked<'o, R>, M>),
@@ -85,6 +150,69 @@ impl<'o, R: Resource<'o>, M: Model<'o> + 'o> Continuation<'o, R, M> {
         }
     }
 
+    /// A copy of the continuation to keep in [RecordedQueue::old], by the node it sends to,
+    /// unless it sends to a view.
+    pub fn recorded(&self) -> Option<(NodeKey, Self)> {
+        match self {
+            Continuation::Node(n) => Some(((address(*n), None), Continuation::Node(*n))),
+            Continuation::MarkedNode(m, n) => {
+                Some(((address(*n), Some(*m)), Continuation::MarkedNode(*m, *n)))
+            }
+            Continuation::Root(_) => None,
+        }
+    }
+
+    pub fn clear_cache(&self) {
+        match self {
+            Continuation::Node(n) => n.clear_cache(),
+            Continuation::MarkedNode(_, n) => n.clear_cache(),
+            Continuation::Root(_) => unreachable!(),
+        }
+    }
+
+    pub fn clear_upstream(&self, time_of_change: Option<Instant>) -> bool {
+        match self {
+            Continuation::Node(n) => n.clear_upstream(time_of_change),
+            Continuation::MarkedNode(_, n) => n.clear_upstream(time_of_change),
+            Continuation::Root(_) => unreachable!(),
+        }
+    }
+
+    /// Whether the node the continuation sends to still has to be cleared when `upstream`
+    /// changes. See [Downstream::reads_from].
+    pub fn reads_from(&self, upstream: *const ()) -> bool {
+        match self {
+            Continuation::Node(n) => n.reads_from(upstream),
+            Continuation::MarkedNode(_, n) => n.reads_from(upstream),
+            Continuation::Root(_) => false,
+        }
+    }
+
+    /// Whether the continuation should run before others: it sends to a `priority` operation,
+    /// or to the view that requested the value.
+    pub fn is_prioritized(&self) -> bool {
+        match self {
+            Continuation::Node(n) => n.info().priority,
+            Continuation::MarkedNode(_, n) => n.info().priority,
+            Continuation::Root(_) => true,
+        }
+    }
+
+    /// Moves the prioritized continuations to the front, keeping the order otherwise. The first
+    /// continuation is run on the current thread, and the rest are spawned in order.
+    pub fn prioritize(continuations: &mut [Self]) {
+        continuations.sort_by_key(|c| !c.is_prioritized());
+    }
+
+    /// Whether the continuation sends to an operation in `removal`.
+    pub fn is_removed(&self, removal: &Removal) -> bool {
+        match self {
+            Continuation::Node(n) => removal.contains(*n),
+            Continuation::MarkedNode(_, n) => removal.contains(*n),
+            Continuation::Root(_) => false,
+        }
+    }
+
     pub fn copy_node(&self) -> Option<Self> {
         match &self {
             Continuation::Node(n) => Some(Continuation::Node(*n)),
@@ -94,22 +222,51 @@ impl<'o, R: Resource<'o>, M: Model<'o> + 'o> Continuation<'o, R, M> {
     }
 }
 
-pub struct RecordedQueue<N, O> {
+/// The operations being removed from a plan together, by address.
+#[derive(Default)]
+pub struct Removal(HashSet<usize>);
+
+impl Removal {
+    pub fn new<'o, M: Model<'o> + 'o>(
+        operations: impl IntoIterator<Item = &'o dyn Node<'o, M>>,
+    ) -> Self {
+        Removal(
+            operations
+                .into_iter()
+                .map(|op| address(op as *const dyn Node<'o, M>))
+                .collect(),
+        )
+    }
+
+    pub fn contains<T: ?Sized>(&self, node: *const T) -> bool {
+        self.0.contains(&address(node))
+    }
+}
+
+/// The address of a node, without its vtable.
+pub fn address<T: ?Sized>(node: *const T) -> usize {
+    node as *const () as usize
+}
+
+pub struct RecordedQueue<N, O, K = NodeKey> {
     pub new: SmallVec<N, 1>,
-    pub old: SmallVec<O, 1>,
+    /// The continuations that have already been run, kept to clear their nodes when the value
+    /// changes. There is one for each node, keyed by [Continuation::recorded], so nodes that
+    /// request the value again aren't added twice.
+    pub old: HashMap<K, O>,
 }
 
-impl<N, O> Default for RecordedQueue<N, O> {
+impl<N, O, K> Default for RecordedQueue<N, O, K> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<N, O> RecordedQueue<N, O> {
+impl<N, O, K> RecordedQueue<N, O, K> {
     pub fn new() -> Self {
         Self {
             new: SmallVec::new(),
-            old: SmallVec::new(),
+            old: HashMap::new(),
         }
     }
 }
@@ -1,11 +1,13 @@
 use crate as peregrine;
-use crate::exec::ExecEnvironment;
+use crate::exec::{ExecEnvironment, OpError};
 use crate::operation::{
-    Continuation, Downstream, InternalResult, Node, ObservedErrorOutput, Upstream,
+    Continuation, Downstream, InternalResult, Node, ObservedErrorOutput, OpInfo, RecordedQueue,
+    Removal, Upstream, address,
 };
 use crate::resource::Resource;
-use crate::timeline::Timelines;
-use crate::{Model, resource};
+use crate::timeline::{Instant, Timelines, advance, instant_to_epoch};
+use crate::{Grounding, Model, resource};
+use anyhow::bail;
 use hifitime::Duration;
 use parking_lot::Mutex;
 use rayon::Scope;
@@ -13,13 +15,14 @@ use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::sync::OnceLock;
 
 pub trait UngroundedUpstream<'o, R: Resource<'o>, M: Model<'o> + 'o>:
     AsRef<dyn Upstream<'o, R, M> + 'o> + Upstream<'o, R, M> + Upstream<'o, peregrine_grounding, M>
 {
 }
 
-resource!(pub peregrine_grounding: Duration);
+resource!(pub peregrine_grounding: Instant);
 resource!(pub peregrine_delay: Duration);
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,26 +58,26 @@ impl<T: Clone + Debug> Clone for MarkedValue<T> {
 }
 
 pub struct UngroundedUpstreamResolver<'o, R: Resource<'o>, M: Model<'o>> {
-    time: Duration,
-    downstream: Option<&'o dyn Downstream<'o, R, M>>,
-    grounded_upstream: Option<(Duration, &'o dyn Upstream<'o, R, M>)>,
+    time: Instant,
+    downstream: Mutex<Option<&'o dyn Downstream<'o, R, M>>>,
+    grounded_upstream: Option<(Instant, &'o dyn Upstream<'o, R, M>)>,
     ungrounded_upstreams: SmallVec<&'o dyn UngroundedUpstream<'o, R, M>, 1>,
-    grounding_responses: Mutex<SmallVec<InternalResult<MarkedValue<Duration>>, 1>>,
+    grounding_responses: Mutex<SmallVec<InternalResult<MarkedValue<Instant>>, 1>>,
     continuation: Mutex<Option<Continuation<'o, R, M>>>,
 
     #[allow(clippy::type_complexity)]
-    cached_decision: Mutex<Option<InternalResult<(Duration, &'o dyn Upstream<'o, R, M>)>>>,
+    cached_decision: Mutex<Option<InternalResult<(Instant, &'o dyn Upstream<'o, R, M>)>>>,
 }
 
 impl<'o, R: Resource<'o>, M: Model<'o>> UngroundedUpstreamResolver<'o, R, M> {
     pub(crate) fn new(
-        time: Duration,
-        grounded: Option<(Duration, &'o dyn Upstream<'o, R, M>)>,
+        time: Instant,
+        grounded: Option<(Instant, &'o dyn Upstream<'o, R, M>)>,
         ungrounded: SmallVec<&'o dyn UngroundedUpstream<'o, R, M>, 1>,
     ) -> Self {
         Self {
             time,
-            downstream: None,
+            downstream: Mutex::new(None),
             grounded_upstream: grounded,
             ungrounded_upstreams: ungrounded,
             grounding_responses: Mutex::new(SmallVec::new()),
@@ -85,15 +88,39 @@ impl<'o, R: Resource<'o>, M: Model<'o>> UngroundedUpstreamResolver<'o, R, M> {
 }
 
 impl<'o, R: Resource<'o>, M: Model<'o>> Node<'o, M> for UngroundedUpstreamResolver<'o, R, M> {
-    fn insert_self(
+    unsafe fn insert_self(
         &'o self,
-        _timelines: &mut Timelines<'o, M>,
+        _timelines: &Timelines<'o, M>,
         _disruptive: bool,
     ) -> anyhow::Result<()> {
         unreachable!()
     }
 
-    fn remove_self(&self, _timelines: &mut Timelines<'o, M>) -> anyhow::Result<()> {
+    unsafe fn remove_self(
+        &self,
+        _timelines: &Timelines<'o, M>,
+        _removal: &Removal,
+    ) -> anyhow::Result<()> {
+        unreachable!()
+    }
+
+    fn detach(&self) {
+        unreachable!()
+    }
+
+    fn info(&self) -> OpInfo {
+        unreachable!()
+    }
+
+    fn missing_upstreams(&self, _timelines: &Timelines<'o, M>) -> Vec<&'static str> {
+        unreachable!()
+    }
+
+    fn is_evaluated(&self) -> bool {
+        unreachable!()
+    }
+
+    fn recorded_downstreams(&self) -> usize {
         unreachable!()
     }
 }
@@ -110,6 +137,10 @@ impl<'o, R: Resource<'o>, M: Model<'o>> Upstream<'o, R, M>
     ) where
         'o: 's,
     {
+        if let Continuation::Node(n) = &continuation {
+            *self.downstream.lock() = Some(*n);
+        }
+
         let decision = self.cached_decision.lock();
         if let Some(r) = *decision {
             match r {
@@ -128,13 +159,13 @@ impl<'o, R: Resource<'o>, M: Model<'o>> Upstream<'o, R, M>
         drop(continuation_lock);
 
         if !self.ungrounded_upstreams.is_empty() {
-            for (i, ungrounded) in self.ungrounded_upstreams[1..].iter().enumerate() {
-                scope.spawn(move |s| {
+            for (i, ungrounded) in self.ungrounded_upstreams.iter().enumerate().skip(1) {
+                env.spawn(scope, move |s, env| {
                     ungrounded.request(
                         Continuation::<peregrine_grounding, M>::MarkedNode(i, self),
                         s,
                         timelines,
-                        env.reset(),
+                        env,
                     )
                 });
             }
@@ -148,11 +179,39 @@ impl<'o, R: Resource<'o>, M: Model<'o>> Upstream<'o, R, M>
         }
     }
 
-    fn notify_downstreams(&self, time_of_change: Duration) {
-        if let Some(d) = self.downstream {
+    fn notify_downstreams(&self, time_of_change: Instant) {
+        if let Some(d) = *self.downstream.lock() {
             d.clear_upstream(Some(time_of_change));
         }
     }
+
+    fn is_cached(&self) -> bool {
+        unreachable!()
+    }
+
+    /// The downstream is sent the value of whichever upstream was decided on, and is only
+    /// pointed elsewhere once the decision is cleared.
+    fn forwards_to(&self, upstream: *const ()) -> bool {
+        match *self.cached_decision.lock() {
+            Some(Ok((_, u))) => u.forwards_to(upstream),
+            _ => true,
+        }
+    }
+
+    /// Any of the upstreams could have been sent the downstream's requests by an earlier
+    /// decision, so they all forget it, along with their groundings forgetting this resolver.
+    fn forget_downstream(&self, downstream: *const ()) {
+        if let Some((_, grounded)) = self.grounded_upstream {
+            grounded.forget_downstream(downstream);
+        }
+        for ungrounded in &self.ungrounded_upstreams {
+            ungrounded.as_ref().forget_downstream(downstream);
+            Upstream::<peregrine_grounding, M>::forget_downstream(
+                *ungrounded,
+                self as *const Self as *const (),
+            );
+        }
+    }
 }
 
 impl<'o, R: Resource<'o>, M: Model<'o>> Downstream<'o, Marked<'o, peregrine_grounding>, M>
@@ -160,7 +219,7 @@ impl<'o, R: Resource<'o>, M: Model<'o>> Downstream<'o, Marked<'o, peregrine_grou
 {
     fn respond<'s>(
         &'o self,
-        value: InternalResult<(u64, MarkedValue<Duration>)>,
+        value: InternalResult<(u64, MarkedValue<Instant>)>,
         scope: &Scope<'s>,
         timelines: &'s Timelines<'o, M>,
         env: ExecEnvironment<'s, 'o>,
@@ -221,12 +280,260 @@ impl<'o, R: Resource<'o>, M: Model<'o>> Downstream<'o, Marked<'o, peregrine_grou
 
     fn clear_cache(&self) {
         *self.cached_decision.lock() = None;
-        if let Some(d) = self.downstream {
+        if let Some(d) = *self.downstream.lock() {
             d.clear_cache();
         }
     }
 
-    fn clear_upstream(&self, _time_of_change: Option<Duration>) -> bool {
-        self.downstream.unwrap().clear_upstream(_time_of_change)
+    fn clear_upstream(&self, time_of_change: Option<Instant>) -> bool {
+        match *self.downstream.lock() {
+            Some(d) => d.clear_upstream(time_of_change),
+            None => false,
+        }
+    }
+
+    fn reads_from(&self, _upstream: *const ()) -> bool {
+        match *self.downstream.lock() {
+            Some(d) => d.reads_from(self as *const Self as *const ()),
+            None => true,
+        }
+    }
+}
+
+/// Grounds an operation at a fixed start time plus a delay computed by another operation,
+/// for placements like `@(start) + (delay) ..= (max_delay)`.
+pub struct DelayedGrounding<'o, M: Model<'o>> {
+    start: Instant,
+    max_delay: Duration,
+    delay: &'o dyn Upstream<'o, peregrine_delay, M>,
+    activity: &'static str,
+    /// The address of the delayed operation, which errors are reported for.
+    owner: OnceLock<usize>,
+    state: Mutex<DelayedGroundingState<'o, M>>,
+}
+
+struct DelayedGroundingState<'o, M: Model<'o>> {
+    result: Option<InternalResult<Instant>>,
+    requested: bool,
+    continuations: RecordedQueue<
+        Continuation<'o, peregrine_grounding, M>,
+        Continuation<'o, peregrine_grounding, M>,
+    >,
+}
+
+impl<'o, M: Model<'o>> DelayedGrounding<'o, M> {
+    pub fn new(
+        start: Grounding<'o, M>,
+        max_delay: Duration,
+        delay: &'o dyn Upstream<'o, peregrine_delay, M>,
+        activity: &'static str,
+    ) -> anyhow::Result<Self> {
+        let Grounding::Static(start) = start else {
+            bail!("delayed operations must be placed relative to a fixed time");
+        };
+        if max_delay < Duration::ZERO {
+            bail!("the maximum delay of an operation cannot be negative, but was {max_delay}");
+        }
+        Ok(DelayedGrounding {
+            start,
+            max_delay,
+            delay,
+            activity,
+            owner: OnceLock::new(),
+            state: Mutex::new(DelayedGroundingState {
+                result: None,
+                requested: false,
+                continuations: RecordedQueue::new(),
+            }),
+        })
+    }
+
+    pub fn set_owner(&self, op: *const ()) {
+        let _ = self.owner.set(op as usize);
+    }
+
+    /// The grounding of the delayed operation.
+    pub fn grounding(&'o self) -> Grounding<'o, M> {
+        Grounding::Dynamic {
+            min: self.start,
+            max: advance(self.start, self.max_delay),
+            node: self,
+        }
+    }
+}
+
+impl<'o, M: Model<'o>> Node<'o, M> for DelayedGrounding<'o, M> {
+    unsafe fn insert_self(
+        &'o self,
+        _timelines: &Timelines<'o, M>,
+        _disruptive: bool,
+    ) -> anyhow::Result<()> {
+        unreachable!()
+    }
+
+    unsafe fn remove_self(
+        &self,
+        _timelines: &Timelines<'o, M>,
+        _removal: &Removal,
+    ) -> anyhow::Result<()> {
+        unreachable!()
+    }
+
+    fn detach(&self) {
+        unreachable!()
+    }
+
+    fn info(&self) -> OpInfo {
+        unreachable!()
+    }
+
+    fn missing_upstreams(&self, _timelines: &Timelines<'o, M>) -> Vec<&'static str> {
+        unreachable!()
+    }
+
+    fn is_evaluated(&self) -> bool {
+        self.state.lock().result.is_some()
+    }
+
+    fn recorded_downstreams(&self) -> usize {
+        self.state.lock().continuations.old.len()
+    }
+}
+
+impl<'o, M: Model<'o>> Upstream<'o, peregrine_grounding, M> for DelayedGrounding<'o, M> {
+    fn request<'s>(
+        &'o self,
+        continuation: Continuation<'o, peregrine_grounding, M>,
+        scope: &Scope<'s>,
+        timelines: &'s Timelines<'o, M>,
+        env: ExecEnvironment<'s, 'o>,
+    ) where
+        'o: 's,
+    {
+        let mut state = self.state.lock();
+        if let Some(result) = state.result {
+            if let Some((key, copy)) = continuation.recorded() {
+                state.continuations.old.entry(key).or_insert(copy);
+            }
+            drop(state);
+            continuation.run(result.map(|t| (0, t)), scope, timelines, env.increment());
+            return;
+        }
+
+        state.continuations.new.push(continuation);
+        if !std::mem::replace(&mut state.requested, true) {
+            drop(state);
+            self.delay
+                .request(Continuation::Node(self), scope, timelines, env.increment());
+        }
+    }
+
+    fn notify_downstreams(&self, _time_of_change: Instant) {
+        unreachable!()
+    }
+
+    fn is_cached(&self) -> bool {
+        self.state.lock().result.is_some()
+    }
+
+    /// The operation that computes the delay isn't in the timelines, so it is detached here
+    /// when the delayed operation is removed.
+    fn forget_downstream(&self, downstream: *const ()) {
+        self.state
+            .lock()
+            .continuations
+            .old
+            .retain(|(node, _), _| *node != address(downstream));
+        if self.owner.get() == Some(&address(downstream)) {
+            self.delay.detach();
+        }
+    }
+}
+
+impl<'o, M: Model<'o>> Downstream<'o, peregrine_delay, M> for DelayedGrounding<'o, M> {
+    fn respond<'s>(
+        &'o self,
+        value: InternalResult<(u64, Duration)>,
+        scope: &Scope<'s>,
+        timelines: &'s Timelines<'o, M>,
+        env: ExecEnvironment<'s, 'o>,
+    ) where
+        'o: 's,
+    {
+        let result = value.and_then(|(_, delay)| {
+            if delay >= Duration::ZERO && delay <= self.max_delay {
+                Ok(advance(self.start, delay))
+            } else {
+                env.errors.push(OpError::new(
+                    self.activity,
+                    instant_to_epoch(self.start),
+                    &[],
+                    self.owner
+                        .get()
+                        .map_or(self as *const Self as *const (), |owner| {
+                            *owner as *const ()
+                        }),
+                    anyhow::anyhow!(
+                        "delay of {delay} is outside of the allowed range from 0 s to {}",
+                        self.max_delay
+                    ),
+                ));
+                Err(ObservedErrorOutput)
+            }
+        });
+
+        let mut state = self.state.lock();
+        state.result = Some(result);
+        state.requested = false;
+        let mut continuations = SmallVec::<_, 1>::new();
+        std::mem::swap(&mut state.continuations.new, &mut continuations);
+        for c in &continuations {
+            if let Some((key, copy)) = c.recorded() {
+                state.continuations.old.entry(key).or_insert(copy);
+            }
+        }
+        drop(state);
+
+        Continuation::prioritize(&mut continuations);
+        let inline = env.inline_count(continuations.len());
+        for c in continuations.drain(inline..) {
+            env.spawn(scope, move |s, env| {
+                c.run(result.map(|t| (0, t)), s, timelines, env)
+            });
+        }
+        for c in continuations {
+            env.run_inline(scope, move |s, env| {
+                c.run(result.map(|t| (0, t)), s, timelines, env)
+            });
+        }
+    }
+
+    fn clear_cache(&self) {
+        let mut state = self.state.lock();
+        if state.result.take().is_none() {
+            return;
+        }
+        let this = self as *const Self as *const ();
+        state.continuations.old.retain(|_, c| c.reads_from(this));
+        let old = state
+            .continuations
+            .old
+            .values()
+            .filter_map(|c| c.copy_node())
+            .collect::<SmallVec<_, 1>>();
+        drop(state);
+
+        for continuation in old {
+            continuation.clear_cache();
+        }
+    }
+
+    fn clear_upstream(&self, _time_of_change: Option<Instant>) -> bool {
+        unreachable!()
+    }
+
+    /// The delay is only computed for this, and is detached when this is no longer used.
+    fn reads_from(&self, _upstream: *const ()) -> bool {
+        true
     }
 }
@@ -0,0 +1,88 @@
+//! A plan that owns its session, so that it can be stored anywhere and moved between threads.
+//!
+//! [Plan] borrows the [Session] it was created from, which makes it awkward to keep in a
+//! struct, and impossible to move into a spawned thread or an async task. An [OwnedPlan]
+//! creates its own session instead (or takes one that is given to it).
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(counter: u32);
+//! # model! { Counter(counter) }
+//! # pub struct Increment;
+//! # impl_activity! { for Increment
+//! #     @(start) {
+//! #         ref mut: counter += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! let start = Time::from_tai_seconds(0.0);
+//! let mut plan = OwnedPlan::<Counter>::new(start, initial_conditions! { counter: 0 })?;
+//! plan.with_plan_mut(|plan| plan.insert(start + Duration::from_seconds(1.0), Increment))?;
+//!
+//! let plan = std::thread::spawn(move || {
+//!     plan.with_plan_mut(|plan| plan.insert(start + Duration::from_seconds(2.0), Increment))
+//!         .unwrap();
+//!     plan
+//! })
+//! .join()
+//! .unwrap();
+//!
+//! assert_eq!(2, plan.plan().sample::<counter>(start + Duration::from_seconds(3.0))?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::operation::initial_conditions::InitialConditions;
+use crate::{EngineError, Model, Plan, Session, Time};
+
+/// A [Plan] together with the [Session] it uses. See the [module documentation][self].
+pub struct OwnedPlan<M: for<'o> Model<'o> + 'static> {
+    /// Declared first so that it is dropped before the session it borrows.
+    plan: Plan<'static, M>,
+    /// Boxed so that it doesn't move when the plan does.
+    session: Box<Session>,
+}
+
+impl<M: for<'o> Model<'o> + 'static> OwnedPlan<M> {
+    /// Creates a plan with a new, empty session.
+    pub fn new(time: Time, initial_conditions: InitialConditions) -> Result<Self, EngineError> {
+        Self::with_session(Session::new(), time, initial_conditions)
+    }
+
+    /// Creates a plan that uses an existing session, for example one made from a history
+    /// that was loaded from disk.
+    pub fn with_session(
+        session: Session,
+        time: Time,
+        initial_conditions: InitialConditions,
+    ) -> Result<Self, EngineError> {
+        let session = Box::new(session);
+        let plan = session.new_plan::<M>(time, initial_conditions)?;
+        // The session is boxed, and is dropped after the plan.
+        let plan = unsafe { std::mem::transmute::<Plan<'_, M>, Plan<'static, M>>(plan) };
+        Ok(OwnedPlan { plan, session })
+    }
+
+    pub fn plan(&self) -> &Plan<'_, M> {
+        // Nothing that the plan can hold outlives the session, except the plan itself.
+        unsafe { std::mem::transmute::<&Plan<'static, M>, &Plan<'_, M>>(&self.plan) }
+    }
+
+    /// Lends out the plan to edit it. Like [Session::with_plan], `f` works for plans of any
+    /// lifetime, so that it can't swap in a plan that borrows another session.
+    pub fn with_plan_mut<T>(&mut self, f: impl for<'o> FnOnce(&mut Plan<'o, M>) -> T) -> T {
+        f(&mut self.plan)
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Drops the plan, and returns the session with everything the plan added to its history.
+    pub fn into_session(self) -> Session {
+        let OwnedPlan { plan, session } = self;
+        drop(plan);
+        *session
+    }
+}
@@ -0,0 +1,100 @@
+//! Resources holding persistent collections, which share structure between history entries.
+//!
+//! Every operation that writes a `ref` resource stores a new value in history, made from a
+//! clone of the value it read. For a [Vec] or a [HashMap][std::collections::HashMap] that is a
+//! deep copy, so a log that every activity appends to costs quadratic time and memory. A
+//! persistent collection, like the `Vector` and `HashMap` of the
+//! [im](https://docs.rs/im) crate, shares everything but the changed parts with the value it
+//! was cloned from, so each write only costs as much as the change.
+//!
+//! Persistent collections can't be stored in history directly, because the history of a `ref`
+//! resource hands out references into its entries, and the collections keep small values
+//! inline. [Persistent] boxes the collection so that it can be:
+//!
+//! ```
+//! # use peregrine::*;
+//! use peregrine::persistent::Persistent;
+//!
+//! resource!(ref event_log: Persistent<im::Vector<u32>> = Persistent::default());
+//! # model! { Logged(event_log) }
+//!
+//! pub struct Log(u32);
+//! impl_activity! { for Log
+//!     @(start) {
+//!         ref mut: event_log.push_back(self.0);
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Logged>(start, initial_conditions! {})?;
+//! for i in 1..=3 {
+//!     plan.insert(start + Duration::from_seconds(i as f64), Log(i))?;
+//! }
+//!
+//! let log: &im::Vector<u32> = plan.sample::<event_log>(start + Duration::from_seconds(4.0))?;
+//! assert_eq!(im::vector![1, 2, 3], *log);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Any [Clone] type can be wrapped, but it only helps for types whose clones are cheap.
+
+use serde::{Deserialize, Serialize};
+use stable_deref_trait::StableDeref;
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+
+/// A boxed value that can be stored in the history of a `ref` resource, read as `&T`.
+/// See [persistent][crate::persistent].
+#[derive(Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Persistent<T>(Box<T>);
+
+impl<T> Persistent<T> {
+    pub fn new(value: T) -> Self {
+        Persistent(Box::new(value))
+    }
+
+    pub fn into_inner(self) -> T {
+        *self.0
+    }
+}
+
+impl<T> Deref for Persistent<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Persistent<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+// The value is on the heap, so it doesn't move when the box does.
+unsafe impl<T> StableDeref for Persistent<T> {}
+
+impl<T> From<T> for Persistent<T> {
+    fn from(value: T) -> Self {
+        Persistent::new(value)
+    }
+}
+
+/// Clones the value, which is how operations get a value to write from the one they read.
+impl<T: Clone> From<&T> for Persistent<T> {
+    fn from(value: &T) -> Self {
+        Persistent::new(value.clone())
+    }
+}
+
+impl<T: Debug> Debug for Persistent<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
@@ -0,0 +1,254 @@
+//! Plans read from JSON or TOML documents, for plans written by hand or by other tools.
+//!
+//! A plan document is a list of activities, each with the label of a type registered with
+//! [register_activity!][crate::register_activity!], a start time, and its arguments:
+//!
+//! ```toml
+//! [[activities]]
+//! type = "Heat"
+//! start = "2030-01-01T01:00:00 UTC"
+//! args = { power = 5.0 }
+//!
+//! [[activities]]
+//! type = "Heat"
+//! start = "2030-01-01T02:00:00 UTC"
+//! version = 1
+//! args = { power = 2.0, duty_cycle = 0.5 }
+//! id = "second-heat"
+//! tags = ["thermal"]
+//! ```
+//!
+//! Times are [hifitime] epochs. `version` is the
+//! [VersionedActivity][crate::activity::VersionedActivity] version the arguments were written
+//! for, 0 by default, and arguments of older versions are upgraded. `id` is the activity's
+//! [key][Plan::insert_with_key], and `tags` go in its [metadata][Plan::metadata].
+//! `args` can be left out for activities without fields.
+//!
+//! [Plan::load] reads a document from a file (TOML if it ends in `.toml`, JSON otherwise) and
+//! inserts every activity. If any of them can't be inserted, none are, and the error lists
+//! every activity that failed rather than only the first.
+//!
+//! [Plan::to_document] goes the other way, saving every activity in the plan with the label of
+//! its registered type, and [Plan::save] writes the document to a file, so that the plan can
+//! be loaded again by another process. Anchors and metadata other than tags aren't saved.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::activity::VersionedActivity;
+//! # use peregrine::plan_file::PlanDocument;
+//! # resource!(documented_heat: f64);
+//! # model! { Documented(documented_heat) }
+//! # #[derive(serde::Serialize, serde::Deserialize)]
+//! # pub struct Heat { power: f64 }
+//! # impl_activity! { for Heat
+//! #     @(start) {
+//! #         ref mut: documented_heat += self.power;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # impl VersionedActivity for Heat {}
+//! # register_activity!(Heat => Documented);
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Documented>(start, initial_conditions! { documented_heat: 0.0 })?;
+//!
+//! let document = PlanDocument::from_json(r#"{ "activities": [
+//!     { "type": "Heat", "start": "1900-01-01T00:00:01 TAI", "args": { "power": 5.0 } },
+//!     { "type": "Heat", "start": "yesterday", "args": { "power": 5.0 } },
+//!     { "type": "Cool", "start": "1900-01-01T00:00:02 TAI" }
+//! ] }"#)?;
+//! let Err(EngineError::InvalidPlanDocument(issues)) = plan.insert_document(&document) else {
+//!     panic!("expected the document to be rejected");
+//! };
+//! assert_eq!(vec![1, 2], issues.iter().map(|issue| issue.index).collect::<Vec<_>>());
+//! assert!(plan.spans().is_empty());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::activity::{ActivityId, ActivityMetadata, SavedArguments};
+use crate::registry::save_registered;
+use crate::{EngineError, LoadIssue, Model, Plan, Time};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The activities of a plan, as read from a file. See [plan_file][crate::plan_file].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlanDocument {
+    pub activities: Vec<PlannedActivity>,
+}
+
+/// An activity in a [PlanDocument].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlannedActivity {
+    /// The label of the activity's registered type.
+    #[serde(rename = "type")]
+    pub activity: String,
+    pub start: String,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub args: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl PlanDocument {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Reads a document from a file, as TOML if its extension is `.toml` and JSON otherwise.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read plan document {}", path.display()))?;
+        let document = if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            #[cfg(feature = "toml")]
+            {
+                Self::from_toml(&text)
+            }
+            #[cfg(not(feature = "toml"))]
+            {
+                Err(anyhow!(
+                    "reading TOML plan documents requires the `toml` feature"
+                ))
+            }
+        } else {
+            Self::from_json(&text)
+        };
+        document.with_context(|| format!("in plan document {}", path.display()))
+    }
+
+    /// Writes the document to a file, as TOML if its extension is `.toml` and JSON otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            #[cfg(feature = "toml")]
+            {
+                self.to_toml()?
+            }
+            #[cfg(not(feature = "toml"))]
+            {
+                return Err(anyhow!(
+                    "writing TOML plan documents requires the `toml` feature"
+                ));
+            }
+        } else {
+            self.to_json()?
+        };
+        std::fs::write(path, text)
+            .with_context(|| format!("could not write plan document {}", path.display()))
+    }
+}
+
+impl<M: for<'o> Model<'o> + 'static> Plan<'_, M> {
+    /// Reads a plan document from a file and inserts its activities. See
+    /// [plan_file][crate::plan_file].
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<Vec<ActivityId>> {
+        let document = PlanDocument::from_path(path)?;
+        Ok(self.insert_document(&document)?)
+    }
+
+    /// Saves the plan's activities to a file that [Plan::load] can read. See
+    /// [plan_file][crate::plan_file].
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.to_document()?.save(path)
+    }
+
+    /// The plan's activities as a document, ordered by start time. Fails if an activity's type
+    /// isn't [registered][crate::register_activity!] for the model.
+    pub fn to_document(&self) -> Result<PlanDocument> {
+        let activities = self
+            .spans()
+            .into_iter()
+            .map(|span| {
+                let decomposed = &self.activities[&span.id];
+                let saved = save_registered(&*decomposed.activity)
+                    .with_context(|| format!("could not save {} {}", span.label, span.id))?;
+                Ok(PlannedActivity {
+                    activity: span.label.to_string(),
+                    start: span.start.to_string(),
+                    version: saved.version,
+                    args: saved.args,
+                    id: decomposed.key.clone(),
+                    tags: decomposed.metadata.tags.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(PlanDocument { activities })
+    }
+
+    /// Inserts every activity in a plan document, returning their IDs in the order of the
+    /// document. If any activity can't be inserted, the plan is left unchanged and every
+    /// failure is returned in [EngineError::InvalidPlanDocument].
+    pub fn insert_document(
+        &mut self,
+        document: &PlanDocument,
+    ) -> Result<Vec<ActivityId>, EngineError> {
+        let mut inserted = vec![];
+        let mut issues = vec![];
+        for (index, planned) in document.activities.iter().enumerate() {
+            match self.insert_planned(planned) {
+                Ok(id) => inserted.push(id),
+                Err(error) => issues.push(LoadIssue {
+                    index,
+                    activity: planned.activity.clone(),
+                    key: planned.id.clone(),
+                    error,
+                }),
+            }
+        }
+        if issues.is_empty() {
+            Ok(inserted)
+        } else {
+            self.remove_all(inserted)?;
+            Err(EngineError::InvalidPlanDocument(issues))
+        }
+    }
+
+    fn insert_planned(&mut self, planned: &PlannedActivity) -> Result<ActivityId> {
+        let start = Time::from_str(&planned.start)
+            .map_err(|e| anyhow!("invalid start time {:?}: {e}", planned.start))?;
+        let args = SavedArguments {
+            version: planned.version,
+            args: planned.args.clone(),
+        };
+        let id = self.insert_registered(&planned.activity, start, args, planned.id.clone())?;
+        if !planned.tags.is_empty() {
+            self.set_metadata(
+                id,
+                ActivityMetadata {
+                    tags: planned.tags.clone(),
+                    ..Default::default()
+                },
+            )?;
+        }
+        Ok(id)
+    }
+}
@@ -0,0 +1,189 @@
+//! Views that know which resource they are of.
+//!
+//! [Plan::profile] views a resource like [Plan::view], but returns a [Profile], which keeps
+//! the resource's label and unit next to its values. Profiles print as a table with a column of
+//! times and a column of values, aligned and headed by the label, so that a view can be
+//! printed while debugging or in a report without formatting it by hand. [Display] prints the
+//! values with their own [Display] implementation, and [Debug] with theirs.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource! {
+//! #     #[unit = "W"]
+//! #     profiled_power: f64
+//! # }
+//! # model! { Profiled(profiled_power) }
+//! # pub struct PowerOn;
+//! # impl_activity! { for PowerOn
+//! #     @(start) {
+//! #         ref mut: profiled_power += 12.5;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Profiled>(start, initial_conditions! { profiled_power: 0.0 })?;
+//! plan.insert(start + Duration::from_hours(1.0), PowerOn)?;
+//!
+//! let profile = plan.profile::<profiled_power>(..)?;
+//! assert_eq!(Some(12.5), profile.last());
+//! assert_eq!(
+//!     "time                     profiled_power [W]\n\
+//!      1900-01-01T00:00:00 TAI  0\n\
+//!      1900-01-01T01:00:00 TAI  12.5\n",
+//!     profile.to_string()
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::resource::Resource;
+use crate::{EngineError, Model, Plan, Time};
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
+use std::ops::{Deref, RangeBounds};
+
+/// The values of a resource in a window, with the resource's label and unit. See
+/// [profile][crate::profile].
+///
+/// Derefs to the slice of times and values that [Plan::view] returns.
+pub struct Profile<'o, R: Resource<'o>> {
+    values: Vec<(Time, R::Read)>,
+    resource: PhantomData<fn() -> R>,
+}
+
+impl<'o, R: Resource<'o>> Profile<'o, R> {
+    pub fn new(values: Vec<(Time, R::Read)>) -> Self {
+        Profile {
+            values,
+            resource: PhantomData,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        R::LABEL
+    }
+
+    pub fn unit(&self) -> Option<&'static str> {
+        R::UNIT
+    }
+
+    /// The last value in the window, if there are any.
+    pub fn last(&self) -> Option<R::Read> {
+        self.values.last().map(|(_, value)| *value)
+    }
+
+    pub fn into_values(self) -> Vec<(Time, R::Read)> {
+        self.values
+    }
+
+    /// Writes the table, formatting each value with `value`.
+    fn table(&self, f: &mut Formatter<'_>, value: impl Fn(&R::Read) -> String) -> std::fmt::Result {
+        let times: Vec<_> = self
+            .values
+            .iter()
+            .map(|(time, _)| time.to_string())
+            .collect();
+        let width = times
+            .iter()
+            .map(String::len)
+            .max()
+            .unwrap_or(0)
+            .max("time".len());
+        write!(f, "{:width$}  {}", "time", R::LABEL)?;
+        if let Some(unit) = R::UNIT {
+            write!(f, " [{unit}]")?;
+        }
+        writeln!(f)?;
+        for (time, (_, read)) in times.iter().zip(&self.values) {
+            writeln!(f, "{time:width$}  {}", value(read))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'o, R: Resource<'o>> Deref for Profile<'o, R> {
+    type Target = [(Time, R::Read)];
+
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+
+impl<'o, R: Resource<'o>> Clone for Profile<'o, R> {
+    fn clone(&self) -> Self {
+        Profile::new(self.values.clone())
+    }
+}
+
+impl<'o, R: Resource<'o>> PartialEq for Profile<'o, R>
+where
+    R::Read: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl<'o, R: Resource<'o>> PartialEq<Vec<(Time, R::Read)>> for Profile<'o, R>
+where
+    R::Read: PartialEq,
+{
+    fn eq(&self, other: &Vec<(Time, R::Read)>) -> bool {
+        &self.values == other
+    }
+}
+
+impl<'o, R: Resource<'o>> From<Profile<'o, R>> for Vec<(Time, R::Read)> {
+    fn from(profile: Profile<'o, R>) -> Self {
+        profile.values
+    }
+}
+
+impl<'o, R: Resource<'o>> IntoIterator for Profile<'o, R> {
+    type Item = (Time, R::Read);
+    type IntoIter = std::vec::IntoIter<(Time, R::Read)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.into_iter()
+    }
+}
+
+impl<'a, 'o, R: Resource<'o>> IntoIterator for &'a Profile<'o, R> {
+    type Item = &'a (Time, R::Read);
+    type IntoIter = std::slice::Iter<'a, (Time, R::Read)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.iter()
+    }
+}
+
+impl<'o, R: Resource<'o>> Display for Profile<'o, R>
+where
+    R::Read: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.table(f, |value| value.to_string())
+    }
+}
+
+impl<'o, R: Resource<'o>> Debug for Profile<'o, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.table(f, |value| format!("{value:?}"))
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Views `R` like [Plan::view], as a [Profile] that can be printed. See
+    /// [profile][crate::profile].
+    pub fn profile<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Profile<'o, R>, EngineError>
+    where
+        Self: 'o,
+    {
+        Ok(Profile::new(self.view::<R>(bounds)?))
+    }
+}
@@ -0,0 +1,108 @@
+//! Reproducible randomness inside operations.
+//!
+//! Operations are reused from history whenever their inputs are the same, so randomness from
+//! anywhere else, like `rand::random()`, is hidden state: a reused result keeps whatever
+//! numbers were drawn the first time, and a resimulated one draws new ones. Instead, an
+//! operation makes an [Rng] from the [random_seed] resource and something that identifies the
+//! draw, like `now`. The numbers are then a function of the operation's inputs and time, so
+//! the same seed always produces the same plan, and changing the seed invalidates exactly the
+//! operations that use it.
+//!
+//! Add `random_seed` to the model, and set it in the initial conditions (it defaults to 0). A
+//! [Monte Carlo][crate::monte_carlo] sampler can set a different seed for each run.
+//!
+//! ```
+//! # use peregrine::*;
+//! use peregrine::random::{Rng, random_seed};
+//!
+//! resource!(pointing_error: f64);
+//! model! { Pointing(pointing_error, random_seed) }
+//!
+//! pub struct Slew;
+//! impl_activity! { for Slew
+//!     @(start) {
+//!         mut: pointing_error = Rng::new(ref: random_seed, now).normal(0.0, 0.1);
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! let session = Session::new();
+//! let start = Time::from_tai_seconds(0.0);
+//! let error = |seed: u64| -> Result<f64> {
+//!     let mut plan = session.new_plan::<Pointing>(
+//!         start,
+//!         initial_conditions! { pointing_error: 0.0, random_seed: seed },
+//!     )?;
+//!     plan.insert(start + Duration::from_seconds(1.0), Slew)?;
+//!     Ok(plan.sample::<pointing_error>(start + Duration::from_seconds(2.0))?)
+//! };
+//! assert_eq!(error(1)?, error(1)?);
+//! assert_ne!(error(1)?, error(2)?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate as peregrine;
+use crate::history::PeregrineDefaultHashBuilder;
+use crate::resource;
+use std::hash::{BuildHasher, Hash};
+
+resource! {
+    /// The seed of every [Rng] in a plan.
+    pub random_seed: u64 = 0
+}
+
+/// A deterministic random number generator, made from a seed and a stream that identifies
+/// what is drawn. See the [module documentation][self].
+///
+/// The generator is SplitMix64, which is fast and statistically sound for simulation, but not
+/// cryptographically secure.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Makes a generator for `stream` under `seed`. Different streams under the same seed are
+    /// independent, so operations that draw at the same time need different streams, like
+    /// `(now, "thruster")`.
+    pub fn new(seed: u64, stream: impl Hash) -> Self {
+        let stream = PeregrineDefaultHashBuilder::default().hash_one(stream);
+        let mut rng = Rng {
+            state: seed ^ stream.rotate_left(32),
+        };
+        rng.next_u64();
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number uniformly distributed in `[0, 1)`.
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number uniformly distributed in `[low, high)`.
+    pub fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.uniform()
+    }
+
+    /// `true` with probability `p`.
+    pub fn bernoulli(&mut self, p: f64) -> bool {
+        self.uniform() < p
+    }
+
+    /// A normally distributed number, with the Box-Muller transform.
+    pub fn normal(&mut self, mean: f64, std_dev: f64) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        mean + std_dev * (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+}
@@ -0,0 +1,312 @@
+//! Activities that can be inserted by name, for servers and command lines that receive plans
+//! as data instead of compiling against the activity types.
+//!
+//! [register_activity!][crate::register_activity!] adds an activity type to the registry for
+//! one or more models, and [Plan::insert_by_name] deserializes its arguments from JSON and
+//! inserts it. Registered activities implement [VersionedActivity] so that arguments saved by
+//! older versions are upgraded on the way in; activities that have never changed can use an
+//! empty implementation.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::activity::{SavedArguments, VersionedActivity};
+//! # resource!(registered_heat: f64);
+//! # model! { Registered(registered_heat) }
+//! #[derive(serde::Serialize, serde::Deserialize)]
+//! pub struct Heat {
+//!     power: f64,
+//! }
+//! impl_activity! { for Heat
+//!     @(start) {
+//!         ref mut: registered_heat += self.power;
+//!     }
+//!     Duration::ZERO
+//! }
+//! impl VersionedActivity for Heat {}
+//!
+//! register_activity!(Heat => Registered);
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Registered>(start, initial_conditions! { registered_heat: 0.0 })?;
+//! assert_eq!(vec!["Heat"], peregrine::registry::registered::<Registered>());
+//!
+//! let args: SavedArguments = serde_json::from_str(r#"{ "args": { "power": 5.0 } }"#)?;
+//! plan.insert_by_name("Heat", start + Duration::from_seconds(1.0), args)?;
+//! assert_eq!(5.0, plan.sample::<registered_heat>(start + Duration::from_seconds(2.0))?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Activities are registered for specific models because an activity's operations are
+//! generated separately for each model it is used in, so a registry that didn't know the
+//! model would have nothing to insert.
+//!
+//! A `Box<dyn Activity<M>>` of a registered type is [Serialize] and [Deserialize], as its label
+//! and [saved][VersionedActivity::save] arguments, so activities chosen at runtime can be kept
+//! in any serde format without a mapping from labels to types of their own:
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::activity::{Activity, VersionedActivity};
+//! # resource!(tagged_heat: f64);
+//! # model! { Tagged(tagged_heat) }
+//! # #[derive(serde::Serialize, serde::Deserialize)]
+//! # pub struct Heat { power: f64 }
+//! # impl_activity! { for Heat
+//! #     @(start) {
+//! #         ref mut: tagged_heat += self.power;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # impl VersionedActivity for Heat {}
+//! # register_activity!(Heat => Tagged);
+//! # fn main() -> Result<()> {
+//! let activities: Vec<Box<dyn Activity<Tagged>>> = vec![Box::new(Heat { power: 5.0 })];
+//! let json = serde_json::to_string(&activities)?;
+//! assert_eq!(r#"[{"type":"Heat","version":0,"args":{"power":5.0}}]"#, json);
+//!
+//! let activities: Vec<Box<dyn Activity<Tagged>>> = serde_json::from_str(&json)?;
+//! assert_eq!("Heat", activities[0].label());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Whole plans are saved the same way, with [Plan::to_document].
+
+use crate::activity::{Activity, ActivityId, ActivityLabel, SavedArguments, VersionedActivity};
+use crate::offload::{Evaluate, Job};
+use crate::{EngineError, History, Model, Plan, Time};
+use anyhow::{Result, anyhow};
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::any::Any;
+use std::marker::PhantomData;
+
+/// Registers activity types so that they can be inserted by name into plans of the given
+/// models. See [registry][crate::registry].
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::*;
+/// # use peregrine::activity::VersionedActivity;
+/// # resource!(registered_images: u32);
+/// # model! { Orbiter(registered_images) }
+/// # model! { Lander(registered_images) }
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// pub struct TakeImages(u32);
+/// impl_activity! { for TakeImages
+///     @(start) {
+///         ref mut: registered_images += self.0;
+///     }
+///     Duration::ZERO
+/// }
+/// impl VersionedActivity for TakeImages {}
+///
+/// register_activity!(TakeImages => Orbiter, Lander);
+/// ```
+#[macro_export]
+macro_rules! register_activity {
+    ($ty:ty => $($model:ty),+ $(,)?) => {
+        $(
+            $crate::reexports::inventory::submit!(
+                $crate::registry::ActivityRegistration::new::<$ty, $model>()
+            );
+        )+
+    };
+}
+
+/// An activity type registered for a model with [register_activity!][crate::register_activity!].
+pub struct ActivityRegistration {
+    label: &'static str,
+    /// Makes a `Box<dyn Insert<M>>` for the model the activity was registered for.
+    inserter: fn() -> Box<dyn Any>,
+    /// Evaluates an [offloaded][crate::offload] operation of the activity.
+    evaluator: fn(&Job, &History) -> Result<()>,
+    /// Saves the arguments of an activity, if it is of this type.
+    saver: fn(&dyn Any) -> Option<Result<SavedArguments>>,
+}
+
+inventory::collect!(ActivityRegistration);
+
+impl ActivityRegistration {
+    #[doc(hidden)]
+    pub const fn new<A, M>() -> Self
+    where
+        A: VersionedActivity + ActivityLabel + Evaluate + for<'o> Activity<'o, M> + 'static,
+        M: for<'o> Model<'o> + 'static,
+    {
+        ActivityRegistration {
+            label: A::LABEL,
+            inserter: make_inserter::<A, M>,
+            evaluator: evaluate::<A>,
+            saver: save::<A>,
+        }
+    }
+
+    fn inserter<M: for<'o> Model<'o> + 'static>(&self) -> Option<Box<dyn Insert<M>>> {
+        (self.inserter)()
+            .downcast::<Box<dyn Insert<M>>>()
+            .ok()
+            .map(|inserter| *inserter)
+    }
+}
+
+/// Loads and inserts activities of a registered type into plans of model `M`.
+trait Insert<M: for<'o> Model<'o> + 'static> {
+    fn load<'o>(&self, args: SavedArguments) -> Result<Box<dyn Activity<'o, M>>, EngineError>;
+
+    fn insert(
+        &self,
+        plan: &mut Plan<'_, M>,
+        time: Time,
+        args: SavedArguments,
+        key: Option<String>,
+    ) -> Result<ActivityId, EngineError>;
+}
+
+struct Inserter<A>(PhantomData<fn() -> A>);
+
+impl<A, M> Insert<M> for Inserter<A>
+where
+    A: VersionedActivity + ActivityLabel + for<'o> Activity<'o, M> + 'static,
+    M: for<'o> Model<'o> + 'static,
+{
+    fn load<'o>(&self, args: SavedArguments) -> Result<Box<dyn Activity<'o, M>>, EngineError> {
+        let activity = A::load(args).map_err(|source| EngineError::InvalidArguments {
+            activity: A::LABEL,
+            source,
+        })?;
+        Ok(Box::new(activity))
+    }
+
+    fn insert(
+        &self,
+        plan: &mut Plan<'_, M>,
+        time: Time,
+        args: SavedArguments,
+        key: Option<String>,
+    ) -> Result<ActivityId, EngineError> {
+        let activity = self.load(args)?;
+        if let Some(key) = &key
+            && plan.keys.contains_key(key)
+        {
+            return Err(EngineError::DuplicateKey(key.clone()));
+        }
+        plan.insert_inner(time, activity, key)
+    }
+}
+
+fn make_inserter<A, M>() -> Box<dyn Any>
+where
+    A: VersionedActivity + ActivityLabel + for<'o> Activity<'o, M> + 'static,
+    M: for<'o> Model<'o> + 'static,
+{
+    let inserter: Box<dyn Insert<M>> = Box::new(Inserter::<A>(PhantomData));
+    Box::new(inserter)
+}
+
+fn save<A: VersionedActivity + 'static>(activity: &dyn Any) -> Option<Result<SavedArguments>> {
+    activity.downcast_ref::<A>().map(A::save)
+}
+
+fn evaluate<A: VersionedActivity + Evaluate>(job: &Job, history: &History) -> Result<()> {
+    let (activity, _): (A, _) =
+        bincode::serde::decode_from_slice(&job.arguments, bincode::config::standard())?;
+    activity.evaluate(job, history)
+}
+
+pub(crate) fn evaluate_registered(job: &Job, history: &History) -> Result<()> {
+    let registration = inventory::iter::<ActivityRegistration>
+        .into_iter()
+        .find(|registration| registration.label == job.activity)
+        .ok_or_else(|| anyhow!("no activity named {:?} is registered", job.activity))?;
+    (registration.evaluator)(job, history)
+}
+
+/// Saves the arguments of an activity of a registered type.
+pub(crate) fn save_registered<'o, M: Model<'o>>(
+    activity: &dyn Activity<'o, M>,
+) -> Result<SavedArguments> {
+    inventory::iter::<ActivityRegistration>
+        .into_iter()
+        .filter(|registration| registration.label == activity.label())
+        .find_map(|registration| (registration.saver)(activity.as_any()))
+        .ok_or_else(|| anyhow!("no activity named {:?} is registered", activity.label()))?
+}
+
+fn inserter<M: for<'o> Model<'o> + 'static>(
+    label: &str,
+) -> Result<Box<dyn Insert<M>>, EngineError> {
+    inventory::iter::<ActivityRegistration>
+        .into_iter()
+        .filter(|registration| registration.label == label)
+        .find_map(|registration| registration.inserter::<M>())
+        .ok_or_else(|| EngineError::UnknownActivity(label.to_string()))
+}
+
+/// A boxed activity as it is serialized: its label next to its saved arguments.
+#[derive(Serialize, Deserialize)]
+struct TaggedArguments {
+    #[serde(rename = "type")]
+    activity: String,
+    #[serde(flatten)]
+    saved: SavedArguments,
+}
+
+impl<'a, 'o, M: Model<'o>> Serialize for dyn Activity<'o, M> + 'a {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TaggedArguments {
+            activity: self.label().to_string(),
+            saved: save_registered(self).map_err(S::Error::custom)?,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, 'o, M: for<'m> Model<'m> + 'static> Deserialize<'de> for Box<dyn Activity<'o, M>> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let tagged = TaggedArguments::deserialize(deserializer)?;
+        inserter::<M>(&tagged.activity)
+            .and_then(|inserter| inserter.load(tagged.saved))
+            .map_err(D::Error::custom)
+    }
+}
+
+/// The labels of the activities registered for model `M`, sorted.
+pub fn registered<M: for<'o> Model<'o> + 'static>() -> Vec<&'static str> {
+    let mut labels: Vec<_> = inventory::iter::<ActivityRegistration>
+        .into_iter()
+        .filter(|registration| registration.inserter::<M>().is_some())
+        .map(|registration| registration.label)
+        .collect();
+    labels.sort_unstable();
+    labels.dedup();
+    labels
+}
+
+impl<M: for<'o> Model<'o> + 'static> Plan<'_, M> {
+    /// Inserts an activity of a type registered for this model with
+    /// [register_activity!][crate::register_activity!], upgrading and deserializing its
+    /// arguments. See [registry][crate::registry].
+    pub fn insert_by_name(
+        &mut self,
+        label: &str,
+        time: Time,
+        args: SavedArguments,
+    ) -> Result<ActivityId, EngineError> {
+        self.insert_registered(label, time, args, None)
+    }
+
+    pub(crate) fn insert_registered(
+        &mut self,
+        label: &str,
+        time: Time,
+        args: SavedArguments,
+        key: Option<String>,
+    ) -> Result<ActivityId, EngineError> {
+        inserter::<M>(label)?.insert(self, time, args, key)
+    }
+}
@@ -0,0 +1,256 @@
+//! An interactive prompt for editing a plan and sampling its resources, to explore a model
+//! without writing a test harness for every question.
+//!
+//! [run] reads commands line by line and applies them to a live plan, so only the operations
+//! affected by each edit are simulated again. The [command line runner][crate::cli] starts it
+//! with `--interactive`, after loading the initial conditions and plan:
+//!
+//! ```text
+//! > insert Heat "+1 h" { "power": 5.0 }
+//! inserted 0
+//! > sample repl_heat 1900-01-01T02:00:00 TAI
+//! 5.0
+//! > shift 0 -30 min
+//! > list
+//! 0  Heat  1900-01-01T00:30:00.000000000Z  0 ns
+//! ```
+//!
+//! Times are [hifitime] epochs, or durations after the start of the plan written as `+1 h`.
+//! Arguments that contain spaces are quoted, except for the last argument of `sample` and
+//! `shift`, and the JSON arguments of `insert`, which take the rest of the line. Activities are
+//! inserted by name from the [registry][crate::registry], with their arguments written for
+//! version 0 like in a [plan document][crate::plan_file]. Errors are printed and the prompt
+//! continues; `quit` or the end of the input stops it.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::activity::VersionedActivity;
+//! # resource!(repl_heat: f64);
+//! # model! { Interactive(repl_heat) }
+//! # #[derive(serde::Serialize, serde::Deserialize)]
+//! # pub struct Heat { power: f64 }
+//! # impl_activity! { for Heat
+//! #     @(start) {
+//! #         ref mut: repl_heat += self.power;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # impl VersionedActivity for Heat {}
+//! # register_activity!(Heat => Interactive);
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Interactive>(start, initial_conditions! { repl_heat: 0.0 })?;
+//!
+//! let commands = "insert Heat \"+1 h\" { \"power\": 5.0 }\nsample repl_heat +2 h\n";
+//! let mut output = vec![];
+//! peregrine::repl::run(&mut plan, commands.as_bytes(), &mut output)?;
+//! assert!(String::from_utf8(output)?.contains("5.0"));
+//! assert_eq!(1, plan.spans().len());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::activity::{ActivityId, SavedArguments};
+use crate::commands::iso_utc;
+use crate::{Duration, Model, Plan, Time};
+use anyhow::{Context, Result, anyhow, bail};
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+const HELP: &str = "\
+commands:
+    insert <type> <time> [<json args>]   insert a registered activity
+    remove <id>                          remove an activity
+    shift <id> <duration>                move an activity, and those anchored to it
+    list                                 list the activities, by start time
+    activities                           list the registered activity types
+    resources                            list the model's resources
+    sample <resource> <time>             print a resource's value at a time
+    view <resource> [<from> [<to>]]      print a resource's values in a window
+    help                                 print this message
+    quit                                 stop
+times are epochs like \"2030-01-01T00:00:00 UTC\", or offsets from the plan start like \"+1 h\"";
+
+/// Reads commands from `input` until `quit` or the end of the input, applying them to `plan`
+/// and writing the results and a prompt to `output`. See [repl][crate::repl].
+///
+/// Only failures to read or write stop the prompt; errors from commands are written to
+/// `output`.
+pub fn run<M: for<'o> Model<'o> + 'static>(
+    plan: &mut Plan<'_, M>,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<()> {
+    let mut line = String::new();
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        match execute(plan, line.trim(), &mut output) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => return Ok(()),
+            Err(e) => writeln!(output, "error: {e:#}")?,
+        }
+    }
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+fn execute<M: for<'o> Model<'o> + 'static>(
+    plan: &mut Plan<'_, M>,
+    line: &str,
+    output: &mut impl Write,
+) -> Result<Flow> {
+    let mut rest = line;
+    let Some(command) = next_argument(&mut rest)? else {
+        return Ok(Flow::Continue);
+    };
+    match command.as_str() {
+        "insert" => {
+            let label = expect_argument(&mut rest, "an activity type")?;
+            let time = parse_time(plan, &expect_argument(&mut rest, "a start time")?)?;
+            let args = match rest.trim() {
+                "" => serde_json::Value::Null,
+                json => serde_json::from_str(json).context("invalid arguments")?,
+            };
+            let id = plan.insert_by_name(&label, time, SavedArguments { version: 0, args })?;
+            writeln!(output, "inserted {id}")?;
+        }
+        "remove" => {
+            let id = parse_id(&expect_argument(&mut rest, "an activity ID")?)?;
+            finish(rest)?;
+            plan.remove(id)?;
+        }
+        "shift" => {
+            let id = parse_id(&expect_argument(&mut rest, "an activity ID")?)?;
+            let offset = parse_duration(rest.trim())?;
+            plan.shift(id, offset)?;
+        }
+        "list" => {
+            finish(rest)?;
+            for span in plan.spans() {
+                writeln!(
+                    output,
+                    "{}  {}  {}  {}",
+                    span.id,
+                    span.label,
+                    iso_utc(span.start),
+                    span.duration
+                )?;
+            }
+        }
+        "activities" => {
+            finish(rest)?;
+            for label in crate::registry::registered::<M>() {
+                writeln!(output, "{label}")?;
+            }
+        }
+        "resources" => {
+            finish(rest)?;
+            for resource in M::schema().resources {
+                match resource.unit {
+                    Some(unit) => writeln!(
+                        output,
+                        "{}  {} ({unit})",
+                        resource.label, resource.read_type
+                    )?,
+                    None => writeln!(output, "{}  {}", resource.label, resource.read_type)?,
+                }
+            }
+        }
+        "sample" => {
+            let label = expect_argument(&mut rest, "a resource")?;
+            let time = parse_time(plan, rest.trim())?;
+            let view = plan.view_labeled(&[&label], time..=time)?.remove(0);
+            let (_, value) = view
+                .times()
+                .into_iter()
+                .zip(view.json_values()?)
+                .max_by_key(|(time, _)| *time)
+                .ok_or_else(|| anyhow!("{label} has no value at {}", iso_utc(time)))?;
+            writeln!(output, "{value}")?;
+        }
+        "view" => {
+            let label = expect_argument(&mut rest, "a resource")?;
+            let from = next_argument(&mut rest)?
+                .map(|from| parse_time(plan, &from))
+                .transpose()?
+                .unwrap_or(plan.start);
+            let to = next_argument(&mut rest)?
+                .map(|to| parse_time(plan, &to))
+                .transpose()?;
+            finish(rest)?;
+            let view = match to {
+                Some(to) => plan.view_labeled(&[&label], from..=to)?,
+                None => plan.view_labeled(&[&label], from..)?,
+            }
+            .remove(0);
+            let mut points: Vec<_> = view.times().into_iter().zip(view.json_values()?).collect();
+            points.sort_by_key(|(time, _)| *time);
+            for (time, value) in points {
+                writeln!(output, "{}  {value}", iso_utc(time))?;
+            }
+        }
+        "help" => writeln!(output, "{HELP}")?,
+        "quit" | "exit" => return Ok(Flow::Quit),
+        _ => bail!("unknown command {command:?}, try help"),
+    }
+    Ok(Flow::Continue)
+}
+
+/// Takes the next whitespace-separated or double-quoted argument from the front of `rest`.
+fn next_argument(rest: &mut &str) -> Result<Option<String>> {
+    let trimmed = rest.trim_start();
+    if trimmed.is_empty() {
+        *rest = trimmed;
+        return Ok(None);
+    }
+    if let Some(quoted) = trimmed.strip_prefix('"') {
+        let end = quoted
+            .find('"')
+            .ok_or_else(|| anyhow!("unterminated quote"))?;
+        *rest = &quoted[end + 1..];
+        Ok(Some(quoted[..end].to_string()))
+    } else {
+        let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        *rest = &trimmed[end..];
+        Ok(Some(trimmed[..end].to_string()))
+    }
+}
+
+fn expect_argument(rest: &mut &str, what: &str) -> Result<String> {
+    next_argument(rest)?.ok_or_else(|| anyhow!("expected {what}"))
+}
+
+fn finish(rest: &str) -> Result<()> {
+    match rest.trim() {
+        "" => Ok(()),
+        extra => bail!("unexpected {extra:?}"),
+    }
+}
+
+fn parse_id(id: &str) -> Result<ActivityId> {
+    Ok(ActivityId::new(
+        id.parse()
+            .with_context(|| format!("invalid activity ID {id:?}"))?,
+    ))
+}
+
+fn parse_time<M: for<'o> Model<'o> + 'static>(plan: &Plan<'_, M>, time: &str) -> Result<Time> {
+    match time.strip_prefix('+') {
+        Some(offset) => Ok(plan.start + parse_duration(offset)?),
+        None => Time::from_str(time).map_err(|e| anyhow!("invalid time {time:?}: {e}")),
+    }
+}
+
+fn parse_duration(duration: &str) -> Result<Duration> {
+    Duration::from_str(duration).map_err(|e| anyhow!("invalid duration {duration:?}: {e}"))
+}
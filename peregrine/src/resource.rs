@@ -1,110 +1,38 @@
-use crate::history::HistoryAdapter;
+use crate::history::{History, HistoryAdapter, PeregrineDefaultHashBuilder};
+use crate::operation::initial_conditions::InitialConditions;
+use crate::timeline::{Instant, Timelines};
+use crate::{EngineError, InitialConditionIssue, Model, Plan, Time};
+use anyhow::Result;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use std::fmt::Debug;
+use std::any::TypeId;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{BuildHasher, Hash};
 use type_map::concurrent::TypeMap;
 use type_reg::untagged::TypeReg;
 
+/// Registers an instantiation of a generic [resource!][crate::resource!] for history serialization.
+///
+/// Non-generic resources are registered automatically. Generic resources can't be, because
+/// each instantiation stores a different type, so each instantiation used in a model should
+/// be registered once somewhere in the crate. Entries of a registered instantiation are
+/// serialized under the resource's label and the type arguments as they are written here, so
+/// changing how they are written, for example from `f32` to `core::primitive::f32`, makes
+/// serialized histories of it unreadable.
+///
+/// ```
+/// # use peregrine::{register_resource, resource};
+/// resource!(pub reading<T>: Option<T>);
+///
+/// register_resource!(reading<f32>);
+/// ```
 #[macro_export]
-macro_rules! resource {
-    ($vis:vis $name:ident: $ty:ty) => {
-        #[derive(Debug, $crate::reexports::serde::Serialize, $crate::reexports::serde::Deserialize)]
-        #[serde(crate = "peregrine::reexports::serde")]
-        #[allow(non_camel_case_types)]
-        $vis enum $name {
-            Unit
-        }
-
-        impl<'h> $crate::resource::Resource<'h> for $name {
-            const LABEL: &'static str = $crate::reexports::peregrine_macros::code_to_str!($name);
-            const STATIC: bool = true;
-            const ID: u64 = $crate::reexports::peregrine_macros::random_u64!();
-            type Read = $ty;
-            type Write = $ty;
-            type History = $crate::history::CopyHistory<$ty>;
-        }
-
-        impl $crate::resource::ResourceHistoryPlugin for $name {
-            fn write_type_string(&self) -> String {
-                $crate::reexports::peregrine_macros::code_to_str!($ty).to_string()
-            }
-
-            fn ser<'h>(&self, input: &'h mut $crate::reexports::type_map::concurrent::TypeMap, type_map: &'h mut $crate::reexports::type_reg::untagged::TypeMap<String>) {
-                if let Some(h) = input.remove::<$crate::history::CopyHistory<$ty>>() {
-                    type_map.insert(self.write_type_string(), h);
-                }
-            }
-
-            fn register(&self, type_reg: &mut $crate::reexports::type_reg::untagged::TypeReg<String>) {
-                type_reg.register::<$crate::history::CopyHistory<$ty>>(self.write_type_string());
-            }
-            fn de<'h>(&self, output: &'h mut $crate::reexports::type_map::concurrent::TypeMap, type_map: &'h mut $crate::reexports::type_reg::untagged::TypeMap<String>) {
-                match type_map.remove(&self.write_type_string()) {
-                    Some(sub) => {
-                        let sub_history = sub.into_inner().downcast::<$crate::history::CopyHistory<$ty>>();
-                        match sub_history {
-                            Ok(downcasted) => {
-                                output.insert(*downcasted);
-                            }
-                            Err(_) => unreachable!()
-                        }
-                    }
-                    None => {}
-                }
-            }
-        }
-
-        $crate::reexports::inventory::submit!(&$name::Unit as &dyn $crate::resource::ResourceHistoryPlugin);
-    };
-
-    ($vis:vis ref $name:ident: $ty:ty) => {
-        #[derive(Debug, $crate::reexports::serde::Serialize, $crate::reexports::serde::Deserialize)]
-        #[serde(crate = "peregrine::reexports::serde")]
-        #[allow(non_camel_case_types)]
-        $vis enum $name {
-            Unit
-        }
-
-        impl<'h> $crate::resource::Resource<'h> for $name {
-            const LABEL: &'static str = $crate::reexports::peregrine_macros::code_to_str!($name);
-            const ID: u64 = $crate::reexports::peregrine_macros::random_u64!();
-            const STATIC: bool = true;
-            type Read = &'h <$ty as std::ops::Deref>::Target;
-            type Write = $ty;
-            type History = $crate::history::DerefHistory<$ty>;
-        }
-
-        impl $crate::resource::ResourceHistoryPlugin for $name {
-            fn write_type_string(&self) -> String {
-                $crate::reexports::peregrine_macros::code_to_str!($ty).to_string()
-            }
-
-            fn ser<'h>(&self, input: &'h mut $crate::reexports::type_map::concurrent::TypeMap, type_map: &'h mut $crate::reexports::type_reg::untagged::TypeMap<String>) {
-                if let Some(h) = input.remove::<$crate::history::DerefHistory<$ty>>() {
-                    type_map.insert(self.write_type_string(), h);
-                }
-            }
-
-            fn register(&self, type_reg: &mut $crate::reexports::type_reg::untagged::TypeReg<String>) {
-                type_reg.register::<$crate::history::DerefHistory<$ty>>(self.write_type_string());
-            }
-            fn de<'h>(&self, output: &'h mut $crate::reexports::type_map::concurrent::TypeMap, type_map: &'h mut $crate::reexports::type_reg::untagged::TypeMap<String>) {
-                match type_map.remove(&self.write_type_string()) {
-                    Some(sub) => {
-                        let sub_history = sub.into_inner().downcast::<$crate::history::DerefHistory<$ty>>();
-                        match sub_history {
-                            Ok(downcasted) => {
-                                output.insert(*downcasted);
-                            }
-                            Err(_) => unreachable!()
-                        }
-                    }
-                    None => {}
-                }
-            }
-        }
-
-        $crate::reexports::inventory::submit!(&$name::Unit as &dyn $crate::resource::ResourceHistoryPlugin);
+macro_rules! register_resource {
+    ($($resource:ident)::+ < $($argument:ty),+ $(,)? >) => {
+        $crate::reexports::inventory::submit!(
+            &$($resource)::+::<$($argument),+>::__Registered(stringify!($($argument),+))
+                as &dyn $crate::resource::ResourceHistoryPlugin
+        );
     };
 }
 
@@ -129,17 +57,266 @@ pub trait Resource<'h>: Sync + ErasedResource<'h> {
 
     const ID: u64;
 
+    /// The unit of the resource's value, declared with a `#[unit = "..."]` attribute.
+    const UNIT: Option<&'static str> = None;
+
+    /// A description of the resource, taken from its doc comment.
+    const DESCRIPTION: Option<&'static str> = None;
+
+    /// The version of the write type's encoding, declared with `#[schema_version = N]`. Bump
+    /// it when the type changes, so that exported histories from before the change are
+    /// recognized, see [portable][crate::history::portable].
+    const SCHEMA_VERSION: u32 = 0;
+
     /// The type that is read from history.
     type Read: 'h + Copy + Send + Sync + Debug;
 
     /// The type that is written from operations to history.
     type Write: 'h + Clone + Debug + Serialize + DeserializeOwned + Send + Sync;
 
-    /// The type of history container to use to store instances of the `Write` type, currently
-    /// either [CopyHistory] or [DerefHistory]. See [Resource] for details.
-    type History: 'static + HistoryAdapter<Self::Write, Self::Read> + Debug + Default + Send + Sync;
+    /// The type of history container to use to store instances of the `Write` type:
+    /// [CopyHistory][crate::history::CopyHistory] or [DerefHistory][crate::history::DerefHistory]
+    /// by default, or a custom container declared with `#[history(Type)]`. See
+    /// [history][crate::history] for details.
+    type History: HistoryAdapter<Self::Write, Self::Read>;
+
+    /// The value to use when the initial conditions of a plan don't include this resource,
+    /// declared with `resource!(name: Type = value)`.
+    fn default_initial_condition() -> Option<Self::Write> {
+        None
+    }
+
+    /// Checks a write by an operation that also read the resource, failing the operation if
+    /// the change is not allowed. Declared with `#[state_machine]`, see [StateMachine].
+    fn check_transition(_from: Self::Read, _to: &Self::Write) -> Result<()> {
+        Ok(())
+    }
+
+    /// Checks a value before it is written to history, failing the operation that wrote it
+    /// if it is invalid. Declared with `#[finite]`, see [Finite].
+    fn check_write(_value: &Self::Write) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Declares the `StateMachine` transitions of an enum.
+///
+/// Each line lists the states that can be reached from a state. Staying in the same state is
+/// always allowed.
+///
+/// ```
+/// # use peregrine::state_machine;
+/// # use peregrine::resource::StateMachine;
+/// #[derive(Copy, Clone, Debug, PartialEq)]
+/// pub enum Mode { Off, Standby, Science }
+///
+/// state_machine! { Mode:
+///     Off => Standby,
+///     Standby => Off | Science,
+///     Science => Standby,
+/// }
+///
+/// assert!(Mode::Off.allows(&Mode::Standby));
+/// assert!(!Mode::Off.allows(&Mode::Science));
+/// ```
+#[macro_export]
+macro_rules! state_machine {
+    ($ty:ident: $($from:ident => $($to:ident)|+),* $(,)?) => {
+        impl $crate::resource::StateMachine for $ty {
+            fn allows(&self, to: &Self) -> bool {
+                match (self, to) {
+                    $(($ty::$from, $($ty::$to)|+) => true,)*
+                    _ => self == to,
+                }
+            }
+        }
+    };
+}
+
+/// A resource value that can only change in certain ways, usually an enum of modes.
+///
+/// Implement it with [state_machine!][crate::state_machine!], and mark the resource with
+/// `#[state_machine]` to have the engine check it:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::{resource, state_machine};
+/// #[derive(Copy, Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+/// pub enum Mode { #[default] Off, Standby, Science }
+///
+/// state_machine! { Mode:
+///     Off => Standby,
+///     Standby => Off | Science,
+///     Science => Standby,
+/// }
+///
+/// resource! {
+///     #[state_machine]
+///     pub mode: Mode = Mode::Off
+/// }
+/// ```
+///
+/// Operations that read the mode and write an illegal next state then fail, with the usual
+/// [ErrorReport][crate::ErrorReport] pointing at the activity responsible. Operations that
+/// write the mode without reading it, like `mut: mode = Mode::Off`, are treated as resets and
+/// aren't checked.
+pub trait StateMachine: Copy + Debug + PartialEq {
+    /// Whether the value can change from `self` to `to`.
+    fn allows(&self, to: &Self) -> bool;
+}
+
+/// The [Resource::check_transition] of a `#[state_machine]` resource.
+pub fn check_state_transition<'h, R, S>(from: S, to: &S) -> Result<()>
+where
+    R: Resource<'h, Read = S, Write = S>,
+    S: StateMachine,
+{
+    if from.allows(to) {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "illegal transition of {} from {from:?} to {to:?}",
+            R::LABEL
+        ))
+    }
+}
+
+/// A value that can be checked for NaN and infinity.
+///
+/// Mark a resource with `#[finite]` to have the engine check every value written to it before
+/// it is stored in history:
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::resource;
+/// resource! {
+///     #[finite]
+///     pub battery_charge: f64 = 100.0
+/// }
+/// ```
+///
+/// Operations that write a non-finite value then fail, with the usual
+/// [ErrorReport][crate::ErrorReport] pointing at the activity responsible, instead of caching
+/// the value and passing it to everything downstream.
+pub trait Finite {
+    fn is_finite(&self) -> bool;
+}
+
+impl Finite for f32 {
+    fn is_finite(&self) -> bool {
+        f32::is_finite(*self)
+    }
+}
+
+impl Finite for f64 {
+    fn is_finite(&self) -> bool {
+        f64::is_finite(*self)
+    }
 }
 
+impl<T: Finite> Finite for Option<T> {
+    fn is_finite(&self) -> bool {
+        self.as_ref().is_none_or(T::is_finite)
+    }
+}
+
+impl<T: Finite, const N: usize> Finite for [T; N] {
+    fn is_finite(&self) -> bool {
+        self.iter().all(T::is_finite)
+    }
+}
+
+impl<T: Finite> Finite for Vec<T> {
+    fn is_finite(&self) -> bool {
+        self.iter().all(T::is_finite)
+    }
+}
+
+/// The [Resource::check_write] of a `#[finite]` resource.
+pub fn check_finite<'h, R>(value: &R::Write) -> Result<()>
+where
+    R: Resource<'h>,
+    R::Write: Finite,
+{
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "non-finite value {value:?} written to {}",
+            R::LABEL
+        ))
+    }
+}
+
+/// A resource with an independent timeline for each key, declared with
+/// `resource!(map name[Key]: Type)`.
+///
+/// Operations touch a single key with `ref: name[key]`, where `key` is computed when the
+/// activity is decomposed. Each key starts from the initial condition of the resource as a
+/// whole, unless it was given its own with [InitialConditions::insert_key].
+pub trait KeyedResource<'h>: Resource<'h> {
+    type Key: Hash + Debug + Send + Sync + 'static;
+}
+
+/// The id of the timeline for one key of a keyed resource.
+pub fn key_id<'h, R: KeyedResource<'h>>(key: &R::Key) -> u64 {
+    let id = PeregrineDefaultHashBuilder::default().hash_one((R::ID, key));
+    // The id of the resource itself belongs to the timeline that untouched keys share.
+    if id == R::ID { id.wrapping_add(1) } else { id }
+}
+
+/// A set of resources that are declared together, and added to a [model!][crate::model!]
+/// together: the elements of an array resource, or the fields of a struct resource.
+pub trait ResourceGroup {
+    fn init_history(history: &History);
+
+    /// Initializes the timelines of every member that `timelines` doesn't already have.
+    fn init_timelines_into<'o, M: Model<'o>>(
+        time: Instant,
+        initial_conditions: &mut InitialConditions,
+        timelines: &mut Timelines<'o, M>,
+    ) -> Result<(), EngineError>;
+
+    /// Adds the problems with the initial conditions of every member to `issues`.
+    fn check_initial_conditions(
+        initial_conditions: &InitialConditions,
+        issues: &mut Vec<InitialConditionIssue>,
+    );
+
+    /// Samples every member into `conditions`, see [Plan::conditions_at][crate::Plan::conditions_at].
+    fn sample_conditions<'o, M: Model<'o> + 'o>(
+        plan: &Plan<'o, M>,
+        time: Time,
+        conditions: &mut InitialConditions,
+    ) -> Result<(), EngineError>;
+
+    /// Adds the schema of every member to `resources`, see [ModelSchema][crate::ModelSchema].
+    fn describe(resources: &mut Vec<ResourceSchema>);
+
+    /// Views the requested members into `views`, see [Plan::view_labeled].
+    fn view_labeled<'o, M: Model<'o> + 'o>(
+        plan: &Plan<'o, M>,
+        views: &mut crate::labeled::LabeledViews<'o>,
+    ) -> Result<(), EngineError>;
+}
+
+/// The elements of an array resource, declared with `resource!(array name: [Type; N])`.
+///
+/// Each element `name<I>` is a separate resource, so operations that touch different elements
+/// don't depend on each other. This trait is implemented for every element, and lets a
+/// [model!][crate::model!] that lists `name[..]` initialize all of them.
+pub trait ResourceArray: ResourceGroup {
+    /// The number of elements.
+    const LEN: usize;
+}
+
+/// The operations on a resource's history container that don't depend on its types, for
+/// serializing, merging, and evicting whole histories.
+///
+/// [resource!][crate::resource!] implements this for every resource, and registers it with
+/// [inventory] so that a [History] can find the containers of every resource in the program.
+/// It only needs to be implemented by hand for resources that aren't declared with the macro,
+/// in terms of the resource's [HistoryContainer][crate::history::HistoryContainer].
 pub trait ResourceHistoryPlugin: Sync {
     fn write_type_string(&self) -> String;
 
@@ -150,6 +327,61 @@ pub trait ResourceHistoryPlugin: Sync {
     );
 
     fn register(&self, type_reg: &mut TypeReg<String>);
+
+    /// The [TypeId] of the history container, used to find the plugin for a resource type.
+    fn history_type_id(&self) -> TypeId;
+
+    /// The schema of the write type, for [portable][crate::history::portable] histories.
+    fn schema(&self) -> crate::history::portable::Schema;
+
+    /// Encodes all entries of this type's history container in `input`, if it is present.
+    fn encode_entries(&self, input: &TypeMap) -> Option<Vec<(u64, Vec<u8>)>>;
+
+    /// The number of entries in this type's history container in `input`.
+    fn history_len(&self, input: &TypeMap) -> usize;
+
+    /// The [memory][crate::history::HistoryContainer::memory_bytes] of this type's history
+    /// container in `input`.
+    fn history_bytes(&self, input: &TypeMap) -> usize;
+
+    /// Removes up to `count` entries from this type's history container in `input`.
+    fn evict(&self, input: &mut TypeMap, count: usize);
+
+    /// Removes up to `count` entries from this type's history container in `input` while it
+    /// is shared, as in [HistoryContainer::evict_shared][crate::history::HistoryContainer::evict_shared].
+    fn evict_shared(&self, input: &TypeMap, count: usize) -> usize;
+
+    /// The label of the resource that registered this plugin.
+    fn resource_label(&self) -> &'static str;
+
+    /// A name of the resource that registered this plugin that no other resource has: its
+    /// label, followed by its type arguments if it is generic.
+    fn resource_name(&self) -> String {
+        self.resource_label().to_string()
+    }
+
+    /// The metadata of the resource that registered this plugin.
+    fn resource_info(&self) -> ResourceInfo;
+
+    /// Decodes entries produced by [ResourceHistoryPlugin::encode_entries] into this type's
+    /// history container in `output`, creating it if it isn't there yet.
+    fn decode_entries(&self, output: &mut TypeMap, entries: Vec<(u64, Vec<u8>)>) -> Result<()>;
+
+    /// Moves this type's history container out of `from` and unions it into `into`.
+    fn merge(&self, into: &mut TypeMap, from: &mut TypeMap);
+
+    /// Registers the write type of the resource under its label, for reading
+    /// [InitialConditions] from config files.
+    fn register_initial_condition(&self, type_reg: &mut TypeReg<String>);
+
+    /// Moves the value registered with [ResourceHistoryPlugin::register_initial_condition]
+    /// out of `values`, if it is there, and into `conditions`.
+    fn take_initial_condition(
+        &self,
+        values: &mut type_reg::untagged::TypeMap<String>,
+        conditions: &mut InitialConditions,
+    );
+
     fn de<'h>(
         &self,
         output: &'h mut TypeMap,
@@ -157,8 +389,92 @@ pub trait ResourceHistoryPlugin: Sync {
     );
 }
 
+/// Descriptive information about a resource, for displaying it to users.
+///
+/// The [Display] implementation formats it like `battery_soc [%] - state of charge`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResourceInfo {
+    pub label: &'static str,
+    pub unit: Option<&'static str>,
+    pub description: Option<&'static str>,
+}
+
+impl ResourceInfo {
+    pub fn of<'h, R: Resource<'h>>() -> Self {
+        ResourceInfo {
+            label: R::LABEL,
+            unit: R::UNIT,
+            description: R::DESCRIPTION,
+        }
+    }
+}
+
+impl Display for ResourceInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label)?;
+        if let Some(unit) = self.unit {
+            write!(f, " [{unit}]")?;
+        }
+        if let Some(description) = self.description {
+            write!(f, " - {description}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The description of a resource in a [ModelSchema][crate::ModelSchema].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ResourceSchema {
+    pub label: &'static str,
+    /// The name of [Resource::Read], without lifetimes.
+    pub read_type: &'static str,
+    pub write_type: &'static str,
+    pub unit: Option<&'static str>,
+    pub description: Option<&'static str>,
+    /// See [Resource::STATIC].
+    pub is_static: bool,
+}
+
+impl ResourceSchema {
+    pub fn of<R: Resource<'static>>() -> Self {
+        ResourceSchema {
+            label: R::LABEL,
+            read_type: std::any::type_name::<R::Read>(),
+            write_type: std::any::type_name::<R::Write>(),
+            unit: R::UNIT,
+            description: R::DESCRIPTION,
+            is_static: R::STATIC,
+        }
+    }
+
+    /// Adds the schema of `R` to `resources`, unless it is already there because a submodel
+    /// shares it.
+    pub fn add<R: Resource<'static>>(resources: &mut Vec<ResourceSchema>) {
+        if !resources.iter().any(|resource| resource.label == R::LABEL) {
+            resources.push(ResourceSchema::of::<R>());
+        }
+    }
+}
+
+/// The metadata of every registered resource in the program, sorted by label.
+///
+/// Generic resources are only included if they were registered with
+/// [register_resource!][crate::register_resource!].
+pub fn registered() -> Vec<ResourceInfo> {
+    let mut infos: Vec<_> = inventory::iter::<&'static dyn ResourceHistoryPlugin>
+        .into_iter()
+        .map(|plugin| plugin.resource_info())
+        .collect();
+    infos.sort_by_key(|info| info.label);
+    infos
+}
+
 pub trait ErasedResource<'o>: 'o + Send + Sync {
     fn id(&self) -> u64;
+
+    /// Merges the operations staged by an [Append][crate::append::Append]. Only timelines
+    /// have any.
+    fn finish_append(&mut self) {}
 }
 
 impl<'o> dyn ErasedResource<'o> {
@@ -169,11 +485,6 @@ impl<'o> dyn ErasedResource<'o> {
     pub(crate) unsafe fn downcast_mut<TO: ErasedResource<'o>>(&mut self) -> &'o mut TO {
         unsafe { &mut *(self as *mut Self as *mut TO) }
     }
-    pub(crate) unsafe fn downcast_owned<TO: ErasedResource<'static> + Sized>(
-        self: Box<Self>,
-    ) -> Box<TO> {
-        unsafe { Box::from_raw(Box::into_raw(self) as *mut TO) }
-    }
 }
 
 impl<'o, R: Resource<'o>> ErasedResource<'o> for R {
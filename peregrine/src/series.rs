@@ -0,0 +1,115 @@
+//! Read-only resources backed by external time series, such as predicted solar flux.
+//!
+//! A [TimeSeries] is a list of times and values, usually read from a CSV file. Given to a plan
+//! with [insert_series][crate::InitialConditions::insert_series] or
+//! [insert_series_file][crate::InitialConditions::insert_series_file], it becomes a
+//! [boundary profile][crate::InitialConditions::insert_profile] of a resource, which
+//! activities can read but not write.
+//!
+//! The values of a series are stored in the history by their own hashes, like everything
+//! else, so a changed file only invalidates the history that depends on the values that
+//! changed. [TimeSeries::content_hash] tells whether a file changed at all, for deciding
+//! whether a plan needs to be rebuilt.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::series::TimeSeries;
+//! # resource!(solar_flux: f64);
+//! # model! { Environment(solar_flux) }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! let flux = TimeSeries::<f64>::parse_csv(
+//!     "time,flux\n\
+//!      2025-01-01T00:00:00 TAI,1361.0\n\
+//!      2025-01-01T06:00:00 TAI,1358.5\n",
+//! )?;
+//! let start = flux.points()[0].0;
+//! let plan = session.new_plan::<Environment>(start, InitialConditions::new().insert_series::<solar_flux>(flux))?;
+//! assert_eq!(1358.5, plan.sample::<solar_flux>(start + Duration::from_hours(7.0))?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::Time;
+use crate::history::PeregrineDefaultHashBuilder;
+use anyhow::{Context, Result, anyhow};
+use std::hash::BuildHasher;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Values of a resource at points in time, ordered by time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeSeries<T> {
+    points: Vec<(Time, T)>,
+    content_hash: u64,
+}
+
+impl<T> TimeSeries<T> {
+    /// The points of the series, ordered by time.
+    pub fn points(&self) -> &[(Time, T)] {
+        &self.points
+    }
+
+    pub fn into_points(self) -> Vec<(Time, T)> {
+        self.points
+    }
+
+    /// A hash of the text the series was read from.
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash
+    }
+
+    /// The latest value at or before `time`.
+    pub fn sample(&self, time: Time) -> Option<&T> {
+        let index = self.points.partition_point(|(t, _)| *t <= time);
+        index.checked_sub(1).map(|i| &self.points[i].1)
+    }
+}
+
+impl<T: FromStr> TimeSeries<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    /// Reads a series from a CSV file, see [TimeSeries::parse_csv].
+    pub fn from_csv(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read time series {}", path.display()))?;
+        Self::parse_csv(&text).with_context(|| format!("in time series {}", path.display()))
+    }
+
+    /// Reads a series from CSV text with a time and a value on each line.
+    ///
+    /// Times are [hifitime] epochs, like `2025-01-01T00:00:00 UTC`. An optional header line,
+    /// blank lines, and lines starting with `#` are skipped. The lines don't have to be in
+    /// order.
+    pub fn parse_csv(text: &str) -> Result<Self> {
+        let mut points = vec![];
+        let mut header = true;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let first = std::mem::replace(&mut header, false);
+            let (time, value) = line
+                .split_once(',')
+                .ok_or_else(|| anyhow!("line {}: expected `time,value`", number + 1))?;
+            let time = match Time::from_str(time.trim()) {
+                Ok(time) => time,
+                Err(_) if first => continue,
+                Err(e) => return Err(anyhow!("line {}: invalid time: {e}", number + 1)),
+            };
+            let value = value
+                .trim()
+                .parse()
+                .with_context(|| format!("line {}: invalid value", number + 1))?;
+            points.push((time, value));
+        }
+        points.sort_by_key(|(time, _)| *time);
+        Ok(TimeSeries {
+            points,
+            content_hash: PeregrineDefaultHashBuilder::default().hash_one(text),
+        })
+    }
+}
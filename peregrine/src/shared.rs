@@ -0,0 +1,503 @@
+//! A plan that can be viewed on some threads while it is edited on others.
+//!
+//! [Plan::insert] takes `&mut Plan`, so nothing can view a plan while it is being edited.
+//! A [SharedPlan] does both through `&self`, with a lock for each resource: a view holds the
+//! locks of the resource it views and of every resource that one depends on, and an edit
+//! holds the locks of the resources its operations write. Views and edits only wait for
+//! each other when the edit could change what the view sees.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(power: f64);
+//! # resource!(data: u32);
+//! # model! { Spacecraft(power, data) }
+//! # pub struct Heat;
+//! # impl_activity! { for Heat
+//! #     @(start) {
+//! #         ref mut: power += 10.0;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # pub struct Record;
+//! # impl_activity! { for Record
+//! #     @(start) {
+//! #         ref mut: data += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let plan = SharedPlan::new(
+//!     session.new_plan::<Spacecraft>(start, initial_conditions! { power: 0.0, data: 0 })?,
+//! );
+//! plan.insert(start + Duration::from_seconds(1.0), Heat)?;
+//!
+//! std::thread::scope(|scope| {
+//!     // Viewing power doesn't wait for edits that only write data.
+//!     scope.spawn(|| plan.view::<power>(start..).unwrap());
+//!     plan.insert(start + Duration::from_seconds(2.0), Record).unwrap();
+//! });
+//!
+//! assert_eq!(1, plan.sample::<data>(start + Duration::from_seconds(3.0))?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The dependencies between resources come from the [OpInfo] of the operations in the plan.
+//! Operations that are placed dynamically also depend on whatever was read to place them,
+//! which isn't known until they are simulated, so views that depend on them wait for all
+//! edits and other views to finish instead.
+//!
+//! An interactive tool can also run [SharedPlan::speculate] on a thread of its own. After
+//! every edit, it simulates the stale parts of the windows most recently viewed of the
+//! resources that are viewed most often, so that the next view of them usually finds
+//! everything cached. Speculation runs in the [background lane][crate::exec::Lane], so it
+//! only starts while no views are running, and its tasks never queue ahead of theirs. It holds
+//! the same locks as a view, so an edit that writes a resource being speculated on waits until
+//! that resource is done.
+
+use crate::activity::ActivityId;
+use crate::exec::{Lane, UnsafeSyncCell};
+use crate::operation::OpInfo;
+use crate::resource::Resource;
+use crate::subscription::TimeRange;
+use crate::{Activity, EngineError, Model, Plan, PlanAccess, Time};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut, RangeBounds};
+use std::sync::Arc;
+
+/// A [Plan] that can be viewed and edited from several threads at once. See the
+/// [module documentation][self].
+pub struct SharedPlan<'o, M: Model<'o>> {
+    plan: UnsafeSyncCell<Plan<'o, M>>,
+    /// Held for the whole of every edit, so that edits happen one at a time.
+    edits: Mutex<()>,
+    /// Held shared by views and edits that only need their resource locks, and exclusively
+    /// by those that need the whole plan.
+    structure: RwLock<()>,
+    /// Held shared by views that depend on a resource, and exclusively by edits that write it.
+    resources: Mutex<HashMap<&'static str, Arc<RwLock<()>>>>,
+    graph: Mutex<ResourceGraph>,
+    speculation: Mutex<Speculation<'o, M>>,
+    /// Notified after every edit, and when speculation is stopped.
+    edited: Condvar,
+}
+
+/// Which resources [SharedPlan::speculate] simulates after edits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpeculationOptions {
+    /// How many times a resource has to have been viewed to be simulated.
+    pub min_views: usize,
+    /// How many of the most viewed resources are simulated after each edit.
+    pub max_resources: usize,
+}
+
+impl Default for SpeculationOptions {
+    fn default() -> Self {
+        SpeculationOptions {
+            min_views: 2,
+            max_resources: 8,
+        }
+    }
+}
+
+struct Speculation<'o, M: Model<'o>> {
+    /// Counts the edits, so that speculation knows when there is something new.
+    edits: u64,
+    stopped: bool,
+    viewed: HashMap<&'static str, Viewed<'o, M>>,
+}
+
+/// How often a resource has been viewed, the window of its last view, and how to simulate it.
+struct Viewed<'o, M: Model<'o>> {
+    views: usize,
+    window: TimeRange,
+    simulate: fn(&SharedPlan<'o, M>, TimeRange),
+}
+
+impl<'o, M: Model<'o> + 'o> SharedPlan<'o, M> {
+    pub fn new(plan: Plan<'o, M>) -> Self {
+        SharedPlan {
+            graph: Mutex::new(ResourceGraph::new(&plan)),
+            plan: UnsafeSyncCell::new(plan),
+            edits: Mutex::new(()),
+            structure: RwLock::new(()),
+            resources: Mutex::default(),
+            speculation: Mutex::new(Speculation {
+                edits: 0,
+                stopped: false,
+                viewed: HashMap::new(),
+            }),
+            edited: Condvar::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> Plan<'o, M> {
+        self.plan.into_inner()
+    }
+
+    fn plan(&self) -> &Plan<'o, M> {
+        unsafe { &*self.plan.get() }
+    }
+
+    /// Like [Plan::insert].
+    pub fn insert(
+        &self,
+        time: Time,
+        activity: impl Activity<'o, M> + 'static,
+    ) -> Result<ActivityId, EngineError> {
+        let _edit = self.edits.lock();
+        let decomposition = self.plan().decompose(time, Box::new(activity))?;
+        let operations: Vec<OpInfo> = decomposition.2.iter().map(|op| op.info()).collect();
+        let id = self.write_locked(&operations, true, |plan| unsafe {
+            plan.insert_decomposed(time, decomposition, None)
+        })?;
+        self.notify_subscriptions();
+        Ok(id)
+    }
+
+    /// Like [Plan::insert_with_key].
+    pub fn insert_with_key(
+        &self,
+        key: impl Into<String>,
+        time: Time,
+        activity: impl Activity<'o, M> + 'static,
+    ) -> Result<ActivityId, EngineError> {
+        let key = key.into();
+        let _edit = self.edits.lock();
+        if self.plan().keys.contains_key(&key) {
+            return Err(EngineError::DuplicateKey(key));
+        }
+        let decomposition = self.plan().decompose(time, Box::new(activity))?;
+        let operations: Vec<OpInfo> = decomposition.2.iter().map(|op| op.info()).collect();
+        let id = self.write_locked(&operations, true, |plan| unsafe {
+            plan.insert_decomposed(time, decomposition, Some(key))
+        })?;
+        self.notify_subscriptions();
+        Ok(id)
+    }
+
+    /// Like [Plan::remove].
+    pub fn remove(&self, id: ActivityId) -> Result<(), EngineError> {
+        let _edit = self.edits.lock();
+        let span = self
+            .plan()
+            .span(id)
+            .ok_or(EngineError::ActivityNotFound(id))?;
+        self.write_locked(&span.operations, false, |plan| {
+            let anchor = plan.anchor_of(id);
+            unsafe {
+                plan.reanchor_dependents(id, anchor);
+                plan.remove_batch(&[id])
+            }
+        })?;
+        self.notify_subscriptions();
+        Ok(())
+    }
+
+    /// Subscriptions may view anything, so they are checked while nothing else runs.
+    fn notify_subscriptions(&self) {
+        if !self.plan().subscriptions.lock().is_empty() {
+            let _exclusive = self.structure.write();
+            unsafe { &mut *self.plan.get() }.notify_subscriptions();
+        }
+        self.speculate_after_edit();
+    }
+
+    /// Makes any other change to the plan, waiting for all views to finish first, and
+    /// blocking new ones until it is done.
+    pub fn edit<T>(&self, edit: impl FnOnce(&mut Plan<'o, M>) -> T) -> T {
+        let _edit = self.edits.lock();
+        let _exclusive = self.structure.write();
+        let plan = unsafe { &mut *self.plan.get() };
+        let result = edit(plan);
+        *self.graph.lock() = ResourceGraph::new(plan);
+        self.speculate_after_edit();
+        result
+    }
+
+    /// Like [Plan::view].
+    pub fn view<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Vec<(Time, R::Read)>, EngineError> {
+        let window = (bounds.start_bound().cloned(), bounds.end_bound().cloned());
+        self.speculation
+            .lock()
+            .viewed
+            .entry(R::LABEL)
+            .and_modify(|viewed| {
+                viewed.views += 1;
+                viewed.window = window;
+            })
+            .or_insert(Viewed {
+                views: 1,
+                window,
+                simulate: Self::simulate_stale::<R>,
+            });
+
+        let (results, errors) = self.read_locked(R::LABEL, |plan| {
+            plan.simulate::<R>(R::ID, window, None, None, Lane::Foreground)
+        })?;
+        // Failed operations are found by looking through the activities, which edits change.
+        let _edit = (!errors.is_empty()).then(|| self.edits.lock());
+        Plan::<M>::collect_view::<R>(self.plan().report::<R>(results, errors))
+    }
+
+    /// Like [Plan::sample].
+    pub fn sample<R: Resource<'o> + 'o>(&self, time: Time) -> Result<R::Read, EngineError> {
+        Ok(self
+            .view::<R>(time..=time)?
+            .last()
+            .ok_or(EngineError::NothingToSample(time))?
+            .1)
+    }
+
+    /// Simulates the most viewed resources after every edit, as described in the
+    /// [module documentation][self], until [SharedPlan::stop_speculating] is called. Blocks,
+    /// so it is usually run on a thread of its own, and starts with whatever is already stale.
+    ///
+    /// The simulated values are only cached; errors are left for the next view to report.
+    pub fn speculate(&self, options: SpeculationOptions) {
+        let mut speculation = self.speculation.lock();
+        // Whatever is already stale is simulated right away.
+        let mut seen = None;
+        loop {
+            while !speculation.stopped && seen == Some(speculation.edits) {
+                self.edited.wait(&mut speculation);
+            }
+            if speculation.stopped {
+                speculation.stopped = false;
+                return;
+            }
+            let edits = speculation.edits;
+            seen = Some(edits);
+
+            let mut viewed: Vec<_> = speculation
+                .viewed
+                .values()
+                .filter(|viewed| viewed.views >= options.min_views)
+                .map(|viewed| (viewed.views, viewed.window, viewed.simulate))
+                .collect();
+            viewed.sort_by_key(|(views, ..)| Reverse(*views));
+            viewed.truncate(options.max_resources);
+
+            MutexGuard::unlocked(&mut speculation, || {
+                for (_, window, simulate) in viewed {
+                    // After another edit, the most viewed resources are simulated first again.
+                    let speculation = self.speculation.lock();
+                    if speculation.edits != edits || speculation.stopped {
+                        break;
+                    }
+                    drop(speculation);
+                    simulate(self, window);
+                }
+            });
+        }
+    }
+
+    /// Makes [SharedPlan::speculate] return after the resource it is simulating, or right away
+    /// when it is next called if it isn't running.
+    pub fn stop_speculating(&self) {
+        self.speculation.lock().stopped = true;
+        self.edited.notify_all();
+    }
+
+    fn speculate_after_edit(&self) {
+        self.speculation.lock().edits += 1;
+        self.edited.notify_all();
+    }
+
+    /// Simulates the stale parts of `window` of `R`, and throws the values away.
+    fn simulate_stale<R: Resource<'o> + 'o>(&self, window: TimeRange) {
+        Lane::wait_for_foreground();
+        self.read_locked(R::LABEL, |plan| {
+            for range in plan.stale_ranges::<R>(R::ID, window) {
+                let _ = plan.simulate::<R>(R::ID, range, None, None, Lane::Background);
+            }
+        });
+    }
+
+    /// Runs an edit on the operations described by `operations`, holding the locks of every
+    /// resource they write.
+    fn write_locked<T>(
+        &self,
+        operations: &[OpInfo],
+        inserting: bool,
+        edit: impl FnOnce(&Plan<'o, M>) -> Result<T, EngineError>,
+    ) -> Result<T, EngineError> {
+        let _structure = self.structure.read();
+        let writes: BTreeSet<_> = operations
+            .iter()
+            .flat_map(|op| op.writes)
+            .copied()
+            .collect();
+        let locks = self.resource_locks(writes);
+        let _guards: Vec<_> = locks.iter().map(|lock| lock.write()).collect();
+
+        // The edit only changes the activities, which views don't read, and the timelines of
+        // the resources it holds locks for, each of which is in a cell of its own.
+        let result = edit(self.plan())?;
+
+        let mut graph = self.graph.lock();
+        for op in operations {
+            graph.update(op, inserting);
+        }
+        Ok(result)
+    }
+
+    /// Runs a view of the resource `label`, holding the locks of every resource it depends on.
+    fn read_locked<T>(&self, label: &'static str, view: impl FnOnce(&Plan<'o, M>) -> T) -> T {
+        loop {
+            let structure = self.structure.read();
+            let Some(cone) = self.graph.lock().cone(label) else {
+                drop(structure);
+                let _exclusive = self.structure.write();
+                return view(self.plan());
+            };
+            let locks = self.resource_locks(cone.iter().copied());
+            let _guards: Vec<_> = locks.iter().map(|lock| lock.read()).collect();
+
+            // An edit may have added dependencies while the locks were being taken. Once they
+            // are all held, nothing can add more.
+            if self.graph.lock().cone(label).as_ref() == Some(&cone) {
+                return view(self.plan());
+            }
+        }
+    }
+
+    /// The locks for the given resources, which are taken in the order given. Every caller
+    /// passes them sorted by label, so that views and edits can't deadlock.
+    fn resource_locks(
+        &self,
+        labels: impl IntoIterator<Item = &'static str>,
+    ) -> Vec<Arc<RwLock<()>>> {
+        let mut resources = self.resources.lock();
+        labels
+            .into_iter()
+            .map(|label| resources.entry(label).or_default().clone())
+            .collect()
+    }
+}
+
+/// A field of [Plan] that a [SharedPlan] edits through a shared reference to the plan. Views
+/// don't read it, so it can change while they run; everything that does read it holds the
+/// edit lock.
+pub(crate) struct EditCell<T>(UnsafeSyncCell<T>);
+
+impl<T> EditCell<T> {
+    pub(crate) fn new(value: T) -> Self {
+        EditCell(UnsafeSyncCell::new(value))
+    }
+
+    /// # Safety
+    ///
+    /// Nothing else can read the value until the reference is dropped.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn edit(&self) -> &mut T {
+        unsafe { &mut *self.0.get() }
+    }
+}
+
+impl<T> Deref for EditCell<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.get() }
+    }
+}
+
+impl<T> DerefMut for EditCell<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.get() }
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> From<Plan<'o, M>> for SharedPlan<'o, M> {
+    fn from(plan: Plan<'o, M>) -> Self {
+        SharedPlan::new(plan)
+    }
+}
+
+impl<'o, M: Model<'o> + 'o> PlanAccess<'o> for SharedPlan<'o, M> {
+    fn view_resource<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Vec<(Time, R::Read)>, EngineError> {
+        self.view::<R>(bounds)
+    }
+    fn sample_resource<R: Resource<'o> + 'o>(&self, time: Time) -> Result<R::Read, EngineError> {
+        self.sample::<R>(time)
+    }
+}
+
+/// Which resources each resource depends on, according to the operations in the plan.
+#[derive(Default)]
+struct ResourceGraph {
+    /// For each resource, how many of the operations that write it read each other
+    /// resource. Operations that write several resources count as reading all of them,
+    /// since evaluating the operation for one of them produces the others too.
+    inputs: HashMap<&'static str, HashMap<&'static str, usize>>,
+    /// How many dynamically placed operations write each resource.
+    dynamic: HashMap<&'static str, usize>,
+}
+
+impl ResourceGraph {
+    fn new<'o, M: Model<'o>>(plan: &Plan<'o, M>) -> Self {
+        let mut graph = ResourceGraph::default();
+        for decomposed in plan.activities.values() {
+            for op in &decomposed.operations {
+                graph.update(&op.info(), true);
+            }
+        }
+        graph
+    }
+
+    fn update(&mut self, op: &OpInfo, inserting: bool) {
+        for write in op.writes {
+            let inputs = self.inputs.entry(write).or_default();
+            for input in op.reads.iter().chain(op.writes) {
+                count(inputs, input, inserting);
+            }
+            if op.dynamic {
+                count(&mut self.dynamic, write, inserting);
+            }
+        }
+    }
+
+    /// The resources whose operations a view of `label` might evaluate, including itself, or
+    /// `None` if that can't be known without simulating.
+    fn cone(&self, label: &'static str) -> Option<BTreeSet<&'static str>> {
+        let mut cone = BTreeSet::from([label]);
+        let mut stack = vec![label];
+        while let Some(resource) = stack.pop() {
+            if self.dynamic.contains_key(resource) {
+                return None;
+            }
+            for &input in self.inputs.get(resource).into_iter().flat_map(|i| i.keys()) {
+                if cone.insert(input) {
+                    stack.push(input);
+                }
+            }
+        }
+        Some(cone)
+    }
+}
+
+fn count<K: Hash + Eq>(counts: &mut HashMap<K, usize>, key: K, inserting: bool) {
+    match (counts.entry(key), inserting) {
+        (entry, true) => *entry.or_default() += 1,
+        (Entry::Occupied(mut entry), false) => {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+        (Entry::Vacant(_), false) => {}
+    }
+}
@@ -0,0 +1,123 @@
+//! Immutable copies of a plan, for reading while the plan itself keeps changing.
+//!
+//! A UI can render a [PlanSnapshot] on another thread while the plan is edited and resimulated,
+//! and swap to a new snapshot when it is ready. Activities are shared between snapshots and the
+//! plan, so taking a snapshot only copies the activities that changed since the last one.
+//!
+//! ```
+//! # use peregrine::*;
+//! # resource!(counter: u32);
+//! # model! { Counter(counter) }
+//! # pub struct Increment;
+//! # impl_activity! { for Increment
+//! #     @(start) {
+//! #         ref mut: counter += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Counter>(start, initial_conditions! { counter: 0 })?;
+//! plan.insert(start + Duration::from_seconds(1.0), Increment)?;
+//!
+//! let snapshot = plan.snapshot().with_view::<counter, _>(&plan, start..)?;
+//! plan.insert(start + Duration::from_seconds(2.0), Increment)?;
+//!
+//! assert_eq!(1, snapshot.activities().len());
+//! assert_eq!(1, snapshot.view::<counter>().unwrap().last().unwrap().1);
+//! assert_eq!(2, plan.sample::<counter>(start + Duration::from_seconds(3.0))?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::activity::{ActivityId, ActivityMetadata};
+use crate::resource::{ErasedResource, Resource};
+use crate::{ActivitySpan, EngineError, Model, Plan, Time};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+/// An activity in a [PlanSnapshot].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotActivity {
+    pub span: ActivitySpan,
+    pub key: Option<String>,
+    pub metadata: ActivityMetadata,
+}
+
+pub(crate) type SnapshotIndex = Arc<BTreeMap<ActivityId, Arc<SnapshotActivity>>>;
+
+/// An immutable copy of a plan's activities, and of any resource views captured with it.
+/// Taken with [Plan::snapshot].
+#[derive(Clone)]
+pub struct PlanSnapshot<'o> {
+    start: Time,
+    activities: SnapshotIndex,
+    /// The generation of the plan when the snapshot was taken.
+    generation: u64,
+    views: HashMap<u64, Arc<dyn ErasedResource<'o>>>,
+}
+
+struct CapturedView<'o, R: Resource<'o>>(Vec<(Time, R::Read)>);
+
+impl<'o, R: Resource<'o>> ErasedResource<'o> for CapturedView<'o, R> {
+    fn id(&self) -> u64 {
+        R::ID
+    }
+}
+
+impl<'o> PlanSnapshot<'o> {
+    pub(crate) fn new(start: Time, activities: SnapshotIndex, generation: u64) -> Self {
+        PlanSnapshot {
+            start,
+            activities,
+            generation,
+            views: HashMap::new(),
+        }
+    }
+
+    /// The start of the plan, where the initial conditions are.
+    pub fn start(&self) -> Time {
+        self.start
+    }
+
+    pub fn activity(&self, id: ActivityId) -> Option<&SnapshotActivity> {
+        self.activities.get(&id).map(|a| &**a)
+    }
+
+    /// All activities in the snapshot, ordered by start time.
+    pub fn activities(&self) -> Vec<&SnapshotActivity> {
+        let mut activities: Vec<_> = self.activities.values().map(|a| &**a).collect();
+        activities.sort_by_key(|a| (a.span.start, a.span.id));
+        activities
+    }
+
+    /// Views `R` on `plan` and keeps the result in the snapshot, replacing any earlier view
+    /// of it.
+    ///
+    /// `plan` must be the plan the snapshot was taken from. Returns
+    /// [EngineError::StaleSnapshot] if it has been edited since.
+    pub fn with_view<R: Resource<'o> + 'o, M: Model<'o> + 'o>(
+        mut self,
+        plan: &Plan<'o, M>,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Self, EngineError> {
+        if plan.generation() != self.generation {
+            return Err(EngineError::StaleSnapshot);
+        }
+        let values = plan.view::<R>(bounds)?;
+        self.views
+            .insert(R::ID, Arc::new(CapturedView::<R>(values)));
+        Ok(self)
+    }
+
+    /// The view of `R` captured with [PlanSnapshot::with_view], if there is one.
+    pub fn view<R: Resource<'o>>(&self) -> Option<&[(Time, R::Read)]> {
+        let view = self.views.get(&R::ID)?;
+        // SAFETY: views are only inserted by `with_view::<R>`, under `R::ID`, and resource IDs
+        // are unique per resource type, so the view behind `R::ID` is a `CapturedView<'o, R>`.
+        let view = unsafe { &*(Arc::as_ptr(view) as *const CapturedView<'o, R>) };
+        Some(&view.0)
+    }
+}
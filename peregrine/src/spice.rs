@@ -0,0 +1,190 @@
+//! Ephemeris geometry from SPICE kernels, read with [ANISE](https://github.com/nyx-space/anise).
+//!
+//! An [Ephemeris] answers geometry questions, like where the spacecraft is or whether the Sun
+//! is hidden behind the Earth, at any [Time]. There are two ways to use it in a model:
+//!
+//! - Sample it ahead of time with [Ephemeris::profile], and seed a resource with the result
+//!   using [InitialConditions::insert_profile][crate::InitialConditions::insert_profile].
+//!   Activities can read the resource, but not write it.
+//! - Query it inside an operation at `now`, the time the operation happens at, for geometry that
+//!   is needed at exact times rather than on a grid. Ephemerides are `Send + Sync`, so they can
+//!   be shared through a static or an [Arc][std::sync::Arc] held by the activity. Operations
+//!   must be deterministic, so the kernels must not change while plans use them.
+//!
+//! ```no_run
+//! # use peregrine::*;
+//! # use peregrine::spice::Ephemeris;
+//! # use std::sync::LazyLock;
+//! resource!(sun_angle: f64);
+//! resource!(in_eclipse: bool);
+//! model! { Geometry(sun_angle, in_eclipse) }
+//!
+//! const SPACECRAFT: i32 = -85;
+//! const EARTH: i32 = 399;
+//! const SUN: i32 = 10;
+//!
+//! static EPHEMERIS: LazyLock<Ephemeris> =
+//!     LazyLock::new(|| Ephemeris::load(["de440s.bsp", "spacecraft.bsp"]).unwrap());
+//!
+//! pub struct PointAtSun;
+//! impl_activity! { for PointAtSun
+//!     @(start) {
+//!         mut: sun_angle = if ref: in_eclipse {
+//!             180.0
+//!         } else {
+//!             EPHEMERIS.sun_angle(EARTH, SPACECRAFT, now)?
+//!         };
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! let start = Time::from_gregorian_utc_at_midnight(2030, 1, 1);
+//! let eclipses = EPHEMERIS.profile(
+//!     start,
+//!     start + Duration::from_days(7.0),
+//!     Duration::from_seconds(60.0),
+//!     |ephemeris, time| ephemeris.occulted(SUN, EARTH, SPACECRAFT, time),
+//! )?;
+//! let initial_conditions = initial_conditions! { sun_angle: 0.0 }.insert_profile::<in_eclipse>(eclipses);
+//! let plan = session.new_plan::<Geometry>(start, initial_conditions)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Duration, Time};
+use anise::almanac::Almanac;
+use anise::astro::Aberration;
+use anise::prelude::Frame;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+pub use anise;
+
+/// A NAIF ID of a body, like `399` for the Earth or a negative ID for a spacecraft.
+pub type NaifId = i32;
+
+/// The kernels loaded for a run. See the [module documentation][self].
+#[derive(Clone)]
+pub struct Ephemeris {
+    almanac: Almanac,
+    aberration: Option<Aberration>,
+}
+
+impl Ephemeris {
+    /// Loads SPICE kernels (SPK, BPC, and text PCK files) or ANISE files.
+    pub fn load(paths: impl IntoIterator<Item = impl AsRef<Path>>) -> Result<Self> {
+        let mut almanac = Almanac::default();
+        for path in paths {
+            let path = path.as_ref();
+            let name = path.to_str().context("kernel paths must be valid UTF-8")?;
+            almanac = almanac
+                .load(name)
+                .with_context(|| format!("could not load kernel {}", path.display()))?;
+        }
+        Ok(Self::from_almanac(almanac))
+    }
+
+    /// Wraps an almanac that was already loaded.
+    pub fn from_almanac(almanac: Almanac) -> Self {
+        Ephemeris {
+            almanac,
+            aberration: None,
+        }
+    }
+
+    /// Applies an aberration correction to every query. None is applied by default.
+    pub fn with_aberration(mut self, aberration: Option<Aberration>) -> Self {
+        self.aberration = aberration;
+        self
+    }
+
+    pub fn almanac(&self) -> &Almanac {
+        &self.almanac
+    }
+
+    /// The position of `target` relative to `observer` in the J2000 frame, in kilometers.
+    pub fn position(&self, target: NaifId, observer: NaifId, time: Time) -> Result<[f64; 3]> {
+        let state = self
+            .almanac
+            .translate(
+                Frame::from_ephem_j2000(target),
+                Frame::from_ephem_j2000(observer),
+                time,
+                self.aberration,
+            )
+            .with_context(|| format!("could not find {target} from {observer} at {time}"))?;
+        Ok(state.radius_km.into())
+    }
+
+    /// The distance between `target` and `observer`, in kilometers.
+    pub fn distance(&self, target: NaifId, observer: NaifId, time: Time) -> Result<f64> {
+        let [x, y, z] = self.position(target, observer, time)?;
+        Ok((x * x + y * y + z * z).sqrt())
+    }
+
+    /// The angle between `target` and the Sun as seen from `observer`, in degrees.
+    pub fn sun_angle(&self, target: NaifId, observer: NaifId, time: Time) -> Result<f64> {
+        self.almanac
+            .sun_angle_deg(target, observer, time, self.aberration)
+            .with_context(|| format!("could not find the sun angle of {target} at {time}"))
+    }
+
+    /// Whether any of `back` is hidden behind `front` as seen from `observer`. With the Sun in
+    /// the back, this is whether the observer is in eclipse, including the penumbra.
+    pub fn occulted(
+        &self,
+        back: NaifId,
+        front: NaifId,
+        observer: NaifId,
+        time: Time,
+    ) -> Result<bool> {
+        let front_frame = Frame::from_ephem_j2000(front);
+        let observer = self
+            .almanac
+            .translate(
+                Frame::from_ephem_j2000(observer),
+                front_frame,
+                time,
+                self.aberration,
+            )
+            .with_context(|| format!("could not find {observer} at {time}"))?;
+        let occultation = self
+            .almanac
+            .occultation(
+                Frame::from_ephem_j2000(back),
+                front_frame,
+                observer,
+                self.aberration,
+            )
+            .with_context(|| format!("could not find the occultation of {back} at {time}"))?;
+        Ok(!occultation.is_visible())
+    }
+
+    /// Samples `query` every `step` from `start` up to and including `end`, as a profile for
+    /// [InitialConditions::insert_profile][crate::InitialConditions::insert_profile]. Points
+    /// with the same value as the one before are left out.
+    pub fn profile<T: PartialEq>(
+        &self,
+        start: Time,
+        end: Time,
+        step: Duration,
+        mut query: impl FnMut(&Self, Time) -> Result<T>,
+    ) -> Result<Vec<(Time, T)>> {
+        anyhow::ensure!(
+            step > Duration::ZERO,
+            "the step of a profile must be positive"
+        );
+        let mut profile: Vec<(Time, T)> = vec![];
+        let mut time = start;
+        while time <= end {
+            let value = query(self, time)?;
+            if profile.last().is_none_or(|(_, last)| *last != value) {
+                profile.push((time, value));
+            }
+            time += step;
+        }
+        Ok(profile)
+    }
+}
@@ -0,0 +1,463 @@
+//! Live updates of a plan's resources, pushed to web clients as
+//! [server-sent events](https://html.spec.whatwg.org/multipage/server-sent-events.html).
+//!
+//! A planning UI that polls [Plan::view] either lags behind edits or simulates far more often
+//! than it needs to. Instead, an [EventServer] accepts browser `EventSource` connections, and
+//! [Plan::stream] subscribes a resource's window to its [Publisher]. After every edit that
+//! makes part of the window stale, connected clients get an `invalidated` event with the
+//! stale ranges, and then a `values` event with the new values of each range:
+//!
+//! ```text
+//! event: invalidated
+//! data: {"resource":"battery","ranges":[{"start":"2030-01-01T01:00:00.000000000Z","end":null}]}
+//!
+//! event: values
+//! data: {"resource":"battery","start":"2030-01-01T01:00:00.000000000Z","end":null,"points":[{"time":"2030-01-01T01:00:00.000000000Z","value":90.0}]}
+//! ```
+//!
+//! Times are UTC, and an `end` of `null` means unbounded. If a range can't be simulated, an
+//! `error` event with the resource and a `message` is sent instead of its values. Clients
+//! connect to `/events`, or to `/events?resources=battery,mode` to only get events for some
+//! resources. Events are only pushed for edits, so a client should view the window itself
+//! when it connects. Nothing is simulated while no clients are connected.
+//!
+//! Publishing only queues the events, so edits never wait on the network. A client that falls
+//! so far behind that its queue fills up is disconnected, and should reconnect and view the
+//! window again. Browsers only let pages from other origins connect if they are allowed with
+//! [EventServer::with_allowed_origin].
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::stream::EventServer;
+//! # use std::io::{BufRead, BufReader, Write};
+//! # resource!(streamed_battery: f64);
+//! # model! { Streamed(streamed_battery) }
+//! # pub struct Drain;
+//! # impl_activity! { for Drain
+//! #     @(start) {
+//! #         ref mut: streamed_battery -= 10.0;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Streamed>(start, initial_conditions! { streamed_battery: 100.0 })?;
+//! plan.view::<streamed_battery>(start..)?;
+//!
+//! let server = EventServer::bind("127.0.0.1:0")?;
+//! let address = server.local_addr()?;
+//! plan.stream::<streamed_battery>(start.., &server.publisher());
+//! server.spawn();
+//!
+//! let mut client = std::net::TcpStream::connect(address)?;
+//! client.write_all(b"GET /events HTTP/1.1\r\n\r\n")?;
+//! let mut client = BufReader::new(client);
+//! let mut line = String::new();
+//! while line != "\r\n" {
+//!     line.clear();
+//!     client.read_line(&mut line)?;
+//! }
+//!
+//! plan.insert(start + Duration::from_seconds(1.0), Drain)?;
+//! let events: Vec<String> = client.lines().take(6).collect::<Result<_, _>>()?;
+//! assert_eq!("event: invalidated", events[0]);
+//! assert_eq!("event: values", events[3]);
+//! assert!(events[4].contains(r#""value":90.0"#));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::commands::iso_utc;
+use crate::resource::Resource;
+use crate::subscription::{ErasedSubscription, SubscriptionId, TimeRange, contains};
+use crate::{Model, Plan, Time};
+use anyhow::Result;
+use crossbeam::channel::{Sender, bounded};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// An event sent to the clients of an [EventServer]. See [stream][crate::stream].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Event {
+    Invalidated {
+        resource: &'static str,
+        ranges: Vec<Range>,
+    },
+    Values {
+        resource: &'static str,
+        #[serde(flatten)]
+        range: Range,
+        points: Vec<Point>,
+    },
+    Error {
+        resource: &'static str,
+        message: String,
+    },
+}
+
+/// A span of time in an [Event], in UTC. `None` means unbounded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Range {
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Point {
+    pub time: String,
+    pub value: serde_json::Value,
+}
+
+impl Event {
+    /// The name of the event, which clients listen for.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Invalidated { .. } => "invalidated",
+            Event::Values { .. } => "values",
+            Event::Error { .. } => "error",
+        }
+    }
+
+    pub fn resource(&self) -> &'static str {
+        match self {
+            Event::Invalidated { resource, .. }
+            | Event::Values { resource, .. }
+            | Event::Error { resource, .. } => resource,
+        }
+    }
+}
+
+impl From<TimeRange> for Range {
+    fn from((start, end): TimeRange) -> Self {
+        let time = |bound: Bound<Time>| match bound {
+            Bound::Included(time) | Bound::Excluded(time) => Some(iso_utc(time)),
+            Bound::Unbounded => None,
+        };
+        Range {
+            start: time(start),
+            end: time(end),
+        }
+    }
+}
+
+/// A connected client, whose events are written by its own thread so that a slow client
+/// can't hold up the others or the plan.
+struct Client {
+    queue: Sender<Arc<str>>,
+    /// Kept so that a client that is dropped for falling behind is disconnected right away,
+    /// instead of when its thread next writes.
+    stream: TcpStream,
+    /// The resources the client asked for, or `None` for all of them.
+    resources: Option<Vec<String>>,
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+type Clients = Mutex<Vec<Client>>;
+
+/// Sends events to every client of an [EventServer] that wants them. Cheap to clone.
+#[derive(Clone)]
+pub struct Publisher {
+    clients: Arc<Clients>,
+}
+
+impl Publisher {
+    /// Queues an event for the clients that asked for its resource, and drops the clients that
+    /// have disconnected or whose queue is full. Never waits on the network.
+    pub fn publish(&self, event: &Event) {
+        let Ok(data) = serde_json::to_string(event) else {
+            return;
+        };
+        let message: Arc<str> = format!("event: {}\ndata: {data}\n\n", event.name()).into();
+        self.clients.lock().retain(|client| {
+            let wanted = client
+                .resources
+                .as_ref()
+                .is_none_or(|resources| resources.iter().any(|r| r == event.resource()));
+            !wanted || client.queue.try_send(message.clone()).is_ok()
+        });
+    }
+
+    /// The number of connected clients, as of the last event.
+    pub fn clients(&self) -> usize {
+        self.clients.lock().len()
+    }
+}
+
+/// An HTTP server that keeps `/events` connections open and streams [Event]s to them.
+///
+/// Each connection has its own thread, so the number of connections is limited (see
+/// [EventServer::with_max_connections]), and connections beyond the limit are refused with a
+/// `503`. Requests have to arrive within [EventServer::TIMEOUT], and clients that stop reading
+/// are dropped once [EventServer::QUEUE_CAPACITY] events are waiting for them.
+pub struct EventServer {
+    listener: TcpListener,
+    clients: Arc<Clients>,
+    connections: Arc<AtomicUsize>,
+    max_connections: usize,
+    allowed_origin: Option<Arc<str>>,
+}
+
+/// Counts a connection against [EventServer::with_max_connections] until it is dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl EventServer {
+    /// How long reading a request or writing an event can take before the connection is
+    /// dropped.
+    pub const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+    /// The largest request line and headers the server reads, in bytes.
+    pub const MAX_REQUEST_SIZE: u64 = 8 << 10;
+    /// The number of events that can wait for a client before it is dropped.
+    pub const QUEUE_CAPACITY: usize = 1024;
+    /// The number of connections [EventServer::bind] allows at once.
+    pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self> {
+        Ok(EventServer {
+            listener: TcpListener::bind(address)?,
+            clients: Arc::default(),
+            connections: Arc::default(),
+            max_connections: Self::DEFAULT_MAX_CONNECTIONS,
+            allowed_origin: None,
+        })
+    }
+
+    /// Limits the connections that are open at once, including the ones still sending their
+    /// request.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Lets pages from `origin` (or any origin, for `*`) connect, through the
+    /// `Access-Control-Allow-Origin` header. Without it, browsers only let pages served from
+    /// the server's own origin connect.
+    ///
+    /// # Panics
+    ///
+    /// If `origin` contains a line break.
+    pub fn with_allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        let origin = origin.into();
+        assert!(
+            !origin.contains(['\r', '\n']),
+            "an allowed origin can't contain line breaks"
+        );
+        self.allowed_origin = Some(origin.into());
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn publisher(&self) -> Publisher {
+        Publisher {
+            clients: self.clients.clone(),
+        }
+    }
+
+    /// Accepts connections forever, handling each one on its own thread.
+    pub fn serve(self) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let mut stream = stream?;
+            if self.connections.fetch_add(1, Ordering::Relaxed) >= self.max_connections {
+                self.connections.fetch_sub(1, Ordering::Relaxed);
+                let _ = stream.set_write_timeout(Some(Self::TIMEOUT));
+                let _ = stream.write_all(
+                    b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+                continue;
+            }
+            let connection = Connection(self.connections.clone());
+            let clients = self.clients.clone();
+            let allowed_origin = self.allowed_origin.clone();
+            thread::spawn(move || {
+                Self::handle(stream, &clients, allowed_origin.as_deref(), connection)
+            });
+        }
+        Ok(())
+    }
+
+    /// Serves connections on a background thread.
+    pub fn spawn(self) -> thread::JoinHandle<Result<()>> {
+        thread::spawn(move || self.serve())
+    }
+
+    fn handle(
+        mut stream: TcpStream,
+        clients: &Clients,
+        allowed_origin: Option<&str>,
+        _connection: Connection,
+    ) -> std::io::Result<()> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Self::TIMEOUT))?;
+        stream.set_write_timeout(Some(Self::TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?).take(Self::MAX_REQUEST_SIZE);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        let mut header = String::new();
+        let mut complete = false;
+        while reader.read_line(&mut header)? > 0 {
+            if header.trim_end().is_empty() && header.ends_with('\n') {
+                complete = true;
+                break;
+            }
+            header.clear();
+        }
+        if !complete {
+            return stream.write_all(
+                b"HTTP/1.1 431 Request Header Fields Too Large\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+        }
+
+        let target = request.split_whitespace().nth(1).unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        if !request.starts_with("GET ") || path != "/events" {
+            return stream.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+        }
+        let resources = query
+            .split('&')
+            .find_map(|parameter| parameter.strip_prefix("resources="))
+            .map(|labels| labels.split(',').map(str::to_string).collect());
+
+        // Registered before the headers are sent, so that a client that has read them won't
+        // miss any events.
+        let (queue, events) = bounded::<Arc<str>>(Self::QUEUE_CAPACITY);
+        clients.lock().push(Client {
+            queue,
+            stream: stream.try_clone()?,
+            resources,
+        });
+        let mut stream = BufWriter::new(stream);
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\n\
+              Content-Type: text/event-stream\r\n\
+              Cache-Control: no-cache\r\n\
+              Connection: keep-alive\r\n",
+        )?;
+        if let Some(origin) = allowed_origin {
+            write!(stream, "Access-Control-Allow-Origin: {origin}\r\n")?;
+        }
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        // Ends when the client is dropped from the publisher, or the connection fails.
+        while let Ok(message) = events.recv() {
+            stream.write_all(message.as_bytes())?;
+            stream.flush()?;
+        }
+        Ok(())
+    }
+}
+
+struct StreamSubscription<R> {
+    id: SubscriptionId,
+    window: TimeRange,
+    reported: Vec<TimeRange>,
+    publisher: Publisher,
+    resource: PhantomData<fn() -> R>,
+}
+
+impl<'o, R: Resource<'o> + 'o, M: Model<'o> + 'o> ErasedSubscription<'o, M>
+    for StreamSubscription<R>
+where
+    R::Read: Serialize,
+{
+    fn id(&self) -> SubscriptionId {
+        self.id
+    }
+
+    fn check(&mut self, plan: &Plan<'o, M>) {
+        let stale = plan.stale_ranges::<R>(R::ID, self.window);
+        let grew = stale
+            .iter()
+            .any(|range| !self.reported.iter().any(|r| contains(*r, *range)));
+        if !grew || self.publisher.clients() == 0 {
+            self.reported = stale;
+            return;
+        }
+
+        self.publisher.publish(&Event::Invalidated {
+            resource: R::LABEL,
+            ranges: stale.iter().map(|range| Range::from(*range)).collect(),
+        });
+        for range in stale {
+            let event = values::<R, M>(plan, range).unwrap_or_else(|e| Event::Error {
+                resource: R::LABEL,
+                message: format!("{e:#}"),
+            });
+            self.publisher.publish(&event);
+        }
+        self.reported = plan.stale_ranges::<R>(R::ID, self.window);
+    }
+}
+
+fn values<'o, R: Resource<'o> + 'o, M: Model<'o> + 'o>(
+    plan: &Plan<'o, M>,
+    range: TimeRange,
+) -> Result<Event>
+where
+    R::Read: Serialize,
+{
+    let points = plan
+        .view::<R>(range)?
+        .into_iter()
+        .map(|(time, value)| {
+            Ok(Point {
+                time: iso_utc(time),
+                value: serde_json::to_value(value)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Event::Values {
+        resource: R::LABEL,
+        range: Range::from(range),
+        points,
+    })
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Publishes the invalidations and new values of `window` of `R` after every edit, as
+    /// described in [stream][crate::stream]. Remove it with [Plan::unsubscribe].
+    pub fn stream<R: Resource<'o> + 'o>(
+        &mut self,
+        window: impl RangeBounds<Time>,
+        publisher: &Publisher,
+    ) -> SubscriptionId
+    where
+        R::Read: Serialize,
+    {
+        let window = (window.start_bound().cloned(), window.end_bound().cloned());
+        let reported = self.stale_ranges::<R>(R::ID, window);
+        let publisher = publisher.clone();
+        self.add_subscription(|id| {
+            Box::new(StreamSubscription::<R> {
+                id,
+                window,
+                reported,
+                publisher,
+                resource: PhantomData,
+            })
+        })
+    }
+}
@@ -0,0 +1,158 @@
+//! Notifications for when edits to a plan change what a view of it would return.
+//!
+//! A live display of a plan subscribes to the resources and windows it shows with
+//! [Plan::subscribe]. After every edit, each subscription checks whether any of its window
+//! became stale, and if so either reports the stale ranges, or views the window again and
+//! reports the new values.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::subscription::{Notification, OnInvalidate};
+//! # use std::sync::mpsc;
+//! # resource!(counter: u32);
+//! # model! { Counter(counter) }
+//! # pub struct Increment;
+//! # impl_activity! { for Increment
+//! #     @(start) {
+//! #         ref mut: counter += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! let mut plan = session.new_plan::<Counter>(start, initial_conditions! { counter: 0 })?;
+//! plan.insert(start + Duration::from_seconds(1.0), Increment)?;
+//! plan.view::<counter>(start..)?;
+//!
+//! let (sender, receiver) = mpsc::channel();
+//! plan.subscribe::<counter>(start.., OnInvalidate::Resimulate, move |notification| {
+//!     sender.send(notification).unwrap();
+//! });
+//!
+//! plan.insert(start + Duration::from_seconds(2.0), Increment)?;
+//! let Notification::Resimulated(Ok(values)) = receiver.try_recv().unwrap() else {
+//!     panic!("expected new values")
+//! };
+//! assert_eq!(2, values.last().unwrap().1);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::resource::Resource;
+use crate::{EngineError, Model, Plan, Time};
+use std::ops::Bound;
+
+/// A span of time, which can be passed straight to [Plan::view] as its bounds.
+pub type TimeRange = (Bound<Time>, Bound<Time>);
+
+/// Identifies a subscription, to remove it with [Plan::unsubscribe].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubscriptionId(pub(crate) u32);
+
+/// What a subscription does when its window goes stale.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnInvalidate {
+    /// Report the stale ranges within the window, and leave it to the subscriber to view them.
+    Notify,
+    /// View the whole window again, and report the result.
+    Resimulate,
+}
+
+/// Passed to a subscription's callback after an edit makes part of its window stale.
+#[derive(Debug)]
+pub enum Notification<T> {
+    /// The stale ranges within the window, ordered by time.
+    Invalidated(Vec<TimeRange>),
+    /// The new view of the window.
+    Resimulated(Result<Vec<(Time, T)>, EngineError>),
+}
+
+pub(crate) trait ErasedSubscription<'o, M: Model<'o>>: Send {
+    fn id(&self) -> SubscriptionId;
+    /// Checks the window after an edit, and notifies the subscriber if it has gone stale.
+    fn check(&mut self, plan: &Plan<'o, M>);
+}
+
+pub(crate) struct Subscription<'o, R: Resource<'o>> {
+    pub(crate) id: SubscriptionId,
+    pub(crate) window: TimeRange,
+    pub(crate) on_invalidate: OnInvalidate,
+    /// The stale ranges within the window the last time it was checked. The subscriber is
+    /// only notified when something outside of them goes stale.
+    pub(crate) reported: Vec<TimeRange>,
+    #[allow(clippy::type_complexity)]
+    pub(crate) callback: Box<dyn FnMut(Notification<R::Read>) + Send>,
+}
+
+impl<'o, R: Resource<'o> + 'o, M: Model<'o> + 'o> ErasedSubscription<'o, M>
+    for Subscription<'o, R>
+{
+    fn id(&self) -> SubscriptionId {
+        self.id
+    }
+
+    fn check(&mut self, plan: &Plan<'o, M>) {
+        let stale = plan.stale_ranges::<R>(R::ID, self.window);
+        let grew = stale
+            .iter()
+            .any(|range| !self.reported.iter().any(|r| contains(*r, *range)));
+        if grew {
+            match self.on_invalidate {
+                OnInvalidate::Notify => {
+                    (self.callback)(Notification::Invalidated(stale.clone()));
+                }
+                OnInvalidate::Resimulate => {
+                    (self.callback)(Notification::Resimulated(plan.view::<R>(self.window)));
+                    self.reported = plan.stale_ranges::<R>(R::ID, self.window);
+                    return;
+                }
+            }
+        }
+        self.reported = stale;
+    }
+}
+
+/// Orders start bounds from earliest to latest.
+pub(crate) fn start_key(bound: Bound<Time>) -> (Option<Time>, bool) {
+    match bound {
+        Bound::Unbounded => (None, false),
+        Bound::Included(t) => (Some(t), false),
+        Bound::Excluded(t) => (Some(t), true),
+    }
+}
+
+/// Orders end bounds from earliest to latest.
+pub(crate) fn end_key(bound: Bound<Time>) -> (bool, Option<Time>, bool) {
+    match bound {
+        Bound::Excluded(t) => (false, Some(t), false),
+        Bound::Included(t) => (false, Some(t), true),
+        Bound::Unbounded => (true, None, false),
+    }
+}
+
+/// The overlap of two ranges, if there is any.
+pub(crate) fn intersect(a: TimeRange, b: TimeRange) -> Option<TimeRange> {
+    let start = if start_key(a.0) >= start_key(b.0) {
+        a.0
+    } else {
+        b.0
+    };
+    let end = if end_key(a.1) <= end_key(b.1) {
+        a.1
+    } else {
+        b.1
+    };
+    let empty = match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Excluded(e))
+        | (Bound::Excluded(s), Bound::Included(e)) => s >= e,
+        _ => false,
+    };
+    (!empty).then_some((start, end))
+}
+
+/// Whether `outer` contains all of `inner`.
+pub(crate) fn contains(outer: TimeRange, inner: TimeRange) -> bool {
+    start_key(outer.0) <= start_key(inner.0) && end_key(inner.1) <= end_key(outer.1)
+}
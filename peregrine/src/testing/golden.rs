@@ -0,0 +1,341 @@
+//! Regression tests against profiles recorded in a file.
+//!
+//! A golden file holds the profiles of some resources from a plan that was reviewed and found
+//! correct. [GoldenProfiles] records the same resources from a new simulation, and
+//! [GoldenProfiles::check] compares them to the file, failing with the windows where they
+//! diverge by more than the [Tolerances]. When the file doesn't exist yet, or the
+//! `PEREGRINE_UPDATE_GOLDEN` environment variable is set, the check writes the file instead,
+//! so that intended changes can be accepted by running the tests once with the variable set
+//! and reviewing the diff of the file.
+//!
+//! Golden files are JSON, with the values of each resource converted to [f64].
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::testing::golden::{GoldenProfiles, Tolerance, Tolerances};
+//! # resource!(golden_battery: f64);
+//! # model! { Golden(golden_battery) }
+//! # #[derive(serde::Serialize)]
+//! # pub struct Drain(f64);
+//! # impl_activity! { for Drain
+//! #     @(start) {
+//! #         ref mut: golden_battery -= self.0;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! # fn main() -> Result<()> {
+//! # let session = Session::new();
+//! # let start = Time::from_tai_seconds(0.0);
+//! # let path = std::env::temp_dir().join(format!("peregrine-golden-doc-{}.json", std::process::id()));
+//! let simulate = |drain: f64| -> Result<GoldenProfiles> {
+//!     let mut plan = session.new_plan::<Golden>(start, initial_conditions! { golden_battery: 100.0 })?;
+//!     plan.insert(start + Duration::from_hours(1.0), Drain(drain))?;
+//!     Ok(GoldenProfiles::new().record::<golden_battery>(&plan, ..)?)
+//! };
+//! let tolerances = Tolerances::new(Tolerance::absolute(0.01));
+//!
+//! // The first check writes the file.
+//! simulate(10.0)?.check(&path, &tolerances)?;
+//! simulate(10.001)?.check(&path, &tolerances)?;
+//!
+//! let error = simulate(12.0)?.check(&path, &tolerances).unwrap_err();
+//! assert!(error.to_string().contains("golden_battery: 1 windows diverge, by up to 2"));
+//! # std::fs::remove_file(path)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::compare::{Difference, RangeDisplay, diff};
+use crate::resource::Resource;
+use crate::subscription::TimeRange;
+use crate::{EngineError, PlanAccess, Time};
+use anyhow::{Context, Result, ensure};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeBounds;
+use std::path::Path;
+
+/// The environment variable that makes [GoldenProfiles::check] write golden files instead of
+/// comparing against them.
+pub const UPDATE_VARIABLE: &str = "PEREGRINE_UPDATE_GOLDEN";
+
+/// Recorded profiles of resources, to be saved as a golden file or checked against one. See
+/// [golden][crate::testing::golden].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GoldenProfiles {
+    /// In the order they were recorded.
+    pub resources: Vec<GoldenProfile>,
+}
+
+/// The profile of one resource in [GoldenProfiles].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GoldenProfile {
+    pub resource: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    pub window: TimeRange,
+    pub values: Vec<(Time, f64)>,
+}
+
+impl GoldenProfiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the profile of `R` in `plan` over `bounds`.
+    pub fn record<'o, R: Resource<'o> + 'o>(
+        mut self,
+        plan: &impl PlanAccess<'o>,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<Self, EngineError>
+    where
+        R::Read: Into<f64>,
+    {
+        let window: TimeRange = (bounds.start_bound().cloned(), bounds.end_bound().cloned());
+        let mut values: Vec<(Time, f64)> = plan
+            .view_resource::<R>(window)?
+            .into_iter()
+            .map(|(time, value)| (time, value.into()))
+            .collect();
+        values.sort_by_key(|(time, _)| *time);
+        self.resources.push(GoldenProfile {
+            resource: R::LABEL.to_string(),
+            unit: R::UNIT.map(str::to_string),
+            window,
+            values,
+        });
+        Ok(self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read golden file {}", path.display()))?;
+        serde_json::from_str(&text)
+            .with_context(|| format!("invalid golden file {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, text + "\n")
+            .with_context(|| format!("could not write golden file {}", path.display()))
+    }
+
+    /// The windows where these profiles diverge from `golden` by more than the tolerances.
+    pub fn compare(&self, golden: &GoldenProfiles, tolerances: &Tolerances) -> GoldenReport {
+        let mut report = GoldenReport::default();
+        for expected in &golden.resources {
+            let Some(actual) = self
+                .resources
+                .iter()
+                .find(|actual| actual.resource == expected.resource)
+            else {
+                report.missing.push(expected.resource.clone());
+                continue;
+            };
+            let tolerance = tolerances.of(&expected.resource);
+            if actual.window != expected.window {
+                report.rewindowed.push(expected.resource.clone());
+            }
+            let windows: Vec<_> = diff(&expected.values, &actual.values, expected.window)
+                .into_iter()
+                .filter(|difference| !tolerance.allows(difference.before, difference.after))
+                .collect();
+            if !windows.is_empty() {
+                report.diverging.push(Divergence {
+                    resource: expected.resource.clone(),
+                    unit: expected.unit.clone(),
+                    tolerance,
+                    windows,
+                });
+            }
+        }
+        report.unexpected = self
+            .resources
+            .iter()
+            .filter(|actual| {
+                !golden
+                    .resources
+                    .iter()
+                    .any(|expected| expected.resource == actual.resource)
+            })
+            .map(|actual| actual.resource.clone())
+            .collect();
+        report
+    }
+
+    /// Compares these profiles against the golden file at `path`, or writes them to it if it
+    /// doesn't exist or [UPDATE_VARIABLE] is set. Fails with the [GoldenReport] if they
+    /// diverge. See [golden][crate::testing::golden].
+    pub fn check(&self, path: impl AsRef<Path>, tolerances: &Tolerances) -> Result<()> {
+        let path = path.as_ref();
+        if std::env::var_os(UPDATE_VARIABLE).is_some() || !path.exists() {
+            return self.save(path);
+        }
+        let report = self.compare(&GoldenProfiles::load(path)?, tolerances);
+        ensure!(
+            report.is_empty(),
+            "profiles diverge from golden file {} (set {UPDATE_VARIABLE} to accept them):\n{report}",
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+/// How far a value can be from the golden value, either in absolute terms or relative to the
+/// golden value, whichever is larger.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Tolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl Tolerance {
+    /// Values have to be the same.
+    pub const EXACT: Tolerance = Tolerance {
+        absolute: 0.0,
+        relative: 0.0,
+    };
+
+    pub fn absolute(absolute: f64) -> Self {
+        Tolerance {
+            absolute,
+            relative: 0.0,
+        }
+    }
+
+    pub fn relative(relative: f64) -> Self {
+        Tolerance {
+            absolute: 0.0,
+            relative,
+        }
+    }
+
+    /// Whether `actual` is close enough to `expected`.
+    pub fn allows(&self, expected: f64, actual: f64) -> bool {
+        (actual - expected).abs() <= self.absolute.max(self.relative * expected.abs())
+    }
+}
+
+impl Display for Tolerance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.absolute, self.relative) {
+            (0.0, 0.0) => write!(f, "exactly"),
+            (absolute, 0.0) => write!(f, "by more than {absolute}"),
+            (0.0, relative) => write!(f, "by more than {}%", relative * 100.0),
+            (absolute, relative) => {
+                write!(f, "by more than {absolute} or {}%", relative * 100.0)
+            }
+        }
+    }
+}
+
+/// The [Tolerance] of every resource in a comparison.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tolerances {
+    default: Tolerance,
+    resources: HashMap<String, Tolerance>,
+}
+
+impl Tolerances {
+    /// Uses `default` for the resources without their own tolerance.
+    pub fn new(default: Tolerance) -> Self {
+        Tolerances {
+            default,
+            resources: HashMap::new(),
+        }
+    }
+
+    /// Uses `tolerance` for `R`.
+    pub fn with<'o, R: Resource<'o>>(mut self, tolerance: Tolerance) -> Self {
+        self.resources.insert(R::LABEL.to_string(), tolerance);
+        self
+    }
+
+    pub fn of(&self, resource: &str) -> Tolerance {
+        self.resources
+            .get(resource)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// How recorded profiles diverge from a golden file, from [GoldenProfiles::compare].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GoldenReport {
+    pub diverging: Vec<Divergence>,
+    /// Resources in the golden file that weren't recorded.
+    pub missing: Vec<String>,
+    /// Resources that were recorded but aren't in the golden file.
+    pub unexpected: Vec<String>,
+    /// Resources that were recorded over a different window than the golden file's. Their
+    /// values are only compared within the golden window.
+    pub rewindowed: Vec<String>,
+}
+
+/// The windows where one resource diverges from its golden profile, with the golden values as
+/// `before`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub resource: String,
+    pub unit: Option<String>,
+    pub tolerance: Tolerance,
+    pub windows: Vec<Difference<f64>>,
+}
+
+impl GoldenReport {
+    pub fn is_empty(&self) -> bool {
+        self.diverging.is_empty()
+            && self.missing.is_empty()
+            && self.unexpected.is_empty()
+            && self.rewindowed.is_empty()
+    }
+}
+
+impl Display for GoldenReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for divergence in &self.diverging {
+            write!(f, "{}", divergence.resource)?;
+            if let Some(unit) = &divergence.unit {
+                write!(f, " [{unit}]")?;
+            }
+            let largest = divergence
+                .windows
+                .iter()
+                .map(|window| window.amount().abs())
+                .fold(0.0, f64::max);
+            writeln!(
+                f,
+                ": {} windows diverge, by up to {largest} (tolerance: {})",
+                divergence.windows.len(),
+                divergence.tolerance
+            )?;
+            for window in &divergence.windows {
+                writeln!(
+                    f,
+                    "  {}: {} -> {} ({:+})",
+                    RangeDisplay(window.range),
+                    window.before,
+                    window.after,
+                    window.amount()
+                )?;
+            }
+        }
+        if !self.missing.is_empty() {
+            writeln!(f, "not recorded: {}", self.missing.join(", "))?;
+        }
+        if !self.unexpected.is_empty() {
+            writeln!(f, "not in the golden file: {}", self.unexpected.join(", "))?;
+        }
+        if !self.rewindowed.is_empty() {
+            writeln!(
+                f,
+                "recorded over a different window: {}",
+                self.rewindowed.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
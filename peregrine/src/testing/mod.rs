@@ -0,0 +1,180 @@
+//! Helpers for testing models, and especially that plans only recompute what they need to.
+//!
+//! - [seconds] makes times a whole number of seconds after the epoch, for short test plans.
+//! - [eval_counter!][crate::eval_counter!] declares an activity that counts how many times its
+//!   operation is evaluated, for checking that an edit doesn't invalidate more than it should.
+//! - [Plan::assert_profile] checks a resource against the values it should have, and prints
+//!   both as tables if they differ.
+//! - [Plan::cache_counts] views a resource and counts how many operations were evaluated and
+//!   how many were found in history.
+//! - [PropertyTest] generates random plans and edits, and checks that the engine's caching
+//!   and incremental simulation don't change the results. See [properties].
+//! - With the `json` feature, [golden] compares profiles against ones recorded in a file.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::testing::{CacheCounts, seconds};
+//! # resource!(tested_count: u32);
+//! # model! { Tested(tested_count) }
+//! # pub struct Increment;
+//! # impl_activity! { for Increment
+//! #     @(start) {
+//! #         ref mut: tested_count += 1;
+//! #     }
+//! #     Duration::ZERO
+//! # }
+//! eval_counter!(pub CountTested: tested_count);
+//!
+//! # fn main() -> Result<()> {
+//! let session = Session::new();
+//! let mut plan = session.new_plan::<Tested>(seconds(0), initial_conditions! { tested_count: 0 })?;
+//! let (counter, evaluations) = CountTested::new();
+//! plan.insert(seconds(1), Increment)?;
+//! plan.insert(seconds(2), counter)?;
+//! plan.assert_profile::<tested_count>(.., &[(seconds(0), 0), (seconds(1), 1), (seconds(2), 1)]);
+//! assert_eq!(1, evaluations.get());
+//!
+//! // Another plan with the same activities finds their results in history.
+//! let mut copy = session.new_plan::<Tested>(seconds(0), initial_conditions! { tested_count: 0 })?;
+//! copy.insert(seconds(1), Increment)?;
+//! copy.insert(seconds(2), CountTested::new().0)?;
+//! assert_eq!(
+//!     CacheCounts { evaluated: 0, from_history: 2 },
+//!     copy.cache_counts::<tested_count>(..)?
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "json")]
+pub mod golden;
+pub mod properties;
+
+pub use properties::PropertyTest;
+
+use crate::resource::Resource;
+use crate::{EngineError, Model, Plan, Profile, Time};
+use std::ops::{Deref, RangeBounds};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Declares an activity that reads and writes a resource without changing it, and counts how
+/// many times its operation is evaluated. See [testing][crate::testing].
+///
+/// The activity holds an [EvalCount] that can be cloned into several activities; `new` makes
+/// one with a fresh count and returns the count too.
+///
+/// ```
+/// # fn main() {}
+/// # use peregrine::*;
+/// # resource!(counted_power: f64);
+/// eval_counter!(pub CountPower: counted_power);
+/// ```
+#[macro_export]
+macro_rules! eval_counter {
+    ($vis:vis $name:ident: $resource:ident) => {
+        $vis struct $name(pub $crate::testing::EvalCount);
+
+        $crate::impl_activity! { for $name
+            @(start) {
+                mut: $resource = ref: $resource;
+                self.0.increment();
+            }
+            $crate::Duration::ZERO
+        }
+
+        impl $name {
+            #[allow(dead_code)]
+            pub fn new() -> (Self, $crate::testing::EvalCount) {
+                let count = $crate::testing::EvalCount::default();
+                (Self(count.clone()), count)
+            }
+        }
+    };
+}
+
+/// A shared count of evaluations, for [eval_counter!][crate::eval_counter!].
+///
+/// Derefs to its [AtomicUsize], for tests that wait on or reset it.
+#[derive(Clone, Debug, Default)]
+pub struct EvalCount(Arc<AtomicUsize>);
+
+impl EvalCount {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl Deref for EvalCount {
+    type Target = AtomicUsize;
+
+    fn deref(&self) -> &AtomicUsize {
+        &self.0
+    }
+}
+
+/// The time `s` seconds after the TAI epoch.
+pub fn seconds(s: i32) -> Time {
+    Time::from_tai_seconds(s as f64)
+}
+
+/// How the operations of a view were computed, from [Plan::cache_counts].
+///
+/// Operations that an earlier view of the same plan already computed aren't counted at all,
+/// because the view doesn't need them again.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheCounts {
+    pub evaluated: usize,
+    pub from_history: usize,
+}
+
+impl<'o, M: Model<'o> + 'o> Plan<'o, M> {
+    /// Panics unless viewing `R` over `bounds` gives exactly `expected`, printing both as
+    /// [Profile] tables if they differ. See [testing][crate::testing].
+    #[track_caller]
+    pub fn assert_profile<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+        expected: &[(Time, R::Read)],
+    ) where
+        Self: 'o,
+        R::Read: PartialEq,
+    {
+        let actual = match self.profile::<R>(bounds) {
+            Ok(actual) => actual,
+            Err(e) => panic!("could not view {}: {e}", R::LABEL),
+        };
+        if actual != expected.to_vec() {
+            let expected = Profile::<R>::new(expected.to_vec());
+            panic!(
+                "profile of {} differs\nexpected:\n{expected:?}\nactual:\n{actual:?}",
+                R::LABEL
+            );
+        }
+    }
+
+    /// Views `R` over `bounds`, and counts the operations that were evaluated and the ones
+    /// found in history. See [testing][crate::testing].
+    pub fn cache_counts<R: Resource<'o> + 'o>(
+        &self,
+        bounds: impl RangeBounds<Time>,
+    ) -> Result<CacheCounts, EngineError>
+    where
+        Self: 'o,
+    {
+        let (_, recording) = self.view_recorded::<R>(bounds)?;
+        let from_history = recording
+            .executions
+            .iter()
+            .filter(|execution| execution.cached)
+            .count();
+        Ok(CacheCounts {
+            evaluated: recording.executions.len() - from_history,
+            from_history,
+        })
+    }
+}
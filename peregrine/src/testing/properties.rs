@@ -0,0 +1,366 @@
+//! Randomized tests of a model's plans, for bugs in incremental simulation.
+//!
+//! A [PropertyTest] generates plans from a set of activity generators: a random number of
+//! activities, of random types and arguments, at random times in a window. For each plan it
+//! checks that the engine's shortcuts don't change the profiles of the checked resources:
+//!
+//! - **Reinsertion**: removing an activity and inserting it again, with a view in between,
+//!   gives the same profiles as before.
+//! - **Edit order**: inserting the activities in a shuffled order, with views between some of
+//!   the insertions, gives the same profiles as inserting them in order.
+//! - **Caching**: the profiles of a plan whose operations were reused from history are the
+//!   same as the ones from simulating it in a new session.
+//!
+//! When a plan fails, its activities are removed one at a time for as long as it keeps
+//! failing, and the error lists the smallest failing plan along with the seed, so that it can
+//! be reproduced. Generators are given an [Rng] to draw arguments from, which is seeded from
+//! the test's seed, so the same seed always generates the same plans.
+//!
+//! ```
+//! # use peregrine::*;
+//! # use peregrine::testing::{PropertyTest, seconds};
+//! # resource!(property_a: u32);
+//! # resource!(property_b: u32);
+//! # model! { Properties(property_a, property_b) }
+//! #[derive(serde::Serialize)]
+//! pub struct AddToA(u32);
+//! impl_activity! { for AddToA
+//!     @(start) {
+//!         ref mut: property_a += self.0;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! pub struct CopyAToB;
+//! impl_activity! { for CopyAToB
+//!     @(start) {
+//!         mut: property_b = ref: property_a;
+//!     }
+//!     Duration::ZERO
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! PropertyTest::new(
+//!     |session| {
+//!         session.new_plan::<Properties>(seconds(0), initial_conditions! { property_a: 0, property_b: 0 })
+//!     },
+//!     seconds(0)..seconds(60),
+//! )
+//! .activity(|rng| AddToA(rng.range(0.0, 10.0) as u32))
+//! .activity(|_| CopyAToB)
+//! .check::<property_a>()
+//! .check::<property_b>()
+//! .cases(16)
+//! .run()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The edit order property assumes that activities at different times commute, so start
+//! times are drawn to be distinct. Activities with durations whose operations land on the same
+//! instant as another activity's, and write the same resource, can still fail it.
+
+use crate::random::Rng;
+use crate::resource::Resource;
+use crate::{Activity, Duration, EngineError, Model, Plan, Session, Time};
+use anyhow::{Context, Result, anyhow, ensure};
+use std::fmt::Write;
+use std::marker::PhantomData;
+use std::ops::Range;
+
+type Template<M> = Box<dyn for<'s> Fn(&'s Session) -> Result<Plan<'s, M>, EngineError>>;
+type Generator<M> = Box<dyn Fn(&mut Rng) -> Box<dyn for<'o> Activity<'o, M>>>;
+
+/// A randomized test of a model's plans. See [properties][crate::testing::properties].
+pub struct PropertyTest<M: for<'o> Model<'o> + 'static> {
+    template: Template<M>,
+    window: Range<Time>,
+    generators: Vec<Generator<M>>,
+    checks: Vec<Box<dyn Check<M>>>,
+    cases: usize,
+    max_activities: usize,
+    seed: u64,
+}
+
+impl<M: for<'o> Model<'o> + 'static> PropertyTest<M> {
+    /// A test of plans made by `template`, with activities inserted within `window`.
+    ///
+    /// The template is called once for each plan the test makes, in a few different sessions,
+    /// and should make the same empty plan every time.
+    pub fn new(
+        template: impl for<'s> Fn(&'s Session) -> Result<Plan<'s, M>, EngineError> + 'static,
+        window: Range<Time>,
+    ) -> Self {
+        PropertyTest {
+            template: Box::new(template),
+            window,
+            generators: vec![],
+            checks: vec![],
+            cases: 32,
+            max_activities: 8,
+            seed: 0,
+        }
+    }
+
+    /// Adds a generator of activities, which draws their arguments from the given [Rng].
+    pub fn activity<A: for<'o> Activity<'o, M> + 'static>(
+        mut self,
+        generate: impl Fn(&mut Rng) -> A + 'static,
+    ) -> Self {
+        self.generators
+            .push(Box::new(move |rng| Box::new(generate(rng))));
+        self
+    }
+
+    /// Checks the profile of `R` over the whole plan.
+    pub fn check<R: for<'h> Resource<'h> + 'static>(mut self) -> Self {
+        self.checks.push(Box::new(Checked::<R>(PhantomData)));
+        self
+    }
+
+    /// The number of plans to generate, 32 by default.
+    pub fn cases(mut self, cases: usize) -> Self {
+        self.cases = cases;
+        self
+    }
+
+    /// The most activities in a generated plan, 8 by default.
+    pub fn max_activities(mut self, max_activities: usize) -> Self {
+        self.max_activities = max_activities;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Generates and checks every case, and fails with the smallest failing plan if any
+    /// property doesn't hold.
+    pub fn run(self) -> Result<()> {
+        ensure!(
+            !self.generators.is_empty(),
+            "a property test needs at least one activity generator"
+        );
+        ensure!(
+            !self.checks.is_empty(),
+            "a property test needs at least one checked resource"
+        );
+        ensure!(
+            self.window.start < self.window.end,
+            "the window of a property test can't be empty"
+        );
+        // Shared by every case, so that later cases reuse the history of earlier ones.
+        let session = Session::new();
+        for index in 0..self.cases {
+            let case = self.generate(index);
+            let Some(failure) = self
+                .failure(&session, &case)
+                .with_context(|| format!("in case {index} of seed {}", self.seed))?
+            else {
+                continue;
+            };
+            let (case, failure) = self.shrink(&session, case, failure)?;
+            return Err(anyhow!(
+                "{failure}\nin case {index} of seed {}, shrunk to:\n{}",
+                self.seed,
+                self.describe(&case)
+            ));
+        }
+        Ok(())
+    }
+
+    fn generate(&self, index: usize) -> Case {
+        let mut rng = Rng::new(self.seed, index);
+        let count = 1 + rng.next_u64() as usize % self.max_activities.max(1);
+        let length = (self.window.end - self.window.start).to_seconds();
+        let mut activities: Vec<Generated> = Vec::with_capacity(count);
+        while activities.len() < count {
+            let time = self.window.start + Duration::from_seconds(rng.range(0.0, length));
+            if activities.iter().any(|activity| activity.time == time) {
+                continue;
+            }
+            activities.push(Generated {
+                generator: rng.next_u64() as usize % self.generators.len(),
+                seed: rng.next_u64(),
+                time,
+            });
+        }
+        Case {
+            activities,
+            edits: rng.next_u64(),
+        }
+    }
+
+    fn shrink(
+        &self,
+        session: &Session,
+        mut case: Case,
+        mut failure: String,
+    ) -> Result<(Case, String)> {
+        let mut index = 0;
+        while index < case.activities.len() && case.activities.len() > 1 {
+            let mut smaller = case.clone();
+            smaller.activities.remove(index);
+            match self.failure(session, &smaller)? {
+                Some(smaller_failure) => {
+                    case = smaller;
+                    failure = smaller_failure;
+                }
+                None => index += 1,
+            }
+        }
+        Ok((case, failure))
+    }
+
+    fn describe(&self, case: &Case) -> String {
+        let mut description = String::new();
+        for activity in &case.activities {
+            let _ = writeln!(
+                description,
+                "  {} at {}",
+                self.instantiate(activity).label(),
+                activity.time
+            );
+        }
+        description
+    }
+
+    fn instantiate(&self, activity: &Generated) -> Box<dyn for<'o> Activity<'o, M>> {
+        (self.generators[activity.generator])(&mut Rng::new(activity.seed, 0))
+    }
+
+    /// Checks every property of a case, and describes the first that doesn't hold.
+    fn failure(&self, session: &Session, case: &Case) -> Result<Option<String>> {
+        let mut rng = Rng::new(case.edits, 0);
+
+        let mut plan = (self.template)(session)?;
+        let mut ids = vec![];
+        for activity in &case.activities {
+            ids.push(plan.insert_boxed(activity.time, self.instantiate(activity))?);
+        }
+        let expected = self.profiles(&plan)?;
+
+        let removed = rng.next_u64() as usize % ids.len();
+        plan.remove(ids[removed])?;
+        self.profiles(&plan)?;
+        let activity = &case.activities[removed];
+        plan.insert_boxed(activity.time, self.instantiate(activity))?;
+        if let Some(difference) = self.difference(&expected, &self.profiles(&plan)?) {
+            return Ok(Some(format!(
+                "removing and reinserting the {} at {} changed {difference}",
+                self.instantiate(activity).label(),
+                activity.time
+            )));
+        }
+        drop(plan);
+
+        let mut order: Vec<usize> = (0..case.activities.len()).collect();
+        for i in (1..order.len()).rev() {
+            order.swap(i, rng.next_u64() as usize % (i + 1));
+        }
+        let mut shuffled = (self.template)(session)?;
+        for index in order {
+            let activity = &case.activities[index];
+            shuffled.insert_boxed(activity.time, self.instantiate(activity))?;
+            if rng.bernoulli(0.5) {
+                self.profiles(&shuffled)?;
+            }
+        }
+        if let Some(difference) = self.difference(&expected, &self.profiles(&shuffled)?) {
+            return Ok(Some(format!(
+                "inserting the activities in a different order changed {difference}"
+            )));
+        }
+        drop(shuffled);
+
+        let fresh_session = Session::new();
+        let mut fresh = (self.template)(&fresh_session)?;
+        for activity in &case.activities {
+            fresh.insert_boxed(activity.time, self.instantiate(activity))?;
+        }
+        if let Some(difference) = self.difference(&self.profiles(&fresh)?, &expected) {
+            return Ok(Some(format!(
+                "reusing history instead of simulating in a new session changed {difference}"
+            )));
+        }
+        Ok(None)
+    }
+
+    fn profiles(&self, plan: &Plan<'_, M>) -> Result<Vec<Vec<(Time, String)>>, EngineError> {
+        self.checks.iter().map(|check| check.view(plan)).collect()
+    }
+
+    /// Describes the first difference between the expected and actual profiles.
+    fn difference(
+        &self,
+        expected: &[Vec<(Time, String)>],
+        actual: &[Vec<(Time, String)>],
+    ) -> Option<String> {
+        for ((check, expected), actual) in self.checks.iter().zip(expected).zip(actual) {
+            if expected == actual {
+                continue;
+            }
+            let index = expected
+                .iter()
+                .zip(actual)
+                .position(|(expected, actual)| expected != actual)
+                .unwrap_or(expected.len().min(actual.len()));
+            let describe = |profile: &[(Time, String)]| match profile.get(index) {
+                Some((time, value)) => format!("{value} at {time}"),
+                None => "nothing".to_string(),
+            };
+            return Some(format!(
+                "{}: expected {}, found {}",
+                check.label(),
+                describe(expected),
+                describe(actual)
+            ));
+        }
+        None
+    }
+}
+
+/// A generated plan: its activities in the order they were generated, and the seed of the
+/// edits made to it.
+#[derive(Clone)]
+struct Case {
+    activities: Vec<Generated>,
+    edits: u64,
+}
+
+/// A generated activity, which is generated again from its seed whenever it is inserted.
+#[derive(Clone)]
+struct Generated {
+    generator: usize,
+    seed: u64,
+    time: Time,
+}
+
+/// Views a checked resource, with its values formatted so that resources of different types
+/// can be compared together.
+trait Check<M: for<'o> Model<'o> + 'static> {
+    fn label(&self) -> &'static str;
+
+    fn view(&self, plan: &Plan<'_, M>) -> Result<Vec<(Time, String)>, EngineError>;
+}
+
+struct Checked<R>(PhantomData<fn() -> R>);
+
+impl<M, R> Check<M> for Checked<R>
+where
+    M: for<'o> Model<'o> + 'static,
+    R: for<'h> Resource<'h> + 'static,
+{
+    fn label(&self) -> &'static str {
+        <R as Resource<'static>>::LABEL
+    }
+
+    fn view(&self, plan: &Plan<'_, M>) -> Result<Vec<(Time, String)>, EngineError> {
+        Ok(plan
+            .view::<R>(..)?
+            .into_iter()
+            .map(|(time, value)| (time, format!("{value:?}")))
+            .collect())
+    }
+}
@@ -0,0 +1,203 @@
+//! An interval tree for the spans of ungrounded nodes.
+//!
+//! It is a treap keyed by the start of each span and the node address, where every subtree
+//! also tracks the earliest and latest end it contains. That is enough to find the spans that
+//! overlap a range, or the latest span that ends before a time, without visiting subtrees that
+//! can't contain an answer.
+
+use super::Instant;
+use std::ops::Bound;
+
+/// Spans are identified by their start and the address of their node, so that nodes with the
+/// same bounds don't collide.
+type Key = (Instant, usize);
+
+struct TreeNode<T> {
+    key: Key,
+    max: Instant,
+    value: T,
+    priority: u64,
+    left: Option<usize>,
+    right: Option<usize>,
+    /// The earliest `max` in this subtree.
+    earliest_end: Instant,
+    /// The latest `max` in this subtree.
+    latest_end: Instant,
+}
+
+pub struct IntervalTree<T> {
+    nodes: Vec<TreeNode<T>>,
+    free: Vec<usize>,
+    root: Option<usize>,
+}
+
+impl<T: Copy> IntervalTree<T> {
+    pub fn new() -> Self {
+        IntervalTree {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+        }
+    }
+
+    pub fn insert(&mut self, min: Instant, max: Instant, id: usize, value: T) {
+        let node = TreeNode {
+            key: (min, id),
+            max,
+            value,
+            // Node addresses are distinct, so mixing them gives well-spread priorities without
+            // making the shape of the tree depend on anything but its contents.
+            priority: mix(id as u64),
+            left: None,
+            right: None,
+            earliest_end: max,
+            latest_end: max,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        let (left, right) = self.split(self.root, |key| key < (min, id));
+        let left = self.merge(left, Some(index));
+        self.root = self.merge(left, right);
+    }
+
+    /// Removes the span starting at `min` with the given id, returning whether it was present.
+    pub fn remove(&mut self, min: Instant, id: usize) -> bool {
+        let (left, rest) = self.split(self.root, |key| key < (min, id));
+        let (found, right) = self.split(rest, |key| key <= (min, id));
+        if let Some(index) = found {
+            self.free.push(index);
+        }
+        self.root = self.merge(left, right);
+        found.is_some()
+    }
+
+    /// All spans that end at or after `from`, and start within the `to` bound.
+    pub fn overlapping(&self, from: Instant, to: Bound<Instant>) -> Vec<(Instant, Instant, T)> {
+        let mut result = Vec::new();
+        self.visit_overlapping(self.root, from, to, &mut result);
+        result
+    }
+
+    /// The latest start among spans that end strictly before `time`.
+    pub fn latest_start_ending_before(&self, time: Instant) -> Option<Instant> {
+        self.root
+            .and_then(|root| self.latest_start_ending_before_in(root, time))
+    }
+
+    /// Spans never end before they start, so any subtree whose earliest end is before `time`
+    /// is guaranteed to contain an answer, and the search never has to backtrack.
+    fn latest_start_ending_before_in(&self, index: usize, time: Instant) -> Option<Instant> {
+        let node = &self.nodes[index];
+        if node.earliest_end >= time {
+            return None;
+        }
+        // Everything in the right subtree starts later than this node, so it is checked first.
+        node.right
+            .and_then(|right| self.latest_start_ending_before_in(right, time))
+            .or_else(|| (node.max < time).then_some(node.key.0))
+            .or_else(|| {
+                node.left
+                    .and_then(|left| self.latest_start_ending_before_in(left, time))
+            })
+    }
+
+    fn visit_overlapping(
+        &self,
+        current: Option<usize>,
+        from: Instant,
+        to: Bound<Instant>,
+        result: &mut Vec<(Instant, Instant, T)>,
+    ) {
+        let Some(index) = current else {
+            return;
+        };
+        let node = &self.nodes[index];
+        if node.latest_end < from {
+            return;
+        }
+        self.visit_overlapping(node.left, from, to, result);
+        let starts_in_bound = match to {
+            Bound::Included(to) => node.key.0 <= to,
+            Bound::Excluded(to) => node.key.0 < to,
+            Bound::Unbounded => true,
+        };
+        if starts_in_bound {
+            if node.max >= from {
+                result.push((node.key.0, node.max, node.value));
+            }
+            self.visit_overlapping(node.right, from, to, result);
+        }
+    }
+
+    /// Splits a subtree into the nodes whose keys satisfy `goes_left`, and the rest.
+    /// `goes_left` must be true for a prefix of the keys.
+    fn split(
+        &mut self,
+        current: Option<usize>,
+        goes_left: impl Fn(Key) -> bool + Copy,
+    ) -> (Option<usize>, Option<usize>) {
+        let Some(index) = current else {
+            return (None, None);
+        };
+        if goes_left(self.nodes[index].key) {
+            let (left, right) = self.split(self.nodes[index].right, goes_left);
+            self.nodes[index].right = left;
+            self.update(index);
+            (Some(index), right)
+        } else {
+            let (left, right) = self.split(self.nodes[index].left, goes_left);
+            self.nodes[index].left = right;
+            self.update(index);
+            (left, Some(index))
+        }
+    }
+
+    /// Joins two subtrees, where every key in `left` is less than every key in `right`.
+    fn merge(&mut self, left: Option<usize>, right: Option<usize>) -> Option<usize> {
+        match (left, right) {
+            (None, other) | (other, None) => other,
+            (Some(l), Some(r)) => {
+                if self.nodes[l].priority > self.nodes[r].priority {
+                    let merged = self.merge(self.nodes[l].right, Some(r));
+                    self.nodes[l].right = merged;
+                    self.update(l);
+                    Some(l)
+                } else {
+                    let merged = self.merge(Some(l), self.nodes[r].left);
+                    self.nodes[r].left = merged;
+                    self.update(r);
+                    Some(r)
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, index: usize) {
+        let node = &self.nodes[index];
+        let mut earliest_end = node.max;
+        let mut latest_end = node.max;
+        for child in [node.left, node.right].into_iter().flatten() {
+            earliest_end = earliest_end.min(self.nodes[child].earliest_end);
+            latest_end = latest_end.max(self.nodes[child].latest_end);
+        }
+        let node = &mut self.nodes[index];
+        node.earliest_end = earliest_end;
+        node.latest_end = latest_end;
+    }
+}
+
+/// The splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
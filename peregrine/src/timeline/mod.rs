@@ -0,0 +1,822 @@
+#![doc(hidden)]
+
+mod interval_tree;
+
+use crate::EngineError;
+use crate::Model;
+use crate::arena::{Arena, ArenaMember};
+use crate::exec::UnsafeSyncCell;
+use crate::history::PassThroughHashBuilder;
+use crate::operation::initial_conditions::{InitialConditionOp, InitialConditions};
+use crate::operation::ungrounded::{UngroundedUpstream, UngroundedUpstreamResolver};
+use crate::operation::{Upstream, UpstreamVec};
+use crate::resource::{ErasedResource, KeyedResource, Resource};
+use hifitime::TimeScale::TAI;
+use hifitime::{Duration, Epoch as Time};
+use interval_tree::IntervalTree;
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::marker::PhantomData;
+use std::ops::Bound::{Excluded, Included};
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The timeline of a resource or key. Edits change it through a shared reference to the
+/// [Timelines], see [Timelines::insert_grounded].
+type TimelineCell<'o> = UnsafeSyncCell<Box<dyn ErasedResource<'o>>>;
+
+/// The timelines of every resource of a plan.
+///
+/// Edits change one timeline at a time through `&self`, so that a
+/// [SharedPlan][crate::SharedPlan] can edit some timelines while others are being viewed.
+/// The methods that do are unsafe: nothing else can use the same timeline while they run.
+pub struct Timelines<'o, M: Model<'o> + ?Sized> {
+    resources: HashMap<u64, TimelineCell<'o>, PassThroughHashBuilder>,
+    /// The type of the resource each timeline in `resources` was made for. Instantiations of
+    /// a generic resource share an ID, so this is what tells them apart before one is
+    /// downcast to the other.
+    instantiations: HashMap<u64, &'static str, PassThroughHashBuilder>,
+    /// The timelines of individual keys of keyed resources. These are created during edits,
+    /// so they are kept apart from the resource timelines and behind a lock, which lets
+    /// a [SharedPlan][crate::SharedPlan] create them while other resources are viewed.
+    /// Entries are never removed, so references to them stay valid after the lock is released.
+    /// Each is kept with the ID of its resource, which can be read without the timeline.
+    keys: RwLock<HashMap<u64, (u64, TimelineCell<'o>), PassThroughHashBuilder>>,
+    herd: &'o Arena,
+    /// The labels of the resources that were given a boundary profile, which operations
+    /// can't write.
+    profiles: HashSet<&'static str>,
+    /// A copy of the initial conditions that the timelines were created from, with profiles
+    /// already loaded, so that [Plan::compact][crate::Plan::compact] can create them again.
+    initial_conditions: InitialConditions,
+    /// The last sequence number given to an operation.
+    sequence: AtomicU64,
+    /// Whether grounded operations are staged by an [Append][crate::append::Append] instead
+    /// of inserted.
+    appending: bool,
+    model: PhantomData<&'o M>,
+}
+
+impl<'o, M: Model<'o> + ?Sized> Drop for Timelines<'o, M> {
+    fn drop(&mut self) {
+        self.herd.clear_nodes();
+    }
+}
+
+impl<'o, M: Model<'o>> Timelines<'o, M> {
+    pub fn new(herd: &'o Arena) -> Self {
+        Self {
+            resources: HashMap::with_hasher(PassThroughHashBuilder),
+            instantiations: HashMap::with_hasher(PassThroughHashBuilder),
+            keys: RwLock::new(HashMap::with_hasher(PassThroughHashBuilder)),
+            herd,
+            profiles: HashSet::new(),
+            initial_conditions: InitialConditions::new(),
+            sequence: AtomicU64::new(0),
+            appending: false,
+            model: PhantomData,
+        }
+    }
+
+    /// Gives out increasing numbers to order operations that happen at the same time and have
+    /// the same explicit order, in the order they were inserted.
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// The last sequence number given out.
+    pub fn sequence(&self) -> u64 {
+        self.sequence.load(Ordering::Relaxed)
+    }
+
+    /// Stages grounded operations that are inserted without disrupting anything, until
+    /// [Timelines::finish_append]. Nothing can be viewed in between.
+    pub(crate) fn start_append(&mut self) {
+        self.appending = true;
+    }
+
+    /// Merges the staged operations into their timelines.
+    pub(crate) fn finish_append(&mut self) {
+        self.appending = false;
+        for timeline in self.resources.values_mut() {
+            unsafe { (*timeline.get()).finish_append() };
+        }
+        for (_, timeline) in self.keys.get_mut().values_mut() {
+            unsafe { (*timeline.get()).finish_append() };
+        }
+    }
+
+    pub fn contains<R: Resource<'o>>(&self) -> bool {
+        self.resources.contains_key(&R::ID)
+    }
+
+    /// Creates the timeline of `R` from its initial conditions, unless it already has one.
+    ///
+    /// Fails if another instantiation of the same generic resource already has a timeline.
+    pub fn init_resource<R: Resource<'o>>(
+        &mut self,
+        time: Instant,
+        initial_conditions: &mut InitialConditions,
+    ) -> Result<(), EngineError> {
+        if let Some(existing) = self.instantiations.get(&R::ID) {
+            return if *existing == std::any::type_name::<R>() {
+                Ok(())
+            } else {
+                Err(EngineError::ConflictingInstantiations(R::LABEL))
+            };
+        }
+        let mut earlier = initial_conditions.take_profile::<R>().map_err(|source| {
+            EngineError::ProfileLoadFailed {
+                resource: R::LABEL,
+                source,
+            }
+        })?;
+        if !earlier.is_empty() {
+            self.profiles.insert(R::LABEL);
+            self.initial_conditions
+                .insert_profile_id::<R>(earlier.clone());
+        }
+        let instants = earlier
+            .iter()
+            .map(|(t, _)| epoch_to_instant(*t))
+            .collect::<Result<Vec<_>, _>>()?;
+        let split = instants.partition_point(|t| *t <= time);
+        let mut later = instants[split..]
+            .iter()
+            .copied()
+            .zip(earlier.split_off(split).into_iter().map(|(_, value)| value));
+        let initial = initial_conditions
+            .take_id::<R>(R::ID)
+            .or_else(|| earlier.pop().map(|(_, value)| value))
+            .or_else(|| later.next().map(|(_, value)| value))
+            .or_else(R::default_initial_condition)
+            .ok_or(EngineError::MissingInitialCondition(R::LABEL))?;
+        self.initial_conditions
+            .insert_id::<R>(R::ID, initial.clone());
+        self.init_for_resource::<R>(time, InitialConditionOp::new(time, initial));
+        for (t, value) in later {
+            let node = self
+                .herd
+                .get()
+                .alloc(InitialConditionOp::<R, M>::new(t, value));
+            // The timelines are borrowed mutably.
+            unsafe { self.timeline_mut::<R>(R::ID) }.insert_grounded((t, i32::MIN, 0), node, false);
+            self.herd.add_nodes(1);
+        }
+        self.init_keys::<R>(time, initial_conditions);
+        Ok(())
+    }
+
+    /// Whether the resource was given a boundary profile, see
+    /// [InitialConditions::insert_profile].
+    pub(crate) fn is_profile(&self, label: &str) -> bool {
+        self.profiles.contains(label)
+    }
+
+    /// The copy of the initial conditions that the timelines were created from.
+    pub(crate) fn initial_conditions_mut(&mut self) -> &mut InitialConditions {
+        &mut self.initial_conditions
+    }
+
+    pub fn init_for_resource<R: Resource<'o>>(
+        &mut self,
+        time: Instant,
+        op: InitialConditionOp<'o, R, M>,
+    ) {
+        assert!(!self.resources.contains_key(&R::ID));
+        self.instantiations
+            .insert(R::ID, std::any::type_name::<R>());
+        self.resources.insert(
+            R::ID,
+            UnsafeSyncCell::new(Box::new(Timeline::init(time, self.herd.get().alloc(op)))),
+        );
+        self.herd.add_nodes(1);
+    }
+
+    /// Creates the timelines of the keys of `R` that were given their own initial conditions.
+    pub fn init_keys<R: Resource<'o>>(
+        &mut self,
+        time: Instant,
+        initial_conditions: &mut InitialConditions,
+    ) {
+        let keys = self.keys.get_mut();
+        for (id, value) in initial_conditions.take_keys::<R>() {
+            self.initial_conditions.insert_id::<R>(id, value.clone());
+            self.herd.add_nodes(1);
+            keys.insert(
+                id,
+                (
+                    R::ID,
+                    UnsafeSyncCell::new(Box::new(Timeline::init(
+                        time,
+                        self.herd
+                            .get()
+                            .alloc(InitialConditionOp::<R, M>::new(time, value)),
+                    ))),
+                ),
+            );
+        }
+    }
+
+    /// Creates the timeline for a key of `R` the first time an operation touches it, starting
+    /// from the initial condition of the resource as a whole.
+    pub fn ensure_key<R: KeyedResource<'o>>(&self, id: u64) -> Result<(), EngineError> {
+        if self.keys.read().contains_key(&id) {
+            return Ok(());
+        }
+        let initial = unsafe {
+            (**self
+                .resources
+                .get(&R::ID)
+                .ok_or(EngineError::MissingInitialCondition(R::LABEL))?
+                .get())
+            .downcast::<Timeline<'o, R, M>>()
+            .initial
+        };
+        let op = InitialConditionOp::<R, M>::new(initial.time(), initial.value().clone());
+        self.keys.write().insert(
+            id,
+            (
+                R::ID,
+                UnsafeSyncCell::new(Box::new(Timeline::init(
+                    initial.time(),
+                    self.herd.get().alloc(op),
+                ))),
+            ),
+        );
+        self.herd.add_nodes(1);
+        Ok(())
+    }
+
+    /// The ids of the timelines of every key of `R` that has its own timeline.
+    pub(crate) fn key_ids<R: Resource<'o>>(&self) -> Vec<u64> {
+        self.keys
+            .read()
+            .iter()
+            .filter(|(_, (resource, _))| *resource == R::ID)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// The timeline `id`, which stays where it is for as long as the timelines do.
+    fn erased(&self, id: u64) -> Option<*mut dyn ErasedResource<'o>> {
+        let pointer = |timeline: &TimelineCell<'o>| unsafe { &raw mut **timeline.get() };
+        match self.resources.get(&id) {
+            Some(timeline) => Some(pointer(timeline)),
+            None => self
+                .keys
+                .read()
+                .get(&id)
+                .map(|(_, timeline)| pointer(timeline)),
+        }
+    }
+
+    /// Keyed resources whose key has never been touched use the timeline of the whole
+    /// resource, which only has the initial condition.
+    fn timeline<R: Resource<'o>>(&self, id: u64) -> Option<&Timeline<'o, R, M>> {
+        let timeline = match self.erased(id) {
+            Some(timeline) => timeline,
+            None => self.erased(R::ID)?,
+        };
+        unsafe { Some((*timeline).downcast::<Timeline<'o, R, M>>()) }
+    }
+
+    /// # Safety
+    ///
+    /// Nothing else can use the timeline `id` until the reference is dropped.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn timeline_mut<R: Resource<'o>>(&self, id: u64) -> &mut Timeline<'o, R, M> {
+        unsafe { (*self.erased(id).unwrap()).downcast_mut::<Timeline<'o, R, M>>() }
+    }
+
+    /// The `id` of the timeline is [Resource::ID], or the [key_id][crate::resource::key_id] of a keyed resource.
+    pub fn find_upstream<R: Resource<'o>>(
+        &self,
+        id: u64,
+        time: Instant,
+        order: Order,
+    ) -> Option<&'o dyn Upstream<'o, R, M>> {
+        self.timeline::<R>(id)?
+            .last_before((time, order.0, order.1), self.herd.get())
+    }
+
+    /// Whether anything writes the timeline `id` strictly before `time` and `order`, as in
+    /// [Timelines::find_upstream], but without allocating the upstream.
+    pub fn has_upstream<R: Resource<'o>>(&self, id: u64, time: Instant, order: Order) -> bool {
+        self.timeline::<R>(id).is_some_and(|timeline| {
+            timeline
+                .search_possible_upstreams((time, order.0, order.1))
+                .is_some()
+        })
+    }
+
+    /// # Safety
+    ///
+    /// Nothing else can use the timeline `id` until this returns.
+    pub unsafe fn insert_grounded<R: Resource<'o>>(
+        &self,
+        id: u64,
+        time: Instant,
+        order: Order,
+        op: &'o dyn Upstream<'o, R, M>,
+        disruptive: bool,
+    ) -> UpstreamVec<'o, R, M> {
+        self.herd.add_nodes(1);
+        let timeline = unsafe { self.timeline_mut::<R>(id) };
+        if self.appending && !disruptive {
+            timeline.append_grounded((time, order.0, order.1), op);
+            return UpstreamVec::new();
+        }
+        timeline.insert_grounded((time, order.0, order.1), op, disruptive)
+    }
+
+    /// # Safety
+    ///
+    /// Nothing else can use the timeline `id` until this returns.
+    pub unsafe fn remove_grounded<R: Resource<'o> + 'o>(
+        &self,
+        id: u64,
+        time: Instant,
+        order: Order,
+    ) -> bool {
+        let removed =
+            unsafe { self.timeline_mut::<R>(id) }.remove_grounded((time, order.0, order.1));
+        if removed {
+            self.herd.remove_nodes(1);
+        }
+        removed
+    }
+
+    /// # Safety
+    ///
+    /// Nothing else can use the timeline `id` until this returns.
+    pub unsafe fn insert_ungrounded<R: Resource<'o>>(
+        &self,
+        id: u64,
+        min: Instant,
+        max: Instant,
+        op: &'o dyn UngroundedUpstream<'o, R, M>,
+        disruptive: bool,
+    ) -> UpstreamVec<'o, R, M> {
+        self.herd.add_nodes(1);
+        unsafe { self.timeline_mut::<R>(id) }.insert_ungrounded(min, max, op, disruptive)
+    }
+
+    /// # Safety
+    ///
+    /// Nothing else can use the timeline `id` until this returns.
+    pub unsafe fn remove_ungrounded<R: Resource<'o> + 'o>(
+        &self,
+        id: u64,
+        min: Instant,
+        node: *const (),
+    ) -> bool {
+        let removed = unsafe { self.timeline_mut::<R>(id) }.remove_ungrounded(min, node);
+        if removed {
+            self.herd.remove_nodes(1);
+        }
+        removed
+    }
+
+    pub(crate) fn uncached<R: Resource<'o>>(&self, id: u64) -> Vec<(Instant, Option<Instant>)> {
+        self.timeline::<R>(id).unwrap().uncached()
+    }
+
+    pub(crate) fn range<R: Resource<'o>>(
+        &self,
+        id: u64,
+        bounds: impl RangeBounds<Instant>,
+    ) -> Vec<MaybeGrounded<'o, R, M>> {
+        self.timeline::<R>(id).unwrap().range(bounds)
+    }
+}
+
+// All Epochs/Times are converted to TAI durations because the Ord implementation
+// on Epoch does a timescale conversion every time, which is very inefficient.
+
+// TAI (international atomic time) is chosen as the base representation
+// because hifitime does all epoch conversions through TAI, so it is the most
+// efficient format to convert to.
+
+/// The representation of times inside the engine: timeline keys, groundings, and the times
+/// passed between operations.
+///
+/// By default this is the TAI duration since the hifitime reference epoch. With the `ticks`
+/// feature it is the same offset as a plain count of nanoseconds, which is much cheaper to
+/// compare and add when building large plans, but only covers roughly 292 years either side
+/// of 1900. Times at the API boundary are [Time]s and [Duration]s in both modes.
+#[cfg(not(feature = "ticks"))]
+pub type Instant = Duration;
+#[cfg(feature = "ticks")]
+pub type Instant = i64;
+
+/// Fails with [EngineError::TimeOutOfRange] if the time can't be represented, which only
+/// happens with the `ticks` feature.
+#[cfg(not(feature = "ticks"))]
+pub fn epoch_to_instant(time: Time) -> Result<Instant, EngineError> {
+    Ok(time.to_tai_duration())
+}
+#[cfg(not(feature = "ticks"))]
+pub fn instant_to_epoch(instant: Instant) -> Time {
+    Time {
+        duration: instant,
+        time_scale: TAI,
+    }
+}
+
+/// Offsets an instant, saturating at the ends of its range.
+#[cfg(not(feature = "ticks"))]
+pub fn advance(instant: Instant, by: Duration) -> Instant {
+    instant + by
+}
+
+/// The nanoseconds since the reference epoch, which is the same in both modes.
+#[cfg(not(feature = "ticks"))]
+pub fn instant_nanoseconds(instant: Instant) -> i128 {
+    instant.total_nanoseconds()
+}
+
+/// Converts the bounds of a view with [epoch_to_instant].
+pub fn instant_bounds(
+    bounds: &impl RangeBounds<Time>,
+) -> Result<(Bound<Instant>, Bound<Instant>), EngineError> {
+    let convert = |bound: Bound<&Time>| -> Result<_, EngineError> {
+        Ok(match bound {
+            Included(time) => Included(epoch_to_instant(*time)?),
+            Excluded(time) => Excluded(epoch_to_instant(*time)?),
+            Bound::Unbounded => Bound::Unbounded,
+        })
+    };
+    Ok((convert(bounds.start_bound())?, convert(bounds.end_bound())?))
+}
+
+#[cfg(feature = "ticks")]
+pub fn epoch_to_instant(time: Time) -> Result<Instant, EngineError> {
+    let nanoseconds = time.to_tai_duration().total_nanoseconds();
+    Instant::try_from(nanoseconds).map_err(|_| EngineError::TimeOutOfRange(time))
+}
+#[cfg(feature = "ticks")]
+pub fn instant_to_epoch(instant: Instant) -> Time {
+    Time {
+        duration: Duration::from_total_nanoseconds(instant as i128),
+        time_scale: TAI,
+    }
+}
+
+/// Offsets an instant, saturating at the ends of its range.
+#[cfg(feature = "ticks")]
+pub fn advance(instant: Instant, by: Duration) -> Instant {
+    let by = by
+        .total_nanoseconds()
+        .clamp(Instant::MIN as i128, Instant::MAX as i128) as Instant;
+    instant.saturating_add(by)
+}
+
+/// The nanoseconds since the reference epoch, which is the same in both modes.
+#[cfg(feature = "ticks")]
+pub fn instant_nanoseconds(instant: Instant) -> i128 {
+    instant as i128
+}
+
+/// Orders nodes that happen at the same time: first by an explicit order from the activity,
+/// lowest first, and then by [Timelines::next_sequence].
+pub type Order = (i32, u64);
+
+/// Grounded nodes are keyed by their time and then their [Order], so that nodes at the same
+/// time are kept in a stable order. Initial conditions come before everything else.
+type GroundedKey = (Instant, i32, u64);
+
+pub struct Timeline<'o, R: Resource<'o>, M: Model<'o>> {
+    grounded: BTreeMap<GroundedKey, &'o dyn Upstream<'o, R, M>>,
+    /// Grounded nodes staged by an [Append][crate::append::Append], which aren't in
+    /// `grounded` until [Timeline::finish_append].
+    appended: Vec<(GroundedKey, &'o dyn Upstream<'o, R, M>)>,
+    initial: &'o InitialConditionOp<'o, R, M>,
+    /// The spans of time that each ungrounded node might happen in.
+    ungrounded: IntervalTree<&'o dyn UngroundedUpstream<'o, R, M>>,
+}
+
+/// Ungrounded nodes are keyed by their latest possible time, and then by address so that
+/// nodes with the same bounds don't collide.
+type UngroundedKey = (Instant, usize);
+
+/// The nodes that might be the latest write before some time.
+struct PossibleUpstreams<'o, R: Resource<'o>, M: Model<'o>> {
+    grounded: Option<&'o dyn Upstream<'o, R, M>>,
+    ungrounded: BTreeMap<UngroundedKey, &'o dyn UngroundedUpstream<'o, R, M>>,
+}
+
+impl<'o, R: Resource<'o>, M: Model<'o>> PossibleUpstreams<'o, R, M> {
+    fn new(grounded: Option<&'o dyn Upstream<'o, R, M>>) -> Self {
+        PossibleUpstreams {
+            grounded,
+            ungrounded: BTreeMap::new(),
+        }
+    }
+
+    fn extend_ungrounded(
+        &mut self,
+        spans: impl IntoIterator<Item = (Instant, Instant, &'o dyn UngroundedUpstream<'o, R, M>)>,
+    ) {
+        self.ungrounded
+            .extend(spans.into_iter().map(|(_, max, node)| {
+                (
+                    (
+                        max,
+                        node as *const dyn UngroundedUpstream<'o, R, M> as *const () as usize,
+                    ),
+                    node,
+                )
+            }));
+    }
+
+    fn into_upstream(
+        self,
+        grounded_time: Instant,
+        eval_time: Instant,
+        bump: ArenaMember<'o>,
+    ) -> &'o dyn Upstream<'o, R, M> {
+        if self.ungrounded.is_empty() {
+            self.grounded.unwrap()
+        } else {
+            bump.alloc(UngroundedUpstreamResolver::new(
+                eval_time,
+                self.grounded.map(|g| (grounded_time, g)),
+                self.ungrounded.into_values().collect(),
+            ))
+        }
+    }
+
+    fn into_upstream_vec(self) -> UpstreamVec<'o, R, M> {
+        let mut result: UpstreamVec<'o, R, M> = self
+            .ungrounded
+            .into_values()
+            .map(|ug| ug.as_ref())
+            .collect();
+        result.extend(self.grounded);
+        result
+    }
+}
+
+impl<'o, R: Resource<'o>, M: Model<'o>> Timeline<'o, R, M> {
+    pub fn init(
+        time: Instant,
+        initial_condition: &'o InitialConditionOp<'o, R, M>,
+    ) -> Timeline<'o, R, M> {
+        Timeline {
+            grounded: BTreeMap::from([(
+                (time, i32::MIN, 0),
+                initial_condition as &'o dyn Upstream<'o, R, M>,
+            )]),
+            appended: vec![],
+            initial: initial_condition,
+            ungrounded: IntervalTree::new(),
+        }
+    }
+
+    /// Finds every node that might be the latest write strictly before `key`.
+    ///
+    /// The latest grounded write is always included. Ungrounded nodes that are certain to
+    /// happen before `time` hide everything that is certain to happen before they can, so the
+    /// search only goes back as far as the latest start of those nodes. Returns the time of the
+    /// grounded write, or the time the search stopped at if there isn't one.
+    fn search_possible_upstreams(
+        &self,
+        key: GroundedKey,
+    ) -> Option<(Instant, PossibleUpstreams<'o, R, M>)> {
+        let time = key.0;
+        let grounded = self.grounded.range(..key).next_back();
+        let definite = self.ungrounded.latest_start_ending_before(time);
+        let stop = match (grounded, definite) {
+            (Some((&(g, ..), _)), Some(d)) => g.max(d),
+            (Some((&(g, ..), _)), None) => g,
+            (None, Some(d)) => d,
+            (None, None) => return None,
+        };
+
+        let mut result = PossibleUpstreams::new(grounded.map(|(_, g)| *g));
+        result.extend_ungrounded(
+            self.ungrounded
+                .overlapping(stop, Excluded(time))
+                .into_iter()
+                .filter(|(min, max, _)| *max > stop || *min >= stop),
+        );
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            resource = R::LABEL,
+            time = %instant_to_epoch(time),
+            stop = %instant_to_epoch(stop),
+            grounded = result.grounded.is_some(),
+            ungrounded = result.ungrounded.len(),
+            "upstream search"
+        );
+        Some((grounded.map(|(&(g, ..), _)| g).unwrap_or(stop), result))
+    }
+
+    pub fn last_before(
+        &self,
+        key: GroundedKey,
+        bump: ArenaMember<'o>,
+    ) -> Option<&'o dyn Upstream<'o, R, M>> {
+        let (grounded_time, possible) = self.search_possible_upstreams(key)?;
+        Some(possible.into_upstream(grounded_time, key.0, bump))
+    }
+
+    /// An ordinary [BTreeMap] insertion, `O(log n)` in the number of grounded operations even
+    /// when the plan is built in chronological order. [Timeline::append_grounded] is the
+    /// amortized `O(1)` path for that, through [Plan::append][crate::Plan::append].
+    pub fn insert_grounded(
+        &mut self,
+        key: GroundedKey,
+        value: &'o dyn Upstream<'o, R, M>,
+        disruptive: bool,
+    ) -> UpstreamVec<'o, R, M> {
+        #[cfg(not(feature = "nightly"))]
+        self.grounded.insert(key, value);
+        #[cfg(feature = "nightly")]
+        self.insert_with_cursor(key, value);
+
+        if disruptive {
+            self.search_possible_upstreams(key)
+                .map(|(_, e)| e.into_upstream_vec())
+                .unwrap_or_default()
+        } else {
+            UpstreamVec::new()
+        }
+    }
+
+    /// Plans are usually built in chronological order, so this first tries to append with a
+    /// cursor at the end of the map, which skips the key comparisons of a search. Finding the
+    /// end still descends the map, so this is a constant factor and not a complexity change.
+    #[cfg(feature = "nightly")]
+    fn insert_with_cursor(&mut self, key: GroundedKey, value: &'o dyn Upstream<'o, R, M>) {
+        let mut cursor_mut = self.grounded.upper_bound_mut(Bound::Unbounded);
+        match cursor_mut.peek_prev() {
+            Some((last, _)) if *last < key => cursor_mut.insert_after(key, value).unwrap(),
+            _ => {
+                self.grounded.insert(key, value);
+            }
+        }
+    }
+
+    /// Stages a grounded node to be merged into the map by [Timeline::finish_append].
+    pub fn append_grounded(&mut self, key: GroundedKey, value: &'o dyn Upstream<'o, R, M>) {
+        self.appended.push((key, value));
+    }
+
+    pub fn remove_grounded(&mut self, key: GroundedKey) -> bool {
+        // The node might be staged, if an append is rolling back an activity.
+        self.finish_append();
+        self.grounded.remove(&key).is_some()
+    }
+
+    pub fn insert_ungrounded(
+        &mut self,
+        min: Instant,
+        max: Instant,
+        value: &'o dyn UngroundedUpstream<'o, R, M>,
+        disruptive: bool,
+    ) -> UpstreamVec<'o, R, M> {
+        // Need to collect the list of all nodes that might lose a downstream after this change
+        let mut result = UpstreamVec::new();
+        if disruptive {
+            let mut collector = self
+                .search_possible_upstreams((min, i32::MIN, 0))
+                .map(|(_, e)| e)
+                .unwrap_or_else(|| PossibleUpstreams::new(None));
+            collector.extend_ungrounded(self.ungrounded.overlapping(min, Included(max)));
+            result.extend(
+                self.grounded
+                    .range((min, i32::MIN, 0)..=(max, i32::MAX, u64::MAX))
+                    .map(|(_, g)| *g),
+            );
+            result.extend(collector.into_upstream_vec());
+        }
+
+        self.ungrounded.insert(
+            min,
+            max,
+            value as *const dyn UngroundedUpstream<'o, R, M> as *const () as usize,
+            value,
+        );
+        result
+    }
+
+    pub fn remove_ungrounded(&mut self, min: Instant, node: *const ()) -> bool {
+        self.ungrounded.remove(min, node as usize)
+    }
+
+    /// The spans of time whose values a view would have to evaluate again, because they come
+    /// from operations that aren't cached, ordered and merged. Each span starts at the
+    /// earliest time of an uncached operation and ends at the next grounded operation after
+    /// its latest time, or is unbounded if there isn't one.
+    pub fn uncached(&self) -> Vec<(Instant, Option<Instant>)> {
+        let next_after = |time: Instant| {
+            self.grounded
+                .range((Excluded((time, i32::MAX, u64::MAX)), Bound::Unbounded))
+                .next()
+                .map(|(&(t, ..), _)| t)
+        };
+        let mut spans: Vec<_> = self
+            .grounded
+            .iter()
+            .filter(|(_, op)| !op.is_cached())
+            .map(|(&(t, ..), _)| (t, next_after(t)))
+            .chain(
+                self.ungrounded
+                    .overlapping(Instant::MIN, Bound::Unbounded)
+                    .into_iter()
+                    .filter(|(.., op)| !op.as_ref().is_cached())
+                    .map(|(min, max, _)| (min, next_after(max))),
+            )
+            .collect();
+        spans.sort();
+
+        let mut merged: Vec<(Instant, Option<Instant>)> = Vec::with_capacity(spans.len());
+        for (start, end) in spans {
+            match merged.last_mut() {
+                Some((_, last_end)) if last_end.is_none_or(|last_end| start <= last_end) => {
+                    *last_end = last_end.zip(end).map(|(a, b)| a.max(b));
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+
+    pub fn range(&self, range: impl RangeBounds<Instant>) -> Vec<MaybeGrounded<'o, R, M>> {
+        let start_time = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => Some(*start),
+            _ => None,
+        };
+        let end_bound = range.end_bound().cloned();
+        let key_bounds = (
+            match range.start_bound() {
+                Bound::Included(t) => Bound::Included((*t, i32::MIN, 0)),
+                Bound::Excluded(t) => Bound::Excluded((*t, i32::MAX, u64::MAX)),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+            match end_bound {
+                Bound::Included(t) => Bound::Included((t, i32::MAX, u64::MAX)),
+                Bound::Excluded(t) => Bound::Excluded((t, i32::MIN, 0)),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+        );
+        let mut result: Vec<_> = self
+            .grounded
+            .range(key_bounds)
+            .map(|((t, ..), g)| MaybeGrounded::Grounded(*t, *g))
+            .collect();
+
+        let from = start_time.unwrap_or(Instant::MIN);
+        let mut collector = PossibleUpstreams::new(None);
+        collector.extend_ungrounded(
+            self.ungrounded
+                .overlapping(from, end_bound)
+                .into_iter()
+                .filter(|(min, max, _)| *max > from || *min >= from),
+        );
+
+        if let Some(t) = start_time
+            && (result.is_empty()
+                || matches!(result[0], MaybeGrounded::Grounded(first_ground_time, _) if first_ground_time > t))
+        {
+            let (grounded_time, below) = self.search_possible_upstreams((t, i32::MIN, 0))
+                .expect("Cannot find operations to cover the beginning of view range. Did you request before the initial conditions?");
+            if let Some(gr) = below.grounded {
+                result.push(MaybeGrounded::Grounded(grounded_time, gr));
+            }
+            collector.ungrounded.extend(below.ungrounded);
+        }
+
+        result.extend(
+            collector
+                .ungrounded
+                .into_values()
+                .map(|ug| MaybeGrounded::Ungrounded(ug)),
+        );
+        #[cfg(feature = "tracing")]
+        tracing::trace!(resource = R::LABEL, nodes = result.len(), "range search");
+        result
+    }
+}
+
+impl<'o, R: Resource<'o>, M: Model<'o>> ErasedResource<'o> for Timeline<'o, R, M> {
+    fn id(&self) -> u64 {
+        R::ID
+    }
+
+    /// Merges the staged nodes into the map, in one pass over both.
+    fn finish_append(&mut self) {
+        if !self.appended.is_empty() {
+            let mut appended: BTreeMap<_, _> = self.appended.drain(..).collect();
+            self.grounded.append(&mut appended);
+        }
+    }
+}
+
+pub enum MaybeGrounded<'o, R: Resource<'o>, M: Model<'o>> {
+    Grounded(Instant, &'o dyn Upstream<'o, R, M>),
+    Ungrounded(&'o dyn UngroundedUpstream<'o, R, M>),
+}
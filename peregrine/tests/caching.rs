@@ -1,5 +1,6 @@
 mod util;
 
+use peregrine::random::{Rng, random_seed};
 use peregrine::*;
 use std::sync::atomic::Ordering;
 use util::*;
@@ -143,3 +144,1111 @@ fn load_cache_after_rollbacks_sim_in_between() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn tiered_history_promotes_evicted_entries() -> Result<()> {
+    use peregrine::history::disk::DiskHistory;
+
+    let path = std::env::temp_dir().join(format!("peregrine-tiered-{}", std::process::id()));
+    let mut session = Session::from(History::tiered(DiskHistory::open(&path)?, 0));
+
+    {
+        let mut plan = init_plan(&session);
+        let (node, counter) = EvalCounter::new();
+        plan.insert(seconds(0), IncrementA)?;
+        plan.insert(seconds(1), node)?;
+        assert_eq!(1, plan.sample::<a>(seconds(1))?);
+        assert_eq!(1, counter.load(Ordering::SeqCst));
+    }
+
+    // Values of resources that aren't `ref` are evicted as they are inserted.
+    assert!(session.history().is_empty());
+    session.history().flush();
+    session.enforce_history_capacity();
+
+    {
+        let mut plan = init_plan(&session);
+        let (node, counter) = EvalCounter::new();
+        plan.insert(seconds(0), IncrementA)?;
+        plan.insert(seconds(1), node)?;
+        assert_eq!(1, plan.sample::<a>(seconds(1))?);
+        assert_eq!(0, counter.load(Ordering::SeqCst));
+    }
+
+    drop(session);
+    assert!(!DiskHistory::open(&path)?.is_empty());
+    std::fs::remove_file(path)?;
+
+    Ok(())
+}
+
+#[test]
+fn disk_history_discards_records_longer_than_the_file() -> Result<()> {
+    use peregrine::history::HistoryBackend;
+    use peregrine::history::disk::DiskHistory;
+
+    let path = std::env::temp_dir().join(format!("peregrine-corrupt-{}", std::process::id()));
+    // A record whose type string claims to be 4 GiB long.
+    std::fs::write(&path, u32::MAX.to_le_bytes())?;
+    assert!(DiskHistory::open(&path)?.is_empty());
+
+    // A record whose value claims to be 4 GiB long.
+    let mut record = 3u32.to_le_bytes().to_vec();
+    record.extend_from_slice(b"u32");
+    record.extend_from_slice(&0u64.to_le_bytes());
+    record.extend_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(&path, record)?;
+    let disk = DiskHistory::open(&path)?;
+    assert!(disk.is_empty());
+
+    // The truncated records are overwritten.
+    disk.insert("u32", 0, vec![1]);
+    drop(disk);
+    assert_eq!(1, DiskHistory::open(&path)?.len());
+    std::fs::remove_file(path)?;
+
+    Ok(())
+}
+
+struct Forgetful;
+impl peregrine::history::HistoryBackend for Forgetful {
+    fn get(&self, _type_string: &str, _hash: u64) -> Option<std::borrow::Cow<'_, [u8]>> {
+        None
+    }
+    fn insert(&self, _type_string: &'static str, _hash: u64, _value: Vec<u8>) {}
+}
+
+#[test]
+fn tiered_history_evicts_least_recently_used() {
+    let history = History::tiered(Forgetful, 8);
+    history.init::<a>();
+    for hash in 0..8 {
+        history.insert::<a>(hash, hash as u32);
+    }
+    assert_eq!(Some(0), history.get::<a>(0));
+
+    history.insert::<a>(8, 8);
+    assert_eq!(8, history.len());
+    assert_eq!(Some(0), history.get::<a>(0));
+    assert_eq!(None, history.get::<a>(1));
+    assert_eq!(Some(8), history.get::<a>(8));
+}
+
+#[test]
+fn resume_checkpointed_view() -> Result<()> {
+    use peregrine::checkpoint::Checkpoint;
+    use peregrine::history::disk::DiskHistory;
+
+    let directory =
+        std::env::temp_dir().join(format!("peregrine-checkpoint-{}", std::process::id()));
+    std::fs::create_dir_all(&directory)?;
+    let history_path = directory.join("history");
+    let checkpoint_path = directory.join("a.checkpoint");
+    let every = Duration::from_seconds(2.0);
+
+    let (early, early_counter) = EvalCounter::new();
+    {
+        let session = Session::from(History::new().with_backend(DiskHistory::open(&history_path)?));
+        let mut plan = init_plan(&session);
+        plan.insert(seconds(0), IncrementA)?;
+        plan.insert(seconds(1), early)?;
+        plan.insert(seconds(2), IncrementA)?;
+        plan.insert(seconds(5), EvalCounter::new().0)?;
+        plan.insert(seconds(6), IncrementA)?;
+
+        let mut chunks = vec![];
+        let crashed =
+            plan.view_checkpointed::<a>(seconds(-1).., every, &checkpoint_path, |chunk| {
+                if chunks.len() == 2 {
+                    anyhow::bail!("crashed");
+                }
+                chunks.push(chunk);
+                Ok(())
+            });
+        assert!(crashed.is_err());
+        assert_eq!(
+            vec![
+                vec![(seconds(-1), 0), (seconds(0), 1)],
+                vec![(seconds(1), 1), (seconds(2), 2)]
+            ],
+            chunks
+        );
+        assert_eq!(
+            seconds(3),
+            Checkpoint::load(&checkpoint_path)?.unwrap().frontier
+        );
+        assert_eq!(1, early_counter.load(Ordering::SeqCst));
+    }
+
+    // As if the process had restarted, with only the history and the checkpoint left.
+    let (early, early_counter) = EvalCounter::new();
+    let (late, late_counter) = EvalCounter::new();
+    let session = Session::from(History::new().with_backend(DiskHistory::open(&history_path)?));
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), early)?;
+    plan.insert(seconds(2), IncrementA)?;
+    plan.insert(seconds(5), late)?;
+    plan.insert(seconds(6), IncrementA)?;
+
+    assert!(
+        plan.view_checkpointed::<b>(seconds(-1).., every, &checkpoint_path, |_| Ok(()))
+            .is_err()
+    );
+
+    let mut values = vec![];
+    plan.view_checkpointed::<a>(seconds(-1).., every, &checkpoint_path, |chunk| {
+        values.extend(chunk);
+        Ok(())
+    })?;
+    assert_eq!(vec![(seconds(5), 2), (seconds(6), 3)], values);
+    assert_eq!(0, early_counter.load(Ordering::SeqCst));
+    assert_eq!(1, late_counter.load(Ordering::SeqCst));
+    assert!(Checkpoint::load(&checkpoint_path)?.is_none());
+
+    drop(plan);
+    drop(session);
+    std::fs::remove_dir_all(directory)?;
+
+    Ok(())
+}
+
+/// Records the batches it receives, announcing each one and then waiting until the test lets
+/// it through.
+struct BatchRecorder {
+    batches: std::sync::Arc<parking_lot::Mutex<Vec<Vec<u64>>>>,
+    started: crossbeam::channel::Sender<()>,
+    gate: crossbeam::channel::Receiver<()>,
+}
+
+impl peregrine::history::HistoryBackend for BatchRecorder {
+    fn get(&self, _type_string: &str, _hash: u64) -> Option<std::borrow::Cow<'_, [u8]>> {
+        None
+    }
+
+    fn insert(&self, type_string: &'static str, hash: u64, value: Vec<u8>) {
+        self.insert_batch(vec![(type_string, hash, value)]);
+    }
+
+    fn insert_batch(&self, entries: Vec<(&'static str, u64, Vec<u8>)>) {
+        let _ = self.started.send(());
+        let _ = self.gate.recv();
+        self.batches
+            .lock()
+            .push(entries.into_iter().map(|(_, hash, _)| hash).collect());
+    }
+}
+
+#[test]
+fn write_back_batches_inserts() {
+    use crossbeam::channel::unbounded;
+    use peregrine::history::HistoryBackend;
+    use peregrine::history::tiered::WriteBack;
+
+    let batches = std::sync::Arc::new(parking_lot::Mutex::new(vec![]));
+    let (started, wait_started) = unbounded();
+    let (open, gate) = unbounded();
+    let write_back = WriteBack::with_limits(
+        BatchRecorder {
+            batches: batches.clone(),
+            started,
+            gate,
+        },
+        16,
+        4,
+    );
+
+    // The first insert is written on its own, and the rest queue up while it is being written.
+    write_back.insert("u32", 0, vec![]);
+    wait_started.recv().unwrap();
+    for hash in 1..10 {
+        write_back.insert("u32", hash, vec![]);
+    }
+    for _ in 0..4 {
+        open.send(()).unwrap();
+    }
+    write_back.flush();
+
+    assert_eq!(
+        vec![vec![0], vec![1, 2, 3, 4], vec![5, 6, 7, 8], vec![9]],
+        *batches.lock()
+    );
+}
+
+pub struct NonDeterministic(std::sync::atomic::AtomicU32);
+impl_activity! { for NonDeterministic
+    @(start) {
+        ref mut:a += self.0.fetch_add(1, Ordering::SeqCst);
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn replay_recorded_view() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), SetBToA)?;
+    plan.insert(seconds(2), AddBToA)?;
+
+    let (view, recording) = plan.view_recorded::<a>(seconds(2)..=seconds(2))?;
+    assert_eq!(2, view[0].1);
+    assert_eq!(3, recording.executions.len());
+    assert!(recording.executions.iter().all(|e| !e.cached));
+    assert_eq!("AddBToA", recording.executions.last().unwrap().activity);
+    plan.replay(&recording)?;
+
+    let (_, recording) = plan.view_recorded::<a>(seconds(2)..=seconds(2))?;
+    assert!(recording.executions.is_empty());
+
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), SetBToA)?;
+    plan.insert(seconds(2), AddBToA)?;
+    let (_, recording) = plan.view_recorded::<a>(seconds(2)..=seconds(2))?;
+    assert_eq!(3, recording.executions.len());
+    assert!(recording.executions.iter().all(|e| e.cached));
+    plan.replay(&recording)?;
+
+    plan.insert(seconds(3), NonDeterministic(0.into()))?;
+    let (_, recording) = plan.view_recorded::<a>(seconds(3)..=seconds(3))?;
+    assert!(plan.replay(&recording).is_err());
+
+    Ok(())
+}
+
+pub struct AddAllToA(Vec<u32>);
+impl_activity! { for AddAllToA
+    @(start) {
+        ref mut:a += self.0.iter().sum::<u32>();
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn replay_after_removing_recorded_activity() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let id = plan.insert(seconds(0), AddAllToA(vec![1, 2, 3]))?;
+    let (view, recording) = plan.view_recorded::<a>(seconds(1)..=seconds(1))?;
+    assert_eq!(6, view[0].1);
+    plan.remove(id)?;
+    plan.insert(seconds(0), AddAllToA(vec![u32::MAX; 4]))?;
+
+    // The removed activity is kept alive by the recording.
+    assert_eq!("AddAllToA", recording.executions.last().unwrap().activity);
+    plan.replay(&recording)?;
+
+    Ok(())
+}
+
+#[test]
+fn detect_non_determinism() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), NonDeterministic(0.into()))?;
+    assert!(plan.sample::<a>(seconds(1)).is_ok());
+
+    plan.set_determinism_check(DeterminismCheck::Uncached);
+    plan.insert(seconds(2), NonDeterministic(0.into()))?;
+    assert!(plan.sample::<a>(seconds(2)).is_err());
+
+    // The poisoned value from before the check was enabled is caught when re-checking cache hits.
+    let mut plan = init_plan(&session);
+    plan.set_determinism_check(DeterminismCheck::Sampled(1));
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), NonDeterministic(5.into()))?;
+    assert!(plan.sample::<a>(seconds(1)).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn audit_finds_hidden_state() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), NonDeterministic(0.into()))?;
+    plan.insert(seconds(2), SetBToA)?;
+    assert!(plan.audit::<b>(seconds(2)..=seconds(2))?.is_empty());
+
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    let hidden = plan.insert(seconds(1), NonDeterministic(3.into()))?;
+    plan.insert(seconds(2), SetBToA)?;
+    let divergences = plan.audit::<b>(seconds(2)..=seconds(2))?;
+    assert_eq!(1, divergences.len());
+    assert_eq!("NonDeterministic", divergences[0].activity);
+    assert_eq!(hidden, divergences[0].activity_id);
+    assert_eq!(0, divergences[0].op_index);
+    assert_eq!(&["a"], divergences[0].writes);
+    assert_eq!(seconds(1), divergences[0].time);
+
+    Ok(())
+}
+
+pub trait Step: Send + Sync {
+    const STEP: u32;
+}
+
+pub struct One;
+impl Step for One {
+    const STEP: u32 = 1;
+}
+
+pub struct Ten;
+impl Step for Ten {
+    const STEP: u32 = 10;
+}
+
+pub struct IncrementBy<S>(std::marker::PhantomData<S>);
+impl_activity! { for<S: Step> IncrementBy<S> where S: 'static
+    @(start) {
+        ref mut: a += S::STEP;
+    }
+    Duration::ZERO
+}
+
+impl<S> IncrementBy<S> {
+    fn new() -> Self {
+        IncrementBy(std::marker::PhantomData)
+    }
+}
+
+#[test]
+fn generic_activities_cache_separately() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let id = plan.insert(seconds(0), IncrementBy::<One>::new())?;
+    assert_eq!(1, plan.sample::<a>(seconds(1))?);
+
+    plan.remove(id)?;
+    plan.insert(seconds(0), IncrementBy::<Ten>::new())?;
+    assert_eq!(10, plan.sample::<a>(seconds(1))?);
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct AddToA(u32);
+impl_activity! { for AddToA
+    @(start) {
+        ref mut: a += self.0;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn serialized_arguments_cache_separately() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let id = plan.insert(seconds(0), AddToA(1))?;
+    assert_eq!(1, plan.sample::<a>(seconds(1))?);
+
+    plan.remove(id)?;
+    plan.insert(seconds(0), AddToA(5))?;
+    assert_eq!(5, plan.sample::<a>(seconds(1))?);
+
+    Ok(())
+}
+
+#[test]
+fn monte_carlo_shares_history() -> Result<()> {
+    let session = Session::new();
+    let (_, evaluations) = EvalCounter::new();
+    let counter = evaluations.clone();
+
+    let results = session
+        .monte_carlo(
+            move |session, amount| {
+                let mut plan = init_plan(session);
+                plan.insert(seconds(0), IncrementA)?;
+                plan.insert(seconds(1), EvalCounter(counter.clone()))?;
+                plan.insert(seconds(2), AddToA(amount))?;
+                Ok(plan)
+            },
+            5,
+            |i| i as u32,
+        )
+        .record::<a>([seconds(1), seconds(3)])
+        .run()?;
+
+    assert_eq!(1, evaluations.load(Ordering::SeqCst));
+    let a = results.get::<a>().unwrap();
+    assert_eq!(&[1.0, 2.0, 3.0, 4.0, 5.0], a.samples(1));
+    assert_eq!(
+        vec![(seconds(1), 1.0), (seconds(3), 3.0)],
+        a.percentile(50.0)
+    );
+    assert_eq!(
+        vec![(seconds(1), 1.0, 1.0), (seconds(3), 1.4, 4.6)],
+        a.envelope(10.0, 90.0)
+    );
+    assert_eq!(vec![(seconds(1), 1.0), (seconds(3), 3.0)], a.mean());
+
+    Ok(())
+}
+
+#[test]
+fn sweep_shares_history() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    let (node, evaluations) = EvalCounter::new();
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), node)?;
+    let id = plan.insert(seconds(2), AddToA(0))?;
+    plan.insert(seconds(3), SetBToA)?;
+
+    let results = plan.sweep(
+        id,
+        1..=4,
+        |&amount| AddToA(amount),
+        |plan| plan.sample::<b>(seconds(4)),
+    )?;
+    assert_eq!(vec![(1, 2), (2, 3), (3, 4), (4, 5)], results);
+    assert_eq!(1, evaluations.load(Ordering::SeqCst));
+    assert_eq!(5, plan.sample::<a>(seconds(4))?);
+
+    Ok(())
+}
+
+pub struct AddTimeToA;
+impl_activity! { for AddTimeToA
+    @(start) {
+        ref mut: a += now.to_tai_seconds() as u32;
+    }
+    @(start) + (Duration::from_seconds(ref: b as f64)) {
+        ref mut: b += now.to_tai_seconds() as u32;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn time_dependent_operations() -> Result<()> {
+    let session = Session::new();
+
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(2), AddTimeToA)?;
+    assert_eq!(2, plan.sample::<a>(seconds(3))?);
+    assert_eq!(2, plan.sample::<b>(seconds(3))?);
+    drop(plan);
+
+    // The same inputs at a different time can't reuse the cached result.
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(3), AddTimeToA)?;
+    assert_eq!(3, plan.sample::<a>(seconds(4))?);
+
+    // The delayed operation sees its grounded time, not the start.
+    plan.insert(seconds(0), IncrementB)?;
+    assert_eq!(5, plan.sample::<b>(seconds(5))?);
+
+    Ok(())
+}
+
+pub struct CommutativeIncrementA;
+impl_activity! { for CommutativeIncrementA
+    @(start) commutative {
+        ref mut: a += 1;
+    }
+    Duration::ZERO
+}
+
+pub struct CommutativeAddTwoToA;
+impl_activity! { for CommutativeAddTwoToA
+    @(start) commutative {
+        ref mut: a += 2;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn commutative_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    let (node, counter) = EvalCounter::new();
+
+    let moved = plan.insert(seconds(0), CommutativeIncrementA)?;
+    plan.insert(seconds(1), CommutativeAddTwoToA)?;
+    plan.insert(seconds(3), node)?;
+
+    assert_eq!(3, plan.sample::<a>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    // Reordering the commutative operations doesn't invalidate anything downstream.
+    plan.remove(moved)?;
+    plan.insert(seconds(2), CommutativeIncrementA)?;
+    assert_eq!(3, plan.sample::<a>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    // But changing what they add up to does.
+    plan.insert(seconds(2), CommutativeIncrementA)?;
+    assert_eq!(4, plan.sample::<a>(seconds(3))?);
+    assert_eq!(2, counter.load(Ordering::SeqCst));
+
+    Ok(())
+}
+
+resource!(array heaters: [u32; 3] = 0);
+
+model! {
+    Heaters(heaters[..])
+}
+
+pub struct HeaterCounter(std::sync::Arc<std::sync::atomic::AtomicU16>);
+impl_activity! { for HeaterCounter
+    @(start) {
+        ref mut: heaters[0] += 1;
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+    Duration::ZERO
+}
+
+pub struct CopyHeaterOneToTwo;
+impl_activity! { for CopyHeaterOneToTwo
+    @(start) {
+        mut: heaters[2] = ref: heaters[1] + 10;
+    }
+    Duration::ZERO
+}
+
+pub struct IncrementHeaterOne;
+impl_activity! { for IncrementHeaterOne
+    @(start) {
+        ref mut: heaters[1] += 1;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn array_elements_are_tracked_separately() -> Result<()> {
+    let session = Session::new();
+    let mut plan: Plan<Heaters> =
+        session.new_plan(seconds(-1), initial_conditions! { heaters[1]: 5 })?;
+    let counter = std::sync::Arc::new(std::sync::atomic::AtomicU16::new(0));
+
+    plan.insert(seconds(1), HeaterCounter(counter.clone()))?;
+    plan.insert(seconds(2), CopyHeaterOneToTwo)?;
+
+    assert_eq!(1, plan.sample::<heaters<0>>(seconds(3))?);
+    assert_eq!(15, plan.sample_heaters::<2>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    // Writing another element doesn't invalidate the operation on the first.
+    plan.insert(seconds(0), IncrementHeaterOne)?;
+    assert_eq!(16, plan.sample::<heaters<2>>(seconds(3))?);
+    assert_eq!(1, plan.sample::<heaters<0>>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    assert_eq!("heaters[2]", <heaters<2> as resource::Resource>::LABEL);
+
+    Ok(())
+}
+
+resource!(
+    struct rover {
+    speed: u32 = 0,
+    /// Degrees from north
+    #[unit = "deg"]
+    heading: u32 = 90,
+}
+);
+
+model! {
+    Rover(rover::*)
+}
+
+pub struct CountedTurn(std::sync::Arc<std::sync::atomic::AtomicU16>);
+impl_activity! { for CountedTurn
+    @(start) {
+        ref mut: rover.heading += 45;
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+    Duration::ZERO
+}
+
+pub struct Accelerate;
+impl_activity! { for Accelerate
+    @(start) {
+        ref mut: rover.speed += ref: rover.heading / 45;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn struct_fields_are_tracked_separately() -> Result<()> {
+    let session = Session::new();
+    let mut plan: Plan<Rover> = session.new_plan(seconds(-1), InitialConditions::new())?;
+    let counter = std::sync::Arc::new(std::sync::atomic::AtomicU16::new(0));
+
+    plan.insert(seconds(1), CountedTurn(counter.clone()))?;
+    plan.insert(seconds(2), Accelerate)?;
+
+    assert_eq!(135, plan.sample::<rover::heading>(seconds(3))?);
+    assert_eq!(3, plan.sample::<rover::speed>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    // Writing the speed doesn't invalidate the operation on the heading.
+    plan.insert(seconds(0), Accelerate)?;
+    assert_eq!(5, plan.sample::<rover::speed>(seconds(3))?);
+    assert_eq!(135, plan.sample::<rover::heading>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    assert_eq!(
+        "rover.heading [deg] - Degrees from north",
+        resource::ResourceInfo::of::<rover::heading>().to_string()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn replace_activity() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    let (node, counter) = EvalCounter::new();
+
+    let id = plan.insert_with_key("b-setter", seconds(0), IncrementB)?;
+    plan.insert(seconds(1), node)?;
+    plan.insert(seconds(2), AddBToA)?;
+
+    assert_eq!(1, plan.sample::<a>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    plan.replace(id, SetBToA)?;
+    assert_eq!(Some(id), plan.get_id("b-setter"));
+    assert_eq!("SetBToA", plan.span(id).unwrap().label);
+
+    assert_eq!(0, plan.sample::<a>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    plan.replace(id, IncrementB)?;
+    assert_eq!(1, plan.sample::<a>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    Ok(())
+}
+
+#[test]
+fn subscriptions() -> Result<()> {
+    use peregrine::subscription::{Notification, OnInvalidate};
+    use std::ops::Bound;
+    use std::sync::mpsc;
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    for i in 0..4 {
+        plan.insert(seconds(2 * i), IncrementA)?;
+    }
+    plan.view::<a>(seconds(0)..)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let subscription = plan.subscribe::<a>(seconds(0).., OnInvalidate::Notify, move |n| {
+        sender.send(n).unwrap();
+    });
+
+    // b doesn't affect a.
+    plan.insert(seconds(1), IncrementB)?;
+    assert!(receiver.try_recv().is_err());
+
+    plan.insert(seconds(3), IncrementA)?;
+    let Ok(Notification::Invalidated(ranges)) = receiver.try_recv() else {
+        panic!("expected a notification");
+    };
+    assert_eq!(
+        vec![(Bound::Included(seconds(3)), Bound::Unbounded)],
+        ranges
+    );
+
+    // Already stale, so there is nothing new to report.
+    plan.insert(seconds(5), IncrementA)?;
+    assert!(receiver.try_recv().is_err());
+
+    assert_eq!(6, plan.sample::<a>(seconds(6))?);
+    assert!(plan.unsubscribe(subscription));
+    plan.insert(seconds(7), IncrementA)?;
+    assert!(receiver.try_recv().is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn streamed_events() -> Result<()> {
+    use peregrine::stream::EventServer;
+    use std::io::{BufRead, BufReader, Write};
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    plan.view::<a>(seconds(0)..)?;
+    plan.view::<b>(seconds(0)..)?;
+
+    let server = EventServer::bind("127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    let publisher = server.publisher();
+    plan.stream::<a>(seconds(0).., &publisher);
+    plan.stream::<b>(seconds(0).., &publisher);
+    server.spawn();
+
+    let connect = |target: &str| -> Result<BufReader<std::net::TcpStream>> {
+        let mut stream = std::net::TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+        write!(
+            stream,
+            "GET {target} HTTP/1.1\r\nAccept: text/event-stream\r\n\r\n"
+        )?;
+        let mut stream = BufReader::new(stream);
+        let mut status = String::new();
+        stream.read_line(&mut status)?;
+        let mut line = status.clone();
+        while line != "\r\n" {
+            line.clear();
+            stream.read_line(&mut line)?;
+        }
+        assert!(status.starts_with("HTTP/1.1 200"), "{status}");
+        Ok(stream)
+    };
+    let only_b = connect("/events?resources=b")?;
+    let all = connect("/events")?;
+    assert_eq!(2, publisher.clients());
+
+    plan.insert(seconds(1), IncrementA)?;
+    plan.insert(seconds(2), IncrementB)?;
+    let events = |stream: BufReader<_>, count| -> Result<Vec<String>> {
+        Ok(stream.lines().take(count).collect::<Result<_, _>>()?)
+    };
+    let b_events = events(only_b, 6)?;
+    assert_eq!("event: invalidated", b_events[0]);
+    assert!(b_events[1].starts_with(r#"data: {"resource":"b","ranges""#));
+    assert_eq!("event: values", b_events[3]);
+
+    let all_events = events(all, 12)?;
+    assert_eq!(
+        r#"data: {"resource":"a","ranges":[{"start":"1900-01-01T00:00:01.000000000Z","end":null}]}"#,
+        all_events[1]
+    );
+    assert!(all_events[4].ends_with(r#""value":2}]}"#));
+    assert!(all_events[7].starts_with(r#"data: {"resource":"b""#));
+
+    let mut missing = std::net::TcpStream::connect(address)?;
+    missing.write_all(b"GET /other HTTP/1.1\r\n\r\n")?;
+    let mut status = String::new();
+    BufReader::new(missing).read_line(&mut status)?;
+    assert!(status.starts_with("HTTP/1.1 404"));
+
+    Ok(())
+}
+
+#[test]
+fn view_deltas() -> Result<()> {
+    use std::ops::Bound;
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    for i in 0..4 {
+        plan.insert(seconds(2 * i), IncrementA)?;
+    }
+
+    let all = plan.view_delta::<a>(seconds(0)..)?;
+    assert_eq!(1, all.len());
+    assert_eq!(plan.view::<a>(seconds(0)..)?, all[0].values);
+
+    plan.insert(seconds(1), IncrementB)?;
+    assert!(plan.view_delta::<a>(seconds(0)..)?.is_empty());
+
+    let id = plan.insert(seconds(3), IncrementA)?;
+    let delta = plan.view_delta::<a>(seconds(0)..)?;
+    assert_eq!(1, delta.len());
+    assert_eq!(
+        (Bound::Included(seconds(3)), Bound::Unbounded),
+        delta[0].range
+    );
+    assert_eq!(
+        vec![(seconds(3), 3), (seconds(4), 4), (seconds(6), 5)],
+        delta[0].values
+    );
+
+    // Nothing reads the removed value at 3 directly, but it is still gone from the view.
+    plan.remove(id)?;
+    let delta = plan.view_delta::<a>(seconds(0)..)?;
+    assert_eq!(2, delta.len());
+    assert_eq!(vec![(seconds(0), 1), (seconds(2), 2)], delta[0].values);
+    assert_eq!(vec![(seconds(4), 3), (seconds(6), 4)], delta[1].values);
+
+    Ok(())
+}
+
+#[test]
+fn dirty_ranges() -> Result<()> {
+    use std::ops::Bound;
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(2), IncrementA)?;
+    plan.insert(seconds(4), SetAToB)?;
+    plan.insert(seconds(6), IncrementA)?;
+
+    assert_eq!(
+        vec![(Bound::Included(seconds(0)), Bound::Unbounded)],
+        plan.dirty_ranges::<a>()
+    );
+    plan.view::<a>(seconds(0)..)?;
+    assert!(plan.dirty_ranges::<a>().is_empty());
+
+    // The write at 4 doesn't read a, so it stops the invalidation.
+    plan.insert(seconds(1), IncrementA)?;
+    let dirty = plan.dirty_ranges::<a>();
+    assert_eq!(
+        vec![(Bound::Included(seconds(1)), Bound::Excluded(seconds(4)))],
+        dirty
+    );
+    assert!(plan.dirty_ranges::<b>().is_empty());
+
+    for range in dirty {
+        plan.view::<a>(range)?;
+    }
+    assert!(plan.dirty_ranges::<a>().is_empty());
+    assert_eq!(1, plan.sample::<a>(seconds(7))?);
+
+    Ok(())
+}
+
+model! {
+    RandomAB(a, b, random_seed)
+}
+
+pub struct Jitter;
+impl_activity! { for Jitter
+    @(start) {
+        ref mut: a += Rng::new(ref: random_seed, now).range(0.0, 1000.0) as u32;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn seeded_randomness() -> Result<()> {
+    let session = Session::new();
+    let jittered = |seed: u64| -> Result<u32> {
+        let mut plan: Plan<RandomAB> = session.new_plan(
+            seconds(-1),
+            initial_conditions! { a: 0, b: 0, random_seed: seed },
+        )?;
+        plan.insert(seconds(0), Jitter)?;
+        plan.insert(seconds(1), Jitter)?;
+        Ok(plan.sample::<a>(seconds(2))?)
+    };
+    assert_eq!(jittered(7)?, jittered(7)?);
+    assert_ne!(jittered(7)?, jittered(8)?);
+
+    let mut rng = Rng::new(3, "statistics");
+    let draws: Vec<f64> = (0..10_000).map(|_| rng.normal(5.0, 2.0)).collect();
+    let mean = draws.iter().sum::<f64>() / draws.len() as f64;
+    let variance = draws.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / draws.len() as f64;
+    assert!((mean - 5.0).abs() < 0.1);
+    assert!((variance.sqrt() - 2.0).abs() < 0.1);
+    assert!((0..1000).all(|_| (0.0..1.0).contains(&rng.uniform())));
+
+    Ok(())
+}
+
+#[test]
+fn incremental_constraints() -> Result<()> {
+    use std::ops::Bound;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    let mut ids = vec![];
+    for i in 0..10 {
+        ids.push(plan.insert(seconds(2 * i), IncrementA)?);
+    }
+
+    let checks = Arc::new(AtomicUsize::new(0));
+    let counter = checks.clone();
+    let constraint = plan.add_constraint::<a>("a below 5", seconds(0).., move |a| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        a < 5
+    });
+    let violated_from = |plan: &Plan<AB>| -> Result<Vec<Bound<Time>>> {
+        Ok(plan.violations()?.into_iter().map(|v| v.range.0).collect())
+    };
+
+    // Every value in the window.
+    assert_eq!(vec![Bound::Included(seconds(8))], violated_from(&plan)?);
+    assert_eq!(10, checks.load(Ordering::SeqCst));
+
+    // Nothing in the window changed.
+    assert_eq!(vec![Bound::Included(seconds(8))], violated_from(&plan)?);
+    plan.insert(seconds(9), IncrementB)?;
+    assert_eq!(vec![Bound::Included(seconds(8))], violated_from(&plan)?);
+    assert_eq!(10, checks.load(Ordering::SeqCst));
+
+    // Only the new value and the ones after it.
+    plan.insert(seconds(15), IncrementA)?;
+    assert_eq!(vec![Bound::Included(seconds(8))], violated_from(&plan)?);
+    assert_eq!(13, checks.load(Ordering::SeqCst));
+
+    // Everything after the removed value, and the initial condition, which now starts the window.
+    plan.remove(ids[0])?;
+    assert_eq!(vec![Bound::Included(seconds(10))], violated_from(&plan)?);
+    assert_eq!(24, checks.load(Ordering::SeqCst));
+
+    assert!(plan.remove_constraint(constraint));
+    assert!(plan.violations()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn cache_counts() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), SetBToA)?;
+    plan.insert(seconds(2), IncrementA)?;
+    assert_eq!(
+        testing::CacheCounts {
+            evaluated: 2,
+            from_history: 0
+        },
+        plan.cache_counts::<a>(..)?
+    );
+
+    // Only the new operation is needed, the others were computed by the last view.
+    plan.insert(seconds(3), IncrementA)?;
+    assert_eq!(
+        testing::CacheCounts {
+            evaluated: 1,
+            from_history: 0
+        },
+        plan.cache_counts::<a>(..)?
+    );
+    plan.assert_profile::<a>(seconds(2).., &[(seconds(2), 2), (seconds(3), 3)]);
+
+    // The first increment is in history, but b was never viewed, so setting it is evaluated.
+    let mut copy = init_plan(&session);
+    copy.insert(seconds(0), IncrementA)?;
+    copy.insert(seconds(1), SetBToA)?;
+    copy.insert(seconds(2), IncrementA)?;
+    assert_eq!(
+        testing::CacheCounts {
+            evaluated: 1,
+            from_history: 1
+        },
+        copy.cache_counts::<b>(..)?
+    );
+
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "profile of a differs")]
+fn assert_profile_mismatch() {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA).unwrap();
+    plan.assert_profile::<a>(seconds(0).., &[(seconds(0), 2)]);
+}
+
+#[test]
+fn property_test() -> Result<()> {
+    testing::PropertyTest::new(
+        |session| session.new_plan::<AB>(seconds(-1), initial_conditions! { a: 0, b: 0 }),
+        seconds(0)..seconds(100),
+    )
+    .activity(|_| IncrementA)
+    .activity(|_| IncrementB)
+    .activity(|_| SetBToA)
+    .activity(|_| SetAToB)
+    .activity(|_| AddBToA)
+    .activity(|rng| AddToA(rng.range(0.0, 5.0) as u32))
+    .check::<a>()
+    .check::<b>()
+    .cases(50)
+    .max_activities(12)
+    .run()
+}
+
+static DRAWS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+pub struct HiddenState;
+impl_activity! { for HiddenState
+    @(start) {
+        ref mut: a += DRAWS.fetch_add(1, Ordering::SeqCst);
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn property_test_finds_hidden_state() {
+    let error = testing::PropertyTest::new(
+        |session| session.new_plan::<AB>(seconds(-1), initial_conditions! { a: 0, b: 0 }),
+        seconds(0)..seconds(100),
+    )
+    .activity(|_| IncrementA)
+    .activity(|_| HiddenState)
+    .check::<a>()
+    .run()
+    .unwrap_err()
+    .to_string();
+    assert!(error.starts_with("reusing history"), "{error}");
+    // Shrunk to the one activity that causes it.
+    assert!(error.contains("shrunk to:\n  HiddenState at "), "{error}");
+    assert_eq!(1, error.matches("\n  ").count(), "{error}");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn event_server_limits() -> Result<()> {
+    use peregrine::stream::EventServer;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+
+    let server = EventServer::bind("127.0.0.1:0")?
+        .with_max_connections(1)
+        .with_allowed_origin("https://planner.example");
+    let address = server.local_addr()?;
+    let publisher = server.publisher();
+    server.spawn();
+
+    let status = |stream: TcpStream| -> Result<String> {
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        Ok(status)
+    };
+
+    let mut client = TcpStream::connect(address)?;
+    client.write_all(b"GET /events HTTP/1.1\r\n\r\n")?;
+    let mut client = BufReader::new(client);
+    let mut headers = vec![];
+    let mut line = String::new();
+    while line != "\r\n" {
+        line.clear();
+        client.read_line(&mut line)?;
+        headers.push(line.clone());
+    }
+    assert!(headers.contains(&"Access-Control-Allow-Origin: https://planner.example\r\n".into()));
+    assert_eq!(1, publisher.clients());
+
+    assert!(status(TcpStream::connect(address)?)?.starts_with("HTTP/1.1 503"));
+
+    let server = EventServer::bind("127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    server.spawn();
+    // Exactly the limit, so that the server reads all of it before answering.
+    let mut oversized = TcpStream::connect(address)?;
+    let request_line = "GET /events HTTP/1.1\r\n";
+    let header = "x".repeat(EventServer::MAX_REQUEST_SIZE as usize - request_line.len());
+    write!(oversized, "{request_line}{header}")?;
+    assert!(status(oversized)?.starts_with("HTTP/1.1 431"));
+
+    Ok(())
+}
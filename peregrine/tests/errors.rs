@@ -0,0 +1,671 @@
+mod util;
+
+use peregrine::*;
+use serde::{Deserialize, Serialize};
+use util::*;
+
+pub struct FailA;
+impl_activity! { for FailA
+    @(start) {
+        if ref:a == 0 {
+            bail!("a is cursed");
+        }
+        mut:a = 1;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn error_report() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let id = plan.insert(seconds(0), FailA)?;
+    plan.insert(seconds(1), IncrementA)?;
+    plan.insert(seconds(2), SetBToA)?;
+
+    let error = plan.view::<b>(seconds(0)..).unwrap_err();
+    let EngineError::ViewFailed(report) = &error else {
+        panic!("expected a view failure, found {error}");
+    };
+    assert_eq!("b", report.resource);
+    assert_eq!(1, report.len());
+
+    let root = report.iter().next().unwrap();
+    assert_eq!("FailA", root.activity);
+    assert_eq!(Some(id), root.activity_id);
+    assert_eq!(Some(0), root.op_index);
+    assert_eq!(vec![id], report.activity_ids());
+    assert_eq!(vec![seconds(2)], report.affected);
+    assert_eq!(seconds(0), root.time);
+    assert_eq!(&["a"], root.poisoned);
+    assert_eq!("a is cursed", root.error.to_string());
+
+    assert_eq!(
+        format!(
+            "could not view b: 1 operation failed\n  - activity FailA ({id:?}, operation 0) at {} (poisoning a): a is cursed",
+            seconds(0)
+        ),
+        error.to_string()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn typed_engine_errors() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let id = plan.insert(seconds(0), IncrementA)?;
+    plan.remove(id)?;
+    assert!(matches!(
+        plan.remove(id),
+        Err(EngineError::ActivityNotFound(missing)) if missing == id
+    ));
+
+    assert!(matches!(
+        session.new_plan::<AB>(seconds(0), initial_conditions! { a: 0 }),
+        Err(EngineError::InvalidInitialConditions(issues)) if issues == vec![InitialConditionIssue {
+            resource: "b",
+            kind: InitialConditionIssueKind::Missing,
+        }]
+    ));
+
+    Ok(())
+}
+
+resource!(counter: u32 = 7);
+
+model! {
+    WithDefault(a, counter)
+}
+
+#[test]
+fn default_initial_conditions() -> Result<()> {
+    let session = Session::new();
+    let plan = session.new_plan::<WithDefault>(seconds(-1), initial_conditions! { a: 0 })?;
+    assert_eq!(vec![(seconds(-1), 7)], plan.view::<counter>(..)?);
+
+    let plan =
+        session.new_plan::<WithDefault>(seconds(-1), initial_conditions! { a: 0, counter: 3 })?;
+    assert_eq!(vec![(seconds(-1), 3)], plan.view::<counter>(..)?);
+
+    Ok(())
+}
+
+resource!(threshold<T>: T);
+resource!(limit<T>: Option<T> = None);
+
+model! {
+    NarrowLimit(limit<u8>)
+}
+
+model! {
+    WithLimits(limit<i64>, use NarrowLimit)
+}
+
+#[test]
+fn conflicting_instantiations() {
+    let session = Session::new();
+    let error = session
+        .new_plan::<WithLimits>(seconds(-1), InitialConditions::new())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        EngineError::ConflictingInstantiations("limit")
+    ));
+}
+
+model! {
+    WithThreshold(a, b, threshold<f64>)
+}
+
+#[test]
+fn invalid_initial_conditions() -> Result<()> {
+    let session = Session::new();
+
+    let Err(EngineError::InvalidInitialConditions(mut issues)) = session.new_plan::<WithThreshold>(
+        seconds(-1),
+        InitialConditions::new().insert::<threshold<f32>>(0.5),
+    ) else {
+        panic!("expected the initial conditions to be rejected")
+    };
+    issues.sort_by_key(|issue| issue.resource);
+    assert_eq!(
+        vec![
+            InitialConditionIssue {
+                resource: "a",
+                kind: InitialConditionIssueKind::Missing,
+            },
+            InitialConditionIssue {
+                resource: "b",
+                kind: InitialConditionIssueKind::Missing,
+            },
+            InitialConditionIssue {
+                resource: "threshold",
+                kind: InitialConditionIssueKind::WrongType {
+                    expected: "f64",
+                    found: "f32",
+                },
+            },
+        ],
+        issues
+    );
+
+    let initial_conditions = initial_conditions! { a: 0, b: 0 }.insert::<threshold<f64>>(0.5);
+    initial_conditions.validate::<WithThreshold>()?;
+    session.new_plan::<WithThreshold>(seconds(-1), initial_conditions)?;
+
+    Ok(())
+}
+
+#[test]
+fn partial_view() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), IncrementB)?;
+    plan.insert(seconds(1), FailA)?;
+    plan.insert(seconds(2), IncrementB)?;
+    plan.insert(seconds(3), AddBToA)?;
+    plan.insert(seconds(4), IncrementB)?;
+
+    let b_view = plan.view_partial::<b>(seconds(0)..)?;
+    assert!(b_view.errors.is_none());
+    assert_eq!(3, b_view.values.len());
+
+    let a_view = plan.view_partial::<a>(seconds(-1)..)?;
+    assert_eq!(vec![(seconds(-1), 0)], a_view.values);
+    let errors = a_view.errors.unwrap();
+    assert_eq!(1, errors.len());
+    assert_eq!(vec![seconds(1), seconds(3)], errors.affected);
+
+    Ok(())
+}
+
+resource!(unmodeled: u32);
+
+pub struct SetAToUnmodeled;
+impl_activity! { for SetAToUnmodeled
+    @(start) {
+        mut: a = ref: unmodeled;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn validate_plan() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), SetBToA)?;
+    plan.validate()?;
+
+    let early = plan.insert(seconds(-2), SetBToA)?;
+    let at_start = plan.insert(seconds(-1), IncrementB)?;
+
+    let Err(EngineError::InvalidPlan(issues)) = plan.validate() else {
+        panic!("expected the plan to be invalid");
+    };
+    let kinds: Vec<_> = issues
+        .iter()
+        .map(|issue| (issue.activity_id, issue.kind))
+        .collect();
+    assert_eq!(
+        vec![
+            (early, ValidationIssueKind::NotAfterPlanStart),
+            (early, ValidationIssueKind::NoUpstream("a")),
+            (at_start, ValidationIssueKind::NotAfterPlanStart),
+        ],
+        kinds
+    );
+
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    let unmodeled_read = plan.insert(seconds(1), SetAToUnmodeled)?;
+    let Err(EngineError::InvalidPlan(issues)) = plan.validate() else {
+        panic!("expected the plan to be invalid");
+    };
+    assert_eq!(1, issues.len());
+    assert_eq!(unmodeled_read, issues[0].activity_id);
+    assert_eq!(ValidationIssueKind::NoUpstream("unmodeled"), issues[0].kind);
+
+    Ok(())
+}
+
+resource!(map unmodeled_map[u8]: u32);
+
+pub struct IncrementAThenUnmodeled(u8);
+impl_activity! { for IncrementAThenUnmodeled
+    @(start) {
+        ref mut: a += 1;
+    }
+    @(start + Duration::from_seconds(1.0)) {
+        ref mut: unmodeled_map[self.0] += 1;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn failed_insertion_is_rolled_back() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    assert_eq!(1, plan.sample::<a>(seconds(5))?);
+
+    // The key's timeline can't be created, after the write to a was already inserted.
+    assert!(matches!(
+        plan.insert(seconds(1), IncrementAThenUnmodeled(0)),
+        Err(EngineError::InsertionFailed { .. })
+    ));
+    assert_eq!(1, plan.sample::<a>(seconds(5))?);
+
+    let id = plan.insert(seconds(2), IncrementA)?;
+    assert!(plan.replace(id, IncrementAThenUnmodeled(0)).is_err());
+    assert_eq!(2, plan.sample::<a>(seconds(5))?);
+
+    Ok(())
+}
+
+pub struct AddToA(u32);
+impl_activity! { for AddToA
+    validate {
+        ensure!(self.0 > 0, "must add a positive amount");
+    }
+    @(start) {
+        ref mut: a += self.0;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn activity_validation() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), AddToA(2))?;
+    assert!(matches!(
+        plan.insert(seconds(1), AddToA(0)),
+        Err(EngineError::InvalidArguments {
+            activity: "AddToA",
+            ..
+        })
+    ));
+    assert_eq!(2, plan.sample::<a>(seconds(2))?);
+
+    Ok(())
+}
+
+#[test]
+fn boxed_activities() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let make = |name: &str| -> Box<dyn Activity<AB>> {
+        match name {
+            "increment" => Box::new(IncrementA),
+            "add" => Box::new(AddToA(3)),
+            _ => Box::new(AddToA(0)),
+        }
+    };
+    for (i, name) in ["increment", "add", "increment"].into_iter().enumerate() {
+        plan.insert_boxed(seconds(i as i32), make(name))?;
+    }
+    assert!(matches!(
+        plan.insert_boxed(seconds(5), make("nothing")),
+        Err(EngineError::InvalidArguments {
+            activity: "AddToA",
+            ..
+        })
+    ));
+    assert_eq!(5, plan.sample::<a>(seconds(6))?);
+
+    Ok(())
+}
+
+#[test]
+fn user_supplied_keys() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let id = plan.insert_with_key("6f1c2d4e", seconds(0), IncrementA)?;
+    assert_eq!(Some(id), plan.get_id("6f1c2d4e"));
+    assert_eq!(Some("6f1c2d4e"), plan.get_key(id));
+
+    assert!(matches!(
+        plan.insert_with_key("6f1c2d4e", seconds(1), IncrementA),
+        Err(EngineError::DuplicateKey(key)) if key == "6f1c2d4e"
+    ));
+    assert_eq!(1, plan.sample::<a>(seconds(2))?);
+
+    plan.remove_by_key("6f1c2d4e")?;
+    assert_eq!(None, plan.get_id("6f1c2d4e"));
+    assert!(matches!(
+        plan.remove_by_key("6f1c2d4e"),
+        Err(EngineError::KeyNotFound(_))
+    ));
+    assert_eq!(0, plan.sample::<a>(seconds(2))?);
+
+    Ok(())
+}
+
+pub struct WaitForB;
+impl_activity! { for WaitForB
+    @(start) + (Duration::from_seconds(ref: b as f64)) ..= (Duration::from_seconds(1.0)) {
+        ref mut: a += 1;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn delay_out_of_range() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), SetPlentyOfB)?;
+    let id = plan.insert(seconds(1), WaitForB)?;
+
+    let error = plan.view::<a>(seconds(0)..seconds(10)).unwrap_err();
+    let EngineError::ViewFailed(report) = &error else {
+        panic!("expected a view failure, found {error}");
+    };
+    assert_eq!(1, report.len());
+
+    let root = report.iter().next().unwrap();
+    assert_eq!("WaitForB", root.activity);
+    assert_eq!(Some(id), root.activity_id);
+    assert_eq!(seconds(1), root.time);
+    assert!(
+        root.error
+            .to_string()
+            .contains("outside of the allowed range")
+    );
+
+    Ok(())
+}
+
+pub struct SetPlentyOfB;
+impl_activity! { for SetPlentyOfB
+    @(start) {
+        mut: b = 5u32 + ref: a;
+    }
+    Duration::ZERO
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Mode {
+    #[default]
+    Off,
+    Standby,
+    Science,
+}
+
+state_machine! { Mode:
+    Off => Standby,
+    Standby => Off | Science,
+    Science => Standby,
+}
+
+resource! {
+    #[state_machine]
+    mode: Mode
+}
+
+model! {
+    Modes(mode)
+}
+
+pub struct EnterMode(Mode);
+impl_activity! { for EnterMode
+    @(start) {
+        ref mut: mode = self.0;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn illegal_state_transition() -> Result<()> {
+    let session = Session::new();
+    let mut plan =
+        session.new_plan::<Modes>(seconds(-1), initial_conditions! { mode: Mode::Off })?;
+
+    plan.insert(seconds(0), EnterMode(Mode::Standby))?;
+    plan.insert(seconds(1), EnterMode(Mode::Science))?;
+    assert_eq!(Mode::Science, plan.sample::<mode>(seconds(2))?);
+
+    let id = plan.insert(seconds(3), EnterMode(Mode::Off))?;
+    let error = plan.view::<mode>(seconds(0)..).unwrap_err();
+    let EngineError::ViewFailed(report) = &error else {
+        panic!("expected a view failure, found {error}");
+    };
+    let root = report.iter().next().unwrap();
+    assert_eq!("EnterMode", root.activity);
+    assert_eq!(Some(id), root.activity_id);
+    assert_eq!(
+        "illegal transition of mode from Science to Off",
+        root.error.to_string()
+    );
+
+    Ok(())
+}
+
+resource!(antenna: peregrine::devices::Device<u8> = peregrine::devices::Device::Free);
+
+model! {
+    Comms(antenna)
+}
+
+/// Claims the antenna for owner `self.0` at the start, and releases it as owner `self.1` after
+/// a second, with no cooldown.
+pub struct Pass(u8, u8);
+impl_activity! { for Pass
+    @(start) {
+        ref mut: antenna.claim(self.0, now)?;
+    }
+    @(start + Duration::from_seconds(1.0)) {
+        ref mut: antenna.release(self.1, now, Duration::ZERO)?;
+    }
+    Duration::from_seconds(1.0)
+}
+
+#[test]
+fn device_ownership() -> Result<()> {
+    use peregrine::devices::Device;
+
+    let session = Session::new();
+    let mut plan: Plan<Comms> = session.new_plan(seconds(0), initial_conditions! {})?;
+
+    // Back to back passes don't conflict without a cooldown.
+    plan.insert(seconds(1), Pass(1, 1))?;
+    plan.insert(seconds(2), Pass(2, 2))?;
+    assert_eq!(Device::Claimed(2), plan.sample::<antenna>(seconds(2))?);
+    assert_eq!(Device::Free, plan.sample::<antenna>(seconds(3))?);
+
+    let wrong_owner = plan.insert(seconds(4), Pass(1, 2))?;
+    let error = plan.sample::<antenna>(seconds(6)).unwrap_err();
+    let EngineError::ViewFailed(report) = &error else {
+        panic!("expected a view failure, found {error}");
+    };
+    assert_eq!(vec![wrong_owner], report.activity_ids());
+    assert_eq!(
+        "device is claimed by 1, not 2",
+        report.iter().next().unwrap().error.to_string()
+    );
+
+    Ok(())
+}
+
+pub struct FailBWithA;
+impl_activity! { for FailBWithA
+    @(start) {
+        if ref:a == 0 {
+            bail!("a is zero");
+        }
+        mut:b = 1;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn deterministic_error_order() -> Result<()> {
+    let mut reports = vec![];
+    for _ in 0..5 {
+        let session = Session::new();
+        let mut plan = init_plan(&session);
+
+        // Independent failures, inserted out of time order.
+        let mut ids = vec![];
+        for i in [7, 2, 9, 0, 4, 8, 1, 6, 3, 5] {
+            ids.push(plan.insert(seconds(i), FailBWithA)?);
+        }
+
+        let error = plan.view::<b>(seconds(0)..).unwrap_err();
+        let EngineError::ViewFailed(report) = &error else {
+            panic!("expected a view failure, found {error}");
+        };
+        assert_eq!(10, report.len());
+        let order: Vec<_> = report.iter().map(|e| e.activity_id.unwrap()).collect();
+        assert_eq!(ids, order);
+        reports.push(error.to_string());
+    }
+    assert!(reports.windows(2).all(|pair| pair[0] == pair[1]));
+
+    Ok(())
+}
+
+resource! {
+    #[finite]
+    ratio: f64 = 1.0
+}
+
+model! {
+    Ratios(ratio)
+}
+
+pub struct Divide(f64);
+impl_activity! { for Divide
+    @(start) {
+        ref mut: ratio /= self.0;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn non_finite_writes() -> Result<()> {
+    let session = Session::new();
+    let mut plan: Plan<Ratios> = session.new_plan(seconds(-1), initial_conditions! {})?;
+
+    plan.insert(seconds(0), Divide(2.0))?;
+    let by_zero = plan.insert(seconds(1), Divide(0.0))?;
+    plan.insert(seconds(2), Divide(2.0))?;
+
+    let error = plan.sample::<ratio>(seconds(3)).unwrap_err();
+    let EngineError::ViewFailed(report) = &error else {
+        panic!("expected a view failure, found {error}");
+    };
+    assert_eq!(vec![by_zero], report.activity_ids());
+    let root = report.iter().next().unwrap();
+    assert_eq!(seconds(1), root.time);
+    assert_eq!(
+        "non-finite value inf written to ratio",
+        root.error.to_string()
+    );
+
+    plan.remove(by_zero)?;
+    assert_eq!(0.25, plan.sample::<ratio>(seconds(3))?);
+
+    Ok(())
+}
+
+/// Version 0 had only a gain, version 1 renamed it to `scale`, and version 2 added an offset.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Calibrate {
+    scale: u32,
+    offset: u32,
+}
+impl_activity! { for Calibrate
+    @(start) {
+        ref mut: a += self.scale * self.offset;
+    }
+    Duration::ZERO
+}
+
+#[cfg(feature = "json")]
+impl activity::VersionedActivity for Calibrate {
+    const VERSION: u32 = 2;
+
+    fn upgrade(from: u32, mut args: serde_json::Value) -> Result<serde_json::Value> {
+        match from {
+            0 => args = serde_json::json!({ "scale": args["gain"] }),
+            1 => args["offset"] = 0.into(),
+            _ => unreachable!(),
+        }
+        Ok(args)
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn versioned_activities() -> Result<()> {
+    use activity::{SavedArguments, VersionedActivity};
+
+    let saved = |version, args| SavedArguments { version, args };
+
+    let upgraded = Calibrate::load(saved(0, serde_json::json!({ "gain": 3 })))?;
+    assert_eq!((3, 0), (upgraded.scale, upgraded.offset));
+    assert_eq!(2, upgraded.save()?.version);
+
+    let error = Calibrate::load(saved(3, serde_json::json!({}))).unwrap_err();
+    assert!(error.to_string().contains("version 3"), "{error}");
+
+    Ok(())
+}
+
+#[cfg(feature = "ticks")]
+#[test]
+fn times_outside_of_ticks() -> Result<()> {
+    use peregrine::timeline::{epoch_to_instant, instant_to_epoch};
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    let far = seconds(0) + Duration::from_days(365.25 * 300.0);
+
+    assert!(matches!(
+        plan.insert(far, IncrementA),
+        Err(EngineError::TimeOutOfRange(time)) if time == far
+    ));
+    assert!(matches!(
+        plan.view::<a>(seconds(0)..far),
+        Err(EngineError::TimeOutOfRange(_))
+    ));
+    assert!(matches!(
+        session.new_plan::<AB>(far, initial_conditions! { a: 0, b: 0 }),
+        Err(EngineError::TimeOutOfRange(_))
+    ));
+
+    let id = plan.insert(seconds(0), IncrementA)?;
+    assert!(matches!(
+        plan.shift(id, Duration::from_days(365.25 * 300.0)),
+        Err(EngineError::TimeOutOfRange(_))
+    ));
+    assert_eq!(Some(seconds(0)), plan.start_of(id));
+
+    for time in [
+        seconds(-1_000_000_000),
+        seconds(0),
+        seconds(1) + Duration::from_nanoseconds(1.0),
+        seconds(1_000_000_000),
+        instant_to_epoch(i64::MIN),
+        instant_to_epoch(i64::MAX),
+    ] {
+        assert_eq!(time, instant_to_epoch(epoch_to_instant(time)?));
+    }
+    assert!(
+        epoch_to_instant(instant_to_epoch(i64::MAX) + Duration::from_nanoseconds(1.0)).is_err()
+    );
+
+    Ok(())
+}
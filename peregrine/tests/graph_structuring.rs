@@ -38,6 +38,35 @@ fn longer_chain() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn appended_insertion() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let mut append = plan.append();
+    for i in 0..100 {
+        append.insert(seconds(4 * i), IncrementA)?;
+        append.insert(seconds(4 * i + 1), SetBToA)?;
+        append.insert(seconds(4 * i + 2), IncrementB)?;
+        append.insert(seconds(4 * i + 3), SetAToB)?;
+    }
+    // Out of order, before everything else.
+    let late = append.insert(seconds(-1), IncrementA)?;
+    drop(append);
+
+    assert_eq!(6, plan.sample::<a>(seconds(8))?);
+    assert_eq!(201, plan.sample::<a>(seconds(400))?);
+
+    plan.remove(late)?;
+    let mut append = plan.append();
+    append.insert(seconds(401), IncrementB)?;
+    drop(append);
+    assert_eq!(200, plan.sample::<a>(seconds(400))?);
+    assert_eq!(201, plan.sample::<b>(seconds(402))?);
+
+    Ok(())
+}
+
 #[test]
 fn backward_insertion() -> Result<()> {
     let session = Session::new();
@@ -85,3 +114,2095 @@ fn basic_removal() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn removed_downstreams_are_forgotten() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let upstream = plan.insert(seconds(1), IncrementA)?;
+    for _ in 0..10 {
+        let id = plan.insert(seconds(3), SetBToA)?;
+        assert_eq!(1, plan.sample::<b>(seconds(3))?);
+        assert_eq!(1, plan.recorded_downstreams(upstream));
+
+        // Requesting the value again after it was cleared doesn't record the downstream twice.
+        let earlier = plan.insert(seconds(0), IncrementA)?;
+        assert_eq!(2, plan.sample::<b>(seconds(3))?);
+        assert_eq!(1, plan.recorded_downstreams(upstream));
+
+        plan.remove_all([earlier, id])?;
+        assert_eq!(0, plan.sample::<b>(seconds(3))?);
+        assert_eq!(0, plan.recorded_downstreams(upstream));
+    }
+
+    // A downstream that reads from a new upstream is forgotten by the old one.
+    plan.insert(seconds(3), SetBToA)?;
+    plan.sample::<b>(seconds(3))?;
+    plan.insert(seconds(2), SetAToB)?;
+    assert_eq!(0, plan.sample::<b>(seconds(3))?);
+    assert_eq!(0, plan.recorded_downstreams(upstream));
+
+    Ok(())
+}
+
+#[test]
+fn activity_tags() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let maintenance = ActivityMetadata {
+        subsystem: Some("power".to_string()),
+        tags: vec!["maintenance".to_string()],
+        ..Default::default()
+    };
+
+    let first = plan.insert(seconds(0), IncrementA)?;
+    let second = plan.insert(seconds(5), IncrementA)?;
+    let third = plan.insert(seconds(10), IncrementA)?;
+    plan.set_metadata(third, maintenance.clone())?;
+    plan.set_metadata(first, maintenance)?;
+
+    assert_eq!(
+        Some("power"),
+        plan.metadata(first).unwrap().subsystem.as_deref()
+    );
+    assert!(plan.metadata(second).unwrap().tags.is_empty());
+    assert_eq!(
+        vec![first, third],
+        plan.activities_tagged("maintenance", ..)
+    );
+    assert_eq!(
+        vec![third],
+        plan.activities_tagged("maintenance", seconds(5)..)
+    );
+
+    for id in plan.activities_tagged("maintenance", seconds(5)..) {
+        plan.remove(id)?;
+    }
+    assert_eq!(2, plan.sample::<a>(seconds(11))?);
+
+    Ok(())
+}
+
+#[test]
+fn cascading_removal() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let root = plan.insert(seconds(0), IncrementA)?;
+    let child = plan.insert_anchored(root, Duration::from_seconds(1.0), IncrementA)?;
+    let grandchild = plan.insert_anchored(child, Duration::from_seconds(1.0), IncrementA)?;
+    let unrelated = plan.insert(seconds(5), IncrementA)?;
+
+    assert_eq!(Some(seconds(2)), plan.start_of(grandchild));
+    assert_eq!(vec![child], plan.anchored_to(root));
+
+    plan.remove(child)?;
+    assert_eq!(
+        Some((root, Duration::from_seconds(2.0))),
+        plan.anchor_of(grandchild)
+    );
+    assert_eq!(3, plan.sample::<a>(seconds(6))?);
+
+    let removed = plan.remove_with(root, Dependents::Cascade)?;
+    assert_eq!(vec![root, grandchild], removed);
+    assert_eq!(Some(seconds(5)), plan.start_of(unrelated));
+    assert_eq!(1, plan.sample::<a>(seconds(6))?);
+
+    Ok(())
+}
+
+#[test]
+fn shifting() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let root = plan.insert_with_key("root", seconds(0), IncrementA)?;
+    let child = plan.insert_anchored(root, Duration::from_seconds(1.0), SetBToA)?;
+    plan.insert(seconds(3), SetBToA)?;
+    assert_eq!(1, plan.sample::<b>(seconds(1))?);
+
+    plan.shift(root, Duration::from_seconds(2.0))?;
+    assert_eq!(Some(seconds(2)), plan.start_of(root));
+    assert_eq!(Some(seconds(3)), plan.start_of(child));
+    assert_eq!(
+        Some((root, Duration::from_seconds(1.0))),
+        plan.anchor_of(child)
+    );
+    assert_eq!(Some(root), plan.get_id("root"));
+    assert_eq!(0, plan.sample::<a>(seconds(1))?);
+    assert_eq!(1, plan.sample::<b>(seconds(3))?);
+
+    plan.shift(child, Duration::from_seconds(-2.0))?;
+    assert_eq!(0, plan.sample::<b>(seconds(1))?);
+    assert!(matches!(
+        plan.shift(ActivityId::new(100), Duration::ZERO),
+        Err(EngineError::ActivityNotFound(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn bulk_removal() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let mut increments = vec![];
+    for i in 0..10 {
+        increments.push(plan.insert(seconds(i * 2), IncrementA)?);
+        plan.insert(seconds(i * 2 + 1), IncrementB)?;
+    }
+    plan.insert(seconds(20), AddBToA)?;
+    assert_eq!(20, plan.sample::<a>(seconds(21))?);
+
+    plan.remove_all(increments[..4].iter().copied())?;
+    assert_eq!(16, plan.sample::<a>(seconds(21))?);
+
+    let missing = increments[0];
+    assert!(matches!(
+        plan.remove_all([increments[4], missing]),
+        Err(EngineError::ActivityNotFound(id)) if id == missing
+    ));
+    assert_eq!(16, plan.sample::<a>(seconds(21))?);
+
+    let removed =
+        plan.remove_where(|span, _| span.label == "IncrementB" && span.start < seconds(10))?;
+    assert_eq!(5, removed.len());
+    assert_eq!(11, plan.sample::<a>(seconds(21))?);
+
+    plan.set_metadata(
+        increments[9],
+        ActivityMetadata {
+            tags: vec!["late".to_string()],
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(
+        vec![increments[9]],
+        plan.remove_where(|_, metadata| metadata.has_tag("late"))?
+    );
+    assert_eq!(10, plan.sample::<a>(seconds(21))?);
+
+    Ok(())
+}
+
+#[test]
+fn clearing_and_trimming() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let early = plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(5), IncrementA)?;
+    let late = plan.insert(seconds(10), IncrementA)?;
+    assert_eq!(3, plan.sample::<a>(seconds(11))?);
+
+    assert!(matches!(
+        plan.trim(seconds(0)..seconds(10), OutsideHorizon::Error),
+        Err(EngineError::OutsideHorizon(ids)) if ids == vec![late]
+    ));
+    assert_eq!(3, plan.sample::<a>(seconds(11))?);
+
+    assert_eq!(
+        vec![early, late],
+        plan.trim(seconds(1)..seconds(10), OutsideHorizon::Remove)?
+    );
+    assert_eq!(1, plan.sample::<a>(seconds(11))?);
+    assert!(
+        plan.trim(seconds(1)..seconds(10), OutsideHorizon::Error)?
+            .is_empty()
+    );
+
+    plan.clear()?;
+    assert!(plan.spans().is_empty());
+    assert_eq!(0, plan.sample::<a>(seconds(11))?);
+
+    plan.insert(seconds(0), IncrementB)?;
+    assert_eq!(1, plan.sample::<b>(seconds(1))?);
+
+    Ok(())
+}
+
+#[test]
+fn plan_snapshots() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let first = plan.insert_with_key("first", seconds(0), IncrementA)?;
+    let second = plan.insert(seconds(1), IncrementA)?;
+    let before = plan.snapshot().with_view::<a, _>(&plan, seconds(0)..)?;
+
+    plan.remove(first)?;
+    plan.insert(seconds(2), IncrementB)?;
+    plan.set_metadata(
+        second,
+        ActivityMetadata {
+            name: Some("renamed".to_string()),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(1, plan.sample::<a>(seconds(3))?);
+
+    let ids: Vec<_> = before.activities().iter().map(|a| a.span.id).collect();
+    assert_eq!(vec![first, second], ids);
+    assert_eq!(
+        Some("first"),
+        before.activity(first).unwrap().key.as_deref()
+    );
+    assert_eq!(None, before.activity(second).unwrap().metadata.name);
+    assert_eq!(
+        vec![(seconds(0), 1), (seconds(1), 2)],
+        before.view::<a>().unwrap()
+    );
+    assert!(before.view::<b>().is_none());
+    assert!(matches!(
+        before.clone().with_view::<b, _>(&plan, seconds(0)..),
+        Err(EngineError::StaleSnapshot)
+    ));
+
+    let after = plan.snapshot();
+    assert!(after.activity(first).is_none());
+    assert_eq!(
+        Some("renamed"),
+        after.activity(second).unwrap().metadata.name.as_deref()
+    );
+    assert_eq!(2, after.activities().len());
+    assert_eq!(2, before.activities().len());
+    let after = after.with_view::<b, _>(&plan, seconds(0)..)?;
+    assert!(after.view::<b>().unwrap().contains(&(seconds(2), 1)));
+
+    Ok(())
+}
+
+#[test]
+fn named_plans() -> Result<()> {
+    let session = Session::new();
+    let initial_conditions = || initial_conditions! { a: 0, b: 0 };
+
+    session.create_plan::<AB>("nominal", seconds(-1), initial_conditions())?;
+    session.create_plan::<AB>("contingency", seconds(-1), initial_conditions())?;
+    assert!(matches!(
+        session.create_plan::<AB>("nominal", seconds(-1), initial_conditions()),
+        Err(EngineError::DuplicatePlan(name)) if name == "nominal"
+    ));
+    assert_eq!(vec!["contingency", "nominal"], session.plans());
+
+    std::thread::scope(|scope| {
+        for (name, count) in [("nominal", 1), ("contingency", 3)] {
+            let session = &session;
+            scope.spawn(move || {
+                session.with_plan::<AB, _>(name, |plan| -> Result<()> {
+                    for i in 0..count {
+                        plan.insert(seconds(i), IncrementA)?;
+                    }
+                    Ok(())
+                })
+            });
+        }
+    });
+
+    let sample = |name| session.with_plan::<AB, _>(name, |plan| plan.sample::<a>(seconds(5)));
+    assert_eq!(1, sample("nominal")??);
+    assert_eq!(3, sample("contingency")??);
+
+    assert!(session.remove_plan("nominal"));
+    assert!(!session.remove_plan("nominal"));
+    assert!(matches!(
+        sample("nominal"),
+        Err(EngineError::PlanNotFound(name)) if name == "nominal"
+    ));
+
+    Ok(())
+}
+
+pub struct SetBThenA;
+impl_activity! { for SetBThenA
+    @(start) {
+        mut:b = ref:a;
+    }
+    @(start + Duration::from_seconds(1.0)) {
+        ref mut: a += ref:b;
+    }
+    Duration::from_seconds(2.0)
+}
+
+#[test]
+fn activity_spans() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let late = plan.insert(seconds(5), IncrementA)?;
+    let early = plan.insert(seconds(0), SetBThenA)?;
+
+    let span = plan.span(early).unwrap();
+    assert_eq!("SetBThenA", span.label);
+    assert_eq!(seconds(2), span.end());
+    assert_eq!(2, span.operations.len());
+    assert_eq!(seconds(1), span.operations[1].min_time);
+    assert_eq!(&["a"], span.operations[0].reads);
+    assert_eq!(&["b"], span.operations[0].writes);
+    assert_eq!(&["b", "a"], span.operations[1].reads);
+    assert_eq!(&["a"], span.operations[1].writes);
+
+    let ids: Vec<_> = plan.spans().into_iter().map(|s| s.id).collect();
+    assert_eq!(vec![early, late], ids);
+
+    Ok(())
+}
+
+pub struct SampledIncrementA;
+impl_activity! { for SampledIncrementA
+    @(start) priority {
+        ref mut: a += 1;
+    }
+    @(start + Duration::from_seconds(1.0)) priority commutative {
+        ref mut: b += 1;
+    }
+    Duration::from_seconds(1.0)
+}
+
+#[test]
+fn prioritized_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let plain = plan.insert(seconds(0), IncrementA)?;
+    for i in 1..5 {
+        plan.insert(seconds(i), SetBToA)?;
+    }
+    let sampled = plan.insert(seconds(2), SampledIncrementA)?;
+
+    assert!(!plan.span(plain).unwrap().operations[0].priority);
+    let span = plan.span(sampled).unwrap();
+    assert!(span.operations.iter().all(|op| op.priority));
+
+    // Priorities change the order work is done in, not the results.
+    assert_eq!(2, plan.sample::<a>(seconds(5))?);
+    assert_eq!(3, plan.sample::<b>(seconds(3))?);
+    assert_eq!(2, plan.sample::<b>(seconds(4))?);
+
+    Ok(())
+}
+
+resource!(c: u32);
+
+model! {
+    SubB(b)
+}
+
+model! {
+    Composed(c, use AB, use SubB)
+}
+
+#[test]
+fn sub_models() -> Result<()> {
+    let session = Session::new();
+    let mut plan =
+        session.new_plan::<Composed>(seconds(-1), initial_conditions! { a: 1, b: 2, c: 3 })?;
+
+    plan.insert(seconds(0), AddBToA)?;
+
+    assert_eq!(vec![(seconds(0), 3)], plan.view::<a>(seconds(0)..)?);
+    assert_eq!(vec![(seconds(-1), 3)], plan.view::<c>(..)?);
+    assert_eq!(vec![(seconds(-1), 3)], plan.c(..)?);
+    assert_eq!(3, plan.sample_a(seconds(0))?);
+    // `b` is in both submodels, so the shorthand has to say which one it comes from.
+    assert_eq!(2, SubBPlanExt::sample_b(&plan, seconds(0))?);
+
+    assert!(matches!(
+        session.new_plan::<Composed>(seconds(-1), initial_conditions! { a: 1, c: 3 }),
+        Err(EngineError::InvalidInitialConditions(issues)) if issues.len() == 1 && issues[0].resource == "b"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn model_schema() {
+    let schema = Composed::schema();
+    assert_eq!("Composed", schema.name);
+
+    // `b` is in both submodels, but only listed once.
+    let labels: Vec<_> = schema.resources.iter().map(|r| r.label).collect();
+    assert_eq!(vec!["c", "a", "b"], labels);
+
+    let b = schema.resource("b").unwrap();
+    assert_eq!(("u32", "u32"), (b.read_type, b.write_type));
+    assert!(b.is_static);
+    assert_eq!(None, b.unit);
+    assert!(schema.resource("d").is_none());
+}
+
+pub struct RepeatIncrementA {
+    times: u32,
+}
+impl_activity! { for RepeatIncrementA
+    for i in 0..self.times {
+        @(start + Duration::from_seconds(i as f64)) {
+            ref mut: a += 1;
+        }
+    }
+    Duration::from_seconds(self.times as f64)
+}
+
+#[test]
+fn operations_in_loops() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let id = plan.insert(seconds(0), RepeatIncrementA { times: 3 })?;
+    plan.insert(seconds(1), RepeatIncrementA { times: 0 })?;
+
+    assert_eq!(
+        vec![(seconds(0), 1), (seconds(1), 2), (seconds(2), 3)],
+        plan.view::<a>(seconds(0)..)?
+    );
+    assert_eq!(3, plan.span(id).unwrap().operations.len());
+
+    Ok(())
+}
+
+pub struct MaybeIncrement {
+    a: bool,
+    b: bool,
+}
+impl_activity! { for MaybeIncrement
+    if self.a {
+        @(start) {
+            ref mut: a += 1;
+        }
+    } else if self.b {
+        @(start) {
+            ref mut: b += 1;
+        }
+    }
+    if self.a && self.b {
+        Duration::from_seconds(1.0)
+    } else {
+        Duration::ZERO
+    }
+}
+
+#[test]
+fn conditional_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let both = plan.insert(seconds(0), MaybeIncrement { a: true, b: true })?;
+    let only_b = plan.insert(seconds(1), MaybeIncrement { a: false, b: true })?;
+    let neither = plan.insert(seconds(2), MaybeIncrement { a: false, b: false })?;
+
+    assert_eq!(1, plan.sample::<a>(seconds(3))?);
+    assert_eq!(1, plan.sample::<b>(seconds(3))?);
+
+    assert_eq!(
+        Duration::from_seconds(1.0),
+        plan.span(both).unwrap().duration
+    );
+    assert_eq!(&["b"], plan.span(only_b).unwrap().operations[0].writes);
+    assert!(plan.span(neither).unwrap().operations.is_empty());
+
+    Ok(())
+}
+
+pub struct DelayedIncrementA;
+impl_activity! { for DelayedIncrementA
+    @(start) + (Duration::from_seconds(ref: b as f64)) ..= (Duration::from_seconds(10.0)) {
+        ref mut: a += 1;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn delayed_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), IncrementB)?;
+    plan.insert(seconds(1), IncrementB)?;
+    let delayed = plan.insert(seconds(2), DelayedIncrementA)?;
+
+    // b is 2 when the delay is computed, so a is incremented at 4.
+    assert_eq!(0, plan.sample::<a>(seconds(3))?);
+    assert_eq!(1, plan.sample::<a>(seconds(5))?);
+    assert_eq!(
+        vec![(seconds(-1), 0), (seconds(4), 1)],
+        plan.view::<a>(seconds(0)..seconds(20))?
+    );
+
+    let info = plan.span(delayed).unwrap().operations[0];
+    assert_eq!(seconds(2), info.min_time);
+    assert_eq!(seconds(12), info.max_time);
+
+    // Changing the delay moves the operation.
+    plan.insert(Time::from_tai_seconds(1.5), IncrementB)?;
+    assert_eq!(0, plan.sample::<a>(seconds(4))?);
+    assert_eq!(1, plan.sample::<a>(Time::from_tai_seconds(5.5))?);
+
+    // Grounded operations inside the delay window are ordered correctly around it.
+    plan.insert(seconds(3), IncrementA)?;
+    plan.insert(seconds(7), SetBToA)?;
+    assert_eq!(1, plan.sample::<a>(seconds(4))?);
+    assert_eq!(2, plan.sample::<a>(seconds(6))?);
+    assert_eq!(2, plan.sample::<b>(seconds(8))?);
+    assert_eq!(
+        vec![(seconds(-1), 0), (seconds(3), 1), (seconds(5), 2)],
+        plan.view::<a>(seconds(0)..seconds(20))?
+    );
+
+    plan.remove(delayed)?;
+    assert_eq!(1, plan.sample::<a>(seconds(6))?);
+
+    Ok(())
+}
+
+#[test]
+fn overlapping_delayed_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), IncrementB)?;
+    let delayed = (1..=30)
+        .map(|s| plan.insert(seconds(s), DelayedIncrementA))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Every delay window overlaps the next nine, and each operation happens one second late.
+    assert_eq!(14, plan.sample::<a>(seconds(15))?);
+    assert_eq!(30, plan.sample::<a>(seconds(40))?);
+
+    for id in delayed.into_iter().step_by(2) {
+        plan.remove(id)?;
+    }
+    assert_eq!(7, plan.sample::<a>(seconds(15))?);
+    assert_eq!(15, plan.sample::<a>(seconds(40))?);
+    assert_eq!(
+        (0..=15).collect::<Vec<_>>(),
+        plan.view::<a>(seconds(0)..seconds(40))?
+            .into_iter()
+            .map(|(_, a)| a)
+            .collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+pub struct IncrementAAtEnd {
+    duration: Duration,
+}
+impl_activity! { for IncrementAAtEnd
+    @(end) {
+        ref mut: a += 1;
+    }
+    @(start) {
+        ref mut: b += 1;
+    }
+    for i in 1..3 {
+        @(end + Duration::from_seconds(i as f64)) {
+            ref mut: b += 1;
+        }
+    }
+    self.duration
+}
+
+#[test]
+fn end_placement() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let id = plan.insert(
+        seconds(0),
+        IncrementAAtEnd {
+            duration: Duration::from_seconds(2.0),
+        },
+    )?;
+
+    assert_eq!(0, plan.sample::<a>(seconds(1))?);
+    assert_eq!(1, plan.sample::<a>(seconds(3))?);
+    assert_eq!(1, plan.sample::<b>(seconds(1))?);
+    assert_eq!(3, plan.sample::<b>(seconds(5))?);
+
+    let operations = plan.span(id).unwrap().operations;
+    assert_eq!(
+        vec![seconds(2), seconds(0), seconds(3), seconds(4)],
+        operations.iter().map(|op| op.min_time).collect::<Vec<_>>()
+    );
+    assert_eq!(&["a"], operations[0].writes);
+
+    Ok(())
+}
+
+#[test]
+fn simultaneous_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let first = plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(0), SetBToA)?;
+    let last = plan.insert(seconds(0), IncrementA)?;
+
+    // Operations at the same time happen in the order they were inserted.
+    assert_eq!(2, plan.sample::<a>(seconds(0))?);
+    assert_eq!(1, plan.sample::<b>(seconds(0))?);
+
+    plan.remove(last)?;
+    assert_eq!(1, plan.sample::<a>(seconds(0))?);
+    assert_eq!(1, plan.sample::<b>(seconds(0))?);
+
+    plan.remove(first)?;
+    assert_eq!(0, plan.sample::<a>(seconds(0))?);
+    assert_eq!(0, plan.sample::<b>(seconds(0))?);
+
+    Ok(())
+}
+
+struct SetBToAFirst;
+impl_activity! { for SetBToAFirst
+    @(start, order = -1) {
+        mut:b = ref:a;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn ordered_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(0), SetBToAFirst)?;
+    plan.insert(seconds(1), IncrementA)?;
+    plan.insert(seconds(1), SetBToA)?;
+
+    assert_eq!(0, plan.sample::<b>(seconds(0))?);
+    assert_eq!(2, plan.sample::<b>(seconds(1))?);
+
+    Ok(())
+}
+
+struct IncrementATwice;
+impl_activity! { for IncrementATwice
+    @(start) {
+        ref mut: a += 1;
+    }
+    @(start) {
+        ref mut: a *= 10;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn fused_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), IncrementATwice)?;
+
+    assert_eq!(
+        vec![(seconds(-1), 0), (seconds(0), 1), (seconds(1), 20)],
+        plan.view::<a>(seconds(-1)..seconds(2))?
+    );
+
+    Ok(())
+}
+
+struct IncrementAThenPriority;
+impl_activity! { for IncrementAThenPriority
+    @(start) {
+        ref mut: a += 1;
+    }
+    @(start) priority {
+        ref mut: a += 1;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn operations_with_different_markers_are_not_fused() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let fused = plan.insert(seconds(0), IncrementATwice)?;
+    let separate = plan.insert(seconds(1), IncrementAThenPriority)?;
+    assert_eq!(1, plan.span(fused).unwrap().operations.len());
+    assert_eq!(2, plan.span(separate).unwrap().operations.len());
+    assert_eq!(12, plan.sample::<a>(seconds(2))?);
+
+    Ok(())
+}
+
+resource!(map instrument_temp[u8]: u32);
+
+model! {
+    Instruments(instrument_temp)
+}
+
+struct WarmInstrument(u8);
+impl_activity! { for WarmInstrument
+    @(start) {
+        ref mut: instrument_temp[self.0] += 10;
+    }
+    Duration::ZERO
+}
+
+struct WarmAll;
+impl_activity! { for WarmAll
+    for instrument in 0..3u8 {
+        @(start) {
+            ref mut: instrument_temp[instrument] += 1;
+        }
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn keyed_resources() -> Result<()> {
+    let session = Session::new();
+    let initial_conditions =
+        initial_conditions! { instrument_temp: 20 }.insert_key::<instrument_temp>(&2, 100);
+    let mut plan: Plan<Instruments> = session.new_plan(seconds(-1), initial_conditions)?;
+
+    plan.insert(seconds(0), WarmInstrument(1))?;
+    plan.insert(seconds(1), WarmAll)?;
+
+    assert_eq!(21, plan.sample_key::<instrument_temp>(&0, seconds(2))?);
+    assert_eq!(
+        vec![(seconds(-1), 20), (seconds(0), 30), (seconds(1), 31)],
+        plan.view_key::<instrument_temp>(&1, seconds(-1)..seconds(2))?
+    );
+    assert_eq!(101, plan.sample_key::<instrument_temp>(&2, seconds(2))?);
+    // Keys that are never touched still have the initial condition.
+    assert_eq!(20, plan.sample_key::<instrument_temp>(&7, seconds(2))?);
+
+    Ok(())
+}
+
+#[test]
+fn concurrent_view_and_edit() -> Result<()> {
+    let session = Session::new();
+    let plan = SharedPlan::new(init_plan(&session));
+
+    for i in 0..50 {
+        plan.insert(seconds(2 * i), IncrementA)?;
+    }
+
+    std::thread::scope(|scope| {
+        // Nothing that writes a reads b, so these can run alongside the edits to b.
+        let view = scope.spawn(|| {
+            (0..20)
+                .map(|_| plan.sample::<a>(seconds(100)).unwrap())
+                .collect::<Vec<_>>()
+        });
+        for i in 0..50 {
+            plan.insert(seconds(2 * i + 1), IncrementB).unwrap();
+        }
+        assert!(view.join().unwrap().iter().all(|&a| a == 50));
+    });
+
+    // Now a depends on b, and views of a see every edit to b.
+    plan.insert(seconds(100), AddBToA)?;
+    std::thread::scope(|scope| {
+        let view = scope.spawn(|| plan.sample::<a>(seconds(101)).unwrap());
+        let id = plan.insert(seconds(99), IncrementB).unwrap();
+        let before = view.join().unwrap();
+        assert!(before == 100 || before == 101);
+        plan.remove(id).unwrap();
+    });
+    assert_eq!(100, plan.sample::<a>(seconds(101))?);
+
+    let mut plan = plan.into_inner();
+    plan.insert(seconds(101), IncrementA)?;
+    assert_eq!(101, plan.sample::<a>(seconds(102))?);
+
+    Ok(())
+}
+
+#[test]
+fn speculative_simulation() -> Result<()> {
+    use peregrine::shared::SpeculationOptions;
+    use std::sync::atomic::Ordering;
+
+    let session = Session::new();
+    let plan = SharedPlan::new(init_plan(&session));
+    let (node, counter) = EvalCounter::new();
+    plan.insert(seconds(1), node)?;
+    plan.insert(seconds(2), SetBToA)?;
+
+    assert_eq!(0, plan.sample::<a>(seconds(3))?);
+    assert_eq!(0, plan.sample::<a>(seconds(3))?);
+    // Viewed only once, so it isn't worth simulating.
+    assert_eq!(0, plan.sample::<b>(seconds(3))?);
+    assert_eq!(1, counter.load(Ordering::SeqCst));
+
+    let inserted = std::thread::scope(|scope| {
+        scope.spawn(|| plan.speculate(SpeculationOptions::default()));
+
+        let inserted = plan.insert(seconds(0), IncrementA);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while counter.load(Ordering::SeqCst) < 2 && std::time::Instant::now() < deadline {
+            std::thread::yield_now();
+        }
+        plan.stop_speculating();
+        inserted
+    });
+    inserted?;
+    assert_eq!(2, counter.load(Ordering::SeqCst));
+
+    // The edit's effect on `a` was simulated in the background.
+    assert_eq!(1, plan.sample::<a>(seconds(3))?);
+    assert_eq!(2, counter.load(Ordering::SeqCst));
+    assert_eq!(1, plan.sample::<b>(seconds(3))?);
+
+    Ok(())
+}
+
+#[test]
+fn background_lane_waits_for_foreground() {
+    use peregrine::exec::Lane;
+    use std::sync::mpsc;
+
+    let (release, released) = mpsc::channel::<()>();
+    let (started, foreground_started) = mpsc::channel();
+    let (finished, background_finished) = mpsc::channel();
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            Lane::Foreground.run(move || {
+                started.send(()).unwrap();
+                released.recv().unwrap();
+            })
+        });
+        foreground_started.recv().unwrap();
+        scope.spawn(move || {
+            Lane::Background.run(|| {
+                let name = std::thread::current().name().map(str::to_string);
+                finished.send(name).unwrap();
+            })
+        });
+
+        let timeout = std::time::Duration::from_millis(100);
+        assert!(background_finished.recv_timeout(timeout).is_err());
+        release.send(()).unwrap();
+        let name = background_finished.recv().unwrap().unwrap();
+        assert!(name.starts_with("peregrine-background-"));
+    });
+}
+
+#[test]
+fn owned_plans() -> Result<()> {
+    let mut plan = OwnedPlan::<AB>::new(seconds(-1), initial_conditions! { a: 0, b: 0 })?;
+    plan.with_plan_mut(|plan| plan.insert(seconds(0), IncrementA))?;
+
+    let plan = std::thread::spawn(move || {
+        plan.with_plan_mut(|plan| plan.insert(seconds(1), SetBToA))
+            .unwrap();
+        assert_eq!(1, plan.plan().sample::<b>(seconds(2)).unwrap());
+        plan
+    })
+    .join()
+    .unwrap();
+
+    let session = plan.into_session();
+    assert!(!session.history().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn chained_plans() -> Result<()> {
+    let session = Session::new();
+    let mut first = init_plan(&session);
+    first.insert(seconds(0), IncrementA)?;
+    first.insert(seconds(3), SetBToA)?;
+    assert_eq!(seconds(3), first.end());
+
+    let mut second: Plan<AB> = session.new_plan(first.end(), first.final_conditions()?)?;
+    second.insert(seconds(4), AddBToA)?;
+    assert_eq!(2, second.sample::<a>(second.end())?);
+    assert_eq!(1, second.sample::<b>(second.end())?);
+
+    let mut first: Plan<Instruments> = session.new_plan(
+        seconds(-1),
+        initial_conditions! { instrument_temp: 20 }.insert_key::<instrument_temp>(&2, 100),
+    )?;
+    first.insert(seconds(0), WarmInstrument(1))?;
+
+    let mut second: Plan<Instruments> = session.new_plan(first.end(), first.final_conditions()?)?;
+    second.insert(seconds(1), WarmAll)?;
+    assert_eq!(21, second.sample_key::<instrument_temp>(&0, seconds(2))?);
+    assert_eq!(31, second.sample_key::<instrument_temp>(&1, seconds(2))?);
+    assert_eq!(101, second.sample_key::<instrument_temp>(&2, seconds(2))?);
+
+    Ok(())
+}
+
+#[test]
+fn deserialized_initial_conditions() -> Result<()> {
+    let session = Session::new();
+    let initial_conditions: InitialConditions = serde_json::from_str(r#"{ "a": 3, "b": 4 }"#)?;
+    let mut plan: Plan<AB> = session.new_plan(seconds(-1), initial_conditions)?;
+    plan.insert(seconds(0), AddBToA)?;
+    assert_eq!(7, plan.sample::<a>(seconds(1))?);
+
+    assert!(serde_json::from_str::<InitialConditions>(r#"{ "a": "three" }"#).is_err());
+    assert!(serde_json::from_str::<InitialConditions>(r#"{ "nonexistent": 3 }"#).is_err());
+
+    Ok(())
+}
+
+resource!(sunlit: bool);
+
+model! {
+    SolarAB(a, b, sunlit)
+}
+
+struct Charge;
+impl_activity! { for Charge
+    @(start) {
+        if ref:sunlit {
+            ref mut: a += 1;
+        }
+    }
+    Duration::ZERO
+}
+
+struct Shade;
+impl_activity! { for Shade
+    @(start) {
+        mut: sunlit = ref: a > 100;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn boundary_profiles() -> Result<()> {
+    let session = Session::new();
+    let initial_conditions = initial_conditions! { a: 0, b: 0 }.insert_profile::<sunlit>(vec![
+        (seconds(2), true),
+        (seconds(-5), false),
+        (seconds(4), false),
+    ]);
+    let mut plan: Plan<SolarAB> = session.new_plan(seconds(-1), initial_conditions)?;
+
+    assert_eq!(
+        vec![
+            (seconds(-1), false),
+            (seconds(2), true),
+            (seconds(4), false)
+        ],
+        plan.view::<sunlit>(..)?
+    );
+
+    for s in 0..6 {
+        plan.insert(seconds(s), Charge)?;
+    }
+    assert_eq!(2, plan.sample::<a>(seconds(6))?);
+
+    assert!(matches!(
+        plan.insert(seconds(3), Shade),
+        Err(EngineError::InsertionFailed {
+            activity: "Shade",
+            ..
+        })
+    ));
+    assert_eq!(2, plan.sample::<a>(seconds(6))?);
+
+    Ok(())
+}
+
+#[test]
+fn compaction() -> Result<()> {
+    let session = Session::new();
+    let initial_conditions = initial_conditions! { a: 0, b: 0 }.insert_profile::<sunlit>(vec![
+        (seconds(-5), false),
+        (seconds(2), true),
+        (seconds(20), false),
+    ]);
+    let mut plan: Plan<SolarAB> = session.new_plan(seconds(-1), initial_conditions)?;
+
+    for i in 0..200 {
+        let id = plan.insert(seconds(i % 30), Charge)?;
+        if i % 10 != 0 {
+            plan.remove(id)?;
+        }
+    }
+    // Replacing the increment moves it after the set at the same time.
+    let first = plan.insert_with_key("first", seconds(25), IncrementA)?;
+    plan.insert(seconds(25), SetBToA)?;
+    plan.replace(first, IncrementA)?;
+    let metadata = ActivityMetadata {
+        name: Some("first".to_string()),
+        ..Default::default()
+    };
+    plan.set_metadata(first, metadata.clone())?;
+
+    let a_view = plan.view::<a>(..)?;
+    let b_view = plan.view::<b>(..)?;
+    let sunlit_view = plan.view::<sunlit>(..)?;
+    let bytes = plan.arena_bytes();
+
+    plan.compact()?;
+
+    assert!(plan.arena_bytes() < bytes);
+    assert_eq!(a_view, plan.view::<a>(..)?);
+    assert_eq!(b_view, plan.view::<b>(..)?);
+    assert_eq!(sunlit_view, plan.view::<sunlit>(..)?);
+    assert_eq!(7, plan.sample::<b>(seconds(25))?);
+    assert_eq!(8, plan.sample::<a>(seconds(25))?);
+    assert_eq!(Some(first), plan.get_id("first"));
+    assert_eq!(Some(&metadata), plan.metadata(first));
+    assert!(matches!(
+        plan.insert(seconds(3), Shade),
+        Err(EngineError::InsertionFailed { .. })
+    ));
+
+    plan.insert(seconds(10), Charge)?;
+    plan.compact()?;
+    assert_eq!(9, plan.sample::<a>(seconds(30))?);
+
+    Ok(())
+}
+
+#[test]
+fn memory_report() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    for i in 0..100 {
+        let id = plan.insert(seconds(i), IncrementA)?;
+        if i % 2 == 1 {
+            plan.remove(id)?;
+        }
+    }
+    let (_, recording) = plan.view_recorded::<a>(..)?;
+
+    let report = session.memory_report();
+    assert_eq!(1, report.arenas.len());
+    // The initial conditions of `a` and `b`, and the remaining increments.
+    assert_eq!(52, report.arenas[0].timeline_nodes);
+    assert_eq!(plan.arena_bytes(), report.arena_bytes());
+    let history = report
+        .history
+        .iter()
+        .find(|container| container.resources.contains(&"a"))
+        .unwrap();
+    assert!(history.resources.contains(&"b"));
+    assert!(history.entries >= 51);
+    assert!(history.bytes > 0);
+
+    plan.compact()?;
+    let compacted = session.memory_report();
+    assert_eq!(
+        vec![0, 52],
+        compacted
+            .arenas
+            .iter()
+            .map(|arena| arena.timeline_nodes)
+            .collect::<Vec<_>>()
+    );
+
+    drop(recording);
+    let compacted = session.memory_report();
+    assert_eq!(1, compacted.arenas.len());
+    assert!(compacted.arena_bytes() < report.arena_bytes());
+    assert_eq!(52, compacted.arenas[0].timeline_nodes);
+
+    Ok(())
+}
+
+#[test]
+fn printed_profiles() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), IncrementA)?;
+
+    let profile = plan.profile::<a>(seconds(0)..)?;
+    assert_eq!("a", profile.label());
+    assert_eq!(profile, plan.view::<a>(seconds(0)..)?);
+    assert_eq!(
+        vec![
+            "time                     a",
+            "1900-01-01T00:00:00 TAI  1",
+            "1900-01-01T00:00:01 TAI  2",
+            "",
+        ],
+        format!("{profile:?}").split('\n').collect::<Vec<_>>()
+    );
+    assert_eq!(profile.to_string(), format!("{profile:?}"));
+    assert_eq!(Some(2), profile.last());
+    Ok(())
+}
+
+#[test]
+fn time_series_files() -> Result<()> {
+    let session = Session::new();
+    let path = std::env::temp_dir().join(format!("peregrine-series-{}.csv", std::process::id()));
+    let start = seconds(-1);
+    let write = |lines: &[(i32, bool)]| {
+        let text: String = lines
+            .iter()
+            .map(|(s, v)| format!("{},{v}\n", seconds(*s)))
+            .collect();
+        std::fs::write(&path, format!("# eclipse predictions\ntime,sunlit\n{text}")).unwrap();
+        series::TimeSeries::<bool>::from_csv(&path).unwrap()
+    };
+
+    let first = write(&[(3, true), (-1, true), (1, false)]);
+    assert_eq!(Some(&false), first.sample(seconds(2)));
+    assert_eq!(None, first.sample(seconds(-2)));
+
+    let initial_conditions = initial_conditions! { a: 0, b: 0 }.insert_series_file::<sunlit>(&path);
+    let mut plan: Plan<SolarAB> = session.new_plan(start, initial_conditions)?;
+    for s in 0..5 {
+        plan.insert(seconds(s), Charge)?;
+    }
+    assert_eq!(3, plan.sample::<a>(seconds(5))?);
+
+    let second = write(&[(3, true), (-1, true), (1, false)]);
+    assert_eq!(first.content_hash(), second.content_hash());
+    let third = write(&[(-1, true)]);
+    assert_ne!(first.content_hash(), third.content_hash());
+
+    std::fs::remove_file(&path)?;
+    assert!(matches!(
+        session.new_plan::<SolarAB>(
+            start,
+            initial_conditions! { a: 0, b: 0 }.insert_series_file::<sunlit>(&path),
+        ),
+        Err(EngineError::ProfileLoadFailed {
+            resource: "sunlit",
+            ..
+        })
+    ));
+
+    Ok(())
+}
+
+struct AddToA {
+    amount: u32,
+    label: &'static str,
+}
+impl_activity! { for AddToA
+    arguments {
+        amount: self.amount,
+        label: self.label,
+    }
+    @(start) {
+        ref mut: a += self.amount;
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn command_sequences() -> Result<()> {
+    use peregrine::commands::{CcsdsCommands, CommandFormatter};
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert_with_key(
+        "second",
+        seconds(2),
+        AddToA {
+            amount: 5,
+            label: "two words",
+        },
+    )?;
+    plan.insert(seconds(1), IncrementA)?;
+    plan.insert(seconds(10), IncrementB)?;
+
+    let commands = plan.commands(..seconds(5));
+    assert_eq!(2, commands.len());
+    assert_eq!("IncrementA", commands[0].mnemonic);
+    assert!(commands[0].arguments.is_empty());
+    assert_eq!(Some("second"), commands[1].key.as_deref());
+    assert_eq!(
+        vec![
+            ("amount", "5".to_string()),
+            ("label", "two words".to_string())
+        ],
+        commands[1].arguments
+    );
+
+    let sequence = CcsdsCommands::new()
+        .with_header("SEQUENCE TEST")
+        .format(&commands);
+    assert_eq!(
+        "; SEQUENCE TEST\n\
+         1900-001T00:00:01.000Z INCREMENT_A\n\
+         1900-001T00:00:02.000Z ADD_TO_A AMOUNT=5, LABEL=\"two words\"\n",
+        sequence
+    );
+
+    Ok(())
+}
+
+#[test]
+fn compare_plans() -> Result<()> {
+    use peregrine::compare::{ComparisonReport, diff};
+    use std::ops::Bound::{Excluded, Included};
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(4), IncrementA)?;
+    let id = plan.insert(seconds(2), IncrementB)?;
+    let window = seconds(0)..seconds(10);
+    let before = plan.view::<a>(window.clone())?;
+
+    plan.replace(
+        id,
+        AddToA {
+            amount: 5,
+            label: "",
+        },
+    )?;
+    let after = plan.view::<a>(window.clone())?;
+    let differences = diff(&before, &after, window.clone());
+    assert_eq!(2, differences.len());
+    assert_eq!(
+        (Included(seconds(2)), Excluded(seconds(4))),
+        differences[0].range
+    );
+    assert_eq!((1, 6), (differences[0].before, differences[0].after));
+    assert_eq!(
+        (Included(seconds(4)), Excluded(seconds(10))),
+        differences[1].range
+    );
+    assert_eq!(5.0, differences[1].amount());
+
+    let other = init_plan(&session);
+    assert!(plan.compare::<b>(&other, ..)?.is_empty());
+
+    let report = ComparisonReport::new()
+        .compare_views::<a>(&before, &after, window.clone())
+        .compare::<b>(&other, &plan, window)?;
+    assert_eq!(5.0, report.resources()[0].max_amount());
+    assert_eq!(
+        "a: 2 windows differ, by up to 5\n\
+         \x20 [1900-01-01T00:00:02 TAI, 1900-01-01T00:00:04 TAI): 1 -> 6 (+5)\n\
+         \x20 [1900-01-01T00:00:04 TAI, 1900-01-01T00:00:10 TAI): 2 -> 7 (+5)\n\
+         b: no differences\n",
+        report.to_string()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn write_conflicts() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    // Increments build on each other, in either order.
+    plan.insert(seconds(1), IncrementA)?;
+    plan.insert(seconds(1), IncrementA)?;
+    assert!(plan.write_conflicts().is_empty());
+
+    let increment = plan.insert(seconds(2), IncrementA)?;
+    let set = plan.insert(seconds(2), SetAToB)?;
+    plan.insert(seconds(3), SetAToB)?;
+    plan.insert(seconds(3), IncrementB)?;
+
+    let conflicts = plan.write_conflicts();
+    assert_eq!(1, conflicts.len());
+    let conflict = conflicts[0];
+    assert_eq!("a", conflict.resource);
+    assert_eq!(
+        (increment, true),
+        (conflict.first.activity_id, conflict.first.reads)
+    );
+    assert_eq!(
+        (set, false),
+        (conflict.second.activity_id, conflict.second.reads)
+    );
+    assert_eq!(seconds(2), conflict.second.min_time);
+    assert_eq!(
+        format!(
+            "a is written by activity IncrementA ({increment:?}, operation 0) at {} and activity SetAToB ({set:?}, operation 0) at {}",
+            seconds(2),
+            seconds(2)
+        ),
+        conflict.to_string()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn lint_issues() -> Result<()> {
+    use peregrine::lint::LintKind;
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    let increment = plan.insert(seconds(1), IncrementA)?;
+    let first_set = plan.insert(seconds(2), SetAToB)?;
+    let second_set = plan.insert(seconds(3), SetAToB)?;
+
+    let kinds: Vec<_> = plan
+        .lint()
+        .into_iter()
+        .map(|issue| (issue.activity_id, issue.kind))
+        .collect();
+    assert_eq!(
+        vec![
+            (
+                increment,
+                LintKind::Overwritten {
+                    resource: "a",
+                    by: first_set,
+                    at: seconds(2)
+                }
+            ),
+            (
+                first_set,
+                LintKind::Overwritten {
+                    resource: "a",
+                    by: second_set,
+                    at: seconds(3)
+                }
+            ),
+            (first_set, LintKind::NeverWritten("b")),
+            (second_set, LintKind::NeverWritten("b")),
+        ],
+        kinds
+    );
+
+    // Reading `a` in between keeps the increment.
+    plan.insert(seconds(1), SetBToA)?;
+    let issues = plan.lint();
+    assert_eq!(1, issues.len());
+    assert_eq!(first_set, issues[0].activity_id);
+
+    Ok(())
+}
+
+#[test]
+fn export_dag() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(1), IncrementA)?;
+    plan.insert(seconds(2), SetBToA)?;
+    plan.insert(seconds(2), IncrementA)?;
+    plan.insert(seconds(3), IncrementB)?;
+
+    let dot = plan.export_dag(seconds(0)..seconds(3), &[]);
+    assert_eq!(
+        format!(
+            "digraph plan {{
+    rankdir=LR;
+    node [shape=box];
+    op0 [label=\"IncrementA ActivityId(0)\\nop 0\\n{}\"];
+    op1 [label=\"SetBToA ActivityId(1)\\nop 0\\n{}\"];
+    op2 [label=\"IncrementA ActivityId(2)\\nop 0\\n{}\"];
+    \"a\" [shape=ellipse, label=\"a\"];
+    \"a\" -> op0 [label=\"a\"];
+    op0 -> op1 [label=\"a\"];
+    op2 -> op1 [label=\"a\", style=dashed];
+    op0 -> op2 [label=\"a\"];
+}}
+",
+            seconds(1),
+            seconds(2),
+            seconds(2)
+        ),
+        dot
+    );
+
+    plan.sample::<a>(seconds(2))?;
+    let dot = plan.export_dag(seconds(0)..=seconds(3), &["b"]);
+    assert!(dot.contains("op0 [label=\"SetBToA ActivityId(1)\\nop 0\\n"));
+    assert!(dot.contains("fillcolor=lightpink"));
+    assert!(dot.contains("op0 -> op1 [label=\"b\"];"));
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+mod registered {
+    use super::*;
+    use peregrine::activity::{Activity, SavedArguments, VersionedActivity};
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    pub struct AddToA(pub u32);
+    impl_activity! { for AddToA
+        @(start) {
+            ref mut: a += self.0;
+        }
+        Duration::ZERO
+    }
+    impl VersionedActivity for AddToA {}
+
+    register_activity!(AddToA => AB);
+
+    #[test]
+    fn insert_by_name() -> Result<()> {
+        let session = Session::new();
+        let mut plan = init_plan(&session);
+        assert_eq!(vec!["AddToA"], registry::registered::<AB>());
+        assert!(registry::registered::<Composed>().is_empty());
+
+        let args = |args| SavedArguments { version: 0, args };
+        let id = plan.insert_by_name("AddToA", seconds(0), args(serde_json::json!(3)))?;
+        assert_eq!("AddToA", plan.span(id).unwrap().label);
+        assert_eq!(3, plan.sample::<a>(seconds(1))?);
+
+        assert!(matches!(
+            plan.insert_by_name("AddToB", seconds(0), args(serde_json::json!(3))),
+            Err(EngineError::UnknownActivity(label)) if label == "AddToB"
+        ));
+        assert!(matches!(
+            plan.insert_by_name("AddToA", seconds(0), args(serde_json::json!("three"))),
+            Err(EngineError::InvalidArguments {
+                activity: "AddToA",
+                ..
+            })
+        ));
+
+        // Registered for one model only.
+        let mut composed =
+            session.new_plan::<Composed>(seconds(-1), initial_conditions! { a: 0, b: 0, c: 0 })?;
+        assert!(
+            composed
+                .insert_by_name("AddToA", seconds(0), args(serde_json::json!(3)))
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn offloaded_operations() -> Result<()> {
+        use peregrine::history::remote::{HistoryServer, RemoteHistory};
+        use peregrine::offload::{Executor, Job, evaluate};
+        use std::sync::{Arc, Mutex};
+
+        struct Worker {
+            history: History,
+            jobs: Arc<Mutex<Vec<Job>>>,
+        }
+        impl Executor for Worker {
+            fn accepts(&self, activity: &str) -> bool {
+                activity == "AddToA"
+            }
+
+            fn execute(&self, job: &Job) -> Result<()> {
+                self.jobs.lock().unwrap().push(job.clone());
+                if job.time > seconds(5) {
+                    bail!("the worker is unavailable");
+                }
+                evaluate(job, &self.history)
+            }
+        }
+
+        let server = HistoryServer::bind("127.0.0.1:0")?;
+        let address = server.local_addr()?;
+        server.spawn();
+
+        let session = Session::from(History::new().with_backend(RemoteHistory::connect(address)?));
+        let mut plan = init_plan(&session);
+        let jobs = Arc::new(Mutex::new(vec![]));
+        plan.set_executor(Worker {
+            history: History::new().with_backend(RemoteHistory::connect(address)?),
+            jobs: jobs.clone(),
+        });
+
+        plan.insert(seconds(0), AddToA(3))?;
+        plan.insert(seconds(1), IncrementA)?;
+        assert_eq!(4, plan.sample::<a>(seconds(2))?);
+        {
+            let jobs = jobs.lock().unwrap();
+            assert_eq!(1, jobs.len());
+            assert_eq!("AddToA", jobs[0].activity);
+            assert_eq!(seconds(0), jobs[0].time);
+            assert_eq!(1, jobs[0].inputs.len());
+        }
+
+        // Results stored by the worker are cached like any others.
+        plan.insert(seconds(3), IncrementB)?;
+        assert_eq!(4, plan.sample::<a>(seconds(4))?);
+        assert_eq!(1, jobs.lock().unwrap().len());
+
+        plan.insert(seconds(6), AddToA(1))?;
+        let error = plan.sample::<a>(seconds(7)).unwrap_err();
+        assert!(format!("{error:?}").contains("the worker is unavailable"));
+
+        let mut job = jobs.lock().unwrap()[0].clone();
+        job.operation = "missing".to_string();
+        assert!(evaluate(&job, &History::new()).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn cli_runner() -> Result<()> {
+        use peregrine::cli::{Format, Options, run};
+
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let path = |name: &str| dir.join(format!("peregrine-cli-{id}-{name}"));
+        std::fs::write(path("ic.json"), r#"{ "a": 1, "b": 2 }"#)?;
+        std::fs::write(
+            path("plan.json"),
+            r#"{ "activities": [{ "type": "AddToA", "start": "1900-01-01T00:00:01 TAI", "args": 3 }] }"#,
+        )?;
+
+        let args = [
+            "--start",
+            "1900-01-01T00:00:00 TAI",
+            "--initial-conditions",
+            &path("ic.json").display().to_string(),
+            "--plan",
+            &path("plan.json").display().to_string(),
+            "--resource",
+            "a",
+            "--output",
+            &path("out.csv").display().to_string(),
+            "--history",
+            &path("history.bin").display().to_string(),
+        ];
+        let options = Options::parse(args.map(str::to_string))?.unwrap();
+        assert_eq!(Format::Csv, options.format);
+        assert_eq!(vec!["a"], options.resources);
+
+        run::<AB>(&options)?;
+        assert_eq!(
+            "resource,time,value\n\
+             a,1900-01-01T00:00:00.000000000Z,1\n\
+             a,1900-01-01T00:00:01.000000000Z,4\n",
+            std::fs::read_to_string(path("out.csv"))?
+        );
+        assert!(History::import_portable(&std::fs::read(path("history.bin"))?).is_ok());
+
+        let json = Options {
+            resources: vec![],
+            output: Some(path("out.json")),
+            format: Format::Json,
+            ..options
+        };
+        run::<AB>(&json)?;
+        let output: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path("out.json"))?)?;
+        assert_eq!("b", output[1]["label"]);
+        assert_eq!(2, output[1]["points"][0]["value"]);
+
+        for name in ["ic.json", "plan.json", "out.csv", "out.json", "history.bin"] {
+            std::fs::remove_file(path(name))?;
+        }
+        assert!(Options::parse(["--help".to_string()])?.is_none());
+        assert!(Options::parse(["--plan".to_string(), "plan.json".to_string()]).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "repl")]
+    #[test]
+    fn repl_session() -> Result<()> {
+        let session = Session::new();
+        let mut plan = init_plan(&session);
+
+        let commands = "\
+            insert AddToA \"1900-01-01T00:00:01 TAI\" 3\n\
+            insert AddToA \"+3 s\" 4\n\
+            insert AddToB \"+1 s\"\n\
+            insert AddToA +1 s\n\
+            sample a +2 s\n\
+            shift 0 2 s\n\
+            view a \"+1 s\" \"+5 s\"\n\
+            remove 1\n\
+            list\n\
+            quit\n\
+            sample a +1 s\n";
+        let mut output = vec![];
+        peregrine::repl::run(&mut plan, commands.as_bytes(), &mut output)?;
+        let output = String::from_utf8(output)?;
+        let lines: Vec<_> = output
+            .lines()
+            .map(|line| line.trim_start_matches("> "))
+            .collect();
+        assert_eq!(
+            vec![
+                "inserted 0",
+                "inserted 1",
+                "error: no activity named \"AddToB\" is registered for the model",
+                "error: invalid duration \"1\": UnknownOrMissingUnit, expect a unit after the last numeric",
+                "3",
+                "1899-12-31T23:59:59.000000000Z  0",
+                "1900-01-01T00:00:02.000000000Z  4",
+                "1900-01-01T00:00:03.000000000Z  7",
+                "0  AddToA  1900-01-01T00:00:03.000000000Z  0 ns",
+                "",
+            ],
+            lines
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn load_plan_document() -> Result<()> {
+        let session = Session::new();
+        let mut plan = init_plan(&session);
+
+        let path = std::env::temp_dir().join(format!("peregrine-plan-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [[activities]]
+            type = "AddToA"
+            start = "1900-01-01T00:00:00 TAI"
+            args = 2
+            id = "first"
+            tags = ["science"]
+
+            [[activities]]
+            type = "AddToA"
+            start = "1900-01-01T00:00:01 TAI"
+            args = 3
+            "#,
+        )?;
+        let ids = plan.load(&path)?;
+        assert_eq!(2, ids.len());
+        assert_eq!(Some(ids[0]), plan.get_id("first"));
+        assert!(plan.metadata(ids[0]).unwrap().has_tag("science"));
+        assert_eq!(5, plan.sample::<a>(seconds(2))?);
+
+        // Loading it again reuses the key, and also fails on the unknown activity, so nothing
+        // from the second load is inserted.
+        std::fs::write(
+            &path,
+            r#"
+            [[activities]]
+            type = "AddToA"
+            start = "1900-01-01T00:00:02 TAI"
+            args = 1
+
+            [[activities]]
+            type = "AddToA"
+            start = "1900-01-01T00:00:00 TAI"
+            args = 2
+            id = "first"
+
+            [[activities]]
+            type = "Unknown"
+            start = "1900-01-01T00:00:00 TAI"
+            "#,
+        )?;
+        let error = plan.load(&path).unwrap_err();
+        std::fs::remove_file(&path)?;
+        let Some(EngineError::InvalidPlanDocument(issues)) = error.downcast_ref() else {
+            panic!("expected an invalid document, found {error}");
+        };
+        assert_eq!(
+            vec![(1, Some("first")), (2, None)],
+            issues
+                .iter()
+                .map(|issue| (issue.index, issue.key.as_deref()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, plan.spans().len());
+        assert_eq!(5, plan.sample::<a>(seconds(3))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn save_plan_document() -> Result<()> {
+        let session = Session::new();
+        let mut plan = init_plan(&session);
+        let first = plan.insert_with_key("first", seconds(0), AddToA(2))?;
+        plan.set_metadata(
+            first,
+            ActivityMetadata {
+                tags: vec!["science".to_string()],
+                ..Default::default()
+            },
+        )?;
+        plan.insert(seconds(1), AddToA(3))?;
+        let unregistered = plan.insert(seconds(2), IncrementA)?;
+        assert!(plan.to_document().is_err());
+        plan.remove(unregistered)?;
+
+        let path =
+            std::env::temp_dir().join(format!("peregrine-saved-{}.json", std::process::id()));
+        plan.save(&path)?;
+        let other_session = Session::new();
+        let mut loaded = init_plan(&other_session);
+        loaded.load(&path)?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(plan.to_document()?, loaded.to_document()?);
+        let first = loaded.get_id("first").unwrap();
+        assert!(loaded.metadata(first).unwrap().has_tag("science"));
+        assert_eq!(5, loaded.sample::<a>(seconds(2))?);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_boxed_activities() -> Result<()> {
+        let session = Session::new();
+        let activities: Vec<Box<dyn Activity<AB>>> = vec![Box::new(AddToA(2)), Box::new(AddToA(3))];
+        let json = serde_json::to_value(&activities)?;
+        assert_eq!(
+            serde_json::json!([
+                { "type": "AddToA", "version": 0, "args": 2 },
+                { "type": "AddToA", "version": 0, "args": 3 },
+            ]),
+            json
+        );
+        let unregistered: Vec<Box<dyn Activity<AB>>> = vec![Box::new(IncrementA)];
+        assert!(serde_json::to_value(&unregistered).is_err());
+
+        let activities: Vec<Box<dyn Activity<AB>>> = serde_json::from_value(json)?;
+        let mut plan = init_plan(&session);
+        for activity in activities {
+            plan.insert_boxed(seconds(0), activity)?;
+        }
+        assert_eq!(5, plan.sample::<a>(seconds(1))?);
+
+        let unknown = serde_json::json!([{ "type": "Unknown", "args": null }]);
+        assert!(serde_json::from_value::<Vec<Box<dyn Activity<AB>>>>(unknown).is_err());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn golden_profiles() -> Result<()> {
+    use peregrine::testing::golden::{GoldenProfiles, Tolerance, Tolerances};
+
+    let session = Session::new();
+    let record = |increments: &[i32]| -> Result<GoldenProfiles> {
+        let mut plan = init_plan(&session);
+        for time in increments {
+            plan.insert(seconds(*time), IncrementA)?;
+        }
+        plan.insert(seconds(10), SetBToA)?;
+        Ok(GoldenProfiles::new()
+            .record::<a>(&plan, seconds(0)..seconds(20))?
+            .record::<b>(&plan, seconds(0)..seconds(20))?)
+    };
+    let golden = record(&[1, 2, 3])?;
+    assert!(
+        record(&[1, 2, 3])?
+            .compare(&golden, &Tolerances::default())
+            .is_empty()
+    );
+
+    // Moving the last increment changes a for a second, and not b.
+    let report = record(&[1, 2, 4])?.compare(&golden, &Tolerances::default());
+    assert_eq!(
+        vec![
+            "a: 1 windows diverge, by up to 1 (tolerance: exactly)",
+            "  [1900-01-01T00:00:03 TAI, 1900-01-01T00:00:04 TAI): 3 -> 2 (-1)",
+            "",
+        ],
+        report.to_string().split('\n').collect::<Vec<_>>()
+    );
+    let tolerances = Tolerances::new(Tolerance::EXACT).with::<a>(Tolerance::relative(0.5));
+    assert!(record(&[1, 2, 4])?.compare(&golden, &tolerances).is_empty());
+
+    // One more increment changes both, and b by less than a third.
+    let report =
+        record(&[0, 1, 2, 3])?.compare(&golden, &Tolerances::new(Tolerance::relative(0.34)));
+    assert_eq!(
+        vec!["a"],
+        report
+            .diverging
+            .iter()
+            .map(|divergence| divergence.resource.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    let only_a = GoldenProfiles {
+        resources: golden.resources[..1].to_vec(),
+    };
+    let report = golden.compare(&only_a, &Tolerances::default());
+    assert_eq!(vec!["b".to_string()], report.unexpected);
+    let report = only_a.compare(&golden, &Tolerances::default());
+    assert_eq!(vec!["b".to_string()], report.missing);
+
+    let path = std::env::temp_dir().join(format!("peregrine-golden-{}.json", std::process::id()));
+    golden.check(&path, &Tolerances::default())?;
+    assert_eq!(golden, GoldenProfiles::load(&path)?);
+    record(&[1, 2, 3])?.check(&path, &Tolerances::default())?;
+    let error = record(&[1, 2, 4])?
+        .check(&path, &Tolerances::default())
+        .unwrap_err();
+    std::fs::remove_file(&path)?;
+    assert!(error.to_string().contains("a: 1 windows diverge"));
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn timeline_export() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    plan.insert(seconds(1), IncrementA)?;
+    plan.insert(seconds(2), IncrementB)?;
+    let id = plan.insert(seconds(3), IncrementA)?;
+    plan.set_metadata(
+        id,
+        ActivityMetadata {
+            tags: vec!["science".to_string()],
+            ..Default::default()
+        },
+    )?;
+
+    let export = plan
+        .export_timeline(seconds(2)..seconds(5))
+        .resource::<a>(&plan)?
+        .resource::<b>(&plan)?;
+    let json = serde_json::to_value(&export)?;
+    assert_eq!(1, json["schema_version"]);
+    assert_eq!(
+        vec!["IncrementB", "IncrementA"],
+        export
+            .activities
+            .iter()
+            .map(|activity| activity.label.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        serde_json::json!(["science"]),
+        json["activities"][1]["tags"]
+    );
+
+    // The value from before the window starts it, and the last segment ends with it.
+    let a_segments = &json["resources"][0]["segments"];
+    assert_eq!(json["start"], a_segments[0]["start"]);
+    assert_eq!(1, a_segments[0]["value"]);
+    assert_eq!(a_segments[0]["end"], a_segments[1]["start"]);
+    assert_eq!(2, a_segments[1]["value"]);
+    assert_eq!(json["end"], a_segments[1]["end"]);
+
+    let round_trip: peregrine::gantt::TimelineExport = serde_json::from_value(json)?;
+    assert_eq!(export.resources, round_trip.resources);
+
+    Ok(())
+}
+
+#[test]
+fn plans_move_between_threads() -> Result<()> {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Plan<'static, AB>>();
+    assert_send_sync::<OwnedPlan<AB>>();
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    let id = plan.insert(seconds(0), IncrementA)?;
+    plan.insert(seconds(1), AddBToA)?;
+
+    let mut plan = std::thread::scope(|scope| {
+        scope
+            .spawn(move || -> Result<_> {
+                plan.replace(id, IncrementB)?;
+                plan.insert(seconds(0), IncrementA)?;
+                Ok(plan)
+            })
+            .join()
+            .unwrap()
+    })?;
+    assert_eq!(2, plan.sample::<a>(seconds(2))?);
+    plan.remove(id)?;
+    assert_eq!(1, plan.sample::<a>(seconds(2))?);
+
+    Ok(())
+}
+
+#[test]
+fn concurrent_views() -> Result<()> {
+    use std::sync::atomic::Ordering;
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    for i in 0..20 {
+        plan.insert(seconds(i * 2), IncrementA)?;
+        plan.insert(seconds(i * 2 + 1), AddBToA)?;
+        plan.insert(seconds(i * 2 + 1), IncrementB)?;
+    }
+    let (counter, evaluations) = EvalCounter::new();
+    plan.insert(seconds(50), counter)?;
+
+    let (a_views, b_views, samples) = std::thread::scope(|scope| {
+        let a_views: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| plan.view::<a>(seconds(0)..seconds(60))))
+            .collect();
+        let b_views = scope.spawn(|| plan.view::<b>(seconds(0)..seconds(60)));
+        let samples = scope.spawn(|| plan.sample::<a>(seconds(55)));
+        (
+            a_views
+                .into_iter()
+                .map(|view| view.join().unwrap())
+                .collect::<Vec<_>>(),
+            b_views.join().unwrap(),
+            samples.join().unwrap(),
+        )
+    });
+
+    // Every thread waited for the operations that the others were simulating, instead of
+    // simulating them again.
+    assert_eq!(1, evaluations.load(Ordering::SeqCst));
+
+    let expected_a = plan.view::<a>(seconds(0)..seconds(60))?;
+    for view in a_views {
+        assert_eq!(expected_a, view?);
+    }
+    assert_eq!(plan.view::<b>(seconds(0)..seconds(60))?, b_views?);
+    assert_eq!(plan.sample::<a>(seconds(55))?, samples?);
+
+    Ok(())
+}
+
+#[test]
+fn tuned_scheduling() -> Result<()> {
+    let tunings = [
+        Tuning::default(),
+        Tuning {
+            stack_limit: 2,
+            inline_continuations: 4,
+        },
+        Tuning {
+            inline_continuations: 0,
+            ..Tuning::default()
+        },
+    ];
+
+    let mut views = vec![];
+    for tuning in tunings {
+        let session = Session::new().with_tuning(tuning);
+        assert_eq!(tuning, session.tuning());
+        let mut plan = init_plan(&session);
+        for i in 0..30 {
+            plan.insert(seconds(i), IncrementA)?;
+            plan.insert(seconds(i), SetBToA)?;
+            if i % 3 == 0 {
+                plan.insert(seconds(i), AddBToA)?;
+            }
+        }
+        views.push((
+            plan.view::<a>(seconds(0)..seconds(30))?,
+            plan.view::<b>(seconds(0)..seconds(30))?,
+        ));
+    }
+
+    // Scheduling changes which thread does the work, not the results.
+    assert!(views.windows(2).all(|pair| pair[0] == pair[1]));
+
+    Ok(())
+}
+
+#[test]
+fn trampolined_chains() -> Result<()> {
+    for stack_limit in [0, 1, 3] {
+        let session = Session::new().with_tuning(Tuning {
+            stack_limit,
+            ..Tuning::default()
+        });
+        let mut plan = init_plan(&session);
+
+        // A straight chain, evaluated in a loop past the stack limit.
+        for i in 0..2000 {
+            plan.insert(seconds(i), IncrementA)?;
+        }
+        plan.insert(seconds(1000), SetBToA)?;
+
+        assert_eq!(2000, plan.sample::<a>(seconds(2000))?);
+        assert_eq!(1001, plan.sample::<b>(seconds(1000))?);
+    }
+
+    Ok(())
+}
+
+/// Becomes ready a little after it is first polled, when woken from another thread.
+struct Delayed(Option<std::sync::Arc<std::sync::atomic::AtomicBool>>);
+
+impl std::future::Future for Delayed {
+    type Output = u32;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        context: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<u32> {
+        match &self.0 {
+            Some(ready) if ready.load(std::sync::atomic::Ordering::SeqCst) => {
+                std::task::Poll::Ready(2)
+            }
+            Some(_) => std::task::Poll::Pending,
+            None => {
+                let ready = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+                let waker = context.waker().clone();
+                let woken = ready.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    woken.store(true, std::sync::atomic::Ordering::SeqCst);
+                    waker.wake();
+                });
+                self.0 = Some(ready);
+                std::task::Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct AwaitIncrementA;
+impl_activity! { for AwaitIncrementA
+    @(start) {
+        ref mut: a += peregrine::exec::block_on(Delayed(None));
+    }
+    Duration::ZERO
+}
+
+#[test]
+fn awaiting_operations() -> Result<()> {
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+
+    for i in 0..10 {
+        plan.insert(seconds(i), AwaitIncrementA)?;
+        plan.insert(seconds(i), IncrementB)?;
+    }
+    plan.insert(seconds(10), AddBToA)?;
+
+    assert_eq!(30, plan.sample::<a>(seconds(10))?);
+
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+pub struct SleepIncrementA;
+#[cfg(feature = "tokio")]
+impl_activity! { for SleepIncrementA
+    @(start) {
+        ref mut: a += peregrine::exec::block_on(async {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            1
+        });
+    }
+    Duration::ZERO
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn awaiting_tokio_operations() -> Result<()> {
+    static RUNTIME: std::sync::LazyLock<tokio::runtime::Runtime> =
+        std::sync::LazyLock::new(|| tokio::runtime::Runtime::new().unwrap());
+    peregrine::exec::set_async_runtime(peregrine::exec::TokioRuntime(RUNTIME.handle().clone()));
+
+    let session = Session::new();
+    let mut plan = init_plan(&session);
+    for i in 0..10 {
+        plan.insert(seconds(i), SleepIncrementA)?;
+    }
+
+    assert_eq!(10, plan.sample::<a>(seconds(10))?);
+
+    Ok(())
+}
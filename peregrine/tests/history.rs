@@ -1,9 +1,24 @@
 use bincode::config::standard;
+use peregrine::history::portable::{Migrations, Schema};
 use peregrine::history::{DerefHistory, HistoryAdapter};
-use peregrine::{History, Result, resource};
+use peregrine::resource::ResourceInfo;
+use peregrine::{History, Result, register_resource, resource};
 
 resource!(a: u32);
 resource!(ref b: String);
+resource!(pair<T>: (T, T));
+resource!(ref list<T>: Vec<T> where T: PartialEq);
+
+register_resource!(pair<u8>);
+register_resource!(pair<i64>);
+register_resource!(list<u8>);
+
+resource! {
+    /// Temperature of the
+    /// main bus.
+    #[unit = "degC"]
+    bus_temperature: f32
+}
 
 #[test]
 fn deref_history_valid_across_realloc() {
@@ -47,3 +62,292 @@ fn history_serde() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(feature = "rkyv")]
+#[test]
+fn history_archive() -> Result<()> {
+    use peregrine::history::archive::ArchivedHistory;
+
+    let history = History::default();
+    history.init::<a>();
+    history.init::<b>();
+
+    history.insert::<a>(0, 5);
+    history.insert::<b>(10, "string".to_string());
+
+    let archive = ArchivedHistory::from_bytes(&history.to_archive()?)?;
+    assert_eq!(2, archive.len());
+
+    let loaded = History::new().with_backend(archive);
+    loaded.init::<a>();
+    loaded.init::<b>();
+
+    assert_eq!(5, loaded.get::<a>(0).unwrap());
+    assert_eq!("string", loaded.get::<b>(10).unwrap());
+    assert_eq!(None, loaded.get::<a>(100));
+
+    // The original history is left intact.
+    assert_eq!(5, history.get::<a>(0).unwrap());
+
+    Ok(())
+}
+
+#[cfg(feature = "rkyv")]
+#[test]
+fn history_archive_file_is_read_in_place() -> Result<()> {
+    use peregrine::history::HistoryBackend;
+    use peregrine::history::archive::ArchivedHistory;
+    use std::borrow::Cow;
+
+    let history = History::default();
+    history.init::<a>();
+    history.insert::<a>(0, 5);
+
+    let path = std::env::temp_dir().join(format!("peregrine-archive-{}", std::process::id()));
+    history.write_archive(&path)?;
+    let archive = ArchivedHistory::read(&path)?;
+
+    // Entries are lent out of the mapped file rather than copied. `a` is stored under its
+    // write type.
+    assert!(matches!(archive.get("u32", 0), Some(Cow::Borrowed(_))));
+
+    let loaded = History::new().with_backend(archive);
+    loaded.init::<a>();
+    assert_eq!(5, loaded.get::<a>(0).unwrap());
+
+    drop(loaded);
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+#[test]
+fn history_merge() {
+    let first = History::default();
+    first.init::<a>();
+    first.init::<b>();
+    first.insert::<a>(0, 5);
+    first.insert::<b>(10, "first".to_string());
+
+    let second = History::default();
+    second.init::<a>();
+    second.insert::<a>(0, 5);
+    second.insert::<a>(1, 6);
+
+    let third = History::default();
+    third.init::<b>();
+    third.insert::<b>(11, "third".to_string());
+
+    first.merge(second);
+    first.merge(third);
+
+    assert_eq!(5, first.get::<a>(0).unwrap());
+    assert_eq!(6, first.get::<a>(1).unwrap());
+    assert_eq!("first", first.get::<b>(10).unwrap());
+    assert_eq!("third", first.get::<b>(11).unwrap());
+}
+
+#[test]
+fn portable_history_round_trip() -> Result<()> {
+    let history = History::default();
+    history.init::<a>();
+    history.init::<b>();
+    history.insert::<a>(0, 5);
+    history.insert::<b>(10, "string".to_string());
+
+    let mut exported = history.export_portable()?;
+    let imported = History::import_portable(&exported)?;
+    imported.init::<a>();
+    imported.init::<b>();
+
+    assert_eq!(5, imported.get::<a>(0).unwrap());
+    assert_eq!("string", imported.get::<b>(10).unwrap());
+
+    // Flip a bit in the body.
+    exported[20] ^= 1;
+    assert!(History::import_portable(&exported).is_err());
+
+    Ok(())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Gain {
+    numerator: u32,
+    denominator: u32,
+}
+
+resource! {
+    #[schema_version = 1]
+    gain: Gain
+}
+
+/// The sections of the portable format, to rewrite a file as an older program would have.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RawSection {
+    type_string: String,
+    resources: Vec<String>,
+    schema: Option<Schema>,
+    entries: Vec<(u64, Vec<u8>)>,
+    checksum: u32,
+}
+
+#[test]
+fn portable_history_schema_mismatch() -> Result<()> {
+    let history = History::default();
+    history.init::<gain>();
+    history.insert::<gain>(
+        0,
+        Gain {
+            numerator: 1,
+            denominator: 2,
+        },
+    );
+    let exported = history.export_portable()?;
+    assert!(History::import_portable(&exported).is_ok());
+
+    // Pretend the file was written when the gain was a plain u32, at version 0, so its section
+    // is shared with the other u32 resources.
+    let body = &exported[12..exported.len() - 4];
+    let (mut sections, _): (Vec<RawSection>, _) =
+        bincode::serde::decode_from_slice(body, standard())?;
+    let section = sections
+        .iter_mut()
+        .find(|section| section.resources == ["gain"])
+        .unwrap();
+    section.type_string = "u32".to_string();
+    section.resources.push("a".to_string());
+    section.schema = Some(Schema::new("u32", 4, 0));
+    section.entries = vec![(0, bincode::serde::encode_to_vec(3u32, standard())?)];
+    section.checksum = {
+        let mut hasher = crc32fast::Hasher::new();
+        for (hash, bytes) in &section.entries {
+            hasher.update(&hash.to_le_bytes());
+            hasher.update(bytes);
+        }
+        hasher.finalize()
+    };
+    let body = bincode::serde::encode_to_vec(&sections, standard())?;
+    let mut old = exported[..12].to_vec();
+    old.extend_from_slice(&body);
+    old.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+
+    let Err(error) = History::import_portable(&old) else {
+        panic!("mismatched schema was imported");
+    };
+    let error = error.to_string();
+    assert!(error.contains("schema version 0"), "{error}");
+
+    let migrations = Migrations::new().add::<gain, u32>(0, |numerator| Gain {
+        numerator,
+        denominator: 1,
+    });
+    let migrated = History::import_portable_with(&old, &migrations)?;
+    migrated.init::<gain>();
+    assert_eq!(
+        Gain {
+            numerator: 3,
+            denominator: 1
+        },
+        migrated.get::<gain>(0).unwrap()
+    );
+    // `a` still writes a u32, so it gets the entries unchanged.
+    migrated.init::<a>();
+    assert_eq!(Some(3), migrated.get::<a>(0));
+
+    let discarded = History::import_portable_with(&old, &Migrations::new().discard_mismatched())?;
+    discarded.init::<gain>();
+    assert!(discarded.get::<gain>(0).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn generic_history_serde() -> Result<()> {
+    let history = History::default();
+    history.init::<pair<u8>>();
+    history.init::<pair<i64>>();
+    history.init::<list<u8>>();
+
+    history.insert::<pair<u8>>(0, (1, 2));
+    history.insert::<pair<i64>>(0, (-1, -2));
+    history.insert::<list<u8>>(0, vec![3, 4]);
+
+    let mut write_types: Vec<_> = history
+        .memory_report()
+        .into_iter()
+        .map(|container| container.write_type)
+        .collect();
+    write_types.sort();
+    assert_eq!(vec!["list<u8>", "pair<i64>", "pair<u8>"], write_types);
+
+    let serialized = bincode::serde::encode_to_vec(history, standard())?;
+    let deserialized: History = bincode::serde::decode_from_slice(&serialized, standard())?.0;
+
+    assert_eq!((1, 2), deserialized.get::<pair<u8>>(0).unwrap());
+    assert_eq!((-1, -2), deserialized.get::<pair<i64>>(0).unwrap());
+    assert_eq!(&[3, 4], deserialized.get::<list<u8>>(0).unwrap());
+
+    Ok(())
+}
+
+#[test]
+fn resource_metadata() {
+    let info = ResourceInfo::of::<bus_temperature>();
+    assert_eq!(Some("degC"), info.unit);
+    assert_eq!(Some("Temperature of the main bus."), info.description);
+    assert_eq!(None, ResourceInfo::of::<a>().unit);
+
+    let registered = peregrine::resource::registered();
+    assert!(registered.contains(&info));
+    assert!(registered.iter().any(|info| info.label == "pair"));
+    assert!(registered.is_sorted_by_key(|info| info.label));
+}
+
+#[test]
+fn persistent_history_shares_structure() {
+    use peregrine::persistent::Persistent;
+    type Map = Persistent<im::HashMap<u32, u32>>;
+
+    let history = DerefHistory::<Map>::default();
+    let first = history.insert(1, Map::new((0..1_000).map(|i| (i, i)).collect()));
+
+    // What an operation does with the value it read: clone it, and write it back.
+    let unchanged = history.insert(2, Map::from(first));
+    assert!(first.ptr_eq(unchanged));
+
+    let mut changed = Map::from(first);
+    changed.insert(1_000, 1_000);
+    let changed = history.insert(3, changed);
+    assert_eq!(1_000, first.len());
+    assert_eq!(1_001, changed.len());
+
+    // Stored exactly like the collection itself.
+    assert_eq!(
+        bincode::serde::encode_to_vec(first, standard()).unwrap(),
+        bincode::serde::encode_to_vec(Map::from(first), standard()).unwrap()
+    );
+}
+
+#[test]
+fn remote_history_rejects_oversized_frames() -> Result<()> {
+    use peregrine::history::HistoryBackend;
+    use peregrine::history::remote::{HistoryServer, RemoteHistory};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let server = HistoryServer::bind("127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    server.spawn();
+
+    // A get whose type string claims to be 4 GiB long.
+    let mut peer = TcpStream::connect(address)?;
+    peer.write_all(&[0])?;
+    peer.write_all(&u32::MAX.to_le_bytes())?;
+    assert_eq!(0, peer.read(&mut [0; 1])?);
+
+    let writer = RemoteHistory::connect(address)?;
+    let reader = RemoteHistory::connect(address)?;
+    writer.insert_batch(vec![("u32", 0, vec![1]), ("u32", 1, vec![2])]);
+    assert_eq!(Some(&[2][..]), reader.get("u32", 1).as_deref());
+    assert_eq!(None, reader.get("u32", 2));
+    Ok(())
+}
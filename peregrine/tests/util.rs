@@ -1,8 +1,7 @@
-#![allow(clippy::self_assignment)]
+#![allow(clippy::self_assignment, dead_code)]
 
+pub use peregrine::testing::seconds;
 use peregrine::*;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU16, Ordering};
 
 resource!(pub a: u32);
 resource!(pub b: u32);
@@ -47,33 +46,14 @@ impl_activity! { for AddBToA
     Duration::ZERO
 }
 
-pub struct EvalCounter(Arc<AtomicU16>);
-impl_activity! { for EvalCounter
-    @(start) {
-        mut:a = ref:a;
-        self.0.fetch_add(1, Ordering::SeqCst);
-    }
-    Duration::ZERO
-}
-
-impl EvalCounter {
-    // Cargo test incorrectly warns that this function is not used.
-    // It totally is, I don't know what its talking about.
-    #[allow(unused)]
-    pub fn new() -> (Self, Arc<AtomicU16>) {
-        let counter = Arc::new(AtomicU16::new(0));
-        (Self(counter.clone()), counter)
-    }
-}
+eval_counter!(pub EvalCounter: a);
 
 model! {
     pub AB(a, b)
 }
 
-pub fn init_plan(session: &Session) -> Plan<AB> {
-    session.new_plan(seconds(-1), initial_conditions! { a: 0, b: 0 })
-}
-
-pub fn seconds(s: i32) -> Time {
-    Time::from_tai_seconds(s as f64)
+pub fn init_plan(session: &Session) -> Plan<'_, AB> {
+    session
+        .new_plan(seconds(-1), initial_conditions! { a: 0, b: 0 })
+        .unwrap()
 }
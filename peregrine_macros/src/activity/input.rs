@@ -1,27 +1,71 @@
-use crate::activity::{Activity, ActivityStructure, Invocation, Placement, StmtOrInvoke, Target};
+use crate::activity::{
+    Activity, ActivityStructure, Arguments, Delay, Invocation, Placement, StmtOrInvoke, Target,
+};
+use crate::operation::{Op, refers_to};
+use quote::{ToTokens, format_ident};
 use syn::parse::discouraged::Speculative;
 use syn::parse::{Parse, ParseStream};
-use syn::{Expr, ItemEnum, ItemStruct, Path, Result, Stmt, Token, braced, parenthesized};
+use syn::{
+    Error, Expr, GenericParam, Generics, ItemEnum, ItemStruct, Pat, Path, Result, Stmt, Token,
+    WhereClause, braced, parenthesized,
+};
 
 impl Parse for Activity {
     fn parse(input: ParseStream) -> Result<Self> {
         let lookahead = input.lookahead1();
-        let (path, structure) = if lookahead.peek(Token![for]) {
+        let (path, generics, structure) = if lookahead.peek(Token![for]) {
             <Token![for]>::parse(input)?;
+            let mut generics: Generics = input.parse()?;
             let path: Path = input.parse()?;
-            (path.clone(), ActivityStructure::Path)
+            generics.where_clause = input.parse::<Option<WhereClause>>()?;
+            (path, generics, ActivityStructure::Path)
         } else if lookahead.peek(Token![struct]) {
             let item: ItemStruct = input.parse()?;
             let path = Path::from(item.ident.clone());
-            (path, ActivityStructure::Item)
+            (path, Generics::default(), ActivityStructure::Item)
         } else if lookahead.peek(Token![enum]) {
             let item: ItemEnum = input.parse()?;
             let path = Path::from(item.ident.clone());
-            (path, ActivityStructure::Item)
+            (path, Generics::default(), ActivityStructure::Item)
         } else {
             return Err(lookahead.error());
         };
 
+        if let Some(param) = generics
+            .params
+            .iter()
+            .find(|p| matches!(p, GenericParam::Lifetime(_)))
+        {
+            return Err(Error::new_spanned(
+                param,
+                "activities cannot have lifetime parameters",
+            ));
+        }
+
+        let mut validate = None;
+        let mut arguments = None;
+        while input.peek(syn::Ident) && input.peek2(syn::token::Brace) {
+            let forked = input.fork();
+            let ident: syn::Ident = forked.parse()?;
+            let duplicate = match ident.to_string().as_str() {
+                "validate" => validate.is_some(),
+                "arguments" => arguments.is_some(),
+                _ => break,
+            };
+            if duplicate {
+                return Err(Error::new_spanned(
+                    &ident,
+                    format!("duplicate `{ident}` block"),
+                ));
+            }
+            input.advance_to(&forked);
+            if ident == "validate" {
+                validate = Some(input.parse()?);
+            } else {
+                arguments = Some(input.parse()?);
+            }
+        }
+
         let mut lines: Vec<StmtOrInvoke> = vec![];
         while !input.is_empty() {
             lines.push(input.parse()?);
@@ -29,16 +73,49 @@ impl Parse for Activity {
 
         Ok(Activity {
             path,
+            generics,
             _structure: structure,
+            validate,
+            arguments,
             lines,
         })
     }
 }
 
+impl Parse for Arguments {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let body;
+        braced!(body in input);
+        let mut arguments = vec![];
+        while !body.is_empty() {
+            let name: syn::Ident = body.parse()?;
+            <Token![:]>::parse(&body)?;
+            let value: Expr = body.parse()?;
+            arguments.push((name, value));
+            if body.parse::<Option<Token![,]>>()?.is_none() {
+                break;
+            }
+        }
+        if !body.is_empty() {
+            return Err(body.error("expected `name: value` arguments separated by commas"));
+        }
+        Ok(Arguments(arguments))
+    }
+}
+
 impl Parse for StmtOrInvoke {
     fn parse(input: ParseStream) -> Result<Self> {
         if input.peek(Token![@]) {
-            Ok(StmtOrInvoke::Invoke(input.parse()?))
+            Ok(StmtOrInvoke::Invoke(Box::new(input.parse()?)))
+        } else if input.peek(Token![for]) {
+            <Token![for]>::parse(input)?;
+            let pat = Pat::parse_multi_with_leading_vert(input)?;
+            <Token![in]>::parse(input)?;
+            let expr = Expr::parse_without_eager_brace(input)?;
+            let body = parse_lines_in_braces(input)?;
+            Ok(StmtOrInvoke::For { pat, expr, body })
+        } else if input.peek(Token![if]) {
+            parse_if(input)
         } else {
             let forked = input.fork();
             let stmt: Result<Stmt> = forked.parse();
@@ -52,6 +129,36 @@ impl Parse for StmtOrInvoke {
     }
 }
 
+fn parse_lines_in_braces(input: ParseStream) -> Result<Vec<StmtOrInvoke>> {
+    let body;
+    braced!(body in input);
+    let mut lines = vec![];
+    while !body.is_empty() {
+        lines.push(body.parse()?);
+    }
+    Ok(lines)
+}
+
+fn parse_if(input: ParseStream) -> Result<StmtOrInvoke> {
+    <Token![if]>::parse(input)?;
+    let cond = Expr::parse_without_eager_brace(input)?;
+    let then_branch = parse_lines_in_braces(input)?;
+    let else_branch = if input.parse::<Option<Token![else]>>()?.is_some() {
+        if input.peek(Token![if]) {
+            Some(vec![parse_if(input)?])
+        } else {
+            Some(parse_lines_in_braces(input)?)
+        }
+    } else {
+        None
+    };
+    Ok(StmtOrInvoke::If {
+        cond,
+        then_branch,
+        else_branch,
+    })
+}
+
 impl Parse for Invocation {
     fn parse(input: ParseStream) -> Result<Self> {
         <Token![@]>::parse(input)?;
@@ -59,27 +166,61 @@ impl Parse for Invocation {
         let start_body;
         parenthesized!(start_body in input);
 
-        let start_expr = start_body.parse()?;
-        assert!(start_body.is_empty());
+        let start_expr: Expr = start_body.parse()?;
+        let order = if start_body.parse::<Option<Token![,]>>()?.is_some() {
+            let keyword: syn::Ident = start_body.parse()?;
+            if keyword != "order" {
+                return Err(Error::new_spanned(keyword, "expected `order = ...`"));
+            }
+            <Token![=]>::parse(&start_body)?;
+            Some(start_body.parse::<Expr>()?)
+        } else {
+            None
+        };
+        if !start_body.is_empty() {
+            return Err(start_body.error("unexpected tokens in placement"));
+        }
 
-        let delay_op = if input.peek(Token![+]) {
+        let delay = if input.peek(Token![+]) {
             <Token![+]>::parse(input)?;
             let delay_body;
             parenthesized!(delay_body in input);
+            let mut op: Op = delay_body.parse()?;
+            op.assign_to(format_ident!("peregrine_delay"));
 
-            Some(delay_body.parse()?)
+            let max = if input.peek(Token![..=]) {
+                <Token![..=]>::parse(input)?;
+                let max_body;
+                parenthesized!(max_body in input);
+                Some(max_body.parse()?)
+            } else {
+                None
+            };
+
+            Some(Box::new(Delay { op, max }))
         } else {
             None
         };
 
-        let target = input.parse()?;
+        let target: Target = input.parse()?;
+        if let Some(order) = &order
+            && !matches!(target, Target::Inline(_))
+        {
+            return Err(Error::new_spanned(
+                order,
+                "only inline operations can be given an order",
+            ));
+        }
+        let at_end = refers_to(start_expr.to_token_stream(), "end");
 
         Ok(Invocation {
             time: Placement {
                 start: start_expr,
-                delay: delay_op,
+                order,
+                delay,
             },
             target,
+            at_end,
         })
     }
 }
@@ -96,6 +237,31 @@ impl Parse for Target {
                 false
             };
 
+            // Markers before an inline op, like `commutative { ... }` or `priority { ... }`.
+            let mut markers = vec![ident.clone()];
+            while forked.peek(syn::Ident) {
+                markers.push(forked.parse()?);
+            }
+            if forked.peek(syn::token::Brace)
+                && markers
+                    .iter()
+                    .all(|marker| marker == "commutative" || marker == "priority")
+            {
+                input.advance_to(&forked);
+                let op_body;
+                braced!(op_body in input);
+                let mut op: Op = op_body.parse()?;
+                for marker in &markers {
+                    if marker == "commutative" {
+                        op.commutative = true;
+                        op.check_commutative(marker)?;
+                    } else {
+                        op.priority = true;
+                    }
+                }
+                return Ok(Target::Inline(op));
+            }
+
             let expr: Expr = input.parse()?;
             let _: Token![;] = input.parse()?;
 
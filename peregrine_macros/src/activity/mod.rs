@@ -1,7 +1,7 @@
 use crate::operation::{Context, Op};
 use proc_macro2::TokenStream;
 use quote::ToTokens;
-use syn::{Expr, Path, Stmt};
+use syn::{Block, Expr, Generics, Ident, Pat, Path, Stmt};
 
 mod input;
 mod output;
@@ -9,15 +9,18 @@ mod output;
 pub fn process_activity(mut activity: Activity) -> TokenStream {
     let path = activity.path.clone();
 
-    for line in &mut activity.lines {
-        if let StmtOrInvoke::Invoke(Invocation {
-            target: Target::Inline(op),
-            ..
-        }) = line
-        {
-            op.context = Context::Activity(path.clone());
+    fuse_operations(&mut activity.lines);
+
+    let generics = Box::new(activity.generics.clone());
+    for_each_invocation_mut(&mut activity.lines, &mut |invocation| {
+        if let Target::Inline(op) = &mut invocation.target {
+            op.context = Context::Activity(path.clone(), generics.clone());
+            op.ordered = invocation.time.order.is_some();
         }
-    }
+        if let Some(delay) = &mut invocation.time.delay {
+            delay.op.context = Context::Activity(path.clone(), generics.clone());
+        }
+    });
 
     activity.into_token_stream()
 }
@@ -25,10 +28,18 @@ pub fn process_activity(mut activity: Activity) -> TokenStream {
 #[derive(Debug)]
 pub struct Activity {
     path: Path,
+    /// Generic parameters declared with `for<...>`, and the where-clause after the path.
+    generics: Generics,
     _structure: ActivityStructure,
+    validate: Option<Block>,
+    arguments: Option<Arguments>,
     lines: Vec<StmtOrInvoke>,
 }
 
+/// The `name: value` pairs of an `arguments` block.
+#[derive(Debug)]
+struct Arguments(Vec<(Ident, Expr)>);
+
 #[derive(Debug)]
 pub enum ActivityStructure {
     Path,
@@ -38,25 +49,149 @@ pub enum ActivityStructure {
 #[derive(Debug)]
 enum StmtOrInvoke {
     Stmt(Stmt),
-    Invoke(Invocation),
+    Invoke(Box<Invocation>),
+    /// A `for` loop, which can place operations on each iteration.
+    For {
+        pat: Pat,
+        expr: Expr,
+        body: Vec<StmtOrInvoke>,
+    },
+    /// An `if`, which can place operations in any of its branches. `else if` is stored
+    /// as an `else` branch containing another `If`.
+    If {
+        cond: Expr,
+        then_branch: Vec<StmtOrInvoke>,
+        else_branch: Option<Vec<StmtOrInvoke>>,
+    },
+}
+
+/// Merges each run of consecutive inline operations that have the same placement and markers,
+/// and touch the same resources, keys, and elements in the same way, into a single operation.
+/// Anything else could change how the engine orders the run against other operations, or which
+/// resource an alias in the fused body refers to.
+///
+/// Each operation has a fixed cost for its node, hash, and history entry, regardless of how
+/// small its body is. Placements are compared by their tokens, so the placement of a fused run
+/// is only evaluated once.
+fn fuse_operations(lines: &mut Vec<StmtOrInvoke>) {
+    let mut fused: Vec<StmtOrInvoke> = Vec::with_capacity(lines.len());
+    for mut line in lines.drain(..) {
+        match &mut line {
+            StmtOrInvoke::For { body, .. } => fuse_operations(body),
+            StmtOrInvoke::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                fuse_operations(then_branch);
+                if let Some(else_branch) = else_branch {
+                    fuse_operations(else_branch);
+                }
+            }
+            StmtOrInvoke::Invoke(next) => {
+                if let Some(StmtOrInvoke::Invoke(previous)) = fused.last_mut()
+                    && previous.can_fuse(next)
+                {
+                    let StmtOrInvoke::Invoke(invocation) = line else {
+                        unreachable!()
+                    };
+                    let Target::Inline(op) = invocation.target else {
+                        unreachable!()
+                    };
+                    let Target::Inline(previous_op) = &mut previous.target else {
+                        unreachable!()
+                    };
+                    previous_op.fuse(op);
+                    continue;
+                }
+            }
+            StmtOrInvoke::Stmt(_) => {}
+        }
+        fused.push(line);
+    }
+    *lines = fused;
+}
+
+fn for_each_invocation(lines: &[StmtOrInvoke], f: &mut impl FnMut(&Invocation)) {
+    for line in lines {
+        match line {
+            StmtOrInvoke::Stmt(_) => {}
+            StmtOrInvoke::Invoke(invocation) => f(invocation),
+            StmtOrInvoke::For { body, .. } => for_each_invocation(body, f),
+            StmtOrInvoke::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                for_each_invocation(then_branch, f);
+                if let Some(else_branch) = else_branch {
+                    for_each_invocation(else_branch, f);
+                }
+            }
+        }
+    }
+}
+
+fn for_each_invocation_mut(lines: &mut [StmtOrInvoke], f: &mut impl FnMut(&mut Invocation)) {
+    for line in lines {
+        match line {
+            StmtOrInvoke::Stmt(_) => {}
+            StmtOrInvoke::Invoke(invocation) => f(invocation),
+            StmtOrInvoke::For { body, .. } => for_each_invocation_mut(body, f),
+            StmtOrInvoke::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                for_each_invocation_mut(then_branch, f);
+                if let Some(else_branch) = else_branch {
+                    for_each_invocation_mut(else_branch, f);
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Invocation {
     time: Placement,
     target: Target,
+    /// Whether the placement refers to `end`, so the operation can only be placed after the
+    /// duration of the activity is known.
+    at_end: bool,
+}
+
+impl Invocation {
+    fn can_fuse(&self, next: &Invocation) -> bool {
+        match (&self.target, &next.target) {
+            (Target::Inline(op), Target::Inline(next_op)) => {
+                self.time.delay.is_none()
+                    && next.time.delay.is_none()
+                    && self.time.start.to_token_stream().to_string()
+                        == next.time.start.to_token_stream().to_string()
+                    && self.time.order.to_token_stream().to_string()
+                        == next.time.order.to_token_stream().to_string()
+                    && op.same_interactions(next_op)
+            }
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Placement {
     start: Expr,
-    delay: Option<Op>,
+    /// The `, order = (expr)` part of a placement, which orders operations at the same time.
+    order: Option<Expr>,
+    delay: Option<Box<Delay>>,
 }
 
-impl StmtOrInvoke {
-    fn is_invoke(&self) -> bool {
-        matches!(self, StmtOrInvoke::Invoke(..))
-    }
+/// The `+ (delay) ..= (max)` part of a placement. The delay is computed by an operation that
+/// writes the internal `peregrine_delay` resource.
+#[derive(Debug)]
+struct Delay {
+    op: Op,
+    max: Option<Expr>,
 }
 
 #[derive(Debug)]
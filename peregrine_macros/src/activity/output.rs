@@ -1,40 +1,158 @@
-use crate::activity::{Activity, Invocation, Placement, StmtOrInvoke, Target};
+use crate::activity::{
+    Activity, Delay, Invocation, Placement, StmtOrInvoke, Target, for_each_invocation,
+};
 use proc_macro2::TokenStream;
 use quote::{ToTokens, TokenStreamExt, quote};
+use std::collections::HashSet;
+use syn::PathArguments;
 
 impl ToTokens for Activity {
     fn to_tokens(&self, tokens: &mut TokenStream) {
-        let Activity { path, lines, .. } = &self;
+        let Activity {
+            path,
+            generics,
+            validate,
+            arguments,
+            lines,
+            ..
+        } = &self;
 
         let mut op_functions = vec![];
-        for line in &self.lines {
-            if let StmtOrInvoke::Invoke(Invocation {
-                target: Target::Inline(op),
-                ..
-            }) = line
-            {
+        let mut evaluate_arms = vec![];
+        let mut element_aliases = vec![];
+        // Operations in loops can be placed more than once, and conditional ones not at all,
+        // so this is just a capacity hint.
+        let mut num_operations = 0usize;
+        let mut has_delays = false;
+        let mut has_end = false;
+        for_each_invocation(lines, &mut |invocation| {
+            num_operations += 1;
+            has_end |= invocation.at_end;
+            if let Target::Inline(op) = &invocation.target {
                 op_functions.push(op.body_function());
+                evaluate_arms.push(op.evaluate_arm());
+                element_aliases.extend(op.elements.iter().cloned());
             }
-        }
+            if let Some(delay) = &invocation.time.delay {
+                op_functions.push(delay.op.body_function());
+                evaluate_arms.push(delay.op.evaluate_arm());
+                element_aliases.extend(delay.op.elements.iter().cloned());
+                has_delays = true;
+            }
+        });
+
+        // The body functions share a scope, so each alias can only be declared once.
+        let mut seen = HashSet::new();
+        element_aliases.retain(|(alias, _)| seen.insert(alias.to_string()));
+        let (aliases, alias_types): (Vec<_>, Vec<_>) = element_aliases.into_iter().unzip();
+
+        // Operations placed relative to `end` are generated after the body has returned the
+        // duration, and then spliced back in so the operations stay in declaration order.
+        let (declare_deferred, place_deferred) = if has_end {
+            (
+                quote! {
+                    #[allow(clippy::type_complexity)]
+                    let mut deferred: Vec<(usize, Box<dyn FnOnce(peregrine::Grounding<'o, M>) -> peregrine::Result<Vec<&'o dyn peregrine::operation::Node<'o, M>>> + '_>)> = Vec::new();
+                },
+                quote! {
+                    let end = start + duration;
+                    let mut inserted = 0;
+                    for (slot, place) in deferred {
+                        let nodes = place(end)?;
+                        let count = nodes.len();
+                        operations.splice(slot + inserted..slot + inserted, nodes);
+                        inserted += count;
+                    }
+                },
+            )
+        } else {
+            (quote! {}, quote! {})
+        };
+
+        // Delay operations refer to the resource they write by its bare name.
+        let delay_import =
+            has_delays.then(|| quote! { use peregrine::operation::ungrounded::peregrine_delay; });
+
+        let validate = validate.as_ref().map(|block| {
+            let stmts = &block.stmts;
+            quote! {
+                fn validate(&self) -> peregrine::Result<()> {
+                    #(#stmts)*
+                    Ok(())
+                }
+            }
+        });
+
+        let arguments = arguments.as_ref().map(|arguments| {
+            let (names, values): (Vec<_>, Vec<_>) = arguments.0.iter().cloned().unzip();
+            let names = names.iter().map(|name| name.to_string());
+            quote! {
+                fn arguments(&self) -> Vec<(&'static str, String)> {
+                    vec![#((#names, (#values).to_string())),*]
+                }
+            }
+        });
+
+        let params = generics.params.iter().collect::<Vec<_>>();
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
 
-        let num_operations = lines.iter().filter(|l| l.is_invoke()).count();
+        // The label shouldn't depend on how the type parameters happen to be named.
+        let mut label_path = path.clone();
+        for segment in &mut label_path.segments {
+            segment.arguments = PathArguments::None;
+        }
 
         let result = quote! {
-            impl<'o, M: peregrine::Model<'o>> peregrine::activity::Activity<'o, M> for #path {
-                fn decompose(&'o self, start: peregrine::Grounding<'o, M>, bump: peregrine::reexports::bumpalo_herd::Member<'o>) -> peregrine::Result<(peregrine::Duration, Vec<&'o dyn peregrine::operation::Node<'o, M>>)> {
+            impl<'o, M: peregrine::Model<'o> #(, #params)*> peregrine::activity::Activity<'o, M> for #path #where_clause {
+                fn decompose(&'o self, start: peregrine::Grounding<'o, M>, bump: &peregrine::reexports::bumpalo_herd::Member<'o>) -> peregrine::Result<(peregrine::Duration, Vec<&'o dyn peregrine::operation::Node<'o, M>>)> {
+                    #delay_import
                     let mut operations: Vec<&'o dyn peregrine::operation::Node<'o, M>> = Vec::with_capacity(#num_operations);
+                    #declare_deferred
                     let duration = { #(#lines)* };
+                    #place_deferred
                     Ok((duration, operations))
                 }
-            }
 
-            impl peregrine::activity::ActivityLabel for #path {
-                const LABEL: &'static str = peregrine::reexports::peregrine_macros::code_to_str!(#path);
+                fn label(&self) -> &'static str {
+                    <Self as peregrine::activity::ActivityLabel>::LABEL
+                }
+
+                fn as_any(&self) -> &dyn std::any::Any {
+                    self
+                }
+
+                #validate
+                #arguments
             }
 
-            impl #path {
-                #(#op_functions)*
+            impl #impl_generics peregrine::activity::ActivityLabel for #path #where_clause {
+                const LABEL: &'static str = peregrine::reexports::peregrine_macros::code_to_str!(#label_path);
             }
+
+            const _: () = {
+                #delay_import
+                #(
+                    #[allow(non_camel_case_types)]
+                    type #aliases = #alias_types;
+                )*
+
+                impl #impl_generics #path #where_clause {
+                    #(#op_functions)*
+                }
+
+                impl #impl_generics peregrine::offload::Evaluate for #path #where_clause {
+                    #[allow(unreachable_code)]
+                    fn evaluate(&self, job: &peregrine::offload::Job, history: &peregrine::History) -> peregrine::Result<()> {
+                        match job.operation.as_str() {
+                            #(#evaluate_arms)*
+                            operation => peregrine::bail!(
+                                "activity {} has no operation {operation}",
+                                <Self as peregrine::activity::ActivityLabel>::LABEL
+                            ),
+                        }
+                    }
+                }
+            };
         };
 
         tokens.append_all(result);
@@ -50,6 +168,27 @@ impl ToTokens for StmtOrInvoke {
             StmtOrInvoke::Invoke(op) => {
                 op.to_tokens(tokens);
             }
+            StmtOrInvoke::For { pat, expr, body } => {
+                tokens.extend(quote! {
+                    for #pat in #expr {
+                        #(#body)*
+                    }
+                });
+            }
+            StmtOrInvoke::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let else_branch = else_branch.as_ref().map(|lines| {
+                    quote! { else { #(#lines)* } }
+                });
+                tokens.extend(quote! {
+                    if #cond {
+                        #(#then_branch)*
+                    } #else_branch
+                });
+            }
         }
     }
 }
@@ -58,13 +197,51 @@ impl ToTokens for Invocation {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         let placement = &self.time;
         let op = &self.target;
-        let result = match self.target {
-            Target::Inline(_) => quote! {
-                operations.push((#op)(#placement, self, bump));
+        let order = self
+            .time
+            .order
+            .as_ref()
+            .map(ToTokens::to_token_stream)
+            .unwrap_or_else(|| quote! { 0 });
+        let result = match (&self.target, &self.time.delay) {
+            (Target::Inline(_), None) => quote! {
+                operations.push((#op)(#placement, #order, self, bump));
             },
-            _ => quote! {
+            (_, None) => quote! {
                 operations.extend((#op)(#placement, self, bump)?);
             },
+            (Target::Inline(_), Some(_)) => quote! {
+                {
+                    let (grounding, delayed) = #placement;
+                    let op: &'o _ = (#op)(grounding, #order, self, bump);
+                    delayed.set_owner(op as *const _ as *const ());
+                    operations.push(op);
+                }
+            },
+            (_, Some(_)) => quote! {
+                {
+                    let (grounding, delayed) = #placement;
+                    let ops = (#op)(grounding, self, bump)?;
+                    if let Some(first) = ops.first() {
+                        delayed.set_owner(*first as *const dyn peregrine::operation::Node<'o, M> as *const ());
+                    }
+                    operations.extend(ops);
+                }
+            },
+        };
+        let result = if self.at_end {
+            quote! {
+                {
+                    let slot = operations.len();
+                    deferred.push((slot, Box::new(move |end: peregrine::Grounding<'o, M>| {
+                        let mut operations: Vec<&'o dyn peregrine::operation::Node<'o, M>> = Vec::with_capacity(1);
+                        #result
+                        Ok(operations)
+                    })));
+                }
+            }
+        } else {
+            result
         };
         tokens.extend(result);
     }
@@ -72,14 +249,33 @@ impl ToTokens for Invocation {
 
 impl ToTokens for Placement {
     fn to_tokens(&self, tokens: &mut TokenStream) {
-        if let Some(_delay) = &self.delay {
-            todo!()
-        }
-
         let start = &self.start;
 
-        let result = quote! {
-            #start
+        let result = if let Some(delay) = &self.delay {
+            let Delay { op, max } = &**delay;
+            let max = max
+                .as_ref()
+                .map(ToTokens::to_token_stream)
+                .unwrap_or_else(|| quote! { peregrine::Duration::MAX });
+            quote! {
+                {
+                    let start: peregrine::Grounding<'o, M> = #start;
+                    let delay: &'o dyn peregrine::operation::Upstream<'o, peregrine_delay, M> = (#op)(start, 0, self, bump);
+                    let node: &'o peregrine::operation::ungrounded::DelayedGrounding<'o, M> = bump.alloc(
+                        peregrine::operation::ungrounded::DelayedGrounding::new(
+                            start,
+                            #max,
+                            delay,
+                            <Self as peregrine::activity::ActivityLabel>::LABEL,
+                        )?
+                    );
+                    (node.grounding(), node)
+                }
+            }
+        } else {
+            quote! {
+                #start
+            }
         };
 
         tokens.extend(result);
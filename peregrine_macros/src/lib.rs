@@ -2,6 +2,7 @@ use syn::parse_macro_input;
 
 use crate::activity::{Activity, process_activity};
 use crate::model::Model;
+use crate::resource::ResourceDeclaration;
 use proc_macro::TokenStream;
 use quote::{ToTokens, quote};
 use rand::Rng;
@@ -9,6 +10,7 @@ use rand::Rng;
 mod activity;
 mod model;
 mod operation;
+mod resource;
 
 #[proc_macro]
 pub fn model(input: TokenStream) -> TokenStream {
@@ -16,6 +18,12 @@ pub fn model(input: TokenStream) -> TokenStream {
     model.into_token_stream().into()
 }
 
+#[proc_macro]
+pub fn resource(input: TokenStream) -> TokenStream {
+    let resource = parse_macro_input!(input as ResourceDeclaration);
+    resource.into_token_stream().into()
+}
+
 #[proc_macro]
 pub fn impl_activity(input: TokenStream) -> TokenStream {
     let activity: Activity = syn::parse(input).unwrap();
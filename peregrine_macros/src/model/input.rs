@@ -1,8 +1,10 @@
 use crate::model::Model;
 use proc_macro2::Ident;
-use syn::parse::{Parse, ParseStream};
+use quote::ToTokens;
+use std::collections::HashMap;
+use syn::parse::{Parse, ParseStream, discouraged::Speculative};
 use syn::punctuated::Punctuated;
-use syn::{Path, Token, Visibility, parenthesized};
+use syn::{Error, Path, PathSegment, Token, Visibility, bracketed, parenthesized, token};
 
 impl Parse for Model {
     fn parse(input: ParseStream) -> syn::Result<Self> {
@@ -12,13 +14,105 @@ impl Parse for Model {
         let body;
         parenthesized!(body in input);
 
-        let resources = Punctuated::<Path, Token![,]>::parse_terminated(&body)?.into_iter();
+        let mut resources = vec![];
+        let mut arrays = vec![];
+        let mut structs = vec![];
+        let mut sub_models = vec![];
+        for entry in Punctuated::<Entry, Token![,]>::parse_terminated(&body)? {
+            match entry {
+                Entry::Resource(path) => resources.push(path),
+                Entry::Array(path) => arrays.push(path),
+                Entry::Struct(path) => structs.push(path),
+                Entry::SubModel(path) => sub_models.push(path),
+            }
+        }
+
+        check_duplicates(&resources, "resource")?;
+        check_duplicates(&arrays, "array resource")?;
+        check_duplicates(&structs, "struct resource")?;
+        check_duplicates(&sub_models, "submodel")?;
 
         Ok(Model {
             visibility,
             name,
-            resources: resources.collect(),
-            _sub_models: vec![],
+            resources,
+            arrays,
+            structs,
+            sub_models,
         })
     }
 }
+
+/// Errors on every entry that was already listed earlier, pointing at both.
+fn check_duplicates(paths: &[Path], kind: &str) -> syn::Result<()> {
+    let mut seen: HashMap<String, &Path> = HashMap::new();
+    let mut error: Option<Error> = None;
+    for path in paths {
+        let key = path.to_token_stream().to_string();
+        if let Some(first) = seen.get(&key) {
+            let mut duplicate =
+                Error::new_spanned(path, format!("{kind} `{key}` is listed more than once"));
+            duplicate.combine(Error::new_spanned(
+                first,
+                format!("`{key}` is first listed here"),
+            ));
+            match &mut error {
+                Some(error) => error.combine(duplicate),
+                None => error = Some(duplicate),
+            }
+        } else {
+            seen.insert(key, path);
+        }
+    }
+    error.map_or(Ok(()), Err)
+}
+
+enum Entry {
+    Resource(Path),
+    Array(Path),
+    Struct(Path),
+    SubModel(Path),
+}
+
+impl Parse for Entry {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.parse::<Option<Token![use]>>()?.is_some() {
+            Ok(Entry::SubModel(input.parse()?))
+        } else if let Some(path) = parse_glob(input)? {
+            Ok(Entry::Struct(path))
+        } else {
+            let path = input.parse()?;
+            if input.peek(token::Bracket) {
+                let content;
+                bracketed!(content in input);
+                content.parse::<Token![..]>()?;
+                Ok(Entry::Array(path))
+            } else {
+                Ok(Entry::Resource(path))
+            }
+        }
+    }
+}
+
+/// Parses a path ending in `::*`, or nothing if the input doesn't start with one.
+fn parse_glob(input: ParseStream) -> syn::Result<Option<Path>> {
+    let fork = input.fork();
+    let mut segments = Punctuated::<PathSegment, Token![::]>::new();
+    while fork.peek(syn::Ident) {
+        segments.push_value(fork.parse::<Ident>()?.into());
+        if !fork.peek(Token![::]) {
+            break;
+        }
+        segments.push_punct(fork.parse()?);
+        if fork.peek(Token![*]) {
+            fork.parse::<Token![*]>()?;
+            segments.pop_punct();
+            input.advance_to(&fork);
+            return Ok(Some(Path {
+                leading_colon: None,
+                segments,
+            }));
+        }
+    }
+    Ok(None)
+}
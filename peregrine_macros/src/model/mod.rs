@@ -8,5 +8,9 @@ pub struct Model {
     visibility: Visibility,
     name: Ident,
     resources: Vec<Path>,
-    _sub_models: Vec<Path>,
+    /// Array resources, listed as `name[..]`.
+    arrays: Vec<Path>,
+    /// Struct resources, listed as `name::*`.
+    structs: Vec<Path>,
+    sub_models: Vec<Path>,
 }
@@ -8,7 +8,9 @@ impl ToTokens for Model {
             visibility,
             name,
             resources,
-            ..
+            arrays,
+            structs,
+            sub_models,
         } = self;
 
         let resource_idents = resources
@@ -30,6 +32,35 @@ impl ToTokens for Model {
             .map(|i| format_ident!("{}_operation_timeline", i))
             .collect::<Vec<_>>();
 
+        let accessor_names = resources
+            .iter()
+            .map(|r| r.segments.last().unwrap().ident.clone())
+            .collect::<Vec<_>>();
+        let array_accessor_names = arrays
+            .iter()
+            .map(|r| r.segments.last().unwrap().ident.clone())
+            .collect::<Vec<_>>();
+        let array_sample_names = array_accessor_names
+            .iter()
+            .map(|i| format_ident!("sample_{i}"))
+            .collect::<Vec<_>>();
+        let sample_names = accessor_names
+            .iter()
+            .map(|i| format_ident!("sample_{i}"))
+            .collect::<Vec<_>>();
+
+        let label = name.to_string();
+        let ext_trait_name = format_ident!("{name}PlanExt");
+        let sub_model_ext_traits = sub_models
+            .iter()
+            .map(|path| {
+                let mut path = path.clone();
+                let last = path.segments.last_mut().unwrap();
+                last.ident = format_ident!("{}PlanExt", last.ident);
+                path
+            })
+            .collect::<Vec<_>>();
+
         let timelines_struct_name = format_ident!("{name}Timelines");
         let initial_conditions_struct_name = format_ident!("{name}InitialConditions");
 
@@ -39,29 +70,96 @@ impl ToTokens for Model {
             impl<'o> peregrine::Model<'o> for #name {
                 fn init_history(history: &peregrine::history::History) {
                     #(history.init::<#resources>();)*
+                    #(<#arrays<0> as peregrine::resource::ResourceGroup>::init_history(history);)*
+                    #(<#structs::Fields as peregrine::resource::ResourceGroup>::init_history(history);)*
+                    #(<#sub_models as peregrine::Model<'o>>::init_history(history);)*
                 }
-                fn init_timelines(time: peregrine::Duration, mut initial_conditions: peregrine::operation::initial_conditions::InitialConditions, herd: &'o peregrine::reexports::bumpalo_herd::Herd) -> peregrine::timeline::Timelines<'o, Self> {
-                    let mut timelines = peregrine::timeline::Timelines::new(herd);
-                    #(timelines.init_for_resource::<#resources>(time, peregrine::operation::initial_conditions::InitialConditionOp::new(time, initial_conditions.take::<#resources>().expect(&format!("expected to find initial condition for resource {}, but found none", <#resources as peregrine::resource::Resource<'o>>::LABEL))));)*
-                    timelines
+                fn init_timelines(time: peregrine::timeline::Instant, mut initial_conditions: peregrine::operation::initial_conditions::InitialConditions, arena: &'o peregrine::arena::Arena) -> Result<peregrine::timeline::Timelines<'o, Self>, peregrine::EngineError> {
+                    let mut timelines = peregrine::timeline::Timelines::new(arena);
+                    Self::init_timelines_into(time, &mut initial_conditions, &mut timelines)?;
+                    Ok(timelines)
                 }
+                fn init_timelines_into<M: peregrine::Model<'o>>(time: peregrine::timeline::Instant, initial_conditions: &mut peregrine::operation::initial_conditions::InitialConditions, timelines: &mut peregrine::timeline::Timelines<'o, M>) -> Result<(), peregrine::EngineError> {
+                    #(
+                        timelines.init_resource::<#resources>(time, initial_conditions)?;
+                    )*
+                    #(<#arrays<0> as peregrine::resource::ResourceGroup>::init_timelines_into(time, initial_conditions, timelines)?;)*
+                    #(<#structs::Fields as peregrine::resource::ResourceGroup>::init_timelines_into(time, initial_conditions, timelines)?;)*
+                    #(<#sub_models as peregrine::Model<'o>>::init_timelines_into(time, initial_conditions, timelines)?;)*
+                    Ok(())
+                }
+                fn check_initial_conditions(initial_conditions: &peregrine::operation::initial_conditions::InitialConditions, issues: &mut Vec<peregrine::InitialConditionIssue>) {
+                    #(initial_conditions.check::<#resources>(issues);)*
+                    #(<#arrays<0> as peregrine::resource::ResourceGroup>::check_initial_conditions(initial_conditions, issues);)*
+                    #(<#structs::Fields as peregrine::resource::ResourceGroup>::check_initial_conditions(initial_conditions, issues);)*
+                    #(<#sub_models as peregrine::Model<'o>>::check_initial_conditions(initial_conditions, issues);)*
+                }
+                fn sample_conditions<M: peregrine::Model<'o> + 'o>(plan: &peregrine::Plan<'o, M>, time: peregrine::Time, conditions: &mut peregrine::operation::initial_conditions::InitialConditions) -> Result<(), peregrine::EngineError> {
+                    #(plan.sample_condition::<#resources>(time, conditions)?;)*
+                    #(<#arrays<0> as peregrine::resource::ResourceGroup>::sample_conditions(plan, time, conditions)?;)*
+                    #(<#structs::Fields as peregrine::resource::ResourceGroup>::sample_conditions(plan, time, conditions)?;)*
+                    #(<#sub_models as peregrine::Model<'o>>::sample_conditions(plan, time, conditions)?;)*
+                    Ok(())
+                }
+                const LABEL: &'static str = #label;
+                fn describe(resources: &mut Vec<peregrine::resource::ResourceSchema>) {
+                    #(peregrine::resource::ResourceSchema::add::<#resources>(resources);)*
+                    #(<#arrays<0> as peregrine::resource::ResourceGroup>::describe(resources);)*
+                    #(<#structs::Fields as peregrine::resource::ResourceGroup>::describe(resources);)*
+                    #(<#sub_models as peregrine::Model<'o>>::describe(resources);)*
+                }
+                fn view_labeled<M: peregrine::Model<'o> + 'o>(plan: &peregrine::Plan<'o, M>, views: &mut peregrine::labeled::LabeledViews<'o>) -> Result<(), peregrine::EngineError> {
+                    #(views.view::<#resources, M>(plan)?;)*
+                    #(<#arrays<0> as peregrine::resource::ResourceGroup>::view_labeled(plan, views)?;)*
+                    #(<#structs::Fields as peregrine::resource::ResourceGroup>::view_labeled(plan, views)?;)*
+                    #(<#sub_models as peregrine::Model<'o>>::view_labeled(plan, views)?;)*
+                    Ok(())
+                }
+            }
+
+            /// Shorthands for viewing and sampling each resource of the model.
+            #visibility trait #ext_trait_name<'o>: peregrine::PlanAccess<'o> {
+                #(
+                    fn #accessor_names(&self, bounds: impl std::ops::RangeBounds<peregrine::Time>) -> Result<Vec<(peregrine::Time, <#resources as peregrine::resource::Resource<'o>>::Read)>, peregrine::EngineError> {
+                        self.view_resource::<#resources>(bounds)
+                    }
+                    fn #sample_names(&self, time: peregrine::Time) -> Result<<#resources as peregrine::resource::Resource<'o>>::Read, peregrine::EngineError> {
+                        self.sample_resource::<#resources>(time)
+                    }
+                )*
+                #(
+                    fn #array_accessor_names<const I: usize>(&self, bounds: impl std::ops::RangeBounds<peregrine::Time>) -> Result<Vec<(peregrine::Time, <#arrays<I> as peregrine::resource::Resource<'o>>::Read)>, peregrine::EngineError> {
+                        self.view_resource::<#arrays<I>>(bounds)
+                    }
+                    fn #array_sample_names<const I: usize>(&self, time: peregrine::Time) -> Result<<#arrays<I> as peregrine::resource::Resource<'o>>::Read, peregrine::EngineError> {
+                        self.sample_resource::<#arrays<I>>(time)
+                    }
+                )*
             }
 
+            impl<'o> #ext_trait_name<'o> for peregrine::Plan<'o, #name> {}
+            #(impl<'o> #sub_model_ext_traits<'o> for peregrine::Plan<'o, #name> {})*
+            impl<'o> #ext_trait_name<'o> for peregrine::SharedPlan<'o, #name> {}
+            #(impl<'o> #sub_model_ext_traits<'o> for peregrine::SharedPlan<'o, #name> {})*
+
             #visibility struct #initial_conditions_struct_name<'h> {
                 #(#resource_idents: <#resources as peregrine::resource::Resource<'h>>::Write,)*
+                _marker: std::marker::PhantomData<&'h ()>,
             }
 
             #visibility struct #timelines_struct_name<'o> {
                 #(#timeline_names: peregrine::timeline::Timeline<'o, #resources, #name>,)*
+                _marker: std::marker::PhantomData<&'o ()>,
             }
 
-            impl<'o> From<(peregrine::Duration, &peregrine::reexports::bumpalo_herd::Member<'o>, #initial_conditions_struct_name<'o>)> for #timelines_struct_name<'o> {
-                fn from((time, bump, inish_condish): (peregrine::Duration, &peregrine::reexports::bumpalo_herd::Member<'o>, #initial_conditions_struct_name)) -> Self {
+            impl<'o> From<(peregrine::timeline::Instant, &peregrine::reexports::bumpalo_herd::Member<'o>, #initial_conditions_struct_name<'o>)> for #timelines_struct_name<'o> {
+                fn from((time, bump, inish_condish): (peregrine::timeline::Instant, &peregrine::reexports::bumpalo_herd::Member<'o>, #initial_conditions_struct_name)) -> Self {
                     Self {
                         #(#timeline_names: peregrine::timeline::Timeline::<#resources, #name>::init(
                             time,
                             bump.alloc(peregrine::operation::initial_conditions::InitialConditionOp::<'o, #resources, #name>::new(time, inish_condish.#resource_idents))
                         ),)*
+                        _marker: std::marker::PhantomData,
                     }
                 }
             }
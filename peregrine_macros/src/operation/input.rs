@@ -1,8 +1,8 @@
 use crate::operation::input::InteractionType::*;
 use crate::operation::{Context, Op};
 use derive_more::{Deref, DerefMut};
-use proc_macro2::Ident;
-use quote::format_ident;
+use proc_macro2::{Ident, Span, TokenStream, TokenTree};
+use quote::{format_ident, quote, quote_spanned};
 use regex::Regex;
 use std::collections::HashMap;
 use syn::buffer::Cursor;
@@ -52,8 +52,111 @@ impl Parse for Op {
             Regex::new(r"ref mut[[:space:]]*:[[:space:]]*(?<ident>[a-zA-Z0-9_]+)").unwrap();
         let tag_only_regex = Regex::new(r"(ref|mut|ref mut)[[:space:]]*:").unwrap();
 
+        // Elements of array resources are separate resources, so `heaters[3]` becomes the
+        // variable `heaters_3`, with a type alias for `heaters<3>` emitted next to the op.
+        let element_regex = Regex::new(
+            r"(?<tag>ref mut|ref|mut)[[:space:]]*:[[:space:]]*(?<ident>[a-zA-Z0-9_]+)[[:space:]]*\[[[:space:]]*(?<index>[0-9]+)(usize)?[[:space:]]*\]",
+        )
+        .unwrap();
+        let field_regex = Regex::new(
+            r"(?<tag>ref mut|ref|mut)[[:space:]]*:[[:space:]]*(?<ident>[a-zA-Z0-9_]+)[[:space:]]*\.[[:space:]]*(?<field>[a-zA-Z_][a-zA-Z0-9_]*)",
+        )
+        .unwrap();
+        let keyed_regex = Regex::new(
+            r"(?<tag>ref mut|ref|mut)[[:space:]]*:[[:space:]]*(?<ident>[a-zA-Z0-9_]+)[[:space:]]*\[",
+        )
+        .unwrap();
+
+        let tag_spans = read_write_tags(asdf.fork().parse()?);
         let input = asdf.to_string();
 
+        let mut elements = vec![];
+        for cap in element_regex.captures_iter(&input) {
+            let alias = format_ident!("{}_{}", &cap["ident"], &cap["index"]);
+            if !elements.iter().any(|(a, _)| *a == alias) {
+                let array = format_ident!("{}", &cap["ident"]);
+                let index: usize = cap["index"].parse().unwrap();
+                elements.push((alias, quote! { #array<#index> }));
+            }
+        }
+        let input = element_regex
+            .replace_all(&input, "$tag: ${ident}_$index")
+            .into_owned();
+
+        // Fields of struct resources are separate resources too, and `spacecraft.power` becomes
+        // `spacecraft_power`. A name followed by parentheses is a method call on a whole resource.
+        let mut rewritten = String::with_capacity(input.len());
+        let mut rest = input.as_str();
+        while let Some(cap) = field_regex.captures(rest) {
+            let whole = cap.get(0).unwrap();
+            let is_call = rest[whole.end()..].trim_start().starts_with(['(', ':']);
+            rewritten.push_str(&rest[..whole.start()]);
+            if is_call {
+                rewritten.push_str(whole.as_str());
+            } else {
+                let alias = format_ident!("{}_{}", &cap["ident"], &cap["field"]);
+                if !elements.iter().any(|(a, _)| *a == alias) {
+                    let resource = format_ident!("{}", &cap["ident"]);
+                    let field = format_ident!("{}", &cap["field"]);
+                    elements.push((alias.clone(), quote! { #resource::#field }));
+                }
+                rewritten.push_str(&format!("{}: {alias}", &cap["tag"]));
+            }
+            rest = &rest[whole.end()..];
+        }
+        rewritten.push_str(rest);
+        let input = rewritten;
+
+        // Any other index is a key of a keyed resource, which is evaluated when the activity is
+        // decomposed. `inst_state[self.instrument]` becomes the variable `inst_state_key`.
+        let mut keys: Vec<(Ident, Ident, String)> = vec![];
+        let mut rewritten = String::with_capacity(input.len());
+        let mut rest = input.as_str();
+        while let Some(cap) = keyed_regex.captures(rest) {
+            let whole = cap.get(0).unwrap();
+            let key_start = whole.end();
+            let mut depth = 1;
+            let key_end = rest[key_start..]
+                .char_indices()
+                .find_map(|(i, c)| {
+                    match c {
+                        '[' | '(' | '{' => depth += 1,
+                        ']' | ')' | '}' => depth -= 1,
+                        _ => {}
+                    }
+                    (depth == 0).then_some(key_start + i)
+                })
+                .ok_or_else(|| syn::Error::new(asdf.span(), "unclosed key of keyed resource"))?;
+            let key = rest[key_start..key_end].trim().to_string();
+            let resource = format_ident!("{}", &cap["ident"]);
+            let alias = format_ident!("{}_key", &cap["ident"]);
+            match keys.iter().find(|(a, ..)| *a == alias) {
+                Some((.., existing)) if *existing != key => {
+                    return Err(syn::Error::new(
+                        asdf.span(),
+                        format!(
+                            "an operation can only use one key of `{resource}`, but found `{existing}` and `{key}`"
+                        ),
+                    ));
+                }
+                Some(_) => {}
+                None => keys.push((alias.clone(), resource, key)),
+            }
+            rewritten.push_str(&rest[..whole.start()]);
+            rewritten.push_str(&format!("{}: {alias}", &cap["tag"]));
+            rest = &rest[key_end + 1..];
+        }
+        rewritten.push_str(rest);
+        let input = rewritten;
+
+        let keys = keys
+            .into_iter()
+            .map(|(alias, resource, key)| {
+                elements.push((alias.clone(), quote! { #resource }));
+                Ok((alias, resource, key.parse::<TokenStream>()?))
+            })
+            .collect::<syn::Result<Vec<_>>>()?;
+
         for cap in read_regex.captures_iter(&input) {
             interactions.insert(format_ident!("{}", cap["ident"]), Read);
         }
@@ -76,7 +179,21 @@ impl Parse for Op {
             }
         }
 
-        let body = tag_only_regex.replace_all(&input, "").parse()?;
+        let lints = read_writes
+            .iter()
+            .filter(|ident| !written(&input, &ident.to_string()))
+            .filter_map(|ident| {
+                let (_, span) = tag_spans.iter().find(|(name, _)| name == ident)?;
+                Some(lint(
+                    *span,
+                    "ref_mut_never_written",
+                    &format!("`ref mut: {ident}` is never written, so it can be `ref: {ident}`"),
+                ))
+            })
+            .collect();
+
+        let body: TokenStream = tag_only_regex.replace_all(&input, "").parse()?;
+        let uses_now = refers_to(body.clone(), "now");
 
         asdf.step(|_| Ok(((), Cursor::empty())))?;
 
@@ -85,8 +202,98 @@ impl Parse for Op {
             reads,
             writes,
             read_writes,
+            ordered: false,
+            commutative: false,
+            priority: false,
+            elements,
+            keys,
             body,
+            uses_now,
+            lints,
             uuid: uuid::Uuid::new_v4().to_string().replace("-", "_"),
         })
     }
 }
+
+/// The resources tagged `ref mut:` in the tokens, with the span of their first tag.
+fn read_write_tags(tokens: TokenStream) -> Vec<(Ident, Span)> {
+    let mut tags: Vec<(Ident, Span)> = vec![];
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, token) in tokens.iter().enumerate() {
+        if let TokenTree::Group(group) = token {
+            for (ident, span) in read_write_tags(group.stream()) {
+                if !tags.iter().any(|(i, _)| *i == ident) {
+                    tags.push((ident, span));
+                }
+            }
+        }
+        if let [
+            TokenTree::Ident(r),
+            TokenTree::Ident(m),
+            TokenTree::Punct(colon),
+            TokenTree::Ident(ident),
+            ..,
+        ] = &tokens[i..]
+            && r == "ref"
+            && m == "mut"
+            && colon.as_char() == ':'
+            && !tags.iter().any(|(i, _)| i == ident)
+        {
+            tags.push((
+                ident.clone(),
+                r.span().join(ident.span()).unwrap_or(ident.span()),
+            ));
+        }
+    }
+    tags
+}
+
+/// Whether the body writes a resource that it tags `ref mut:`, by assigning to it, calling a
+/// method on it, borrowing it mutably, or with a separate `mut:` tag.
+fn written(input: &str, ident: &str) -> bool {
+    let tag = Regex::new(&format!(
+        r"(?<tag>ref mut|ref|mut)[[:space:]]*:[[:space:]]*{ident}\b"
+    ))
+    .unwrap();
+    let assignment = Regex::new(r"^[[:space:]]*(\.|(\+|-|\*|/|%|\||&|\^|<<|>>)?=[^=])").unwrap();
+    tag.captures_iter(input).any(|cap| {
+        let whole = cap.get(0).unwrap();
+        match &cap["tag"] {
+            "mut" => true,
+            "ref mut" => {
+                assignment.is_match(&input[whole.end()..])
+                    || input[..whole.start()].trim_end().ends_with("mut")
+            }
+            _ => false,
+        }
+    })
+}
+
+/// A warning at `span`, emitted as a use of a deprecated constant so that it shows up on
+/// stable Rust. It can be silenced with `#[allow(deprecated)]`.
+fn lint(span: Span, name: &str, message: &str) -> TokenStream {
+    let name = Ident::new(name, span);
+    quote_spanned! {span=>
+        {
+            #[deprecated(note = #message)]
+            #[allow(non_upper_case_globals)]
+            const #name: () = ();
+            let _ = #name;
+        }
+    }
+}
+
+/// Whether `name` appears in the tokens as a variable, and not as a field, method, or path segment.
+pub fn refers_to(tokens: TokenStream, name: &str) -> bool {
+    let mut after_accessor = false;
+    for token in tokens {
+        match &token {
+            TokenTree::Ident(ident) if ident == name && !after_accessor => return true,
+            TokenTree::Group(group) if refers_to(group.stream(), name) => return true,
+            _ => {}
+        }
+        after_accessor =
+            matches!(&token, TokenTree::Punct(p) if p.as_char() == '.' || p.as_char() == ':');
+    }
+    false
+}
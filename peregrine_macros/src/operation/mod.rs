@@ -1,8 +1,11 @@
 mod input;
 mod output;
 
+pub use input::refers_to;
+
 use proc_macro2::{Ident, TokenStream};
-use syn::Path;
+use quote::quote;
+use syn::{Generics, Path};
 
 #[derive(Debug)]
 pub struct Op {
@@ -10,13 +13,109 @@ pub struct Op {
     pub reads: Vec<Ident>,
     pub writes: Vec<Ident>,
     pub read_writes: Vec<Ident>,
+    /// Whether the placement gives the op an explicit order, which then has to be hashed.
+    pub ordered: bool,
+    /// Whether the op was marked `commutative`, so its effect on the resource it read-writes
+    /// doesn't depend on its order among other commutative ops.
+    pub commutative: bool,
+    /// Whether the op was marked `priority`, so the engine runs it before other downstreams of
+    /// the same upstreams.
+    pub priority: bool,
+    /// Aliases for the elements of array resources that the op touches, and the types they
+    /// stand for.
+    pub elements: Vec<(Ident, TokenStream)>,
+    /// The keyed resources that the op touches: the alias of each resource, the resource, and
+    /// the expression for its key.
+    pub keys: Vec<(Ident, Ident, TokenStream)>,
     body: TokenStream,
+    /// Whether the body refers to `now`, the time of the operation.
+    uses_now: bool,
+    /// Warnings about how the op tags its resources, emitted into the body.
+    lints: Vec<TokenStream>,
     uuid: String,
 }
 
 #[derive(Debug)]
 pub enum Context {
-    Activity(Path),
+    Activity(Path, Box<Generics>),
     _Arguments(Vec<Ident>),
     None,
 }
+
+impl Op {
+    /// Turns an op whose body is an expression into one that writes the value of that
+    /// expression to `resource`.
+    pub fn assign_to(&mut self, resource: Ident) {
+        let body = &self.body;
+        self.body = quote! { #resource = { #body }; };
+        self.writes.push(resource);
+    }
+
+    /// Whether `other` reads and writes exactly the same resources (and keys, and elements) as
+    /// this op, in the same way, with the same markers.
+    pub fn same_interactions(&self, other: &Op) -> bool {
+        fn sorted(idents: &[Ident]) -> Vec<String> {
+            let mut names: Vec<_> = idents.iter().map(ToString::to_string).collect();
+            names.sort();
+            names
+        }
+        fn keys(op: &Op) -> Vec<String> {
+            let mut keys: Vec<_> = op
+                .keys
+                .iter()
+                .map(|(alias, resource, key)| format!("{alias} = {resource}[{key}]"))
+                .collect();
+            keys.sort();
+            keys
+        }
+        // The aliases in the reads and writes stand for these elements, which have to be the
+        // same too.
+        fn elements(op: &Op) -> Vec<String> {
+            let mut elements: Vec<_> = op
+                .elements
+                .iter()
+                .map(|(alias, ty)| format!("{alias} = {ty}"))
+                .collect();
+            elements.sort();
+            elements
+        }
+        self.commutative == other.commutative
+            && self.priority == other.priority
+            && keys(self) == keys(other)
+            && elements(self) == elements(other)
+            && sorted(&self.reads) == sorted(&other.reads)
+            && sorted(&self.writes) == sorted(&other.writes)
+            && sorted(&self.read_writes) == sorted(&other.read_writes)
+    }
+
+    /// Appends the body of `other` to this op, so that both run as a single node.
+    pub fn fuse(&mut self, other: Op) {
+        let body = &self.body;
+        let other_body = &other.body;
+        self.body = quote! { { #body }; { #other_body }; };
+        self.uses_now |= other.uses_now;
+        self.lints.extend(other.lints);
+    }
+
+    /// Declares the aliases for the array elements the op touches.
+    pub fn element_aliases(&self) -> TokenStream {
+        let (aliases, types): (Vec<_>, Vec<_>) = self.elements.iter().cloned().unzip();
+        quote! {
+            #(
+                #[allow(non_camel_case_types)]
+                type #aliases = #types;
+            )*
+        }
+    }
+
+    /// Checks that a `commutative` op has the one resource it commutes on.
+    pub fn check_commutative(&self, keyword: &Ident) -> syn::Result<()> {
+        if self.commutative && (self.read_writes.len() != 1 || !self.writes.is_empty()) {
+            return Err(syn::Error::new_spanned(
+                keyword,
+                "commutative operations must read and write exactly one resource, and write nothing else",
+            ));
+        }
+        Ok(())
+    }
+}
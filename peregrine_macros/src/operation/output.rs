@@ -1,6 +1,7 @@
 use crate::operation::{Context, Op};
 use proc_macro2::{Ident, TokenStream};
 use quote::{ToTokens, format_ident, quote};
+use syn::{GenericParam, Generics, Path, PathArguments};
 
 impl Op {
     pub fn body_function(&self) -> TokenStream {
@@ -14,34 +15,88 @@ impl Op {
         } = self.make_idents();
 
         let body = &self.body;
+        let lints = &self.lints;
+        let now = self.uses_now.then(|| quote! { now: peregrine::Time, });
+        let previous = read_writes
+            .iter()
+            .map(|i| format_ident!("_peregrine_previous_{i}"))
+            .collect::<Vec<_>>();
 
         quote! {
-            fn #op_body_function<'h>(&self, #(#all_reads: <#all_reads as peregrine::resource::Resource<'h>>::Read,)*) -> peregrine::Result<(#(<#all_writes as peregrine::resource::Resource<'h>>::Write,)*)> {
+            fn #op_body_function<'h>(&self, #now #(#all_reads: <#all_reads as peregrine::resource::Resource<'h>>::Read,)*) -> peregrine::Result<(#(<#all_writes as peregrine::resource::Resource<'h>>::Write,)*)> {
                 #(let mut #write_onlys: <#write_onlys as peregrine::resource::Resource<'h>>::Write;)*
+                #(let #previous = #read_writes;)*
                 #(let mut #read_writes: <#read_writes as peregrine::resource::Resource<'h>>::Write = #read_writes.into();)*
+                #(#lints)*
                 #body
+                #(<#read_writes as peregrine::resource::Resource<'h>>::check_transition(#previous, &#read_writes)?;)*
                 Ok((#(#all_writes,)*))
             }
         }
     }
 
+    /// A match arm of the activity's [Evaluate][peregrine::offload::Evaluate] implementation,
+    /// which evaluates this operation for a worker.
+    pub fn evaluate_arm(&self) -> TokenStream {
+        let Idents {
+            all_reads,
+            all_writes,
+            op_body_function,
+            uses_now,
+            ..
+        } = self.make_idents();
+        let now_arg = uses_now.then(|| quote! { job.time, });
+
+        quote! {
+            stringify!(#op_body_function) => {
+                let mut inputs = job.inputs.iter();
+                #(
+                    history.init::<#all_reads>();
+                    let #all_reads = inputs.next()
+                        .and_then(|input| history.get::<#all_reads>(*input))
+                        .ok_or_else(|| peregrine::anyhow!("input {} is missing from history", <#all_reads as peregrine::resource::Resource>::LABEL))?;
+                )*
+                let (#(#all_writes,)*) = self.#op_body_function(#now_arg #(#all_reads,)*)?;
+                #(
+                    <#all_writes as peregrine::resource::Resource>::check_write(&#all_writes)?;
+                    history.init::<#all_writes>();
+                    history.insert::<#all_writes>(job.hash, #all_writes);
+                )*
+                Ok(())
+            }
+        }
+    }
+
     fn make_idents(&self) -> Idents {
         let Op {
             context,
             reads,
             writes,
             read_writes,
+            uses_now,
+            ordered,
+            commutative,
+            priority,
+            keys,
             uuid,
             ..
         } = self;
 
-        let activity = if let Context::Activity(p) = context {
-            p.clone()
+        let (activity, generics) = if let Context::Activity(p, g) = context {
+            (p.clone(), (**g).clone())
         } else {
             todo!()
         };
 
-        let activity_ident = activity.get_ident().unwrap();
+        let activity_ident = activity.segments.last().unwrap().ident.clone();
+
+        // The activity path is used in both type and expression positions, so it needs a turbofish.
+        let mut activity = activity;
+        if let PathArguments::AngleBracketed(args) =
+            &mut activity.segments.last_mut().unwrap().arguments
+        {
+            args.colon2_token = Some(Default::default());
+        }
 
         let output = format_ident!("{activity_ident}OpOutput_{uuid}");
         let op = format_ident!("{activity_ident}Op_{uuid}");
@@ -49,17 +104,40 @@ impl Op {
         let op_body_function = format_ident!("{activity_ident}_op_body_{uuid}");
         let continuations = format_ident!("{activity_ident}Continuations_{uuid}");
 
+        let all_reads: Vec<Ident> = reads.iter().chain(read_writes.iter()).cloned().collect();
+        let all_writes: Vec<Ident> = writes.iter().chain(read_writes.iter()).cloned().collect();
+
+        // The timeline of a keyed resource is picked by the key the op was created with.
+        let timeline_id =
+            |resource: &Ident| match keys.iter().position(|(alias, ..)| alias == resource) {
+                Some(index) => quote! { self.keys[#index] },
+                None => quote! { <#resource as peregrine::resource::Resource<'o>>::ID },
+            };
+        let read_ids = all_reads.iter().map(timeline_id).collect();
+        let write_ids = all_writes.iter().map(timeline_id).collect();
+
         Idents {
             op_internals,
             op,
             output,
             continuations,
             op_body_function,
-            activity: activity_ident.clone(),
+            activity,
+            generics,
+            uses_now: *uses_now,
+            ordered: *ordered,
+            commutative: *commutative,
+            priority: *priority,
             write_onlys: writes.clone(),
             read_writes: read_writes.clone(),
-            all_reads: reads.iter().chain(read_writes.iter()).cloned().collect(),
-            all_writes: writes.iter().chain(read_writes.iter()).cloned().collect(),
+            keys: keys
+                .iter()
+                .map(|(alias, _, key)| (alias.clone(), key.clone()))
+                .collect(),
+            read_ids,
+            write_ids,
+            all_reads,
+            all_writes,
         }
     }
 }
@@ -69,9 +147,11 @@ impl ToTokens for Op {
         let idents = self.make_idents();
         let definition = generate_operation(&idents);
         let instantiation = result(&idents);
+        let aliases = self.element_aliases();
 
         let result = quote! {
             {
+                #aliases
                 #definition
                 #instantiation
             }
@@ -87,9 +167,19 @@ struct Idents {
     output: Ident,
     op_body_function: Ident,
     continuations: Ident,
-    activity: Ident,
+    activity: Path,
+    generics: Generics,
+    uses_now: bool,
+    ordered: bool,
+    commutative: bool,
+    priority: bool,
     write_onlys: Vec<Ident>,
     read_writes: Vec<Ident>,
+    /// The alias and key expression of each keyed resource.
+    keys: Vec<(Ident, TokenStream)>,
+    /// The id of the timeline of each read, and each write.
+    read_ids: Vec<TokenStream>,
+    write_ids: Vec<TokenStream>,
     all_reads: Vec<Ident>,
     all_writes: Vec<Ident>,
 }
@@ -102,11 +192,50 @@ fn generate_operation(idents: &Idents) -> TokenStream {
         op_body_function,
         continuations,
         activity,
+        generics,
+        uses_now,
+        ordered,
+        commutative,
+        priority,
+        read_writes,
         all_reads,
         all_writes,
+        keys,
+        read_ids,
+        write_ids,
         ..
     } = idents;
 
+    let num_keys = keys.len();
+    let key_aliases = keys.iter().map(|(alias, _)| alias).collect::<Vec<_>>();
+    let key_indices = 0..num_keys;
+
+    let param_list = generics.params.iter();
+    let params = quote! { #(, #param_list)* };
+    let args = generic_args(generics);
+    let where_clause = &generics.where_clause;
+
+    // Different instantiations of a generic activity can behave differently for the same inputs.
+    let instantiation_hash = (!generics.params.is_empty()).then(|| {
+        quote! { std::any::type_name::<#activity>().hash(&mut state); }
+    });
+
+    // Operations that read their own time can't share results with the same operation at
+    // other times.
+    let time_hash = uses_now
+        .then(|| quote! { peregrine::timeline::instant_nanoseconds(time).hash(&mut state); });
+    // Explicit orders are part of the plan, like the time, but the default order only depends
+    // on when the op was inserted, so it is left out to keep results reusable between plans.
+    let order_hash = ordered.then(|| quote! { self.order.hash(&mut state); });
+    let now_arg = uses_now.then(|| quote! { peregrine::timeline::instant_to_epoch(time), });
+    let rerun_time = uses_now.then(|| {
+        quote! {
+            let time = unsafe { (*self.internals.get()).grounding_result }
+                .and_then(|result| result.ok())
+                .ok_or_else(|| peregrine::anyhow!("the operation's time is not known"))?;
+        }
+    });
+
     let first_write = &all_writes[0];
     let all_but_one_write = &all_writes[1..];
 
@@ -115,14 +244,33 @@ fn generate_operation(idents: &Idents) -> TokenStream {
         .map(|i| format_ident!("_peregrine_engine_resource_hash_{i}"))
         .collect::<Vec<_>>();
 
+    // A commutative op adds its contribution to the hash of the resource it commutes on,
+    // instead of hashing them together, so that any ordering of the same commutative ops after
+    // the same upstream ends with the same hash.
+    let (hashed_inputs, commutative_hash) = if *commutative {
+        let resource = format_ident!("_peregrine_engine_resource_hash_{}", read_writes[0]);
+        (
+            all_read_response_hashes
+                .iter()
+                .filter(|hash| **hash != resource)
+                .collect::<Vec<_>>(),
+            Some(quote! { .wrapping_add(#resource) }),
+        )
+    } else {
+        (all_read_response_hashes.iter().collect(), None)
+    };
+
     let all_read_responses = all_reads
         .iter()
         .map(|i| format_ident!("{i}_response"))
         .collect::<Vec<_>>();
 
+    // Recorded continuations are keyed by the write they were sent, along with the node.
+    let write_indices = (0..all_writes.len()).collect::<Vec<_>>();
+
     quote! {
         struct #op_internals<'o, M: peregrine::Model<'o>> {
-            grounding_result: Option<peregrine::operation::InternalResult<peregrine::Duration>>,
+            grounding_result: Option<peregrine::operation::InternalResult<peregrine::timeline::Instant>>,
 
             #(#all_reads: Option<&'o dyn peregrine::operation::Upstream<'o, #all_reads, M>>,)*
             #(#all_read_responses: Option<peregrine::operation::InternalResult<(u64, <#all_reads as peregrine::resource::Resource<'o>>::Read)>>,)*
@@ -130,20 +278,27 @@ fn generate_operation(idents: &Idents) -> TokenStream {
             result: peregrine::operation::InternalResult<#output<'o>>
         }
 
-        struct #op<'o, M: peregrine::Model<'o>> {
+        struct #op<'o, M: peregrine::Model<'o> #params> #where_clause {
             grounding: peregrine::Grounding<'o, M>,
+            order: i32,
+            sequence: peregrine::reexports::crossbeam::atomic::AtomicCell<u64>,
             grounding_state: peregrine::reexports::crossbeam::atomic::AtomicCell<peregrine::operation::OperationState>,
             value_state: peregrine::reexports::crossbeam::atomic::AtomicCell<peregrine::operation::OperationState>,
             response_counter: peregrine::reexports::crossbeam::atomic::AtomicCell<u8>,
+            removed: peregrine::reexports::crossbeam::atomic::AtomicCell<bool>,
 
-            continuations: peregrine::reexports::parking_lot::Mutex<peregrine::operation::RecordedQueue<#continuations<'o, M>, #continuations<'o, M>>>,
+            continuations: peregrine::reexports::parking_lot::Mutex<peregrine::operation::RecordedQueue<#continuations<'o, M>, #continuations<'o, M>, (usize, peregrine::operation::NodeKey)>>,
             grounding_continuations: peregrine::reexports::parking_lot::Mutex<peregrine::operation::RecordedQueue<peregrine::operation::Continuation<'o, peregrine::operation::ungrounded::peregrine_grounding, M>, peregrine::operation::Continuation<'o, peregrine::operation::ungrounded::peregrine_grounding, M>>>,
 
             activity: &'o #activity,
+            /// The hash of the activity's arguments, or 0 if it isn't serializable.
+            arguments: u64,
+            /// The timelines of the keys of keyed resources.
+            keys: [u64; #num_keys],
             internals: peregrine::exec::UnsafeSyncCell<#op_internals<'o, M>>
         }
 
-        #[derive(Copy, Clone, Default)]
+        #[derive(Copy, Clone)]
         struct #output<'h> {
             hash: u64,
             #(#all_writes: <#all_writes as peregrine::resource::Resource<'h>>::Read,)*
@@ -154,21 +309,29 @@ fn generate_operation(idents: &Idents) -> TokenStream {
             #(#all_writes(peregrine::operation::Continuation<'o, #all_writes, M>),)*
         }
 
-        impl<'s, 'o: 's, M: peregrine::Model<'o>> #op<'o, M> {
-            fn new(grounding: peregrine::Grounding<'o, M>, activity: &'o #activity) -> Self {
+        impl<'s, 'o: 's, M: peregrine::Model<'o> #params> #op<'o, M #args> #where_clause {
+            fn new(grounding: peregrine::Grounding<'o, M>, order: i32, activity: &'o #activity, keys: [u64; #num_keys]) -> Self {
                 #op {
+                    keys,
                     grounding,
+                    order,
+                    sequence: Default::default(),
                     grounding_state: peregrine::reexports::crossbeam::atomic::AtomicCell::new(match grounding {
                         peregrine::Grounding::Static(t) => peregrine::operation::OperationState::Done,
                         _ => peregrine::operation::OperationState::Dormant,
                     }),
                     value_state: Default::default(),
                     response_counter: Default::default(),
+                    removed: Default::default(),
 
                     continuations: Default::default(),
                     grounding_continuations: Default::default(),
 
                     activity,
+                    arguments: {
+                        use peregrine::activity::{HashNothing as _, HashSerialized as _};
+                        (&peregrine::activity::ArgumentsHash(activity)).arguments_hash()
+                    },
                     internals: peregrine::exec::UnsafeSyncCell::new(#op_internals {
                         grounding_result: match grounding {
                             peregrine::Grounding::Static(t) => Some(Ok(t)),
@@ -188,29 +351,31 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                 };
                 let mut continuations = self.continuations.lock();
 
-                let start_index = if env.stack_counter < peregrine::exec::STACK_LIMIT { 1 } else { 0 };
-
                 let mut swapped_continuations = peregrine::reexports::smallvec::SmallVec::new();
                 std::mem::swap(&mut continuations.new, &mut swapped_continuations);
+                swapped_continuations.sort_by_key(|c| match c {
+                    #(#continuations::#all_writes(c) => !c.is_prioritized(),)*
+                });
 
-                for c in swapped_continuations.drain(start_index..) {
+                let inline = env.inline_count(swapped_continuations.len());
+                for c in swapped_continuations.drain(inline..) {
                     match c {
                         #(#continuations::#all_writes(c) => {
-                            if let Some(copy) = c.copy_node() {
-                                continuations.old.push(#continuations::#all_writes(copy));
+                            if let Some((key, copy)) = c.recorded() {
+                                continuations.old.entry((#write_indices, key)).or_insert(#continuations::#all_writes(copy));
                             }
-                            scope.spawn(move |s| c.run(result.map(|r| (r.hash, r.#all_writes)), s, timelines, env.reset()));
+                            env.spawn(scope, move |s, env| c.run(result.map(|r| (r.hash, r.#all_writes)), s, timelines, env));
                         })*
                     }
                 }
 
-                if env.stack_counter < peregrine::exec::STACK_LIMIT {
-                    match swapped_continuations.remove(0) {
+                for c in swapped_continuations.drain(..) {
+                    match c {
                         #(#continuations::#all_writes(c) => {
-                            if let Some(copy) = c.copy_node() {
-                                continuations.old.push(#continuations::#all_writes(copy));
+                            if let Some((key, copy)) = c.recorded() {
+                                continuations.old.entry((#write_indices, key)).or_insert(#continuations::#all_writes(copy));
                             }
-                            c.run(result.map(|r| (r.hash, r.#all_writes)), scope, timelines, env.increment());
+                            env.run_inline(scope, move |s, env| c.run(result.map(|r| (r.hash, r.#all_writes)), s, timelines, env));
                         })*
                     }
                 }
@@ -222,29 +387,34 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                 };
                 let mut continuations = self.grounding_continuations.lock();
 
-                assert!(!continuations.new.is_empty());
-                let start_index = if env.stack_counter < peregrine::exec::STACK_LIMIT { 1 } else { 0 };
+                // The grounding might have only been requested to compute the value.
+                if continuations.new.is_empty() {
+                    return;
+                }
 
                 let mut swapped_continuations = peregrine::reexports::smallvec::SmallVec::new();
                 std::mem::swap(&mut continuations.new, &mut swapped_continuations);
-
-                for c in swapped_continuations.drain(start_index..) {
-                    if let Some(copy) = c.copy_node() {
-                        continuations.old.push(copy);
+                for c in &swapped_continuations {
+                    if let Some((key, copy)) = c.recorded() {
+                        continuations.old.entry(key).or_insert(copy);
                     }
-                    scope.spawn(move |s| c.run(grounding_result.unwrap().map(|d| (0, d)), s, timelines, env.reset()));
+                }
+                // Downstreams of the grounding can depend on it again through the resources
+                // that this node writes, so the lock can't be held while they run.
+                drop(continuations);
+                peregrine::operation::Continuation::prioritize(&mut swapped_continuations);
+
+                let inline = env.inline_count(swapped_continuations.len());
+                for c in swapped_continuations.drain(inline..) {
+                    env.spawn(scope, move |s, env| c.run(grounding_result.unwrap().map(|d| (0, d)), s, timelines, env));
                 }
 
-                if env.stack_counter < peregrine::exec::STACK_LIMIT {
-                    let last = swapped_continuations.remove(0);
-                    if let Some(copy) = last.copy_node() {
-                        continuations.old.push(copy);
-                    }
-                    last.run(grounding_result.unwrap().map(|d| (0, d)), scope, timelines, env.increment());
+                for c in swapped_continuations.drain(..) {
+                    env.run_inline(scope, move |s, env| c.run(grounding_result.unwrap().map(|d| (0, d)), s, timelines, env));
                 }
             }
 
-            fn send_requests(&'o self, time: peregrine::Duration, scope: &peregrine::reexports::rayon::Scope<'s>, timelines: &'s peregrine::timeline::Timelines<'o, M>, env: peregrine::exec::ExecEnvironment<'s, 'o>) {
+            fn send_requests(&'o self, time: peregrine::timeline::Instant, scope: &peregrine::reexports::rayon::Scope<'s>, timelines: &'s peregrine::timeline::Timelines<'o, M>, env: peregrine::exec::ExecEnvironment<'s, 'o>) {
                 let internals = self.internals.get();
                 let (#(#all_read_responses,)*) = unsafe {
                     (#((*internals).#all_read_responses,)*)
@@ -258,7 +428,7 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                 #(
                     unsafe {
                         if (*internals).#all_reads.is_none() {
-                            (*internals).#all_reads = Some(timelines.find_upstream(time))
+                            (*internals).#all_reads = Some(timelines.find_upstream(#read_ids, time, (self.order, self.sequence.load())))
                                 .expect("Could not find an upstream node. Did you insert before the initial conditions?");
                         }
                     }
@@ -267,10 +437,10 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                         let #all_reads = unsafe {
                             (*internals).#all_reads
                         };
-                        if num_requests == 0 && env.stack_counter < peregrine::exec::STACK_LIMIT {
-                            #all_reads.unwrap().request(peregrine::operation::Continuation::Node(self), scope, timelines, env.increment());
+                        if num_requests == 0 && env.can_inline() {
+                            env.run_inline(scope, move |s, env| #all_reads.unwrap().request(peregrine::operation::Continuation::Node(self), s, timelines, env));
                         } else {
-                            scope.spawn(move |s| #all_reads.unwrap().request(peregrine::operation::Continuation::Node(self), s, timelines, env.reset()));
+                            env.spawn(scope, move |s, env| #all_reads.unwrap().request(peregrine::operation::Continuation::Node(self), s, timelines, env));
                         }
                     }
                 )*
@@ -286,39 +456,123 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                     (#((*internals).#all_read_responses.unwrap()?,)*)
                 };
 
+                let time = unsafe {
+                    (*internals).grounding_result.unwrap().unwrap()
+                };
+                let _span = peregrine::exec::op_span(#activity::LABEL, peregrine::timeline::instant_to_epoch(time));
+
                 let hash = {
                     use std::hash::{Hasher, BuildHasher, Hash};
 
                     let mut state = peregrine::history::PeregrineDefaultHashBuilder::default().build_hasher();
                     std::any::TypeId::of::<#output>().hash(&mut state);
+                    #instantiation_hash
+                    if self.arguments != 0 {
+                        self.arguments.hash(&mut state);
+                    }
+                    #time_hash
+                    #order_hash
 
-                    #(#all_read_response_hashes.hash(&mut state);)*
+                    #(#hashed_inputs.hash(&mut state);)*
 
                     state.finish()
-                };
+                }#commutative_hash;
 
-                let result = if let Some(#first_write) = env.history.get::<#first_write>(hash) {
+                let cached = env.history.get::<#first_write>(hash);
+                let was_cached = cached.is_some();
+                peregrine::exec::trace_lookup(hash, was_cached);
+                let result = if let Some(#first_write) = cached {
                     #(let #all_but_one_write = env.history.get::<#all_but_one_write>(hash).expect("expected all write outputs from past run to be written to history");)*
-                    Ok(#output {
-                        hash,
-                        #(#all_writes),*
-                    })
+                    if let Some(audit) = env.audit {
+                        use peregrine::exec::Rerun;
+                        if let Err(error) = self.rerun(env.history, &[#(#all_read_response_hashes),*], hash) {
+                            audit.report(
+                                #activity::LABEL,
+                                &[#(<#all_writes as peregrine::resource::Resource<'static>>::LABEL),*],
+                                peregrine::timeline::instant_to_epoch(time),
+                                self as *const Self as *const (),
+                                error
+                            );
+                        }
+                    }
+                    if env.determinism_check.check_cached(hash) {
+                        use peregrine::exec::Rerun;
+                        self.rerun(env.history, &[#(#all_read_response_hashes),*], hash)
+                            .with_context(|| format!("cached value of activity {} at {} could not be reproduced", #activity::LABEL, time))
+                            .map(|_| #output {
+                                hash,
+                                #(#all_writes),*
+                            })
+                    } else {
+                        Ok(#output {
+                            hash,
+                            #(#all_writes),*
+                        })
+                    }
+                } else if let Some(executor) = env.executor.filter(|executor| executor.accepts(#activity::LABEL)) {
+                    use peregrine::activity::{EncodeNothing as _, EncodeSerialized as _};
+                    (|| {
+                        let arguments = (&peregrine::activity::ArgumentsBytes(self.activity)).encoded_arguments()
+                            .ok_or_else(|| peregrine::anyhow!("activity {} can't be offloaded because it isn't serializable", #activity::LABEL))?;
+                        executor.execute(&peregrine::offload::Job {
+                            activity: #activity::LABEL.to_string(),
+                            operation: stringify!(#op_body_function).to_string(),
+                            arguments,
+                            time: peregrine::timeline::instant_to_epoch(time),
+                            inputs: vec![#(#all_read_response_hashes),*],
+                            hash,
+                        })?;
+                        #(
+                            let #all_writes = env.history.get::<#all_writes>(hash)
+                                .ok_or_else(|| peregrine::anyhow!("the executor didn't store output {} in history", <#all_writes as peregrine::resource::Resource<'o>>::LABEL))?;
+                        )*
+                        Ok(#output {
+                            hash,
+                            #(#all_writes),*
+                        })
+                    })()
                 } else {
                     use peregrine::{Activity, Context};
                     use peregrine::activity::ActivityLabel;
-                    let time = unsafe {
-                        (*self.internals.get()).grounding_result.unwrap().unwrap()
-                    };
-                    self.activity.#op_body_function(#(#all_reads,)*)
-                        .with_context(|| format!("occurred in activity {} at {}", #activity::LABEL, time))
-                        .map(|(#(#all_writes,)*)| #output {
-                            hash,
-                            #(#all_writes: env.history.insert::<#all_writes>(hash, #all_writes),)*
+                    self.activity.#op_body_function(#now_arg #(#all_reads,)*)
+                        .and_then(|first| {
+                            if env.determinism_check.check_uncached() {
+                                let second = self.activity.#op_body_function(#now_arg #(#all_reads,)*)?;
+                                let (first_string, second_string) = (format!("{first:?}"), format!("{second:?}"));
+                                if first_string != second_string {
+                                    peregrine::bail!("operation is not deterministic: first evaluation produced {first_string}, but the second produced {second_string}");
+                                }
+                            }
+                            Ok(first)
+                        })
+                        .and_then(|(#(#all_writes,)*)| {
+                            #(<#all_writes as peregrine::resource::Resource<'o>>::check_write(&#all_writes)?;)*
+                            Ok(#output {
+                                hash,
+                                #(#all_writes: env.history.insert::<#all_writes>(hash, #all_writes),)*
+                            })
                         })
                 };
 
-                result.map_err(|e| {
-                    env.errors.push(e);
+                if let Some(recorder) = env.recorder {
+                    recorder.record(peregrine::exec::RecordedExecution::new(
+                        #activity::LABEL,
+                        peregrine::timeline::instant_to_epoch(time),
+                        hash,
+                        vec![#(#all_read_response_hashes),*],
+                        was_cached,
+                        self
+                    ));
+                }
+
+                result.map_err(|error| {
+                    env.errors.push(peregrine::exec::OpError::new(
+                        #activity::LABEL,
+                        peregrine::timeline::instant_to_epoch(time),
+                        &[#(<#all_writes as peregrine::resource::Resource<'static>>::LABEL),*],
+                        self as *const Self as *const (),
+                        error
+                    ));
                     peregrine::operation::ObservedErrorOutput
                 })
             }
@@ -329,32 +583,39 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                 match self.value_state.swap(OperationState::Dormant) {
                     OperationState::Dormant => {}
                     OperationState::Done => {
+                        // The downstreams keep pointing to this node as their upstream, so they stay
+                        // in the list in case this node is removed before they request it again,
+                        // unless they were removed or have found another upstream since.
+                        let this = self as *const Self as *const ();
                         let mut continuations_lock = self.continuations.lock();
                         assert!(continuations_lock.new.is_empty());
-                        for continuation in continuations_lock.old.drain(..) {
+                        continuations_lock.old.retain(|_, continuation| {
                             match continuation {
                                 #(#continuations::#all_writes(c) => {
-                                    match c {
-                                        peregrine::operation::Continuation::Node(n) => n.clear_cache(),
-                                        peregrine::operation::Continuation::MarkedNode(_, n) => n.clear_cache(),
-                                        _ => unreachable!()
+                                    let kept = c.reads_from(this);
+                                    if kept {
+                                        c.clear_cache();
                                     }
+                                    kept
                                 })*
                             }
-                        }
+                        });
                     },
                     OperationState::Waiting => unreachable!()
                 }
             }
         }
 
-        impl<'o, M: peregrine::Model<'o>> peregrine::operation::Node<'o, M> for #op<'o, M> {
-            fn insert_self(&'o self, timelines: &mut peregrine::timeline::Timelines<'o, M>, disruptive: bool) -> peregrine::Result<()> {
+        impl<'o, M: peregrine::Model<'o> #params> peregrine::operation::Node<'o, M> for #op<'o, M #args> #where_clause {
+            unsafe fn insert_self(&'o self, timelines: &peregrine::timeline::Timelines<'o, M>, disruptive: bool) -> peregrine::Result<()> {
                 let notify_time = self.grounding.min();
+                let order = (self.order, timelines.next_sequence());
+                self.sequence.store(order.1);
+                #(timelines.ensure_key::<#key_aliases>(self.keys[#key_indices])?;)*
                 #(
                     let previous = match self.grounding {
-                        peregrine::Grounding::Static(t) => timelines.insert_grounded::<#all_writes>(t, self, disruptive),
-                        peregrine::Grounding::Dynamic { min, max, .. } => timelines.insert_ungrounded::<#all_writes>(min, max, self, disruptive),
+                        peregrine::Grounding::Static(t) => unsafe { timelines.insert_grounded::<#all_writes>(#write_ids, t, order, self, disruptive) },
+                        peregrine::Grounding::Dynamic { min, max, .. } => unsafe { timelines.insert_ungrounded::<#all_writes>(#write_ids, min, max, self, disruptive) },
                     };
                     if disruptive {
                         assert!(previous.len() > 0);
@@ -365,37 +626,120 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                 )*
                 Ok(())
             }
-            fn remove_self(&self, timelines: &mut peregrine::timeline::Timelines<'o, M>) -> peregrine::Result<()> {
+            unsafe fn remove_self(&self, timelines: &peregrine::timeline::Timelines<'o, M>, removal: &peregrine::operation::Removal) -> peregrine::Result<()> {
                 #(
                     let removed = match self.grounding {
-                        peregrine::Grounding::Static(t) => timelines.remove_grounded::<#all_writes>(t),
-                        peregrine::Grounding::Dynamic { min, max, .. } => timelines.remove_ungrounded::<#all_writes>(min, max),
+                        peregrine::Grounding::Static(t) => unsafe { timelines.remove_grounded::<#all_writes>(#write_ids, t, (self.order, self.sequence.load())) },
+                        peregrine::Grounding::Dynamic { min, .. } => unsafe { timelines.remove_ungrounded::<#all_writes>(#write_ids, min, self as *const Self as *const ()) },
                     };
                     if !removed {
                         peregrine::bail!("Removal failed; could not find self at the expected time.")
                     }
                 )*
 
+                self.detach();
+
+                let this = self as *const Self as *const ();
                 let mut lock = self.continuations.lock();
                 assert!(lock.new.is_empty());
-                for continuation in lock.old.drain(..) {
+                for (_, continuation) in lock.old.drain() {
                     match continuation {
                         #(#continuations::#all_writes(c) => {
-                            match c {
-                                peregrine::operation::Continuation::Node(n) => n.clear_upstream(None),
-                                peregrine::operation::Continuation::MarkedNode(_, n) => n.clear_upstream(None),
-                                _ => unreachable!()
-                            };
+                            if c.is_removed(removal) || !c.reads_from(this) {
+                                continue;
+                            }
+                            c.clear_upstream(None);
                         })*
                     }
                 }
 
                 Ok(())
             }
+
+            fn detach(&self) {
+                self.removed.store(true);
+                let this = self as *const Self as *const ();
+                #(
+                    if let Some(upstream) = unsafe { (*self.internals.get()).#all_reads } {
+                        upstream.forget_downstream(this);
+                    }
+                )*
+                if let peregrine::Grounding::Dynamic { node, .. } = self.grounding {
+                    node.forget_downstream(this);
+                }
+            }
+
+            fn info(&self) -> peregrine::operation::OpInfo {
+                use peregrine::activity::ActivityLabel;
+
+                let (min_time, max_time, dynamic) = match self.grounding {
+                    peregrine::Grounding::Static(t) => (t, t, false),
+                    peregrine::Grounding::Dynamic { min, max, .. } => (min, max, true),
+                };
+                peregrine::operation::OpInfo {
+                    activity: #activity::LABEL,
+                    min_time: peregrine::timeline::instant_to_epoch(min_time),
+                    max_time: peregrine::timeline::instant_to_epoch(max_time),
+                    reads: &[#(<#all_reads as peregrine::resource::Resource<'static>>::LABEL),*],
+                    writes: &[#(<#all_writes as peregrine::resource::Resource<'static>>::LABEL),*],
+                    dynamic,
+                    priority: #priority,
+                }
+            }
+
+            fn missing_upstreams(&self, timelines: &peregrine::timeline::Timelines<'o, M>) -> Vec<&'static str> {
+                let time = self.grounding.min();
+                let order = (self.order, self.sequence.load());
+                let mut missing = vec![];
+                #(
+                    if !timelines.has_upstream::<#all_reads>(#read_ids, time, order) {
+                        missing.push(<#all_reads as peregrine::resource::Resource<'static>>::LABEL);
+                    }
+                )*
+                missing
+            }
+
+            fn is_evaluated(&self) -> bool {
+                self.value_state.load() == peregrine::operation::OperationState::Done
+            }
+
+            fn recorded_downstreams(&self) -> usize {
+                self.continuations.lock().old.len() + self.grounding_continuations.lock().old.len()
+            }
+        }
+
+        impl<'o, M: peregrine::Model<'o> #params> peregrine::exec::Rerun<'o> for #op<'o, M #args> #where_clause {
+            fn rerun(&self, history: &'o peregrine::History, inputs: &[u64], hash: u64) -> peregrine::Result<()> {
+                use peregrine::Context;
+                use peregrine::activity::ActivityLabel;
+
+                #rerun_time
+                let mut inputs = inputs.iter();
+                #(
+                    let #all_reads = inputs.next()
+                        .and_then(|input| history.get::<#all_reads>(*input))
+                        .ok_or_else(|| peregrine::anyhow!("input {} is missing from history", <#all_reads as peregrine::resource::Resource<'o>>::LABEL))?;
+                )*
+                let (#(#all_writes,)*) = self.activity.#op_body_function(#now_arg #(#all_reads,)*)
+                    .with_context(|| format!("occurred while rerunning activity {}", #activity::LABEL))?;
+                #(
+                    let stored = history.get::<#all_writes>(hash)
+                        .ok_or_else(|| peregrine::anyhow!("output {} is missing from history", <#all_writes as peregrine::resource::Resource<'o>>::LABEL))?;
+                    let (stored, recomputed) = (format!("{stored:?}"), format!("{:?}", #all_writes));
+                    if stored != recomputed {
+                        peregrine::bail!(
+                            "output {} of activity {} diverged: history has {stored}, but rerunning produced {recomputed}",
+                            <#all_writes as peregrine::resource::Resource<'o>>::LABEL,
+                            #activity::LABEL
+                        );
+                    }
+                )*
+                Ok(())
+            }
         }
 
         #(
-            impl<'o, M: peregrine::Model<'o>> peregrine::operation::Downstream<'o, #all_reads, M> for #op<'o, M> {
+            impl<'o, M: peregrine::Model<'o> #params> peregrine::operation::Downstream<'o, #all_reads, M> for #op<'o, M #args> #where_clause {
                 fn respond<'s>(
                     &'o self,
                     value: peregrine::operation::InternalResult<(u64, <#all_reads as peregrine::resource::Resource<'o>>::Read)>,
@@ -434,7 +778,7 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                     self.clear_cached_continuations();
                 }
 
-                fn clear_upstream(&self, time_of_change: Option<peregrine::Duration>) -> bool {
+                fn clear_upstream(&self, time_of_change: Option<peregrine::timeline::Instant>) -> bool {
                     let internals = self.internals.get();
                     let (clear, retain) = if let Some(time_of_change) = time_of_change {
                         unsafe {
@@ -458,11 +802,15 @@ fn generate_operation(idents: &Idents) -> TokenStream {
 
                     retain
                 }
+
+                fn reads_from(&self, upstream: *const ()) -> bool {
+                    !self.removed.load() && unsafe { (*self.internals.get()).#all_reads }.is_none_or(|u| u.forwards_to(upstream))
+                }
             }
         )*
 
         #(
-            impl<'o, M: peregrine::Model<'o>> peregrine::operation::Upstream<'o, #all_writes, M> for #op<'o, M> {
+            impl<'o, M: peregrine::Model<'o> #params> peregrine::operation::Upstream<'o, #all_writes, M> for #op<'o, M #args> #where_clause {
                 fn request<'s>(
                     &'o self,
                     continuation: peregrine::operation::Continuation<'o, #all_writes, M>,
@@ -501,26 +849,29 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                     }
                 }
 
-                fn notify_downstreams(&self, time_of_change: peregrine::Duration) {
+                fn notify_downstreams(&self, time_of_change: peregrine::timeline::Instant) {
+                    let this = self as *const Self as *const ();
                     let mut lock = self.continuations.lock();
                     assert!(lock.new.is_empty());
-                    lock.old.retain(|continuation| {
+                    lock.old.retain(|_, continuation| {
                         match continuation {
-                            #continuations::#all_writes(c) => {
-                                match c {
-                                    peregrine::operation::Continuation::Node(n) => n.clear_upstream(Some(time_of_change)),
-                                    peregrine::operation::Continuation::MarkedNode(_, n) => n.clear_upstream(Some(time_of_change)),
-                                    _ => unreachable!()
-                                }
-                            }
+                            #continuations::#all_writes(c) => c.reads_from(this) && c.clear_upstream(Some(time_of_change)),
                             _ => true
                         }
                     })
                 }
+
+                fn is_cached(&self) -> bool {
+                    self.value_state.load() == peregrine::operation::OperationState::Done
+                }
+
+                fn forget_downstream(&self, downstream: *const ()) {
+                    self.continuations.lock().old.retain(|(_, (node, _)), _| *node != peregrine::operation::address(downstream));
+                }
             }
         )*
 
-        impl<'o, M: peregrine::Model<'o>> peregrine::operation::Upstream<'o, peregrine::operation::ungrounded::peregrine_grounding, M> for #op<'o, M> {
+        impl<'o, M: peregrine::Model<'o> #params> peregrine::operation::Upstream<'o, peregrine::operation::ungrounded::peregrine_grounding, M> for #op<'o, M #args> #where_clause {
             fn request<'s>(
                 &'o self,
                 continuation: peregrine::operation::Continuation<'o, peregrine::operation::ungrounded::peregrine_grounding, M>,
@@ -545,15 +896,23 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                 }
             }
 
-            fn notify_downstreams(&self, time_of_change: peregrine::Duration) {
+            fn notify_downstreams(&self, time_of_change: peregrine::timeline::Instant) {
                 unreachable!()
             }
+
+            fn is_cached(&self) -> bool {
+                self.grounding_state.load() == peregrine::operation::OperationState::Done
+            }
+
+            fn forget_downstream(&self, downstream: *const ()) {
+                self.grounding_continuations.lock().old.retain(|(node, _), _| *node != peregrine::operation::address(downstream));
+            }
         }
 
-        impl<'o, M: peregrine::Model<'o>> peregrine::operation::Downstream<'o, peregrine::operation::ungrounded::peregrine_grounding, M> for #op<'o, M> {
+        impl<'o, M: peregrine::Model<'o> #params> peregrine::operation::Downstream<'o, peregrine::operation::ungrounded::peregrine_grounding, M> for #op<'o, M #args> #where_clause {
             fn respond<'s>(
                 &'o self,
-                value: peregrine::operation::InternalResult<(u64, peregrine::Duration)>,
+                value: peregrine::operation::InternalResult<(u64, peregrine::timeline::Instant)>,
                 scope: &peregrine::reexports::rayon::Scope<'s>,
                 timelines: &'s peregrine::timeline::Timelines<'o, M>,
                 env: peregrine::exec::ExecEnvironment<'s, 'o>
@@ -563,11 +922,17 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                 use peregrine::operation::OperationState;
                 use peregrine::activity::ActivityLabel;
 
+                unsafe {
+                    (*self.internals.get()).grounding_result = Some(value.map(|(_, t)| t));
+                }
                 self.grounding_state.store(OperationState::Done);
 
                 self.run_grounding_continuations(scope, timelines, env);
                 if value.is_err() {
-                    self.run_value_continuations(scope, timelines, env);
+                    if self.value_state.load() == OperationState::Waiting {
+                        self.value_state.store(OperationState::Done);
+                        self.run_value_continuations(scope, timelines, env);
+                    }
                 } else {
                     match self.value_state.load() {
                         OperationState::Waiting => self.send_requests(value.unwrap().1, scope, timelines, env),
@@ -583,21 +948,26 @@ fn generate_operation(idents: &Idents) -> TokenStream {
                 match self.grounding_state.swap(OperationState::Dormant) {
                     OperationState::Dormant => {}
                     OperationState::Done => {
+                        // The downstreams keep pointing to this node as their upstream, so they stay
+                        // in the list in case this node is removed before they request it again,
+                        // unless they were removed or have found another upstream since.
+                        let this = self as *const Self as *const ();
                         let mut continuations_lock = self.grounding_continuations.lock();
                         assert!(continuations_lock.new.is_empty());
-                        for continuation in continuations_lock.old.drain(..) {
-                            match continuation {
-                                peregrine::operation::Continuation::Node(n) => n.clear_cache(),
-                                peregrine::operation::Continuation::MarkedNode(_, n) => n.clear_cache(),
-                                _ => unreachable!()
+                        continuations_lock.old.retain(|_, continuation| {
+                            let kept = continuation.reads_from(this);
+                            if kept {
+                                continuation.clear_cache();
                             }
-                        }
+                            kept
+                        });
                     },
                     OperationState::Waiting => unreachable!()
                 }
 
                 let internals = self.internals.get();
                 unsafe {
+                    (*internals).grounding_result = None;
                     #(
                         (*internals).#all_reads = None;
                         (*internals).#all_read_responses = None;
@@ -606,29 +976,52 @@ fn generate_operation(idents: &Idents) -> TokenStream {
 
                 self.clear_cached_continuations();
             }
-            fn clear_upstream(&self, time_of_change: Option<peregrine::Duration>) -> bool {
+            fn clear_upstream(&self, time_of_change: Option<peregrine::timeline::Instant>) -> bool {
                 unreachable!()
             }
+
+            fn reads_from(&self, _upstream: *const ()) -> bool {
+                !self.removed.load()
+            }
         }
 
         #(
-            impl<'o, M: peregrine::Model<'o>> AsRef<dyn peregrine::operation::Upstream<'o, #all_writes, M> + 'o> for #op<'o, M> {
+            impl<'o, M: peregrine::Model<'o> #params> AsRef<dyn peregrine::operation::Upstream<'o, #all_writes, M> + 'o> for #op<'o, M #args> #where_clause {
                 fn as_ref(&self) -> &(dyn peregrine::operation::Upstream<'o, #all_writes, M> + 'o) {
                     self
                 }
             }
 
-            impl<'o, M: peregrine::Model<'o>> peregrine::operation::ungrounded::UngroundedUpstream<'o, #all_writes, M> for #op<'o, M> {}
+            impl<'o, M: peregrine::Model<'o> #params> peregrine::operation::ungrounded::UngroundedUpstream<'o, #all_writes, M> for #op<'o, M #args> #where_clause {}
         )*
     }
 }
 
+/// The generic arguments that refer back to the params of `generics`, with a leading comma.
+fn generic_args(generics: &Generics) -> TokenStream {
+    let args = generics.params.iter().map(|param| match param {
+        GenericParam::Type(t) => t.ident.to_token_stream(),
+        GenericParam::Const(c) => c.ident.to_token_stream(),
+        GenericParam::Lifetime(l) => l.lifetime.to_token_stream(),
+    });
+    quote! { #(, #args)* }
+}
+
 fn result(idents: &Idents) -> TokenStream {
-    let Idents { op, .. } = idents;
+    let Idents {
+        op, generics, keys, ..
+    } = idents;
+    let args = generic_args(generics);
+    let (key_aliases, key_exprs): (Vec<_>, Vec<_>) = keys.iter().cloned().unzip();
 
+    // Keys are evaluated where the op is placed, so they can refer to the activity and to
+    // local variables of the body.
     quote! {
         {
-            |grounding: peregrine::Grounding<'o, M>, context, bump: peregrine::reexports::bumpalo_herd::Member<'o>| bump.alloc(#op::<'o, M>::new(grounding, context))
+            |grounding: peregrine::Grounding<'o, M>, order: i32, context, bump: &peregrine::reexports::bumpalo_herd::Member<'o>| {
+                let keys = [#(peregrine::resource::key_id::<#key_aliases>(&(#key_exprs)),)*];
+                bump.alloc(#op::<'o, M #args>::new(grounding, order, context, keys))
+            }
         }
     }
 }
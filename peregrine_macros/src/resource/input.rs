@@ -0,0 +1,236 @@
+use crate::resource::{Resource, ResourceDeclaration};
+use proc_macro2::Ident;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{
+    Attribute, Error, Expr, ExprLit, GenericParam, Generics, Lit, Meta, Token, Type, Visibility,
+    WhereClause, braced, bracketed, parse_quote,
+};
+
+impl Parse for Resource {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut attributes = Attribute::parse_outer(input)?;
+        let mut unit = None;
+        let mut state_machine = false;
+        let mut finite = false;
+        let mut history = None;
+        let mut schema_version = None;
+        let mut doc_lines = vec![];
+        let mut error = None;
+        attributes.retain(|attr| {
+            if attr.path().is_ident("state_machine") {
+                match attr.meta.require_path_only() {
+                    Ok(_) => state_machine = true,
+                    Err(e) => error = Some(e),
+                }
+                false
+            } else if attr.path().is_ident("finite") {
+                match attr.meta.require_path_only() {
+                    Ok(_) => finite = true,
+                    Err(e) => error = Some(e),
+                }
+                false
+            } else if attr.path().is_ident("history") {
+                match attr.parse_args::<Type>() {
+                    Ok(ty) => history = Some(ty),
+                    Err(e) => error = Some(e),
+                }
+                false
+            } else if attr.path().is_ident("schema_version") {
+                match attr
+                    .meta
+                    .require_name_value()
+                    .and_then(|nv| match &nv.value {
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Int(i), ..
+                        }) => Ok(i.clone()),
+                        other => Err(Error::new_spanned(other, "expected an integer literal")),
+                    }) {
+                    Ok(i) => schema_version = Some(i),
+                    Err(e) => error = Some(e),
+                }
+                false
+            } else if attr.path().is_ident("unit") {
+                match attr
+                    .meta
+                    .require_name_value()
+                    .and_then(|nv| match &nv.value {
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Str(s), ..
+                        }) => Ok(s.clone()),
+                        other => Err(Error::new_spanned(other, "expected a string literal")),
+                    }) {
+                    Ok(s) => unit = Some(s),
+                    Err(e) => error = Some(e),
+                }
+                false
+            } else {
+                if let Meta::NameValue(nv) = &attr.meta
+                    && nv.path.is_ident("doc")
+                    && let Expr::Lit(ExprLit {
+                        lit: Lit::Str(s), ..
+                    }) = &nv.value
+                {
+                    doc_lines.push(s.value().trim().to_string());
+                }
+                true
+            }
+        });
+        if let Some(e) = error {
+            return Err(e);
+        }
+        let description = (!doc_lines.is_empty()).then(|| doc_lines.join(" ").trim().to_string());
+
+        let visibility: Visibility = input.parse()?;
+        let ref_token = input.parse::<Option<Token![ref]>>()?;
+        let by_ref = ref_token.is_some();
+        if let Some(ref_token) = ref_token
+            && state_machine
+        {
+            return Err(Error::new_spanned(
+                ref_token,
+                "state machine resources must be Copy",
+            ));
+        }
+        let kind = if input.peek(syn::Ident) && input.peek2(syn::Ident) {
+            let fork = input.fork();
+            let kind = fork.parse::<Ident>()?;
+            if kind == "array" || kind == "map" {
+                Some(input.parse::<Ident>()?)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        let array_keyword = kind.as_ref().filter(|kind| *kind == "array");
+        let name: Ident = input.parse()?;
+        let mut generics: Generics = input.parse()?;
+
+        let key = match &kind {
+            Some(keyword) if keyword == "map" => {
+                if !generics.params.is_empty() {
+                    return Err(Error::new_spanned(
+                        &generics,
+                        "keyed resources cannot be generic",
+                    ));
+                }
+                let content;
+                bracketed!(content in input);
+                Some(content.parse::<Type>()?)
+            }
+            _ => None,
+        };
+
+        if let Some(param) = generics
+            .params
+            .iter()
+            .find(|p| matches!(p, GenericParam::Lifetime(_)))
+        {
+            return Err(Error::new_spanned(
+                param,
+                "resources cannot have lifetime parameters",
+            ));
+        }
+
+        input.parse::<Token![:]>()?;
+        let mut ty: Type = input.parse()?;
+        let mut array_len = None;
+        if let Some(keyword) = array_keyword {
+            if by_ref {
+                return Err(Error::new_spanned(
+                    keyword,
+                    "array resources must have Copy elements",
+                ));
+            }
+            if !generics.params.is_empty() {
+                return Err(Error::new_spanned(
+                    &generics,
+                    "array resources cannot be generic",
+                ));
+            }
+            let Type::Array(array) = ty else {
+                return Err(Error::new_spanned(
+                    ty,
+                    "expected an array type, like `[f32; 4]`",
+                ));
+            };
+            let Expr::Lit(ExprLit {
+                lit: Lit::Int(len), ..
+            }) = &array.len
+            else {
+                return Err(Error::new_spanned(
+                    &array.len,
+                    "the length of an array resource must be an integer literal",
+                ));
+            };
+            array_len = Some(len.base10_parse::<usize>()?);
+            ty = *array.elem;
+        }
+        let default = if input.parse::<Option<Token![=]>>()?.is_some() {
+            Some(input.parse::<Expr>()?)
+        } else {
+            None
+        };
+        generics.where_clause = input.parse::<Option<WhereClause>>()?;
+
+        Ok(Resource {
+            attributes,
+            unit,
+            state_machine,
+            finite,
+            history,
+            schema_version,
+            description,
+            visibility,
+            by_ref,
+            array_len,
+            key,
+            name,
+            generics,
+            ty,
+            default,
+            label: None,
+        })
+    }
+}
+
+impl Parse for ResourceDeclaration {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let fork = input.fork();
+        Attribute::parse_outer(&fork)?;
+        fork.parse::<Visibility>()?;
+        if !fork.peek(Token![struct]) {
+            return Ok(ResourceDeclaration::Resource(Box::new(input.parse()?)));
+        }
+
+        let attributes = Attribute::parse_outer(input)?;
+        let visibility: Visibility = input.parse()?;
+        input.parse::<Token![struct]>()?;
+        let name: Ident = input.parse()?;
+        let content;
+        braced!(content in input);
+        let mut fields = Punctuated::<Resource, Token![,]>::parse_terminated(&content)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        for field in &mut fields {
+            if field.array_len.is_some() || field.key.is_some() || !field.generics.params.is_empty()
+            {
+                return Err(Error::new_spanned(
+                    &field.name,
+                    "fields of struct resources must be plain resources",
+                ));
+            }
+            field.label = Some(format!("{name}.{}", field.name));
+            // The module the fields are declared in already has the visibility of the struct.
+            field.visibility = parse_quote! { pub };
+        }
+
+        Ok(ResourceDeclaration::Struct {
+            attributes,
+            visibility,
+            name,
+            fields,
+        })
+    }
+}
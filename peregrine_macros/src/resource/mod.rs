@@ -0,0 +1,44 @@
+mod input;
+mod output;
+
+use proc_macro2::Ident;
+use syn::{Attribute, Expr, Generics, LitInt, LitStr, Type, Visibility};
+
+pub struct Resource {
+    /// Attributes forwarded to the label type, including doc comments.
+    attributes: Vec<Attribute>,
+    unit: Option<LitStr>,
+    /// Whether writes are checked with `StateMachine`, declared with `#[state_machine]`.
+    state_machine: bool,
+    /// Whether writes are checked for NaN and infinity, declared with `#[finite]`.
+    finite: bool,
+    /// A custom history container, declared with `#[history(Type)]`.
+    history: Option<Type>,
+    /// The version of the write type's encoding, declared with `#[schema_version = N]`.
+    schema_version: Option<LitInt>,
+    description: Option<String>,
+    visibility: Visibility,
+    by_ref: bool,
+    /// The length of an `array` resource, whose elements are tracked as separate resources.
+    array_len: Option<usize>,
+    /// The key type of a `map` resource, which has a separate timeline for each key.
+    key: Option<Type>,
+    name: Ident,
+    generics: Generics,
+    ty: Type,
+    default: Option<Expr>,
+    /// Replaces the label derived from the name, for the fields of struct resources.
+    label: Option<String>,
+}
+
+/// A single resource, or a struct resource whose fields are separate resources.
+pub enum ResourceDeclaration {
+    Resource(Box<Resource>),
+    Struct {
+        /// Attributes forwarded to the module of the fields, including doc comments.
+        attributes: Vec<Attribute>,
+        visibility: Visibility,
+        name: Ident,
+        fields: Vec<Resource>,
+    },
+}
@@ -0,0 +1,436 @@
+use crate::resource::{Resource, ResourceDeclaration};
+use proc_macro2::TokenStream;
+use quote::{ToTokens, TokenStreamExt, quote};
+use rand::Rng;
+use syn::{GenericParam, Generics, Lifetime, LifetimeParam, WherePredicate, parse_quote};
+
+impl ToTokens for Resource {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let Resource {
+            attributes,
+            unit,
+            state_machine,
+            finite,
+            history: custom_history,
+            schema_version,
+            description,
+            visibility,
+            by_ref,
+            array_len,
+            key,
+            name,
+            generics,
+            ty,
+            default,
+            label: label_override,
+        } = self;
+
+        let is_generic = !generics.params.is_empty();
+
+        // Each element of an array resource is its own resource, selected by a const parameter.
+        let array_generics: Option<Generics> = array_len.map(|_| parse_quote! { <const I: usize> });
+        let generics = array_generics.as_ref().unwrap_or(generics);
+
+        let (read, mut history) = if *by_ref {
+            (
+                quote! { &'h <#ty as std::ops::Deref>::Target },
+                quote! { peregrine::history::DerefHistory<#ty> },
+            )
+        } else {
+            (
+                quote! { #ty },
+                quote! { peregrine::history::CopyHistory<#ty> },
+            )
+        };
+        if let Some(custom) = custom_history {
+            history = custom.to_token_stream();
+        }
+
+        // Concrete types are checked by the trait bounds directly, but generic payloads need
+        // the bounds spelled out so that users don't have to repeat them in a where-clause.
+        let (def_generics, def_where_clause) = (&generics.params, &generics.where_clause);
+        let mut generics = generics.clone();
+        if is_generic {
+            let predicates = &mut generics.make_where_clause().predicates;
+            let common: WherePredicate = parse_quote! {
+                #ty: Clone + std::fmt::Debug + peregrine::reexports::serde::Serialize + peregrine::reexports::serde::de::DeserializeOwned + Send + Sync + 'static
+            };
+            predicates.push(common);
+            if *by_ref {
+                predicates.push(parse_quote! {
+                    #ty: peregrine::reexports::stable_deref_trait::StableDeref
+                });
+                predicates.push(parse_quote! {
+                    <#ty as std::ops::Deref>::Target: std::fmt::Debug + Sync
+                });
+                predicates.push(parse_quote! {
+                    #ty: for<'a> From<&'a <#ty as std::ops::Deref>::Target>
+                });
+            } else {
+                predicates.push(parse_quote! { #ty: Copy });
+            }
+        }
+
+        let type_params = generics.type_params().map(|p| &p.ident).collect::<Vec<_>>();
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        let mut resource_generics = generics.clone();
+        resource_generics.params.insert(
+            0,
+            GenericParam::Lifetime(LifetimeParam::new(Lifetime::new(
+                "'h",
+                proc_macro2::Span::call_site(),
+            ))),
+        );
+        let (resource_impl_generics, _, _) = resource_generics.split_for_impl();
+
+        let (phantom, serde_bound) = if is_generic {
+            (
+                quote! {
+                    #[doc(hidden)]
+                    #[serde(skip)]
+                    __Phantom(std::convert::Infallible, std::marker::PhantomData<fn() -> (#(#type_params,)*)>),
+                    /// Made by `register_resource!`, with the type arguments it was given.
+                    #[doc(hidden)]
+                    #[serde(skip)]
+                    __Registered(&'static str),
+                },
+                quote! { #[serde(bound = "")] },
+            )
+        } else {
+            (quote! {}, quote! {})
+        };
+
+        // Each instantiation of a generic resource has a different payload type, so its type
+        // string is the label followed by the type arguments it was registered with. Only
+        // registered instantiations are ever asked for theirs.
+        let write_type_string = if is_generic {
+            quote! {
+                let label = <Self as peregrine::resource::Resource<'static>>::LABEL;
+                match self {
+                    Self::__Registered(arguments) => format!("{label}<{arguments}>"),
+                    _ => label.to_string(),
+                }
+            }
+        } else {
+            quote! { peregrine::reexports::peregrine_macros::code_to_str!(#ty).to_string() }
+        };
+        // Instantiations of a generic resource share its label, so they are told apart by
+        // their type string, which has the type arguments.
+        let resource_name = if is_generic {
+            quote! {
+                fn resource_name(&self) -> String {
+                    self.write_type_string()
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        // Generic resources can't be registered until they are instantiated, see `register_resource!`.
+        let submit = if let Some(len) = array_len {
+            let indices = 0..*len;
+            quote! {
+                #(peregrine::reexports::inventory::submit!(&#name::<#indices>::Unit as &dyn peregrine::resource::ResourceHistoryPlugin);)*
+            }
+        } else if is_generic {
+            quote! {}
+        } else {
+            quote! {
+                peregrine::reexports::inventory::submit!(&#name::Unit as &dyn peregrine::resource::ResourceHistoryPlugin);
+            }
+        };
+
+        let default = default.as_ref().map(|value| {
+            quote! {
+                fn default_initial_condition() -> Option<Self::Write> {
+                    Some(#value)
+                }
+            }
+        });
+
+        let check_transition = state_machine.then(|| {
+            quote! {
+                fn check_transition(from: Self::Read, to: &Self::Write) -> peregrine::Result<()> {
+                    peregrine::resource::check_state_transition::<Self, #ty>(from, to)
+                }
+            }
+        });
+
+        let check_write = finite.then(|| {
+            quote! {
+                fn check_write(value: &Self::Write) -> peregrine::Result<()> {
+                    peregrine::resource::check_finite::<Self>(value)
+                }
+            }
+        });
+
+        let schema_version = schema_version.as_ref().map(|version| {
+            quote! { const SCHEMA_VERSION: u32 = #version; }
+        });
+
+        let unit = match unit {
+            Some(unit) => quote! { Some(#unit) },
+            None => quote! { None },
+        };
+        let description = match description {
+            Some(description) => quote! { Some(#description) },
+            None => quote! { None },
+        };
+
+        let (label, id) = match array_len {
+            Some(len) => {
+                let labels = (0..*len).map(|i| format!("{name}[{i}]"));
+                let ids = (0..*len).map(|_| rand::rng().random::<u64>());
+                (quote! { [#(#labels),*][I] }, quote! { [#(#ids),*][I] })
+            }
+            None => (
+                match label_override {
+                    Some(label) => quote! { #label },
+                    None => quote! { peregrine::reexports::peregrine_macros::code_to_str!(#name) },
+                },
+                quote! { peregrine::reexports::peregrine_macros::random_u64!() },
+            ),
+        };
+
+        let array_impl = array_len.map(|len| {
+            let indices = (0..len).collect::<Vec<_>>();
+            quote! {
+                impl<const I: usize> peregrine::resource::ResourceArray for #name<I> {
+                    const LEN: usize = #len;
+                }
+
+                impl<const I: usize> peregrine::resource::ResourceGroup for #name<I> {
+                    fn init_history(history: &peregrine::history::History) {
+                        history.init::<Self>();
+                    }
+
+                    fn init_timelines_into<'o, M: peregrine::Model<'o>>(time: peregrine::timeline::Instant, initial_conditions: &mut peregrine::operation::initial_conditions::InitialConditions, timelines: &mut peregrine::timeline::Timelines<'o, M>) -> Result<(), peregrine::EngineError> {
+                        #(
+                            timelines.init_resource::<#name<#indices>>(time, initial_conditions)?;
+                        )*
+                        Ok(())
+                    }
+
+                    fn check_initial_conditions(initial_conditions: &peregrine::operation::initial_conditions::InitialConditions, issues: &mut Vec<peregrine::InitialConditionIssue>) {
+                        #(initial_conditions.check::<#name<#indices>>(issues);)*
+                    }
+
+                    fn sample_conditions<'o, M: peregrine::Model<'o> + 'o>(plan: &peregrine::Plan<'o, M>, time: peregrine::Time, conditions: &mut peregrine::operation::initial_conditions::InitialConditions) -> Result<(), peregrine::EngineError> {
+                        #(plan.sample_condition::<#name<#indices>>(time, conditions)?;)*
+                        Ok(())
+                    }
+
+                    fn describe(resources: &mut Vec<peregrine::resource::ResourceSchema>) {
+                        #(peregrine::resource::ResourceSchema::add::<#name<#indices>>(resources);)*
+                    }
+
+                    fn view_labeled<'o, M: peregrine::Model<'o> + 'o>(plan: &peregrine::Plan<'o, M>, views: &mut peregrine::labeled::LabeledViews<'o>) -> Result<(), peregrine::EngineError> {
+                        #(views.view::<#name<#indices>, M>(plan)?;)*
+                        Ok(())
+                    }
+                }
+            }
+        });
+
+        let keyed_impl = key.as_ref().map(|key| {
+            quote! {
+                impl<'h> peregrine::resource::KeyedResource<'h> for #name {
+                    type Key = #key;
+                }
+            }
+        });
+
+        let result = quote! {
+            #(#attributes)*
+            #[derive(Debug, peregrine::reexports::serde::Serialize, peregrine::reexports::serde::Deserialize)]
+            #[serde(crate = "peregrine::reexports::serde")]
+            #serde_bound
+            #[allow(non_camel_case_types)]
+            #visibility enum #name <#def_generics> #def_where_clause {
+                Unit,
+                #phantom
+            }
+
+            impl #resource_impl_generics peregrine::resource::Resource<'h> for #name #ty_generics #where_clause {
+                const LABEL: &'static str = #label;
+                const STATIC: bool = true;
+                const ID: u64 = #id;
+                const UNIT: Option<&'static str> = #unit;
+                const DESCRIPTION: Option<&'static str> = #description;
+                #schema_version
+                type Read = #read;
+                type Write = #ty;
+                type History = #history;
+
+                #default
+                #check_transition
+                #check_write
+            }
+
+            impl #impl_generics peregrine::resource::ResourceHistoryPlugin for #name #ty_generics #where_clause {
+                fn write_type_string(&self) -> String {
+                    #write_type_string
+                }
+
+                fn ser<'h>(&self, input: &'h mut peregrine::reexports::type_map::concurrent::TypeMap, type_map: &'h mut peregrine::reexports::type_reg::untagged::TypeMap<String>) {
+                    if let Some(h) = input.remove::<#history>() {
+                        type_map.insert(self.write_type_string(), h);
+                    }
+                }
+
+                fn register(&self, type_reg: &mut peregrine::reexports::type_reg::untagged::TypeReg<String>) {
+                    type_reg.register::<#history>(self.write_type_string());
+                }
+                fn history_type_id(&self) -> std::any::TypeId {
+                    std::any::TypeId::of::<#history>()
+                }
+                fn schema(&self) -> peregrine::history::portable::Schema {
+                    peregrine::history::portable::Schema::new(
+                        &self.write_type_string(),
+                        std::mem::size_of::<#ty>(),
+                        <Self as peregrine::resource::Resource<'static>>::SCHEMA_VERSION,
+                    )
+                }
+                fn encode_entries(&self, input: &peregrine::reexports::type_map::concurrent::TypeMap) -> Option<Vec<(u64, Vec<u8>)>> {
+                    input.get::<#history>().map(peregrine::history::HistoryContainer::encode_entries)
+                }
+                fn history_len(&self, input: &peregrine::reexports::type_map::concurrent::TypeMap) -> usize {
+                    input.get::<#history>().map(peregrine::history::HistoryContainer::len).unwrap_or(0)
+                }
+                fn history_bytes(&self, input: &peregrine::reexports::type_map::concurrent::TypeMap) -> usize {
+                    input.get::<#history>().map(peregrine::history::HistoryContainer::memory_bytes).unwrap_or(0)
+                }
+                fn evict(&self, input: &mut peregrine::reexports::type_map::concurrent::TypeMap, count: usize) {
+                    if let Some(h) = input.get_mut::<#history>() {
+                        peregrine::history::HistoryContainer::evict(h, count);
+                    }
+                }
+                fn evict_shared(&self, input: &peregrine::reexports::type_map::concurrent::TypeMap, count: usize) -> usize {
+                    input.get::<#history>().map(|h| peregrine::history::HistoryContainer::evict_shared(h, count)).unwrap_or(0)
+                }
+                fn resource_label(&self) -> &'static str {
+                    <Self as peregrine::resource::Resource<'static>>::LABEL
+                }
+                #resource_name
+                fn resource_info(&self) -> peregrine::resource::ResourceInfo {
+                    peregrine::resource::ResourceInfo::of::<Self>()
+                }
+                fn decode_entries(&self, output: &mut peregrine::reexports::type_map::concurrent::TypeMap, entries: Vec<(u64, Vec<u8>)>) -> peregrine::Result<()> {
+                    let decoded = <#history as peregrine::history::HistoryContainer>::decode_entries(entries)?;
+                    match output.get::<#history>() {
+                        Some(existing) => peregrine::history::HistoryContainer::absorb(existing, decoded),
+                        None => {
+                            output.insert(decoded);
+                        }
+                    }
+                    Ok(())
+                }
+                fn merge(&self, into: &mut peregrine::reexports::type_map::concurrent::TypeMap, from: &mut peregrine::reexports::type_map::concurrent::TypeMap) {
+                    if let Some(other) = from.remove::<#history>() {
+                        match into.get::<#history>() {
+                            Some(existing) => peregrine::history::HistoryContainer::absorb(existing, other),
+                            None => {
+                                into.insert(other);
+                            }
+                        }
+                    }
+                }
+                fn register_initial_condition(&self, type_reg: &mut peregrine::reexports::type_reg::untagged::TypeReg<String>) {
+                    type_reg.register::<#ty>(<Self as peregrine::resource::Resource<'static>>::LABEL.to_string());
+                }
+                fn take_initial_condition(&self, values: &mut peregrine::reexports::type_reg::untagged::TypeMap<String>, conditions: &mut peregrine::operation::initial_conditions::InitialConditions) {
+                    let label = <Self as peregrine::resource::Resource<'static>>::LABEL;
+                    // Another resource may share the label, with a different type.
+                    if values.get::<#ty, _>(label).is_some()
+                        && let Some(value) = values.remove(label)
+                        && let Ok(value) = value.into_inner().downcast::<#ty>()
+                    {
+                        *conditions = std::mem::take(conditions).insert::<Self>(*value);
+                    }
+                }
+                fn de<'h>(&self, output: &'h mut peregrine::reexports::type_map::concurrent::TypeMap, type_map: &'h mut peregrine::reexports::type_reg::untagged::TypeMap<String>) {
+                    match type_map.remove(&self.write_type_string()) {
+                        Some(sub) => {
+                            let sub_history = sub.into_inner().downcast::<#history>();
+                            match sub_history {
+                                Ok(downcasted) => {
+                                    output.insert(*downcasted);
+                                }
+                                Err(_) => unreachable!()
+                            }
+                        }
+                        None => {}
+                    }
+                }
+            }
+
+            #array_impl
+            #keyed_impl
+
+            #submit
+        };
+
+        tokens.append_all(result);
+    }
+}
+
+impl ToTokens for ResourceDeclaration {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        match self {
+            ResourceDeclaration::Resource(resource) => resource.to_tokens(tokens),
+            ResourceDeclaration::Struct {
+                attributes,
+                visibility,
+                name,
+                fields,
+            } => {
+                let field_names = fields.iter().map(|f| &f.name).collect::<Vec<_>>();
+                tokens.append_all(quote! {
+                    #(#attributes)*
+                    #[allow(non_camel_case_types)]
+                    #visibility mod #name {
+                        #[allow(unused_imports)]
+                        use super::*;
+
+                        #(#fields)*
+
+                        /// All fields of the resource, for adding them to a model with `name::*`.
+                        pub enum Fields {}
+
+                        impl peregrine::resource::ResourceGroup for Fields {
+                            fn init_history(history: &peregrine::history::History) {
+                                #(history.init::<#field_names>();)*
+                            }
+
+                            fn init_timelines_into<'o, M: peregrine::Model<'o>>(time: peregrine::timeline::Instant, initial_conditions: &mut peregrine::operation::initial_conditions::InitialConditions, timelines: &mut peregrine::timeline::Timelines<'o, M>) -> Result<(), peregrine::EngineError> {
+                                #(
+                                    timelines.init_resource::<#field_names>(time, initial_conditions)?;
+                                )*
+                                Ok(())
+                            }
+
+                            fn check_initial_conditions(initial_conditions: &peregrine::operation::initial_conditions::InitialConditions, issues: &mut Vec<peregrine::InitialConditionIssue>) {
+                                #(initial_conditions.check::<#field_names>(issues);)*
+                            }
+
+                            fn sample_conditions<'o, M: peregrine::Model<'o> + 'o>(plan: &peregrine::Plan<'o, M>, time: peregrine::Time, conditions: &mut peregrine::operation::initial_conditions::InitialConditions) -> Result<(), peregrine::EngineError> {
+                                #(plan.sample_condition::<#field_names>(time, conditions)?;)*
+                                Ok(())
+                            }
+
+                            fn describe(resources: &mut Vec<peregrine::resource::ResourceSchema>) {
+                                #(peregrine::resource::ResourceSchema::add::<#field_names>(resources);)*
+                            }
+
+                            fn view_labeled<'o, M: peregrine::Model<'o> + 'o>(plan: &peregrine::Plan<'o, M>, views: &mut peregrine::labeled::LabeledViews<'o>) -> Result<(), peregrine::EngineError> {
+                                #(views.view::<#field_names, M>(plan)?;)*
+                                Ok(())
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
@@ -61,21 +61,23 @@ async fn main() -> peregrine::Result<()> {
             b: "".to_string(),
             c: 0,
         },
-    );
+    )?;
 
     plan.reserve_activity_capacity(30_000_000);
 
     let mut cursor = plan_start + Duration::from_microseconds(1.0);
 
+    let mut append = plan.append();
     for _ in 0..10_000_000 {
-        plan.insert(cursor, IncrementA)?;
-        plan.insert(cursor, IncrementC)?;
+        append.insert(cursor, IncrementA)?;
+        append.insert(cursor, IncrementC)?;
         cursor += 1.seconds();
-        plan.insert(cursor, ConvertAToB)?;
+        append.insert(cursor, ConvertAToB)?;
         cursor += 1.seconds();
-        plan.insert(cursor, ConvertBToA)?;
+        append.insert(cursor, ConvertBToA)?;
         cursor += 1.seconds();
     }
+    drop(append);
 
     plan.insert(cursor + 1.seconds(), AddCToA)?;
 
@@ -1,5 +1,4 @@
 use crate::{battery, mode};
-use peregrine::Duration;
 use peregrine::impl_activity;
 use peregrine::reexports::hifitime::TimeUnits;
 use serde::{Deserialize, Serialize};
@@ -10,10 +9,9 @@ pub struct RechargePotato {
 }
 
 impl_activity! { for RechargePotato
-    let end = start + 1.hours();
     @(end) {
         ref mut: battery += 4.0;
         mut: mode = "help".to_string();
     }
-    Duration::ZERO
+    1.hours()
 }
@@ -1,4 +1,5 @@
-use peregrine::{model, resource};
+use crate::activities::recharge_potato::RechargePotato;
+use peregrine::{Session, Time, initial_conditions, model, resource};
 
 mod activities;
 
@@ -9,4 +10,20 @@ model! {
 resource!(battery: f32);
 resource!(ref mode: String);
 
-fn main() {}
+fn main() -> peregrine::Result<()> {
+    let session = Session::new();
+    let start = Time::from_tai_seconds(0.0);
+    let mut plan = session.new_plan::<PotatoSat>(
+        start,
+        initial_conditions! {
+            battery: 50.0,
+            mode: "idle".to_string(),
+        },
+    )?;
+
+    plan.insert(start, RechargePotato { amount: 4 })?;
+
+    println!("{:?}", plan.view::<battery>(start..)?);
+
+    Ok(())
+}